@@ -10,7 +10,8 @@ extern crate nix;
 
 use pa_proto::paths;
 use pa_proto::packet::{Packet, Message};
-use pa_proto::command::{Command, CommandKind, PROTOCOL_MIN_VERSION};
+use pa_proto::command::*;
+use pa_proto::tagstruct::{TagStructReader, FromTagStruct};
 
 use tempfile::Builder;
 use nix::sys::socket::{recvmsg, sendmsg, MsgFlags, CmsgSpace, ControlMessage};
@@ -18,10 +19,12 @@ use nix::sys::uio::IoVec;
 use nix::unistd::close;
 use std::os::unix::net::{UnixListener, UnixStream};
 use std::os::unix::io::{AsRawFd, RawFd};
+use std::collections::HashMap;
 use std::error::Error;
+use std::fmt::Debug;
 use std::process;
-use std::{env, thread};
-use std::sync::Arc;
+use std::{cmp, env, thread};
+use std::sync::{Arc, Mutex};
 use std::sync::atomic::AtomicUsize;
 use std::sync::atomic::Ordering;
 
@@ -62,15 +65,19 @@ fn main() -> Result<(), Box<Error + Send + Sync>> {
     info!("forwarding to real server");
 
     let mut threads = Vec::new();
+    let trace = Arc::new(Trace {
+        protocol_version: AtomicUsize::new(PROTOCOL_MIN_VERSION as usize),
+        pending: Mutex::new(HashMap::new()),
+    });
 
-    let client2server = (faux_stream.clone(), real_stream.clone());
+    let client2server = (faux_stream.clone(), real_stream.clone(), trace.clone());
     threads.push(thread::Builder::new().name("client -> server".into()).spawn(move || {
-        forward(Direction::ClientToServer, &*client2server.0, &*client2server.1)
+        forward(Direction::ClientToServer, &*client2server.0, &*client2server.1, &client2server.2)
     })?);
 
-    let server2client = (faux_stream.clone(), real_stream.clone());
+    let server2client = (faux_stream.clone(), real_stream.clone(), trace.clone());
     threads.push(thread::Builder::new().name("client <- server".into()).spawn(move || {
-        forward(Direction::ServerToClient, &*server2client.1, &*server2client.0)
+        forward(Direction::ServerToClient, &*server2client.1, &*server2client.0, &server2client.2)
     })?);
 
     for thread in threads {
@@ -97,6 +104,102 @@ impl Direction {
     }
 }
 
+/// Decodes and formats the reply to a command.
+type ReplyDecoder = fn(&mut TagStructReader, &Trace) -> Result<String, pa_proto::Error>;
+
+/// State shared by both directions of the connection.
+struct Trace {
+    /// The protocol version, which influences command and reply contents.
+    protocol_version: AtomicUsize,
+    /// Decoders for the replies to commands sent by the client, by tag.
+    pending: Mutex<HashMap<u32, ReplyDecoder>>,
+}
+
+impl Trace {
+    fn protocol_version(&self) -> u16 {
+        self.protocol_version.load(Ordering::SeqCst) as u16
+    }
+
+    fn set_protocol_version(&self, version: u16) {
+        self.protocol_version.store(version as usize, Ordering::SeqCst);
+    }
+}
+
+fn decode<R>(ts: &mut TagStructReader, trace: &Trace) -> Result<String, pa_proto::Error>
+where R: for<'a> FromTagStruct<'a> + Debug {
+    Ok(format!("{:?}", R::from_tag_struct(ts, trace.protocol_version())?))
+}
+
+fn decode_auth_reply(ts: &mut TagStructReader, trace: &Trace) -> Result<String, pa_proto::Error> {
+    let reply = AuthReply::from_tag_struct(ts, trace.protocol_version())?;
+    // Both sides use the lower of their versions from now on
+    trace.set_protocol_version(cmp::min(trace.protocol_version(), reply.server_protocol_version()));
+    info!("protocol version negotiated to {}", trace.protocol_version());
+    Ok(format!("{:?}", reply))
+}
+
+/// Returns the decoder for replies to a command, or `None` if the command has no reply.
+fn reply_decoder(kind: &CommandKind) -> Option<ReplyDecoder> {
+    let decoder: ReplyDecoder = match kind {
+        CommandKind::Auth(_) => decode_auth_reply,
+        CommandKind::SetClientName(_) => decode::<SetClientNameReply>,
+        CommandKind::CreatePlaybackStream(_) => decode::<CreatePlaybackStreamReply>,
+        CommandKind::CreateRecordStream(_) => decode::<CreateRecordStreamReply>,
+        CommandKind::CreateUploadStream(_) => decode::<CreateUploadStreamReply>,
+        CommandKind::FinishUploadStream(_) => decode::<FinishUploadStreamReply>,
+        CommandKind::GetPlaybackLatency(_) |
+        CommandKind::GetRecordLatency(_) => decode::<LatencyInfo>,
+        CommandKind::DeletePlaybackStream(_) |
+        CommandKind::DeleteRecordStream(_) |
+        CommandKind::DeleteUploadStream(_) |
+        CommandKind::RemoveSample(_) |
+        CommandKind::Exit |
+        CommandKind::DrainPlaybackStream(_) |
+        CommandKind::CorkPlaybackStream(_) |
+        CommandKind::CorkRecordStream(_) |
+        CommandKind::FlushPlaybackStream(_) |
+        CommandKind::FlushRecordStream(_) => decode::<()>,
+        CommandKind::GetServerInfo => decode::<ServerInfo>,
+        CommandKind::GetSinkInfoList => decode::<Vec<SinkInfo>>,
+        CommandKind::GetSourceInfoList => decode::<Vec<SourceInfo>>,
+        CommandKind::GetClientInfoList => decode::<Vec<ClientInfo>>,
+        CommandKind::GetCardInfoList => decode::<Vec<CardInfo>>,
+        CommandKind::GetModuleInfoList => decode::<Vec<ModuleInfo>>,
+        CommandKind::GetSinkInputInfoList => decode::<Vec<SinkInputInfo>>,
+        CommandKind::GetSourceOutputInfoList => decode::<Vec<SourceOutputInfo>>,
+        CommandKind::GetSampleInfoList => decode::<Vec<SampleInfo>>,
+        CommandKind::GetSinkInfo(_) => decode::<SinkInfo>,
+        CommandKind::GetSourceInfo(_) => decode::<SourceInfo>,
+        CommandKind::GetClientInfo(_) => decode::<ClientInfo>,
+        CommandKind::GetCardInfo(_) => decode::<CardInfo>,
+        CommandKind::GetSinkInputInfo(_) => decode::<SinkInputInfo>,
+        CommandKind::GetSourceOutputInfo(_) => decode::<SourceOutputInfo>,
+        CommandKind::GetModuleInfo(_) => decode::<ModuleInfo>,
+        CommandKind::Subscribe(_) => decode::<()>,
+        CommandKind::LoadModule(_) => decode::<LoadModuleReply>,
+        CommandKind::UnloadModule(_) |
+        CommandKind::SetSinkVolume(_) |
+        CommandKind::SetSourceVolume(_) |
+        CommandKind::SetSinkInputVolume(_) |
+        CommandKind::SetSourceOutputVolume(_) |
+        CommandKind::SetSinkMute(_) |
+        CommandKind::SetSourceMute(_) |
+        CommandKind::SetSinkInputMute(_) |
+        CommandKind::SetSourceOutputMute(_) |
+        CommandKind::SetDefaultSink(_) |
+        CommandKind::SetDefaultSource(_) |
+        CommandKind::KillClient(_) |
+        CommandKind::KillSinkInput(_) |
+        CommandKind::KillSourceOutput(_) |
+        CommandKind::MoveSinkInput(_) |
+        CommandKind::MoveSourceOutput(_) |
+        CommandKind::SuspendSink(_) |
+        CommandKind::SuspendSource(_) => decode::<()>,
+        _ => return None,
+    };
+    Some(decoder)
+}
+
 static MSG_COUNTER: AtomicUsize = AtomicUsize::new(0);
 
 fn msg_counter() -> usize {
@@ -108,26 +211,46 @@ fn inc_msg_counter() {
 }
 
 /// Forwards data and ancillary messages from Unix socket `reader` to unix socket `writer`.
-fn forward(dir: Direction, reader: &UnixStream, writer: &UnixStream) -> Result<(), Box<Error + Send + Sync>> {
+fn forward(dir: Direction, reader: &UnixStream, writer: &UnixStream, trace: &Trace) -> Result<(), Box<Error + Send + Sync>> {
     let dir = dir.as_str();
     let mut buf = vec![0; 1024 * 64];
     let mut cmsgspace = CmsgSpace::<[RawFd; 2]>::new();
 
     let (rfd, wfd) = (reader.as_raw_fd(), writer.as_raw_fd());
 
-    // for printing, we need to keep track of the protocol version, since it influences cmd contents
-    let mut protocol_version = PROTOCOL_MIN_VERSION;
-    let mut print_data = |bytes| -> Result<(), pa_proto::Error> {
+    let print_data = |bytes| -> Result<(), pa_proto::Error> {
         let packet = Packet::decode(&bytes)?;
         let msg = Message::from_packet(&packet)?;
         match msg {
             Message::Control { tagstruct } => {
-                let cmd = Command::from_tagstruct(tagstruct, protocol_version)?;
-                println!("{} [{:03}]: {:?}", dir, msg_counter(), cmd);
-
-                if let CommandKind::Auth(auth) = cmd.kind() {
-                    protocol_version = auth.protocol_version();
-                    info!("{} protocol version updated to {}", dir, protocol_version);
+                let cmd = Command::from_tagstruct(tagstruct, trace.protocol_version())?;
+
+                match cmd.kind() {
+                    CommandKind::Reply { params } => {
+                        let decoder = trace.pending.lock().unwrap().remove(&cmd.tag());
+                        match decoder {
+                            Some(decoder) => {
+                                let reply = decoder(&mut params.clone(), trace)?;
+                                println!("{} [{:03}]: Reply to {}: {}", dir, msg_counter(), cmd.tag(), reply);
+                            }
+                            None => println!("{} [{:03}]: {:?}", dir, msg_counter(), cmd),
+                        }
+                    }
+                    CommandKind::Error { .. } => {
+                        trace.pending.lock().unwrap().remove(&cmd.tag());
+                        println!("{} [{:03}]: {:?}", dir, msg_counter(), cmd);
+                    }
+                    kind => {
+                        println!("{} [{:03}]: {:?}", dir, msg_counter(), cmd);
+
+                        if let CommandKind::Auth(auth) = kind {
+                            trace.set_protocol_version(auth.protocol_version());
+                            info!("{} protocol version updated to {}", dir, auth.protocol_version());
+                        }
+                        if let Some(decoder) = reply_decoder(kind) {
+                            trace.pending.lock().unwrap().insert(cmd.tag(), decoder);
+                        }
+                    }
                 }
             }
             _ => unimplemented!(),
@@ -16,6 +16,18 @@ pub struct Auth<'a> {
 }
 
 impl<'a> Auth<'a> {
+    /// Creates an authentication request.
+    ///
+    /// # Parameters
+    ///
+    /// * `version`: The client's protocol version (usually `PROTOCOL_VERSION`).
+    /// * `supports_shm`: Whether the client can use POSIX shared memory.
+    /// * `supports_memfd`: Whether the client can use `memfd`-based shared memory.
+    /// * `cookie`: The authentication cookie.
+    pub fn new(version: u16, supports_shm: bool, supports_memfd: bool, cookie: &'a [u8]) -> Self {
+        Self { version, supports_shm, supports_memfd, cookie }
+    }
+
     /// Client's protocol version.
     ///
     /// Protocol versions are backwards-compatible, so a client with a higher version than the
@@ -111,6 +123,17 @@ impl AuthReply {
     }
 }
 
+impl<'a> FromTagStruct<'a> for AuthReply {
+    fn from_tag_struct(ts: &mut TagStructReader<'a>, _protocol_version: u16) -> Result<Self, Error> {
+        let reply = ts.read_u32()?;
+        Ok(Self {
+            version: (reply & VERSION_MASK) as u16,
+            use_memfd: reply & FLAG_MEMFD != 0,
+            use_shm: reply & FLAG_SHM != 0,
+        })
+    }
+}
+
 impl ToTagStruct for AuthReply {
     fn to_tag_struct(&self, w: &mut TagStructWriter, _protocol_version: u16) -> Result<(), Error> {
         // Auth reply is a tagstruct with just a u32 that looks similar to the "version"
@@ -2,6 +2,9 @@ use super::prelude::*;
 
 use sink::{Sink, SinkState};
 use stream::{BufferAttr, StreamFlags, Stream};
+use string::PaString;
+use time::Microseconds;
+use types::FormatInfo;
 
 use std::u32;
 use std::ffi::CStr;
@@ -36,6 +39,9 @@ struct CreatePlaybackStreamParams<'a> {
     /// Set the channel volumes.
     volume: Option<CVolume>,
     syncid: u32,
+    buffer_attr: BufferAttr,
+    /// Accepted stream formats (proto>=21).
+    formats: Vec<FormatInfo>,
 }
 
 /// Parameters for `CreatePlaybackStream` command.
@@ -45,6 +51,37 @@ pub struct CreatePlaybackStream<'a> {
 }
 
 impl<'a> CreatePlaybackStream<'a> {
+    /// Creates a request for a new playback stream.
+    ///
+    /// The mute preference is taken from the `START_MUTED` and `START_UNMUTED` flags. The stream
+    /// is created with the server's default volume.
+    pub fn new(
+        stream_props: PropList,
+        sample_spec: SampleSpec,
+        channel_map: ChannelMap,
+        buffer_attr: BufferAttr,
+        stream_flags: StreamFlags,
+        sink_spec: Option<SinkSpec<'a>>,
+    ) -> Self {
+        let muted = if stream_flags.contains(StreamFlags::START_MUTED) {
+            Some(true)
+        } else if stream_flags.contains(StreamFlags::START_UNMUTED) {
+            Some(false)
+        } else {
+            None
+        };
+
+        Self {
+            inner: Box::new(CreatePlaybackStreamParams {
+                stream_props, sample_spec, channel_map, stream_flags, sink_spec, muted,
+                volume: None,
+                syncid: INVALID_INDEX,
+                buffer_attr,
+                formats: Vec::new(),
+            }),
+        }
+    }
+
     pub fn stream_props(&self) -> &PropList {
         &self.inner.stream_props
     }
@@ -88,6 +125,20 @@ impl<'a> CreatePlaybackStream<'a> {
     pub fn sync_id(&self) -> u32 {
         self.inner.syncid
     }
+
+    /// Get the requested buffer metrics.
+    ///
+    /// Fields set to `u32::MAX` are left to the server to decide.
+    pub fn buffer_attr(&self) -> &BufferAttr {
+        &self.inner.buffer_attr
+    }
+
+    /// Get the list of stream formats the client can provide (proto>=21).
+    ///
+    /// If empty, the sample spec and channel map describe the stream format.
+    pub fn formats(&self) -> &[FormatInfo] {
+        &self.inner.formats
+    }
 }
 
 impl<'a> FromTagStruct<'a> for CreatePlaybackStream<'a> {
@@ -184,36 +235,150 @@ impl<'a> FromTagStruct<'a> for CreatePlaybackStream<'a> {
             inner: Box::new(CreatePlaybackStreamParams {
                 stream_props, sample_spec, channel_map, stream_flags, sink_spec, muted, volume,
                 syncid,
+                buffer_attr: buf_attr,
+                formats,
             }),
         })
     }
 }
 
 impl<'a> ToTagStruct for CreatePlaybackStream<'a> {
-    fn to_tag_struct(&self, _w: &mut TagStructWriter, _protocol_version: u16) -> Result<(), Error> {
-        unimplemented!()    // TODO
+    fn to_tag_struct(&self, w: &mut TagStructWriter, protocol_version: u16) -> Result<(), Error> {
+        let params = &self.inner;
+        let flags = params.stream_flags;
+
+        w.write(&params.sample_spec);
+        w.write(&params.channel_map);
+        match params.sink_spec {
+            None => {
+                w.write(INVALID_INDEX);
+                w.write(None::<&PaStr>);
+            }
+            Some(SinkSpec::Index(index)) => {
+                w.write(index);
+                w.write(None::<&PaStr>);
+            }
+            Some(SinkSpec::Name(name)) => {
+                w.write(INVALID_INDEX);
+                w.write(name);
+            }
+        }
+        w.write(params.buffer_attr.maxlength);
+        w.write(flags.contains(StreamFlags::START_CORKED));
+        w.write(params.buffer_attr.tlength);
+        w.write(params.buffer_attr.prebuf);
+        w.write(params.buffer_attr.minreq);
+        w.write(params.syncid);
+        match params.volume {
+            Some(ref cvolume) => w.write(cvolume),
+            None => {
+                // Must contain a volume for every channel even if it isn't used
+                let mut cvolume = CVolume::new();
+                for _ in 0..params.sample_spec.channels() {
+                    cvolume.push(Volume::NORM).expect("too many channels");
+                }
+                w.write(&cvolume);
+            }
+        }
+
+        // proto>=12
+        w.write(flags.contains(StreamFlags::NO_REMAP_CHANNELS));
+        w.write(flags.contains(StreamFlags::NO_REMIX_CHANNELS));
+        w.write(flags.contains(StreamFlags::FIX_FORMAT));
+        w.write(flags.contains(StreamFlags::FIX_RATE));
+        w.write(flags.contains(StreamFlags::FIX_CHANNELS));
+        w.write(flags.contains(StreamFlags::DONT_MOVE));
+        w.write(flags.contains(StreamFlags::VARIABLE_RATE));
+        // proto>=13
+        w.write(params.muted == Some(true));
+        w.write(flags.contains(StreamFlags::ADJUST_LATENCY));
+        w.write(&params.stream_props);
+
+        if protocol_version >= 14 {
+            w.write(params.volume.is_some());
+            w.write(flags.contains(StreamFlags::EARLY_REQUESTS));
+        }
+        if protocol_version >= 15 {
+            w.write(params.muted.is_some());
+            w.write(flags.contains(StreamFlags::DONT_INHIBIT_AUTO_SUSPEND));
+            w.write(flags.contains(StreamFlags::FAIL_ON_SUSPEND));
+        }
+        if protocol_version >= 17 {
+            w.write(flags.contains(StreamFlags::RELATIVE_VOLUME));
+        }
+        if protocol_version >= 18 {
+            w.write(flags.contains(StreamFlags::PASSTHROUGH));
+        }
+        if protocol_version >= 21 {
+            w.write(params.formats.len() as u8);
+            for format in &params.formats {
+                w.write(format);
+            }
+        }
+
+        Ok(())
     }
 }
 
-#[derive(Debug)]
-pub struct CreatePlaybackStreamReply<'a> {
+/// Server reply to `CreatePlaybackStream`.
+#[derive(Debug, Clone)]
+pub struct CreatePlaybackStreamReply {
     /// Server-internal stream index.
     pub stream_index: u32,
     pub sink_input_index: u32,
     /// Number of bytes that can be written to the playback buffer.
     pub missing: u32,
     /// Attributes of the created buffer.
-    pub buffer_metrics: &'a BufferAttr,
+    pub buffer_metrics: BufferAttr,
     /// Actually chosen sample specs.
-    pub sample_spec: &'a SampleSpec,
+    pub sample_spec: SampleSpec,
     /// Actually chosen channel map.
-    pub channel_map: &'a ChannelMap,
-    pub stream: &'a Stream,
-    /// The sink the created stream has been connected to.
-    pub sink: &'a Sink,
+    pub channel_map: ChannelMap,
+    /// Index of the sink the created stream has been connected to.
+    pub sink_index: u32,
+    /// Name of the sink the created stream has been connected to.
+    pub sink_name: PaString,
+    /// Whether the sink is currently suspended.
+    pub sink_suspended: bool,
+    /// Latency of the sink.
+    pub sink_latency: Microseconds,
+    /// The stream's sample format (proto>=21).
+    pub format: Option<FormatInfo>,
+}
+
+impl CreatePlaybackStreamReply {
+    /// Creates a reply for a stream connected to `sink`.
+    ///
+    /// `format` is only sent to clients using protocol version 21 or higher, which must receive
+    /// one.
+    pub fn new(
+        stream_index: u32,
+        sink_input_index: u32,
+        missing: u32,
+        buffer_metrics: BufferAttr,
+        sample_spec: SampleSpec,
+        channel_map: ChannelMap,
+        stream: &Stream,
+        sink: &Sink,
+        format: Option<FormatInfo>,
+    ) -> Self {
+        Self {
+            stream_index,
+            sink_input_index,
+            missing,
+            buffer_metrics,
+            sample_spec,
+            channel_map,
+            sink_index: sink.index(),
+            sink_name: sink.name().to_owned(),
+            sink_suspended: sink.state() == SinkState::Suspended,
+            sink_latency: stream.latency(),
+            format,
+        }
+    }
 }
 
-impl<'a> ToTagStruct for CreatePlaybackStreamReply<'a> {
+impl ToTagStruct for CreatePlaybackStreamReply {
     fn to_tag_struct(&self, w: &mut TagStructWriter, protocol_version: u16) -> Result<(), Error> {
         w.write(self.stream_index);
         w.write(self.sink_input_index);
@@ -224,18 +389,92 @@ impl<'a> ToTagStruct for CreatePlaybackStreamReply<'a> {
         w.write(self.buffer_metrics.prebuf);
         w.write(self.buffer_metrics.minreq);
         // proto>=12
-        w.write(self.sample_spec);
-        w.write(self.channel_map);
-        w.write(self.sink.index());
-        w.write(self.sink.name());
-        w.write(self.sink.state() == SinkState::Suspended);
+        w.write(&self.sample_spec);
+        w.write(&self.channel_map);
+        w.write(self.sink_index);
+        w.write(&*self.sink_name);
+        w.write(self.sink_suspended);
         // proto>=13
-        w.write(self.stream.latency());
+        w.write(self.sink_latency);
 
         if protocol_version >= 21 {
             // Send back the sample format of the sink
+            let format = self.format.as_ref()
+                .ok_or_else(|| Error::string("stream format required for protocol version >= 21"))?;
+            w.write(format);
+        }
+
+        Ok(())
+    }
+}
 
+impl<'a> FromTagStruct<'a> for CreatePlaybackStreamReply {
+    fn from_tag_struct(ts: &mut TagStructReader<'a>, protocol_version: u16) -> Result<Self, Error> {
+        let (stream_index, sink_input_index, missing) = (ts.read_u32()?, ts.read_u32()?, ts.read_u32()?);
+        let mut buffer_metrics = BufferAttr::default();
+        buffer_metrics.maxlength = ts.read_u32()?;
+        buffer_metrics.tlength = ts.read_u32()?;
+        buffer_metrics.prebuf = ts.read_u32()?;
+        buffer_metrics.minreq = ts.read_u32()?;
+
+        Ok(Self {
+            stream_index,
+            sink_input_index,
+            missing,
+            buffer_metrics,
+            sample_spec: ts.read_sample_spec()?,
+            channel_map: ts.read_channel_map()?,
+            sink_index: ts.read_u32()?,
+            sink_name: PaString::from(ts.read_string_non_null()?.to_owned()),
+            sink_suspended: ts.read_bool()?,
+            sink_latency: ts.read_usec()?,
+            format: if protocol_version >= 21 {
+                Some(ts.read_format_info()?)
+            } else {
+                None
+            },
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sample_spec::SampleFormat;
+
+    #[test]
+    fn create_playback_stream_roundtrip() {
+        let spec = SampleSpec::new_checked(SampleFormat::S16Le, 2, 44100).unwrap();
+        let mut buffer_attr = BufferAttr::default();
+        buffer_attr.maxlength = u32::MAX;
+        buffer_attr.tlength = 8192;
+        let name = CStr::from_bytes_with_nul(b"sink\0").unwrap();
+        let params = CreatePlaybackStream::new(
+            PropList::new(),
+            spec,
+            ChannelMap::default_for(2).unwrap(),
+            buffer_attr,
+            StreamFlags::START_CORKED | StreamFlags::START_MUTED | StreamFlags::ADJUST_LATENCY,
+            Some(SinkSpec::Name(name)),
+        );
+
+        for &version in &[13, 15, 21, 32] {
+            let mut buf = Vec::new();
+            params.to_tag_struct(&mut TagStructWriter::new(&mut buf), version).unwrap();
+
+            let mut ts = TagStructReader::from_raw(&buf);
+            let parsed = CreatePlaybackStream::from_tag_struct(&mut ts, version).unwrap();
+            assert!(ts.is_at_end());
+            // The mute preference isn't transmitted as a flag
+            assert_eq!(parsed.stream_flags(), params.stream_flags() - StreamFlags::START_MUTED);
+            assert_eq!(parsed.sample_spec().channels(), 2);
+            assert_eq!(parsed.buffer_attr().tlength, 8192);
+            assert_eq!(parsed.muted(), Some(true));
+            assert_eq!(parsed.sync_id(), u32::MAX);
+            match parsed.sink_spec() {
+                Some(SinkSpec::Name(parsed_name)) => assert_eq!(*parsed_name, name),
+                spec => panic!("unexpected sink spec {:?}", spec),
+            }
         }
-        unimplemented!()
     }
 }
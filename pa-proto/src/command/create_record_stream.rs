@@ -0,0 +1,351 @@
+//! The `CREATE_RECORD_STREAM` command.
+
+use super::prelude::*;
+
+use stream::{BufferAttr, StreamFlags};
+use string::PaString;
+use time::Microseconds;
+use types::FormatInfo;
+
+use std::u32;
+use std::ffi::CStr;
+
+const INVALID_INDEX: u32 = u32::MAX;
+
+/// Specifies a source to connect a record stream to.
+#[derive(Debug)]
+pub enum SourceSpec<'a> {
+    /// Source index.
+    Index(u32),
+    /// Named source.
+    Name(&'a CStr),
+}
+
+#[derive(Debug)]
+struct CreateRecordStreamParams<'a> {
+    stream_props: PropList,
+    sample_spec: SampleSpec,
+    channel_map: ChannelMap,
+    stream_flags: StreamFlags,
+    source_spec: Option<SourceSpec<'a>>,
+    buffer_attr: BufferAttr,
+    /// Only record the sink input with this index (for monitoring a single stream).
+    direct_on_input: Option<u32>,
+    /// Accepted stream formats (proto>=22).
+    formats: Vec<FormatInfo>,
+    /// Stream volume (proto>=22).
+    volume: Option<CVolume>,
+    /// Mute preference (proto>=22).
+    muted: Option<bool>,
+}
+
+/// Parameters for the `CreateRecordStream` command.
+#[derive(Debug)]
+pub struct CreateRecordStream<'a> {
+    inner: Box<CreateRecordStreamParams<'a>>,
+}
+
+impl<'a> CreateRecordStream<'a> {
+    /// Creates a request for a new record stream.
+    ///
+    /// Only `maxlength` and `fragsize` of `buffer_attr` apply to record streams.
+    pub fn new(
+        stream_props: PropList,
+        sample_spec: SampleSpec,
+        channel_map: ChannelMap,
+        buffer_attr: BufferAttr,
+        stream_flags: StreamFlags,
+        source_spec: Option<SourceSpec<'a>>,
+    ) -> Self {
+        let muted = if stream_flags.contains(StreamFlags::START_MUTED) {
+            Some(true)
+        } else if stream_flags.contains(StreamFlags::START_UNMUTED) {
+            Some(false)
+        } else {
+            None
+        };
+
+        Self {
+            inner: Box::new(CreateRecordStreamParams {
+                stream_props, sample_spec, channel_map, stream_flags, source_spec, buffer_attr,
+                direct_on_input: None,
+                formats: Vec::new(),
+                volume: None,
+                muted,
+            }),
+        }
+    }
+
+    pub fn stream_props(&self) -> &PropList {
+        &self.inner.stream_props
+    }
+
+    pub fn stream_flags(&self) -> StreamFlags {
+        self.inner.stream_flags
+    }
+
+    pub fn sample_spec(&self) -> &SampleSpec {
+        &self.inner.sample_spec
+    }
+
+    pub fn channel_map(&self) -> &ChannelMap {
+        &self.inner.channel_map
+    }
+
+    /// Get the source specification.
+    ///
+    /// If `None`, the stream will be connected to the default source.
+    pub fn source_spec(&self) -> Option<&SourceSpec> {
+        self.inner.source_spec.as_ref()
+    }
+
+    /// Get the requested buffer metrics.
+    pub fn buffer_attr(&self) -> &BufferAttr {
+        &self.inner.buffer_attr
+    }
+
+    /// Index of the sink input to record from, if the stream should only capture a single stream.
+    pub fn direct_on_input(&self) -> Option<u32> {
+        self.inner.direct_on_input
+    }
+
+    /// Get the list of stream formats the client accepts (proto>=22).
+    pub fn formats(&self) -> &[FormatInfo] {
+        &self.inner.formats
+    }
+
+    pub fn volume(&self) -> Option<&CVolume> {
+        self.inner.volume.as_ref()
+    }
+
+    /// Get the stream mute preference (see `CreatePlaybackStream::muted`).
+    pub fn muted(&self) -> Option<bool> {
+        self.inner.muted
+    }
+}
+
+impl<'a> FromTagStruct<'a> for CreateRecordStream<'a> {
+    fn from_tag_struct(ts: &mut TagStructReader<'a>, protocol_version: u16) -> Result<Self, Error> {
+        let mut stream_flags = StreamFlags::empty();
+        let mut buffer_attr = BufferAttr::default();
+        let mut formats = Vec::new();
+        let (mut volume, mut muted) = (None, None);
+
+        let sample_spec = ts.read_sample_spec()?;
+        let channel_map = ts.read_channel_map()?;
+        let source_index = ts.read_u32()?;
+        let source_name = ts.read_string()?;
+        buffer_attr.maxlength = ts.read_u32()?;
+        stream_flags.set(StreamFlags::START_CORKED, ts.read_bool()?);
+        buffer_attr.fragsize = ts.read_u32()?;
+
+        // proto>=12
+        stream_flags.set(StreamFlags::NO_REMAP_CHANNELS, ts.read_bool()?);
+        stream_flags.set(StreamFlags::NO_REMIX_CHANNELS, ts.read_bool()?);
+        stream_flags.set(StreamFlags::FIX_FORMAT, ts.read_bool()?);
+        stream_flags.set(StreamFlags::FIX_RATE, ts.read_bool()?);
+        stream_flags.set(StreamFlags::FIX_CHANNELS, ts.read_bool()?);
+        stream_flags.set(StreamFlags::DONT_MOVE, ts.read_bool()?);
+        stream_flags.set(StreamFlags::VARIABLE_RATE, ts.read_bool()?);
+        // proto>=13
+        stream_flags.set(StreamFlags::PEAK_DETECT, ts.read_bool()?);
+        stream_flags.set(StreamFlags::ADJUST_LATENCY, ts.read_bool()?);
+        let stream_props = ts.read_proplist()?;
+        let direct_on_input = match ts.read_u32()? {
+            INVALID_INDEX => None,
+            index => Some(index),
+        };
+
+        if protocol_version >= 14 {
+            stream_flags.set(StreamFlags::EARLY_REQUESTS, ts.read_bool()?);
+        }
+        if protocol_version >= 15 {
+            stream_flags.set(StreamFlags::DONT_INHIBIT_AUTO_SUSPEND, ts.read_bool()?);
+            stream_flags.set(StreamFlags::FAIL_ON_SUSPEND, ts.read_bool()?);
+        }
+        if protocol_version >= 22 {
+            for _ in 0..ts.read_u8()? {
+                formats.push(ts.read_format_info()?);
+            }
+
+            let cvolume = ts.read_cvolume()?;
+            let mute = ts.read_bool()?;
+            let volume_set = ts.read_bool()?;
+            let muted_set = ts.read_bool()?;
+            stream_flags.set(StreamFlags::RELATIVE_VOLUME, ts.read_bool()?);
+            stream_flags.set(StreamFlags::PASSTHROUGH, ts.read_bool()?);
+
+            if volume_set {
+                volume = Some(cvolume);
+            }
+            if muted_set || mute {
+                muted = Some(mute);
+            }
+        }
+
+        let source_spec = match (source_index, source_name) {
+            (INVALID_INDEX, None) => None,  // default source
+            (INVALID_INDEX, Some(name)) => Some(SourceSpec::Name(name)),
+            (index, None) => Some(SourceSpec::Index(index)),
+            (_index, Some(_name)) => {
+                return Err(Error::string("cannot specify both source index and name"));
+            }
+        };
+
+        Ok(Self {
+            inner: Box::new(CreateRecordStreamParams {
+                stream_props, sample_spec, channel_map, stream_flags, source_spec, buffer_attr,
+                direct_on_input, formats, volume, muted,
+            }),
+        })
+    }
+}
+
+impl<'a> ToTagStruct for CreateRecordStream<'a> {
+    fn to_tag_struct(&self, w: &mut TagStructWriter, protocol_version: u16) -> Result<(), Error> {
+        let params = &self.inner;
+        let flags = params.stream_flags;
+
+        w.write(&params.sample_spec);
+        w.write(&params.channel_map);
+        match params.source_spec {
+            None => {
+                w.write(INVALID_INDEX);
+                w.write(None::<&PaStr>);
+            }
+            Some(SourceSpec::Index(index)) => {
+                w.write(index);
+                w.write(None::<&PaStr>);
+            }
+            Some(SourceSpec::Name(name)) => {
+                w.write(INVALID_INDEX);
+                w.write(name);
+            }
+        }
+        w.write(params.buffer_attr.maxlength);
+        w.write(flags.contains(StreamFlags::START_CORKED));
+        w.write(params.buffer_attr.fragsize);
+
+        // proto>=12
+        w.write(flags.contains(StreamFlags::NO_REMAP_CHANNELS));
+        w.write(flags.contains(StreamFlags::NO_REMIX_CHANNELS));
+        w.write(flags.contains(StreamFlags::FIX_FORMAT));
+        w.write(flags.contains(StreamFlags::FIX_RATE));
+        w.write(flags.contains(StreamFlags::FIX_CHANNELS));
+        w.write(flags.contains(StreamFlags::DONT_MOVE));
+        w.write(flags.contains(StreamFlags::VARIABLE_RATE));
+        // proto>=13
+        w.write(flags.contains(StreamFlags::PEAK_DETECT));
+        w.write(flags.contains(StreamFlags::ADJUST_LATENCY));
+        w.write(&params.stream_props);
+        w.write(params.direct_on_input.unwrap_or(INVALID_INDEX));
+
+        if protocol_version >= 14 {
+            w.write(flags.contains(StreamFlags::EARLY_REQUESTS));
+        }
+        if protocol_version >= 15 {
+            w.write(flags.contains(StreamFlags::DONT_INHIBIT_AUTO_SUSPEND));
+            w.write(flags.contains(StreamFlags::FAIL_ON_SUSPEND));
+        }
+        if protocol_version >= 22 {
+            w.write(params.formats.len() as u8);
+            for format in &params.formats {
+                w.write(format);
+            }
+
+            match params.volume {
+                Some(ref cvolume) => w.write(cvolume),
+                None => {
+                    let mut cvolume = CVolume::new();
+                    for _ in 0..params.sample_spec.channels() {
+                        cvolume.push(Volume::NORM).expect("too many channels");
+                    }
+                    w.write(&cvolume);
+                }
+            }
+            w.write(params.muted == Some(true));
+            w.write(params.volume.is_some());
+            w.write(params.muted.is_some());
+            w.write(flags.contains(StreamFlags::RELATIVE_VOLUME));
+            w.write(flags.contains(StreamFlags::PASSTHROUGH));
+        }
+
+        Ok(())
+    }
+}
+
+/// Server reply to `CreateRecordStream`.
+#[derive(Debug, Clone)]
+pub struct CreateRecordStreamReply {
+    /// Channel used to refer to the stream in later commands and memblocks.
+    pub channel: u32,
+    pub source_output_index: u32,
+    /// Attributes of the created buffer (only `maxlength` and `fragsize` are transmitted).
+    pub buffer_metrics: BufferAttr,
+    /// Actually chosen sample specs.
+    pub sample_spec: SampleSpec,
+    /// Actually chosen channel map.
+    pub channel_map: ChannelMap,
+    /// Index of the source the stream has been connected to.
+    pub source_index: u32,
+    /// Name of the source the stream has been connected to.
+    pub source_name: PaString,
+    /// Whether the source is currently suspended.
+    pub source_suspended: bool,
+    /// Configured latency of the source.
+    pub source_latency: Microseconds,
+    /// The stream's sample format (proto>=22).
+    pub format: Option<FormatInfo>,
+}
+
+impl ToTagStruct for CreateRecordStreamReply {
+    fn to_tag_struct(&self, w: &mut TagStructWriter, protocol_version: u16) -> Result<(), Error> {
+        w.write(self.channel);
+        w.write(self.source_output_index);
+        // proto>=9
+        w.write(self.buffer_metrics.maxlength);
+        w.write(self.buffer_metrics.fragsize);
+        // proto>=12
+        w.write(&self.sample_spec);
+        w.write(&self.channel_map);
+        w.write(self.source_index);
+        w.write(&*self.source_name);
+        w.write(self.source_suspended);
+        // proto>=13
+        w.write(self.source_latency);
+
+        if protocol_version >= 22 {
+            let format = self.format.as_ref()
+                .ok_or_else(|| Error::string("stream format required for protocol version >= 22"))?;
+            w.write(format);
+        }
+
+        Ok(())
+    }
+}
+
+impl<'a> FromTagStruct<'a> for CreateRecordStreamReply {
+    fn from_tag_struct(ts: &mut TagStructReader<'a>, protocol_version: u16) -> Result<Self, Error> {
+        let (channel, source_output_index) = (ts.read_u32()?, ts.read_u32()?);
+        let mut buffer_metrics = BufferAttr::default();
+        buffer_metrics.maxlength = ts.read_u32()?;
+        buffer_metrics.fragsize = ts.read_u32()?;
+
+        Ok(Self {
+            channel,
+            source_output_index,
+            buffer_metrics,
+            sample_spec: ts.read_sample_spec()?,
+            channel_map: ts.read_channel_map()?,
+            source_index: ts.read_u32()?,
+            source_name: PaString::from(ts.read_string_non_null()?.to_owned()),
+            source_suspended: ts.read_bool()?,
+            source_latency: ts.read_usec()?,
+            format: if protocol_version >= 22 {
+                Some(ts.read_format_info()?)
+            } else {
+                None
+            },
+        })
+    }
+}
@@ -1,8 +1,17 @@
 //! The `GET_*_INFO` and `GET_*_INFO_LIST` commands.
+//!
+//! Replies to the `GET_*_INFO_LIST` commands are a concatenation of the info structures of all
+//! entities, so they can be decoded as eg. a `Vec<SinkInfo>`.
 
 use super::prelude::*;
-use sink::Sink;
+use super::{SinkSpec, SourceSpec};
+use super::introspect::Device;
+use sink::{Available, Direction, Sink, SinkFlags, SinkState};
+use source::{Source, SourceFlags, SourceState};
+use time::Microseconds;
+use types::FormatInfo;
 
+use num_traits::FromPrimitive;
 use std::u32;
 use string::PaString;
 
@@ -43,7 +52,7 @@ where
                 .unwrap_or_else(|| PaStr::from_bytes_with_nul(b"(null)\0").unwrap()));
             w.write(sink.sample_spec().protocol_downgrade(protocol_version));
             w.write(sink.channel_map());
-            w.write(u32::MAX);  // sink module (we don't have modules)
+            w.write(sink.owner_module().unwrap_or(u32::MAX));
             w.write(sink.cvolume());
             w.write(sink.muted());
             w.write(u32::MAX);  // sink's monitor source
@@ -88,45 +97,587 @@ where
     }
 }
 
-// FIXME: `pactl list` hangs after receiving this - maybe it expects at least one module?
-// (doesn't seem like it - it still hangs)
 #[derive(Debug)]
-pub struct GetModuleInfoListReply {
+pub struct GetSourceInfoListReply<'a, S>
+where
+    S: IntoIterator<Item=&'a Source> + Clone
+{
+    pub sources: S,
+    _priv: (),
+}
+
+impl<'a, S> GetSourceInfoListReply<'a, S>
+where
+    S: IntoIterator<Item=&'a Source> + Clone
+{
+    pub fn new(sources: S) -> Self {
+        Self {
+            sources,
+            _priv: (),
+        }
+    }
+}
+
+impl<'a, S> ToTagStruct for GetSourceInfoListReply<'a, S>
+where
+    S: IntoIterator<Item=&'a Source> + Clone
+{
+    fn to_tag_struct(&self, w: &mut TagStructWriter, protocol_version: u16) -> Result<(), Error> {
+        // like sinks, source infos are concatenated without any separator
+        for source in self.sources.clone() {
+            w.write(source.index());
+            w.write(source.name());
+            // source description (which may not be a null string)
+            w.write(source.props()
+                .get(Prop::DeviceDescription)
+                .map(|bytes| PaStr::from_bytes_with_nul(bytes).unwrap())
+                .unwrap_or_else(|| PaStr::from_bytes_with_nul(b"(null)\0").unwrap()));
+            w.write(source.sample_spec().protocol_downgrade(protocol_version));
+            w.write(source.channel_map());
+            w.write(source.owner_module().unwrap_or(u32::MAX));
+            w.write(source.cvolume());
+            w.write(source.muted());
+            w.write(u32::MAX);  // sink this source is monitoring (we don't have monitors)
+            w.write(None);  // name of monitored sink
+            w.write(source.actual_latency());
+            w.write(PaString::new("Unknown Driver").unwrap());   // TODO: driver name
+            w.write(source.flags().bits());
+            // proto>=13
+            w.write(source.props());
+            w.write(source.requested_latency());
+            if protocol_version >= 15 {
+                w.write(source.base_volume());
+                w.write(source.state() as u32);
+                w.write(source.volume_steps());
+                w.write(u32::MAX);  // TODO: card index (invalid dummy value)
+            }
+            if protocol_version >= 16 {
+                w.write(source.ports().len() as u32);
+                for port in source.ports() {
+                    w.write(port.name());
+                    w.write(port.description());
+                    w.write(port.priority());
+                    if protocol_version >= 24 {
+                        w.write(port.available() as u32);
+                    }
+                }
+
+                // active port name
+                w.write(source.active_port().name());
+            }
+            if protocol_version >= 22 {
+                // send supported sample formats
+                w.write(source.formats().len() as u8);
+                for format in source.formats() {
+                    w.write(format);
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Information about a sink, as sent in reply to `GetSinkInfoList`.
+///
+/// Fields that aren't transmitted with the negotiated protocol version are set to defaults.
+#[derive(Debug, Clone)]
+pub struct SinkInfo {
+    pub index: u32,
+    pub name: PaString,
+    pub description: PaString,
+    pub sample_spec: SampleSpec,
+    pub channel_map: ChannelMap,
+    /// Index of the module that created the sink, if any.
+    pub owner_module: Option<u32>,
+    pub cvolume: CVolume,
+    pub muted: bool,
+    /// Index of the sink's monitor source, if any.
+    pub monitor_source: Option<u32>,
+    pub monitor_source_name: Option<PaString>,
+    pub actual_latency: Microseconds,
+    pub driver: Option<PaString>,
+    pub flags: SinkFlags,
+    pub props: PropList,
+    pub requested_latency: Microseconds,
+    /// proto>=15
+    pub base_volume: Volume,
+    /// proto>=15, `None` if unknown.
+    pub state: Option<SinkState>,
+    /// proto>=15
+    pub volume_steps: u32,
+    /// proto>=15
+    pub card: Option<u32>,
+    /// proto>=16
+    pub ports: Vec<PortInfo>,
+    /// proto>=16
+    pub active_port: Option<PaString>,
+    /// proto>=21
+    pub formats: Vec<FormatInfo>,
+}
+
+impl<'a> FromTagStruct<'a> for SinkInfo {
+    fn from_tag_struct(ts: &mut TagStructReader<'a>, protocol_version: u16) -> Result<Self, Error> {
+        let mut info = SinkInfo {
+            index: ts.read_u32()?,
+            name: read_owned_string(ts)?,
+            description: read_owned_string(ts)?,
+            sample_spec: ts.read_sample_spec()?,
+            channel_map: ts.read_channel_map()?,
+            owner_module: read_index(ts)?,
+            cvolume: ts.read_cvolume()?,
+            muted: ts.read_bool()?,
+            monitor_source: read_index(ts)?,
+            monitor_source_name: read_owned_string_opt(ts)?,
+            actual_latency: ts.read_usec()?,
+            driver: read_owned_string_opt(ts)?,
+            flags: SinkFlags::from_bits_truncate(ts.read_u32()?),
+            // proto>=13
+            props: ts.read_proplist()?,
+            requested_latency: ts.read_usec()?,
+            base_volume: Volume::NORM,
+            state: None,
+            volume_steps: Volume::NORM.as_u32() + 1,
+            card: None,
+            ports: Vec::new(),
+            active_port: None,
+            formats: Vec::new(),
+        };
+
+        if protocol_version >= 15 {
+            info.base_volume = ts.read_volume()?;
+            info.state = SinkState::from_u32(ts.read_u32()?);
+            info.volume_steps = ts.read_u32()?;
+            info.card = read_index(ts)?;
+        }
+        if protocol_version >= 16 {
+            let (ports, active_port) = read_ports(ts, protocol_version)?;
+            info.ports = ports;
+            info.active_port = active_port;
+        }
+        if protocol_version >= 21 {
+            for _ in 0..ts.read_u8()? {
+                info.formats.push(ts.read_format_info()?);
+            }
+        }
+
+        Ok(info)
+    }
+}
+
+/// Information about a source, as sent in reply to `GetSourceInfoList`.
+///
+/// Fields that aren't transmitted with the negotiated protocol version are set to defaults.
+#[derive(Debug, Clone)]
+pub struct SourceInfo {
+    pub index: u32,
+    pub name: PaString,
+    pub description: PaString,
+    pub sample_spec: SampleSpec,
+    pub channel_map: ChannelMap,
+    /// Index of the module that created the source, if any.
+    pub owner_module: Option<u32>,
+    pub cvolume: CVolume,
+    pub muted: bool,
+    /// Index of the sink this source is monitoring, if it's a monitor source.
+    pub monitor_of_sink: Option<u32>,
+    pub monitor_of_sink_name: Option<PaString>,
+    pub actual_latency: Microseconds,
+    pub driver: Option<PaString>,
+    pub flags: SourceFlags,
+    pub props: PropList,
+    pub requested_latency: Microseconds,
+    /// proto>=15
+    pub base_volume: Volume,
+    /// proto>=15, `None` if unknown.
+    pub state: Option<SourceState>,
+    /// proto>=15
+    pub volume_steps: u32,
+    /// proto>=15
+    pub card: Option<u32>,
+    /// proto>=16
+    pub ports: Vec<PortInfo>,
+    /// proto>=16
+    pub active_port: Option<PaString>,
+    /// proto>=22
+    pub formats: Vec<FormatInfo>,
+}
+
+impl<'a> FromTagStruct<'a> for SourceInfo {
+    fn from_tag_struct(ts: &mut TagStructReader<'a>, protocol_version: u16) -> Result<Self, Error> {
+        let mut info = SourceInfo {
+            index: ts.read_u32()?,
+            name: read_owned_string(ts)?,
+            description: read_owned_string(ts)?,
+            sample_spec: ts.read_sample_spec()?,
+            channel_map: ts.read_channel_map()?,
+            owner_module: read_index(ts)?,
+            cvolume: ts.read_cvolume()?,
+            muted: ts.read_bool()?,
+            monitor_of_sink: read_index(ts)?,
+            monitor_of_sink_name: read_owned_string_opt(ts)?,
+            actual_latency: ts.read_usec()?,
+            driver: read_owned_string_opt(ts)?,
+            flags: SourceFlags::from_bits_truncate(ts.read_u32()?),
+            // proto>=13
+            props: ts.read_proplist()?,
+            requested_latency: ts.read_usec()?,
+            base_volume: Volume::NORM,
+            state: None,
+            volume_steps: Volume::NORM.as_u32() + 1,
+            card: None,
+            ports: Vec::new(),
+            active_port: None,
+            formats: Vec::new(),
+        };
+
+        if protocol_version >= 15 {
+            info.base_volume = ts.read_volume()?;
+            info.state = SourceState::from_u32(ts.read_u32()?);
+            info.volume_steps = ts.read_u32()?;
+            info.card = read_index(ts)?;
+        }
+        if protocol_version >= 16 {
+            let (ports, active_port) = read_ports(ts, protocol_version)?;
+            info.ports = ports;
+            info.active_port = active_port;
+        }
+        if protocol_version >= 22 {
+            for _ in 0..ts.read_u8()? {
+                info.formats.push(ts.read_format_info()?);
+            }
+        }
+
+        Ok(info)
+    }
+}
+
+/// Information about a port of a sink or source.
+#[derive(Debug, Clone)]
+pub struct PortInfo {
+    pub name: PaString,
+    pub description: PaString,
+    pub priority: u32,
+    /// proto>=24
+    pub available: Available,
+}
+
+/// Reads the port list and active port of a sink or source (proto>=16).
+fn read_ports(ts: &mut TagStructReader, protocol_version: u16) -> Result<(Vec<PortInfo>, Option<PaString>), Error> {
+    let mut ports = Vec::new();
+    for _ in 0..ts.read_u32()? {
+        let mut port = PortInfo {
+            name: read_owned_string(ts)?,
+            description: read_owned_string(ts)?,
+            priority: ts.read_u32()?,
+            available: Available::Unknown,
+        };
+        if protocol_version >= 24 {
+            let available = ts.read_u32()?;
+            port.available = Available::from_u32(available)
+                .ok_or_else(|| Error::string(format!("invalid port availability {}", available)))?;
+        }
+        ports.push(port);
+    }
+
+    let active_port = read_owned_string_opt(ts)?;
+    Ok((ports, active_port))
+}
+
+fn read_owned_string(ts: &mut TagStructReader) -> Result<PaString, Error> {
+    Ok(PaString::from(ts.read_string_non_null()?.to_owned()))
+}
+
+fn read_owned_string_opt(ts: &mut TagStructReader) -> Result<Option<PaString>, Error> {
+    Ok(ts.read_string()?.map(|s| PaString::from(s.to_owned())))
+}
+
+/// Reads an index, mapping the invalid index to `None`.
+fn read_index(ts: &mut TagStructReader) -> Result<Option<u32>, Error> {
+    match ts.read_u32()? {
+        u32::MAX => Ok(None),
+        index => Ok(Some(index)),
+    }
+}
+
+/// Parameters of `GetSinkInfo`, `GetSourceInfo` and `GetCardInfo`, which query a single device.
+#[derive(Debug)]
+pub struct GetDeviceInfo<'a> {
+    device: Device<'a>,
+}
+
+impl<'a> GetDeviceInfo<'a> {
+    /// Queries a single sink.
+    pub fn sink(sink: SinkSpec<'a>) -> Self {
+        Self { device: sink.into() }
+    }
+
+    /// Queries a single source.
+    pub fn source(source: SourceSpec<'a>) -> Self {
+        Self { device: source.into() }
+    }
+
+    /// Queries the card with index `index`.
+    pub fn card(index: u32) -> Self {
+        Self { device: SinkSpec::Index(index).into() }
+    }
+
+    /// Index of the device, if it's selected by index.
+    pub fn index(&self) -> Option<u32> {
+        self.device.index()
+    }
+
+    /// Name of the device, if it's selected by name.
+    pub fn name(&self) -> Option<&'a PaStr> {
+        self.device.name()
+    }
+}
+
+impl<'a> FromTagStruct<'a> for GetDeviceInfo<'a> {
+    fn from_tag_struct(ts: &mut TagStructReader<'a>, _protocol_version: u16) -> Result<Self, Error> {
+        Ok(Self {
+            device: Device::read(ts, false)?,
+        })
+    }
+}
+
+impl<'a> ToTagStruct for GetDeviceInfo<'a> {
+    fn to_tag_struct(&self, w: &mut TagStructWriter, _protocol_version: u16) -> Result<(), Error> {
+        self.device.write(w);
+        Ok(())
+    }
+}
+
+/// Parameters of `GetSampleInfo`, which queries a single sample in the sample cache.
+#[derive(Debug)]
+pub struct GetSampleInfo<'a> {
+    sample: Device<'a>,
+}
+
+impl<'a> GetSampleInfo<'a> {
+    /// Queries the sample with index `index`.
+    pub fn by_index(index: u32) -> Self {
+        Self { sample: SinkSpec::Index(index).into() }
+    }
+
+    /// Queries the sample called `name`.
+    pub fn by_name(name: &'a PaStr) -> Self {
+        Self { sample: SinkSpec::Name(name.as_cstr()).into() }
+    }
+
+    /// Index of the sample, if it's selected by index.
+    pub fn index(&self) -> Option<u32> {
+        self.sample.index()
+    }
+
+    /// Name of the sample, if it's selected by name.
+    pub fn name(&self) -> Option<&'a PaStr> {
+        self.sample.name()
+    }
+}
+
+impl<'a> FromTagStruct<'a> for GetSampleInfo<'a> {
+    fn from_tag_struct(ts: &mut TagStructReader<'a>, _protocol_version: u16) -> Result<Self, Error> {
+        Ok(Self {
+            sample: Device::read(ts, false)?,
+        })
+    }
+}
+
+impl<'a> ToTagStruct for GetSampleInfo<'a> {
+    fn to_tag_struct(&self, w: &mut TagStructWriter, _protocol_version: u16) -> Result<(), Error> {
+        self.sample.write(w);
+        Ok(())
+    }
+}
+
+/// Parameters of `GetClientInfo`, `GetSinkInputInfo` and `GetSourceOutputInfo`, which query a
+/// single object by index.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GetInfo {
+    index: u32,
+}
+
+impl GetInfo {
+    pub fn new(index: u32) -> Self {
+        Self { index }
+    }
+
+    /// Index of the object to query.
+    pub fn index(&self) -> u32 {
+        self.index
+    }
+}
+
+impl<'a> FromTagStruct<'a> for GetInfo {
+    fn from_tag_struct(ts: &mut TagStructReader<'a>, _protocol_version: u16) -> Result<Self, Error> {
+        Ok(Self {
+            index: ts.read_u32()?,
+        })
+    }
+}
+
+impl ToTagStruct for GetInfo {
+    fn to_tag_struct(&self, w: &mut TagStructWriter, _protocol_version: u16) -> Result<(), Error> {
+        w.write(self.index);
+        Ok(())
+    }
+}
+
+/// Request information about a single module.
+#[derive(Debug)]
+pub struct GetModuleInfo {
+    module_index: u32,
+}
+
+impl GetModuleInfo {
+    /// Creates a command querying the module with index `module_index`.
+    pub fn new(module_index: u32) -> Self {
+        Self { module_index }
+    }
+
+    /// Index of the module to query.
+    pub fn module_index(&self) -> u32 {
+        self.module_index
+    }
+}
+
+impl<'a> FromTagStruct<'a> for GetModuleInfo {
+    fn from_tag_struct(ts: &mut TagStructReader<'a>, _protocol_version: u16) -> Result<Self, Error> {
+        Ok(Self {
+            module_index: ts.read_u32()?,
+        })
+    }
+}
+
+impl ToTagStruct for GetModuleInfo {
+    fn to_tag_struct(&self, w: &mut TagStructWriter, _protocol_version: u16) -> Result<(), Error> {
+        w.write(self.module_index);
+        Ok(())
+    }
+}
+
+/// Reply to `GetModuleInfoList`, listing all loaded modules.
+///
+/// Decoded as a `Vec<ModuleInfo>`.
+#[derive(Debug)]
+pub struct GetModuleInfoListReply<I> {
+    modules: I,
     _priv: (),
 }
 
-impl GetModuleInfoListReply {
-    /// Creates a dummy reply for servers that do not support modules.
-    pub fn new_dummy() -> Self {
-        Self { _priv: () }
+impl<I> GetModuleInfoListReply<I>
+where I: IntoIterator<Item=ModuleInfo> {
+    pub fn new(modules: I) -> Self {
+        Self {
+            modules,
+            _priv: (),
+        }
+    }
+}
+
+impl<I> ToTagStruct for GetModuleInfoListReply<I>
+where I: IntoIterator<Item=ModuleInfo> + Clone {
+    fn to_tag_struct(&self, w: &mut TagStructWriter, protocol_version: u16) -> Result<(), Error> {
+        for module in self.modules.clone() {
+            module.to_tag_struct(w, protocol_version)?;
+        }
+        Ok(())
+    }
+}
+
+/// Information about a loaded module.
+///
+/// This is also the reply to a `GetModuleInfo` command.
+#[derive(Debug, Clone)]
+pub struct ModuleInfo {
+    /// Index of the module.
+    pub index: u32,
+    /// Name of the module (eg. `module-null-sink`).
+    pub name: PaString,
+    /// The argument string the module was loaded with.
+    pub argument: Option<PaString>,
+    /// Number of entities (eg. streams) that use the module, or `None` if unknown.
+    pub n_used: Option<u32>,
+    /// Module properties (proto>=15, empty otherwise).
+    pub props: PropList,
+}
+
+impl ModuleInfo {
+    /// Creates a module info object.
+    ///
+    /// # Parameters
+    ///
+    /// * `index`: Index of the module.
+    /// * `name`: Name of the module (eg. `module-null-sink`).
+    /// * `argument`: The argument string the module was loaded with.
+    /// * `n_used`: Number of entities (eg. streams) that use the module, or `None` if unknown.
+    /// * `props`: Module properties.
+    pub fn new(
+        index: u32,
+        name: &PaStr,
+        argument: Option<&PaStr>,
+        n_used: Option<u32>,
+        props: &PropList,
+    ) -> Self {
+        Self {
+            index,
+            name: name.to_owned(),
+            argument: argument.map(ToOwned::to_owned),
+            n_used,
+            props: props.clone(),
+        }
     }
 }
 
-impl ToTagStruct for GetModuleInfoListReply {
+impl ToTagStruct for ModuleInfo {
     fn to_tag_struct(&self, w: &mut TagStructWriter, protocol_version: u16) -> Result<(), Error> {
-        w.write(0u32);  // ID
-        w.write(PaStr::from_bytes_with_nul(b"Default Module\0").unwrap());
-        w.write(<&PaStr>::default());   // "argument"
-        w.write(1u32);  // "get_n_used" users of module?
+        w.write(self.index);
+        w.write(&*self.name);
+        w.write(self.argument.as_ref().map(|arg| &**arg));
+        w.write(self.n_used.unwrap_or(u32::MAX));
 
         if protocol_version < 15 {
-            w.write(false); // autoload
+            w.write(false); // autoload (obsolete)
         } else {
-            w.write(PropList::new());   // module props
+            w.write(&self.props);
         }
 
         Ok(())
     }
 }
 
+impl<'a> FromTagStruct<'a> for ModuleInfo {
+    fn from_tag_struct(ts: &mut TagStructReader<'a>, protocol_version: u16) -> Result<Self, Error> {
+        let mut info = ModuleInfo {
+            index: ts.read_u32()?,
+            name: read_owned_string(ts)?,
+            argument: read_owned_string_opt(ts)?,
+            n_used: read_index(ts)?,
+            props: PropList::new(),
+        };
+
+        if protocol_version < 15 {
+            ts.read_bool()?;    // autoload (obsolete)
+        } else {
+            info.props = ts.read_proplist()?;
+        }
+
+        Ok(info)
+    }
+}
+
+/// Reply to `GetClientInfoList`, listing all connected clients.
+///
+/// Decoded as a `Vec<ClientInfo>`.
 #[derive(Debug)]
 pub struct GetClientInfoListReply<I> {
     clients: I,
     _priv: (),
 }
 
-impl<'a, I> GetClientInfoListReply<I>
-where I: IntoIterator<Item=ClientInfo<'a>> {
+impl<I> GetClientInfoListReply<I>
+where I: IntoIterator<Item=ClientInfo> {
     pub fn new(clients: I) -> Self {
         Self {
             clients,
@@ -135,35 +686,767 @@ where I: IntoIterator<Item=ClientInfo<'a>> {
     }
 }
 
-impl<'a, I> ToTagStruct for GetClientInfoListReply<I>
-where I: IntoIterator<Item=ClientInfo<'a>> + Clone {
-    fn to_tag_struct(&self, w: &mut TagStructWriter, _protocol_version: u16) -> Result<(), Error> {
+impl<I> ToTagStruct for GetClientInfoListReply<I>
+where I: IntoIterator<Item=ClientInfo> + Clone {
+    fn to_tag_struct(&self, w: &mut TagStructWriter, protocol_version: u16) -> Result<(), Error> {
         for client in self.clients.clone() {
-            w.write(client.index);
-            w.write(client.app_name);
-            w.write(u32::MAX);  // INVALID_INDEX = no/unknown module
-            w.write(client.driver);
-            w.write(client.props);
+            client.to_tag_struct(w, protocol_version)?;
         }
         Ok(())
     }
 }
 
-#[derive(Debug)]
-pub struct ClientInfo<'a> {
-    index: u32,
-    app_name: &'a PaStr,
-    driver: &'a PaStr,
-    props: &'a PropList,    // proto>=13
+/// Information about a connected client.
+#[derive(Debug, Clone)]
+pub struct ClientInfo {
+    /// Index of the client.
+    pub index: u32,
+    /// The client's application name (`"(null)"` if it didn't set one).
+    pub name: PaString,
+    /// Index of the module that owns the client, if any.
+    pub owner_module: Option<u32>,
+    pub driver: Option<PaString>,
+    /// Client properties (proto>=13).
+    pub props: PropList,
 }
 
-impl<'a> ClientInfo<'a> {
-    pub fn new(index: u32, driver: &'a PaStr, props: &'a PropList) -> Self {
+impl ClientInfo {
+    pub fn new(index: u32, driver: &PaStr, props: &PropList) -> Self {
         Self {
             index,
-            app_name: props.get_string(Prop::ApplicationName).unwrap_or_default(),
-            driver,
-            props,
+            name: props.get_string(Prop::ApplicationName)
+                .unwrap_or_else(|| PaStr::from_bytes_with_nul(b"(null)\0").unwrap())
+                .to_owned(),
+            owner_module: None,
+            driver: Some(driver.to_owned()),
+            props: props.clone(),
+        }
+    }
+}
+
+impl ToTagStruct for ClientInfo {
+    fn to_tag_struct(&self, w: &mut TagStructWriter, _protocol_version: u16) -> Result<(), Error> {
+        w.write(self.index);
+        w.write(&*self.name);
+        w.write(self.owner_module.unwrap_or(u32::MAX));
+        w.write(self.driver.as_ref().map(|driver| &**driver));
+        w.write(&self.props);
+        Ok(())
+    }
+}
+
+impl<'a> FromTagStruct<'a> for ClientInfo {
+    fn from_tag_struct(ts: &mut TagStructReader<'a>, _protocol_version: u16) -> Result<Self, Error> {
+        Ok(Self {
+            index: ts.read_u32()?,
+            name: read_owned_string(ts)?,
+            owner_module: read_index(ts)?,
+            driver: read_owned_string_opt(ts)?,
+            props: ts.read_proplist()?,
+        })
+    }
+}
+
+/// Reply to `GetSinkInputInfoList`, listing all sink inputs.
+///
+/// Decoded as a `Vec<SinkInputInfo>`.
+#[derive(Debug)]
+pub struct GetSinkInputInfoListReply<I> {
+    sink_inputs: I,
+    _priv: (),
+}
+
+impl<I> GetSinkInputInfoListReply<I>
+where I: IntoIterator<Item=SinkInputInfo> {
+    pub fn new(sink_inputs: I) -> Self {
+        Self {
+            sink_inputs,
+            _priv: (),
+        }
+    }
+}
+
+impl<I> ToTagStruct for GetSinkInputInfoListReply<I>
+where I: IntoIterator<Item=SinkInputInfo> + Clone {
+    fn to_tag_struct(&self, w: &mut TagStructWriter, protocol_version: u16) -> Result<(), Error> {
+        for sink_input in self.sink_inputs.clone() {
+            sink_input.to_tag_struct(w, protocol_version)?;
+        }
+        Ok(())
+    }
+}
+
+/// Information about a sink input (a playback stream connected to a sink), as sent in reply to
+/// `GetSinkInputInfoList`.
+///
+/// Fields that aren't transmitted with the negotiated protocol version are set to defaults.
+#[derive(Debug, Clone)]
+pub struct SinkInputInfo {
+    pub index: u32,
+    pub name: PaString,
+    /// Index of the module that created the sink input, if any.
+    pub owner_module: Option<u32>,
+    /// Index of the client that owns the sink input, if any.
+    pub client: Option<u32>,
+    /// Index of the sink the input is connected to.
+    pub sink: u32,
+    pub sample_spec: SampleSpec,
+    pub channel_map: ChannelMap,
+    pub cvolume: CVolume,
+    /// Latency due to buffering in the sink input.
+    pub buffer_usec: Microseconds,
+    /// Latency of the sink.
+    pub sink_usec: Microseconds,
+    pub resample_method: Option<PaString>,
+    pub driver: Option<PaString>,
+    pub muted: bool,
+    pub props: PropList,
+    /// proto>=19
+    pub corked: bool,
+    /// Whether the stream has a volume at all (proto>=20, `true` otherwise).
+    pub has_volume: bool,
+    /// proto>=20, `true` otherwise.
+    pub volume_writable: bool,
+    /// Format of the stream (proto>=21).
+    pub format: Option<FormatInfo>,
+}
+
+impl ToTagStruct for SinkInputInfo {
+    fn to_tag_struct(&self, w: &mut TagStructWriter, protocol_version: u16) -> Result<(), Error> {
+        w.write(self.index);
+        w.write(&*self.name);
+        w.write(self.owner_module.unwrap_or(u32::MAX));
+        w.write(self.client.unwrap_or(u32::MAX));
+        w.write(self.sink);
+        w.write(&self.sample_spec);
+        w.write(&self.channel_map);
+        w.write(&self.cvolume);
+        w.write(self.buffer_usec);
+        w.write(self.sink_usec);
+        w.write(self.resample_method.as_ref().map(|method| &**method));
+        w.write(self.driver.as_ref().map(|driver| &**driver));
+        w.write(self.muted);
+        w.write(&self.props);
+
+        if protocol_version >= 19 {
+            w.write(self.corked);
+        }
+        if protocol_version >= 20 {
+            w.write(self.has_volume);
+            w.write(self.volume_writable);
+        }
+        if protocol_version >= 21 {
+            let format = self.format.as_ref()
+                .ok_or_else(|| Error::string("sink input format required for protocol version >= 21"))?;
+            w.write(format);
+        }
+
+        Ok(())
+    }
+}
+
+impl<'a> FromTagStruct<'a> for SinkInputInfo {
+    fn from_tag_struct(ts: &mut TagStructReader<'a>, protocol_version: u16) -> Result<Self, Error> {
+        let mut info = SinkInputInfo {
+            index: ts.read_u32()?,
+            name: read_owned_string(ts)?,
+            owner_module: read_index(ts)?,
+            client: read_index(ts)?,
+            sink: ts.read_u32()?,
+            sample_spec: ts.read_sample_spec()?,
+            channel_map: ts.read_channel_map()?,
+            cvolume: ts.read_cvolume()?,
+            buffer_usec: ts.read_usec()?,
+            sink_usec: ts.read_usec()?,
+            resample_method: read_owned_string_opt(ts)?,
+            driver: read_owned_string_opt(ts)?,
+            // proto>=11
+            muted: ts.read_bool()?,
+            // proto>=13
+            props: ts.read_proplist()?,
+            corked: false,
+            has_volume: true,
+            volume_writable: true,
+            format: None,
+        };
+
+        if protocol_version >= 19 {
+            info.corked = ts.read_bool()?;
+        }
+        if protocol_version >= 20 {
+            info.has_volume = ts.read_bool()?;
+            info.volume_writable = ts.read_bool()?;
+        }
+        if protocol_version >= 21 {
+            info.format = Some(ts.read_format_info()?);
+        }
+
+        Ok(info)
+    }
+}
+
+/// Reply to `GetSourceOutputInfoList`, listing all source outputs.
+///
+/// Decoded as a `Vec<SourceOutputInfo>`.
+#[derive(Debug)]
+pub struct GetSourceOutputInfoListReply<I> {
+    source_outputs: I,
+    _priv: (),
+}
+
+impl<I> GetSourceOutputInfoListReply<I>
+where I: IntoIterator<Item=SourceOutputInfo> {
+    pub fn new(source_outputs: I) -> Self {
+        Self {
+            source_outputs,
+            _priv: (),
+        }
+    }
+}
+
+impl<I> ToTagStruct for GetSourceOutputInfoListReply<I>
+where I: IntoIterator<Item=SourceOutputInfo> + Clone {
+    fn to_tag_struct(&self, w: &mut TagStructWriter, protocol_version: u16) -> Result<(), Error> {
+        for source_output in self.source_outputs.clone() {
+            source_output.to_tag_struct(w, protocol_version)?;
+        }
+        Ok(())
+    }
+}
+
+/// Information about a source output (a record stream connected to a source), as sent in reply
+/// to `GetSourceOutputInfoList`.
+///
+/// Fields that aren't transmitted with the negotiated protocol version are set to defaults.
+#[derive(Debug, Clone)]
+pub struct SourceOutputInfo {
+    pub index: u32,
+    pub name: PaString,
+    /// Index of the module that created the source output, if any.
+    pub owner_module: Option<u32>,
+    /// Index of the client that owns the source output, if any.
+    pub client: Option<u32>,
+    /// Index of the source the output is connected to.
+    pub source: u32,
+    pub sample_spec: SampleSpec,
+    pub channel_map: ChannelMap,
+    /// Latency due to buffering in the source output.
+    pub buffer_usec: Microseconds,
+    /// Latency of the source.
+    pub source_usec: Microseconds,
+    pub resample_method: Option<PaString>,
+    pub driver: Option<PaString>,
+    pub props: PropList,
+    /// proto>=19
+    pub corked: bool,
+    /// proto>=22, empty otherwise.
+    pub cvolume: CVolume,
+    /// proto>=22
+    pub muted: bool,
+    /// proto>=22
+    pub has_volume: bool,
+    /// proto>=22
+    pub volume_writable: bool,
+    /// Format of the stream (proto>=22).
+    pub format: Option<FormatInfo>,
+}
+
+impl ToTagStruct for SourceOutputInfo {
+    fn to_tag_struct(&self, w: &mut TagStructWriter, protocol_version: u16) -> Result<(), Error> {
+        w.write(self.index);
+        w.write(&*self.name);
+        w.write(self.owner_module.unwrap_or(u32::MAX));
+        w.write(self.client.unwrap_or(u32::MAX));
+        w.write(self.source);
+        w.write(&self.sample_spec);
+        w.write(&self.channel_map);
+        w.write(self.buffer_usec);
+        w.write(self.source_usec);
+        w.write(self.resample_method.as_ref().map(|method| &**method));
+        w.write(self.driver.as_ref().map(|driver| &**driver));
+        w.write(&self.props);
+
+        if protocol_version >= 19 {
+            w.write(self.corked);
+        }
+        if protocol_version >= 22 {
+            let format = self.format.as_ref()
+                .ok_or_else(|| Error::string("source output format required for protocol version >= 22"))?;
+            w.write(&self.cvolume);
+            w.write(self.muted);
+            w.write(self.has_volume);
+            w.write(self.volume_writable);
+            w.write(format);
+        }
+
+        Ok(())
+    }
+}
+
+impl<'a> FromTagStruct<'a> for SourceOutputInfo {
+    fn from_tag_struct(ts: &mut TagStructReader<'a>, protocol_version: u16) -> Result<Self, Error> {
+        let mut info = SourceOutputInfo {
+            index: ts.read_u32()?,
+            name: read_owned_string(ts)?,
+            owner_module: read_index(ts)?,
+            client: read_index(ts)?,
+            source: ts.read_u32()?,
+            sample_spec: ts.read_sample_spec()?,
+            channel_map: ts.read_channel_map()?,
+            buffer_usec: ts.read_usec()?,
+            source_usec: ts.read_usec()?,
+            resample_method: read_owned_string_opt(ts)?,
+            driver: read_owned_string_opt(ts)?,
+            // proto>=13
+            props: ts.read_proplist()?,
+            corked: false,
+            cvolume: CVolume::new(),
+            muted: false,
+            has_volume: false,
+            volume_writable: false,
+            format: None,
+        };
+
+        if protocol_version >= 19 {
+            info.corked = ts.read_bool()?;
+        }
+        if protocol_version >= 22 {
+            info.cvolume = ts.read_cvolume()?;
+            info.muted = ts.read_bool()?;
+            info.has_volume = ts.read_bool()?;
+            info.volume_writable = ts.read_bool()?;
+            info.format = Some(ts.read_format_info()?);
+        }
+
+        Ok(info)
+    }
+}
+
+/// Reply to `GetSampleInfoList`, listing the samples in the sample cache.
+///
+/// Decoded as a `Vec<SampleInfo>`.
+#[derive(Debug)]
+pub struct GetSampleInfoListReply<I> {
+    samples: I,
+    _priv: (),
+}
+
+impl<I> GetSampleInfoListReply<I>
+where I: IntoIterator<Item=SampleInfo> {
+    pub fn new(samples: I) -> Self {
+        Self {
+            samples,
+            _priv: (),
+        }
+    }
+}
+
+impl<I> ToTagStruct for GetSampleInfoListReply<I>
+where I: IntoIterator<Item=SampleInfo> + Clone {
+    fn to_tag_struct(&self, w: &mut TagStructWriter, protocol_version: u16) -> Result<(), Error> {
+        for sample in self.samples.clone() {
+            sample.to_tag_struct(w, protocol_version)?;
+        }
+        Ok(())
+    }
+}
+
+/// Information about a sample in the sample cache.
+#[derive(Debug, Clone)]
+pub struct SampleInfo {
+    pub index: u32,
+    pub name: PaString,
+    /// Default volume the sample is played at.
+    pub cvolume: CVolume,
+    /// Playing time of the sample.
+    pub duration: Microseconds,
+    pub sample_spec: SampleSpec,
+    pub channel_map: ChannelMap,
+    /// Size of the sample data in bytes.
+    pub length: u32,
+    /// Whether the sample is only loaded from `filename` when it's played.
+    pub lazy: bool,
+    pub filename: Option<PaString>,
+    pub props: PropList,
+}
+
+impl ToTagStruct for SampleInfo {
+    fn to_tag_struct(&self, w: &mut TagStructWriter, _protocol_version: u16) -> Result<(), Error> {
+        w.write(self.index);
+        w.write(&*self.name);
+        w.write(&self.cvolume);
+        w.write(self.duration);
+        w.write(&self.sample_spec);
+        w.write(&self.channel_map);
+        w.write(self.length);
+        w.write(self.lazy);
+        w.write(self.filename.as_ref().map(|filename| &**filename));
+        // proto>=13
+        w.write(&self.props);
+        Ok(())
+    }
+}
+
+impl<'a> FromTagStruct<'a> for SampleInfo {
+    fn from_tag_struct(ts: &mut TagStructReader<'a>, _protocol_version: u16) -> Result<Self, Error> {
+        Ok(Self {
+            index: ts.read_u32()?,
+            name: read_owned_string(ts)?,
+            cvolume: ts.read_cvolume()?,
+            duration: ts.read_usec()?,
+            sample_spec: ts.read_sample_spec()?,
+            channel_map: ts.read_channel_map()?,
+            length: ts.read_u32()?,
+            lazy: ts.read_bool()?,
+            filename: read_owned_string_opt(ts)?,
+            props: ts.read_proplist()?,
+        })
+    }
+}
+
+/// Information about a card, as sent in reply to `GetCardInfoList` (proto>=15).
+///
+/// Fields that aren't transmitted with the negotiated protocol version are set to defaults.
+#[derive(Debug, Clone)]
+pub struct CardInfo {
+    pub index: u32,
+    pub name: PaString,
+    /// Index of the module that created the card, if any.
+    pub owner_module: Option<u32>,
+    pub driver: Option<PaString>,
+    pub profiles: Vec<CardProfileInfo>,
+    /// Name of the active profile.
+    pub active_profile: Option<PaString>,
+    pub props: PropList,
+    /// proto>=26
+    pub ports: Vec<CardPortInfo>,
+}
+
+/// A profile of a card, determining which sinks and sources the card provides.
+#[derive(Debug, Clone)]
+pub struct CardProfileInfo {
+    pub name: PaString,
+    pub description: PaString,
+    pub n_sinks: u32,
+    pub n_sources: u32,
+    pub priority: u32,
+    /// Whether the profile can be activated (proto>=29, `true` otherwise).
+    pub available: bool,
+}
+
+/// A port of a card (proto>=26).
+#[derive(Debug, Clone)]
+pub struct CardPortInfo {
+    pub name: PaString,
+    pub description: PaString,
+    pub priority: u32,
+    pub available: Available,
+    pub direction: Direction,
+    pub props: PropList,
+    /// Names of the profiles the port is part of.
+    pub profiles: Vec<PaString>,
+    /// proto>=27
+    pub latency_offset: i64,
+}
+
+impl<'a> FromTagStruct<'a> for CardInfo {
+    fn from_tag_struct(ts: &mut TagStructReader<'a>, protocol_version: u16) -> Result<Self, Error> {
+        let index = ts.read_u32()?;
+        let name = read_owned_string(ts)?;
+        let owner_module = read_index(ts)?;
+        let driver = read_owned_string_opt(ts)?;
+
+        let mut profiles = Vec::new();
+        for _ in 0..ts.read_u32()? {
+            let mut profile = CardProfileInfo {
+                name: read_owned_string(ts)?,
+                description: read_owned_string(ts)?,
+                n_sinks: ts.read_u32()?,
+                n_sources: ts.read_u32()?,
+                priority: ts.read_u32()?,
+                available: true,
+            };
+            if protocol_version >= 29 {
+                profile.available = ts.read_u32()? != 0;
+            }
+            profiles.push(profile);
+        }
+
+        let mut info = CardInfo {
+            index, name, owner_module, driver, profiles,
+            active_profile: read_owned_string_opt(ts)?,
+            props: ts.read_proplist()?,
+            ports: Vec::new(),
+        };
+
+        if protocol_version >= 26 {
+            for _ in 0..ts.read_u32()? {
+                let name = read_owned_string(ts)?;
+                let description = read_owned_string(ts)?;
+                let priority = ts.read_u32()?;
+                let available = ts.read_u32()?;
+                let available = Available::from_u32(available)
+                    .ok_or_else(|| Error::string(format!("invalid port availability {}", available)))?;
+                let direction = match ts.read_u8()? {
+                    1 => Direction::Output,
+                    2 => Direction::Input,
+                    dir => return Err(Error::string(format!("invalid port direction {}", dir))),
+                };
+                let props = ts.read_proplist()?;
+                let mut port_profiles = Vec::new();
+                for _ in 0..ts.read_u32()? {
+                    port_profiles.push(read_owned_string(ts)?);
+                }
+
+                info.ports.push(CardPortInfo {
+                    name, description, priority, available, direction, props,
+                    profiles: port_profiles,
+                    latency_offset: if protocol_version >= 27 { ts.read_i64()? } else { 0 },
+                });
+            }
+        }
+
+        Ok(info)
+    }
+}
+
+/// Server reply to `GetServerInfo`.
+#[derive(Debug, Clone)]
+pub struct ServerInfo {
+    /// Name of the server package (eg. `pulseaudio`).
+    pub package_name: PaString,
+    pub package_version: PaString,
+    /// Name of the user the server runs as.
+    pub user_name: PaString,
+    pub host_name: PaString,
+    /// Default sample spec of the server.
+    pub sample_spec: SampleSpec,
+    pub default_sink_name: Option<PaString>,
+    pub default_source_name: Option<PaString>,
+    /// A random cookie identifying this server instance.
+    pub cookie: u32,
+    /// Default channel map of the server (proto>=15).
+    pub channel_map: Option<ChannelMap>,
+}
+
+impl ToTagStruct for ServerInfo {
+    fn to_tag_struct(&self, w: &mut TagStructWriter, protocol_version: u16) -> Result<(), Error> {
+        w.write(&*self.package_name);
+        w.write(&*self.package_version);
+        w.write(&*self.user_name);
+        w.write(&*self.host_name);
+        w.write(self.sample_spec.protocol_downgrade(protocol_version));
+        w.write(self.default_sink_name.as_ref().map(|name| &**name));
+        w.write(self.default_source_name.as_ref().map(|name| &**name));
+        w.write(self.cookie);
+        if protocol_version >= 15 {
+            let default_map;
+            w.write(match self.channel_map {
+                Some(ref map) => map,
+                None => {
+                    default_map = ChannelMap::default_for(self.sample_spec.channels())
+                        .ok_or_else(|| Error::string("no channel map for the server's sample spec"))?;
+                    &default_map
+                }
+            });
+        }
+        Ok(())
+    }
+}
+
+impl<'a> FromTagStruct<'a> for ServerInfo {
+    fn from_tag_struct(ts: &mut TagStructReader<'a>, protocol_version: u16) -> Result<Self, Error> {
+        Ok(Self {
+            package_name: read_owned_string(ts)?,
+            package_version: read_owned_string(ts)?,
+            user_name: read_owned_string(ts)?,
+            host_name: read_owned_string(ts)?,
+            sample_spec: ts.read_sample_spec()?,
+            default_sink_name: read_owned_string_opt(ts)?,
+            default_source_name: read_owned_string_opt(ts)?,
+            cookie: ts.read_u32()?,
+            channel_map: if protocol_version >= 15 {
+                Some(ts.read_channel_map()?)
+            } else {
+                None
+            },
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sink_info_roundtrip() {
+        let sinks = [Sink::new_dummy(0), Sink::new_dummy(3)];
+
+        for &version in &[13, 15, 16, 21, 24, 32] {
+            let mut buf = Vec::new();
+            GetSinkInfoListReply::new(sinks.iter())
+                .to_tag_struct(&mut TagStructWriter::new(&mut buf), version)
+                .unwrap();
+
+            let mut ts = TagStructReader::from_raw(&buf);
+            let infos = Vec::<SinkInfo>::from_tag_struct(&mut ts, version).unwrap();
+            assert_eq!(infos.len(), 2);
+            assert_eq!(infos[1].index, 3);
+            assert_eq!(infos[1].name.to_str().unwrap(), "Dummy Sink");
+            assert_eq!(infos[1].monitor_source, None);
+            assert_eq!(infos[1].state, if version >= 15 { Some(SinkState::Idle) } else { None });
+            assert_eq!(infos[1].ports.len(), if version >= 16 { 1 } else { 0 });
+            assert_eq!(infos[1].formats.len(), if version >= 21 { 1 } else { 0 });
+        }
+    }
+
+    #[test]
+    fn get_sample_info() {
+        let name = PaStr::from_bytes_with_nul(b"bell\0").unwrap();
+        for query in &[GetSampleInfo::by_name(name), GetSampleInfo::by_index(3)] {
+            let mut buf = Vec::new();
+            query.to_tag_struct(&mut TagStructWriter::new(&mut buf), 32).unwrap();
+            let parsed = GetSampleInfo::from_tag_struct(&mut TagStructReader::from_raw(&buf), 32).unwrap();
+            assert_eq!((parsed.index(), parsed.name()), (query.index(), query.name()));
+        }
+
+        // Exactly one of index and name has to be given
+        for &(index, name) in &[(3, Some(name)), (u32::MAX, None)] {
+            let mut buf = Vec::new();
+            {
+                let mut w = TagStructWriter::new(&mut buf);
+                w.write(index);
+                w.write(name);
+            }
+            assert!(GetSampleInfo::from_tag_struct(&mut TagStructReader::from_raw(&buf), 32).is_err());
+        }
+    }
+
+    #[test]
+    fn module_info_roundtrip() {
+        let name = PaStr::from_bytes_with_nul(b"module-null-sink\0").unwrap();
+        let mut props = PropList::new();
+        props.set(Prop::ApplicationName, "test\0");
+        let modules = vec![ModuleInfo::new(1, name, None, Some(2), &props)];
+
+        for &version in &[13, 32] {
+            let mut buf = Vec::new();
+            GetModuleInfoListReply::new(modules.clone())
+                .to_tag_struct(&mut TagStructWriter::new(&mut buf), version)
+                .unwrap();
+
+            let mut ts = TagStructReader::from_raw(&buf);
+            let infos = Vec::<ModuleInfo>::from_tag_struct(&mut ts, version).unwrap();
+            assert_eq!(infos.len(), 1);
+            assert_eq!(&*infos[0].name, name);
+            assert!(infos[0].argument.is_none());
+            assert_eq!(infos[0].n_used, Some(2));
+            assert_eq!(infos[0].props.get(Prop::ApplicationName).is_some(), version >= 15);
+        }
+    }
+
+    #[test]
+    fn stream_info_roundtrip() {
+        let spec = SampleSpec::new_checked(::sample_spec::SampleFormat::S16Le, 2, 44100).unwrap();
+        let map = ChannelMap::default_for(2).unwrap();
+        let mut cvolume = CVolume::new();
+        cvolume.push(Volume::NORM).unwrap();
+        cvolume.push(Volume::NORM).unwrap();
+        let sink_input = SinkInputInfo {
+            index: 4,
+            name: PaString::new("music").unwrap(),
+            owner_module: None,
+            client: Some(7),
+            sink: 1,
+            sample_spec: spec.clone(),
+            channel_map: map.clone(),
+            cvolume: cvolume.clone(),
+            buffer_usec: Microseconds(0),
+            sink_usec: Microseconds(0),
+            resample_method: None,
+            driver: None,
+            muted: false,
+            props: PropList::new(),
+            corked: true,
+            has_volume: true,
+            volume_writable: true,
+            format: Some(FormatInfo::new(::types::FormatEncoding::Pcm)),
+        };
+        let source_output = SourceOutputInfo {
+            index: 5,
+            name: PaString::new("recording").unwrap(),
+            owner_module: Some(2),
+            client: None,
+            source: 3,
+            sample_spec: spec,
+            channel_map: map,
+            buffer_usec: Microseconds(0),
+            source_usec: Microseconds(0),
+            resample_method: None,
+            driver: None,
+            props: PropList::new(),
+            corked: false,
+            cvolume: cvolume.clone(),
+            muted: false,
+            has_volume: false,
+            volume_writable: false,
+            format: Some(FormatInfo::new(::types::FormatEncoding::Pcm)),
+        };
+
+        for &version in &[13, 32] {
+            let mut buf = Vec::new();
+            GetSinkInputInfoListReply::new(vec![sink_input.clone()])
+                .to_tag_struct(&mut TagStructWriter::new(&mut buf), version)
+                .unwrap();
+            let mut ts = TagStructReader::from_raw(&buf);
+            let infos = Vec::<SinkInputInfo>::from_tag_struct(&mut ts, version).unwrap();
+            assert_eq!(infos.len(), 1);
+            assert_eq!(infos[0].index, 4);
+            assert_eq!(infos[0].name.to_str().unwrap(), "music");
+            assert_eq!(infos[0].client, Some(7));
+            assert_eq!(infos[0].corked, version >= 19);
+            assert_eq!(infos[0].format.is_some(), version >= 21);
+
+            let mut buf = Vec::new();
+            GetSourceOutputInfoListReply::new(vec![source_output.clone()])
+                .to_tag_struct(&mut TagStructWriter::new(&mut buf), version)
+                .unwrap();
+            let mut ts = TagStructReader::from_raw(&buf);
+            let infos = Vec::<SourceOutputInfo>::from_tag_struct(&mut ts, version).unwrap();
+            assert_eq!(infos.len(), 1);
+            assert_eq!(infos[0].source, 3);
+            assert_eq!(infos[0].owner_module, Some(2));
+            assert_eq!(infos[0].client, None);
+            assert_eq!(infos[0].format.is_some(), version >= 22);
+        }
+    }
+
+    #[test]
+    fn server_info_roundtrip() {
+        let info = ServerInfo {
+            package_name: PaString::new("pulsar").unwrap(),
+            package_version: PaString::new("0.1.0").unwrap(),
+            user_name: PaString::new("user").unwrap(),
+            host_name: PaString::new("host").unwrap(),
+            sample_spec: SampleSpec::new_checked(::sample_spec::SampleFormat::S16Le, 2, 44100).unwrap(),
+            default_sink_name: Some(PaString::new("sink").unwrap()),
+            default_source_name: None,
+            cookie: 0xdeadbeef,
+            channel_map: None,
+        };
+
+        for &version in &[13, 32] {
+            let mut buf = Vec::new();
+            info.to_tag_struct(&mut TagStructWriter::new(&mut buf), version).unwrap();
+
+            let mut ts = TagStructReader::from_raw(&buf);
+            let parsed = ServerInfo::from_tag_struct(&mut ts, version).unwrap();
+            assert!(ts.is_at_end());
+            assert_eq!(parsed.host_name.to_str().unwrap(), "host");
+            assert_eq!(parsed.default_sink_name.unwrap().to_str().unwrap(), "sink");
+            assert!(parsed.default_source_name.is_none());
+            assert_eq!(parsed.cookie, 0xdeadbeef);
+            assert_eq!(parsed.channel_map.map(|map| map.len()), if version >= 15 { Some(2) } else { None });
         }
     }
 }
@@ -0,0 +1,462 @@
+//! Commands modifying sinks, sources, streams and clients.
+//!
+//! These are the setters of PulseAudio's introspection API: changing volumes and mute states,
+//! selecting the default devices, moving and killing streams, and suspending devices.
+//!
+//! Sinks and sources are selected either by index or by name (see `SinkSpec` and `SourceSpec`),
+//! while sink inputs, source outputs and clients are always selected by index.
+
+use super::prelude::*;
+use super::{SinkSpec, SourceSpec};
+
+use std::ffi::CStr;
+use std::u32;
+
+/// A sink or source, selected by index or by name.
+///
+/// On the wire, this is an index followed by a name, one of which is invalid.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Device<'a> {
+    index: u32,
+    name: Option<&'a CStr>,
+}
+
+impl<'a> Device<'a> {
+    pub fn all() -> Self {
+        Self { index: u32::MAX, name: None }
+    }
+
+    pub fn index(&self) -> Option<u32> {
+        match self.index {
+            u32::MAX => None,
+            index => Some(index),
+        }
+    }
+
+    pub fn name(&self) -> Option<&'a PaStr> {
+        self.name.map(Into::into)
+    }
+
+    /// Reads the device selector, which must select exactly one device unless `allow_all` is set.
+    pub fn read(ts: &mut TagStructReader<'a>, allow_all: bool) -> Result<Self, Error> {
+        let device = Self {
+            index: ts.read_u32()?,
+            name: ts.read_string()?,
+        };
+        match (device.index(), device.name) {
+            (Some(_), Some(_)) => Err(Error::string("cannot specify both device index and name")),
+            (None, None) if !allow_all => Err(Error::string("no device index or name specified")),
+            _ => Ok(device),
+        }
+    }
+
+    pub fn write(&self, w: &mut TagStructWriter) {
+        w.write(self.index);
+        w.write(self.name());
+    }
+}
+
+impl<'a> From<SinkSpec<'a>> for Device<'a> {
+    fn from(spec: SinkSpec<'a>) -> Self {
+        match spec {
+            SinkSpec::Index(index) => Self { index, name: None },
+            SinkSpec::Name(name) => Self { index: u32::MAX, name: Some(name) },
+        }
+    }
+}
+
+impl<'a> From<SourceSpec<'a>> for Device<'a> {
+    fn from(spec: SourceSpec<'a>) -> Self {
+        match spec {
+            SourceSpec::Index(index) => Self { index, name: None },
+            SourceSpec::Name(name) => Self { index: u32::MAX, name: Some(name) },
+        }
+    }
+}
+
+/// Parameters of `SetSinkVolume` and `SetSourceVolume`.
+#[derive(Debug)]
+pub struct SetDeviceVolume<'a> {
+    device: Device<'a>,
+    volume: CVolume,
+}
+
+impl<'a> SetDeviceVolume<'a> {
+    /// Sets the channel volumes of a sink.
+    pub fn sink(sink: SinkSpec<'a>, volume: CVolume) -> Self {
+        Self { device: sink.into(), volume }
+    }
+
+    /// Sets the channel volumes of a source.
+    pub fn source(source: SourceSpec<'a>, volume: CVolume) -> Self {
+        Self { device: source.into(), volume }
+    }
+
+    /// Index of the device, if it's selected by index.
+    pub fn index(&self) -> Option<u32> {
+        self.device.index()
+    }
+
+    /// Name of the device, if it's selected by name.
+    pub fn name(&self) -> Option<&'a PaStr> {
+        self.device.name()
+    }
+
+    /// The new channel volumes.
+    pub fn volume(&self) -> &CVolume {
+        &self.volume
+    }
+}
+
+impl<'a> FromTagStruct<'a> for SetDeviceVolume<'a> {
+    fn from_tag_struct(ts: &mut TagStructReader<'a>, _protocol_version: u16) -> Result<Self, Error> {
+        Ok(Self {
+            device: Device::read(ts, false)?,
+            volume: ts.read_cvolume()?,
+        })
+    }
+}
+
+impl<'a> ToTagStruct for SetDeviceVolume<'a> {
+    fn to_tag_struct(&self, w: &mut TagStructWriter, _protocol_version: u16) -> Result<(), Error> {
+        self.device.write(w);
+        w.write(&self.volume);
+        Ok(())
+    }
+}
+
+/// Parameters of `SetSinkMute` and `SetSourceMute`.
+#[derive(Debug)]
+pub struct SetDeviceMute<'a> {
+    device: Device<'a>,
+    mute: bool,
+}
+
+impl<'a> SetDeviceMute<'a> {
+    /// Mutes or unmutes a sink.
+    pub fn sink(sink: SinkSpec<'a>, mute: bool) -> Self {
+        Self { device: sink.into(), mute }
+    }
+
+    /// Mutes or unmutes a source.
+    pub fn source(source: SourceSpec<'a>, mute: bool) -> Self {
+        Self { device: source.into(), mute }
+    }
+
+    /// Index of the device, if it's selected by index.
+    pub fn index(&self) -> Option<u32> {
+        self.device.index()
+    }
+
+    /// Name of the device, if it's selected by name.
+    pub fn name(&self) -> Option<&'a PaStr> {
+        self.device.name()
+    }
+
+    /// Whether to mute (`true`) or unmute (`false`) the device.
+    pub fn mute(&self) -> bool {
+        self.mute
+    }
+}
+
+impl<'a> FromTagStruct<'a> for SetDeviceMute<'a> {
+    fn from_tag_struct(ts: &mut TagStructReader<'a>, _protocol_version: u16) -> Result<Self, Error> {
+        Ok(Self {
+            device: Device::read(ts, false)?,
+            mute: ts.read_bool()?,
+        })
+    }
+}
+
+impl<'a> ToTagStruct for SetDeviceMute<'a> {
+    fn to_tag_struct(&self, w: &mut TagStructWriter, _protocol_version: u16) -> Result<(), Error> {
+        self.device.write(w);
+        w.write(self.mute);
+        Ok(())
+    }
+}
+
+/// Parameters of `SuspendSink` and `SuspendSource`.
+#[derive(Debug)]
+pub struct SuspendDevice<'a> {
+    device: Device<'a>,
+    suspend: bool,
+}
+
+impl<'a> SuspendDevice<'a> {
+    /// Suspends or resumes a sink, or all sinks if `sink` is `None`.
+    pub fn sink(sink: Option<SinkSpec<'a>>, suspend: bool) -> Self {
+        Self {
+            device: sink.map_or_else(Device::all, Into::into),
+            suspend,
+        }
+    }
+
+    /// Suspends or resumes a source, or all sources if `source` is `None`.
+    pub fn source(source: Option<SourceSpec<'a>>, suspend: bool) -> Self {
+        Self {
+            device: source.map_or_else(Device::all, Into::into),
+            suspend,
+        }
+    }
+
+    /// Index of the device, if it's selected by index.
+    pub fn index(&self) -> Option<u32> {
+        self.device.index()
+    }
+
+    /// Name of the device, if it's selected by name.
+    ///
+    /// If neither index nor name are set, the command applies to all devices.
+    pub fn name(&self) -> Option<&'a PaStr> {
+        self.device.name()
+    }
+
+    /// Whether to suspend (`true`) or resume (`false`) the device.
+    pub fn suspend(&self) -> bool {
+        self.suspend
+    }
+}
+
+impl<'a> FromTagStruct<'a> for SuspendDevice<'a> {
+    fn from_tag_struct(ts: &mut TagStructReader<'a>, _protocol_version: u16) -> Result<Self, Error> {
+        Ok(Self {
+            device: Device::read(ts, true)?,
+            suspend: ts.read_bool()?,
+        })
+    }
+}
+
+impl<'a> ToTagStruct for SuspendDevice<'a> {
+    fn to_tag_struct(&self, w: &mut TagStructWriter, _protocol_version: u16) -> Result<(), Error> {
+        self.device.write(w);
+        w.write(self.suspend);
+        Ok(())
+    }
+}
+
+/// Parameters of `SetDefaultSink` and `SetDefaultSource`.
+#[derive(Debug)]
+pub struct SetDefaultDevice<'a> {
+    name: &'a CStr,
+}
+
+impl<'a> SetDefaultDevice<'a> {
+    /// Makes the device called `name` the default.
+    pub fn new(name: &'a PaStr) -> Self {
+        Self { name: name.as_cstr() }
+    }
+
+    /// Name of the new default device.
+    pub fn name(&self) -> &'a PaStr {
+        self.name.into()
+    }
+}
+
+impl<'a> FromTagStruct<'a> for SetDefaultDevice<'a> {
+    fn from_tag_struct(ts: &mut TagStructReader<'a>, _protocol_version: u16) -> Result<Self, Error> {
+        Ok(Self {
+            name: ts.read_string_non_null()?,
+        })
+    }
+}
+
+impl<'a> ToTagStruct for SetDefaultDevice<'a> {
+    fn to_tag_struct(&self, w: &mut TagStructWriter, _protocol_version: u16) -> Result<(), Error> {
+        w.write(self.name());
+        Ok(())
+    }
+}
+
+/// Parameters of `SetSinkInputVolume` and `SetSourceOutputVolume`.
+#[derive(Debug)]
+pub struct SetStreamVolume {
+    index: u32,
+    volume: CVolume,
+}
+
+impl SetStreamVolume {
+    /// Sets the channel volumes of the sink input or source output with index `index`.
+    pub fn new(index: u32, volume: CVolume) -> Self {
+        Self { index, volume }
+    }
+
+    /// Index of the sink input or source output.
+    pub fn index(&self) -> u32 {
+        self.index
+    }
+
+    /// The new channel volumes.
+    pub fn volume(&self) -> &CVolume {
+        &self.volume
+    }
+}
+
+impl<'a> FromTagStruct<'a> for SetStreamVolume {
+    fn from_tag_struct(ts: &mut TagStructReader<'a>, _protocol_version: u16) -> Result<Self, Error> {
+        Ok(Self {
+            index: ts.read_u32()?,
+            volume: ts.read_cvolume()?,
+        })
+    }
+}
+
+impl ToTagStruct for SetStreamVolume {
+    fn to_tag_struct(&self, w: &mut TagStructWriter, _protocol_version: u16) -> Result<(), Error> {
+        w.write(self.index);
+        w.write(&self.volume);
+        Ok(())
+    }
+}
+
+/// Parameters of `SetSinkInputMute` and `SetSourceOutputMute`.
+#[derive(Debug)]
+pub struct SetStreamMute {
+    index: u32,
+    mute: bool,
+}
+
+impl SetStreamMute {
+    /// Mutes or unmutes the sink input or source output with index `index`.
+    pub fn new(index: u32, mute: bool) -> Self {
+        Self { index, mute }
+    }
+
+    /// Index of the sink input or source output.
+    pub fn index(&self) -> u32 {
+        self.index
+    }
+
+    /// Whether to mute (`true`) or unmute (`false`) the stream.
+    pub fn mute(&self) -> bool {
+        self.mute
+    }
+}
+
+impl<'a> FromTagStruct<'a> for SetStreamMute {
+    fn from_tag_struct(ts: &mut TagStructReader<'a>, _protocol_version: u16) -> Result<Self, Error> {
+        Ok(Self {
+            index: ts.read_u32()?,
+            mute: ts.read_bool()?,
+        })
+    }
+}
+
+impl ToTagStruct for SetStreamMute {
+    fn to_tag_struct(&self, w: &mut TagStructWriter, _protocol_version: u16) -> Result<(), Error> {
+        w.write(self.index);
+        w.write(self.mute);
+        Ok(())
+    }
+}
+
+/// Parameters of `MoveSinkInput` and `MoveSourceOutput`.
+#[derive(Debug)]
+pub struct MoveStream<'a> {
+    index: u32,
+    device: Device<'a>,
+}
+
+impl<'a> MoveStream<'a> {
+    /// Moves the sink input with index `index` to `sink`.
+    pub fn to_sink(index: u32, sink: SinkSpec<'a>) -> Self {
+        Self { index, device: sink.into() }
+    }
+
+    /// Moves the source output with index `index` to `source`.
+    pub fn to_source(index: u32, source: SourceSpec<'a>) -> Self {
+        Self { index, device: source.into() }
+    }
+
+    /// Index of the sink input or source output to move.
+    pub fn index(&self) -> u32 {
+        self.index
+    }
+
+    /// Index of the destination device, if it's selected by index.
+    pub fn device_index(&self) -> Option<u32> {
+        self.device.index()
+    }
+
+    /// Name of the destination device, if it's selected by name.
+    pub fn device_name(&self) -> Option<&'a PaStr> {
+        self.device.name()
+    }
+}
+
+impl<'a> FromTagStruct<'a> for MoveStream<'a> {
+    fn from_tag_struct(ts: &mut TagStructReader<'a>, _protocol_version: u16) -> Result<Self, Error> {
+        Ok(Self {
+            index: ts.read_u32()?,
+            device: Device::read(ts, false)?,
+        })
+    }
+}
+
+impl<'a> ToTagStruct for MoveStream<'a> {
+    fn to_tag_struct(&self, w: &mut TagStructWriter, _protocol_version: u16) -> Result<(), Error> {
+        w.write(self.index);
+        self.device.write(w);
+        Ok(())
+    }
+}
+
+/// Parameters of `KillClient`, `KillSinkInput` and `KillSourceOutput`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Kill {
+    index: u32,
+}
+
+impl Kill {
+    /// Disconnects the client (or destroys the stream) with index `index`.
+    pub fn new(index: u32) -> Self {
+        Self { index }
+    }
+
+    /// Index of the client, sink input or source output.
+    pub fn index(&self) -> u32 {
+        self.index
+    }
+}
+
+impl<'a> FromTagStruct<'a> for Kill {
+    fn from_tag_struct(ts: &mut TagStructReader<'a>, _protocol_version: u16) -> Result<Self, Error> {
+        Ok(Self {
+            index: ts.read_u32()?,
+        })
+    }
+}
+
+impl ToTagStruct for Kill {
+    fn to_tag_struct(&self, w: &mut TagStructWriter, _protocol_version: u16) -> Result<(), Error> {
+        w.write(self.index);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn device_selectors() {
+        let name = CStr::from_bytes_with_nul(b"sink\0").unwrap();
+        let mut volume = CVolume::new();
+        volume.push(Volume::NORM).unwrap();
+
+        let mut buf = Vec::new();
+        SetDeviceVolume::sink(SinkSpec::Name(name), volume)
+            .to_tag_struct(&mut TagStructWriter::new(&mut buf), 32).unwrap();
+        let parsed = SetDeviceVolume::from_tag_struct(&mut TagStructReader::from_raw(&buf), 32).unwrap();
+        assert_eq!(parsed.index(), None);
+        assert_eq!(parsed.name().map(PaStr::as_cstr), Some(name));
+        assert_eq!(parsed.volume().len(), 1);
+
+        // Only suspending accepts an empty selector, which applies to all devices
+        let mut buf = Vec::new();
+        SuspendDevice::sink(None, true).to_tag_struct(&mut TagStructWriter::new(&mut buf), 32).unwrap();
+        let parsed = SuspendDevice::from_tag_struct(&mut TagStructReader::from_raw(&buf), 32).unwrap();
+        assert_eq!((parsed.index(), parsed.name(), parsed.suspend()), (None, None, true));
+        assert!(SetDeviceMute::from_tag_struct(&mut TagStructReader::from_raw(&buf), 32).is_err());
+    }
+}
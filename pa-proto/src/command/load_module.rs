@@ -0,0 +1,123 @@
+//! The `LOAD_MODULE` and `UNLOAD_MODULE` commands.
+
+use super::prelude::*;
+
+use std::ffi::CStr;
+
+/// Load a module into the server.
+#[derive(Debug)]
+pub struct LoadModule<'a> {
+    name: &'a CStr,
+    argument: Option<&'a CStr>,
+}
+
+impl<'a> LoadModule<'a> {
+    /// Creates a command loading the module `name` with an optional argument string.
+    pub fn new(name: &'a PaStr, argument: Option<&'a PaStr>) -> Self {
+        Self {
+            name: name.as_cstr(),
+            argument: argument.map(PaStr::as_cstr),
+        }
+    }
+
+    /// Name of the module to load (eg. `module-null-sink`).
+    pub fn name(&self) -> &'a PaStr {
+        self.name.into()
+    }
+
+    /// The module argument string, if any.
+    ///
+    /// This is a list of `key=value` pairs in PulseAudio's module argument syntax.
+    pub fn argument(&self) -> Option<&'a PaStr> {
+        self.argument.map(Into::into)
+    }
+}
+
+impl<'a> FromTagStruct<'a> for LoadModule<'a> {
+    fn from_tag_struct(ts: &mut TagStructReader<'a>, _protocol_version: u16) -> Result<Self, Error> {
+        let name = ts.read_string_non_null()?;
+        if name.to_bytes().is_empty() || name.to_bytes().contains(&b'/') {
+            return Err(Error::string(format!("invalid module name {:?}", name)));
+        }
+
+        Ok(Self {
+            name,
+            argument: ts.read_string()?,
+        })
+    }
+}
+
+impl<'a> ToTagStruct for LoadModule<'a> {
+    fn to_tag_struct(&self, w: &mut TagStructWriter, _protocol_version: u16) -> Result<(), Error> {
+        w.write(self.name());
+        w.write(self.argument());
+        Ok(())
+    }
+}
+
+/// Server reply to `LoadModule`, containing the index of the loaded module.
+#[derive(Debug)]
+pub struct LoadModuleReply {
+    module_index: u32,
+}
+
+impl LoadModuleReply {
+    pub fn new(module_index: u32) -> Self {
+        Self { module_index }
+    }
+
+    /// Index of the newly loaded module.
+    pub fn module_index(&self) -> u32 {
+        self.module_index
+    }
+}
+
+impl<'a> FromTagStruct<'a> for LoadModuleReply {
+    fn from_tag_struct(ts: &mut TagStructReader<'a>, _protocol_version: u16) -> Result<Self, Error> {
+        Ok(Self {
+            module_index: ts.read_u32()?,
+        })
+    }
+}
+
+impl ToTagStruct for LoadModuleReply {
+    fn to_tag_struct(&self, w: &mut TagStructWriter, _protocol_version: u16) -> Result<(), Error> {
+        w.write(self.module_index);
+        Ok(())
+    }
+}
+
+/// Unload a previously loaded module.
+///
+/// The server replies with an empty reply on success.
+#[derive(Debug)]
+pub struct UnloadModule {
+    module_index: u32,
+}
+
+impl UnloadModule {
+    /// Creates a command unloading the module with index `module_index`.
+    pub fn new(module_index: u32) -> Self {
+        Self { module_index }
+    }
+
+    /// Index of the module to unload.
+    pub fn module_index(&self) -> u32 {
+        self.module_index
+    }
+}
+
+impl<'a> FromTagStruct<'a> for UnloadModule {
+    fn from_tag_struct(ts: &mut TagStructReader<'a>, _protocol_version: u16) -> Result<Self, Error> {
+        Ok(Self {
+            module_index: ts.read_u32()?,
+        })
+    }
+}
+
+impl ToTagStruct for UnloadModule {
+    fn to_tag_struct(&self, w: &mut TagStructWriter, _protocol_version: u16) -> Result<(), Error> {
+        w.write(self.module_index);
+        Ok(())
+    }
+}
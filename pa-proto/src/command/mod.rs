@@ -11,15 +11,28 @@
 
 mod auth;
 mod create_playback_stream;
+mod create_record_stream;
 mod get_info;
+mod introspect;
+mod load_module;
 mod register_memfd_shmid;
 mod set_client_name;
+mod stream_control;
+mod subscribe;
+mod upload_stream;
 
 pub use self::auth::{Auth, AuthReply};
-pub use self::create_playback_stream::{CreatePlaybackStream, CreatePlaybackStreamReply};
+pub use self::create_playback_stream::{CreatePlaybackStream, CreatePlaybackStreamReply, SinkSpec};
+pub use self::create_record_stream::{CreateRecordStream, CreateRecordStreamReply, SourceSpec};
 pub use self::get_info::*;
+pub use self::introspect::{SetDeviceVolume, SetDeviceMute, SuspendDevice, SetDefaultDevice};
+pub use self::introspect::{SetStreamVolume, SetStreamMute, MoveStream, Kill};
+pub use self::load_module::{LoadModule, LoadModuleReply, UnloadModule};
 pub use self::register_memfd_shmid::*;
 pub use self::set_client_name::{SetClientName, SetClientNameReply};
+pub use self::stream_control::{StreamChannel, CorkStream, Request, Underflow, GetLatency, LatencyInfo};
+pub use self::subscribe::{Subscribe, SubscriptionEvent, SubscriptionMask, Facility, EventType};
+pub use self::upload_stream::{CreateUploadStream, CreateUploadStreamReply, FinishUploadStreamReply, RemoveSample};
 
 use self::PaCommand::*;
 
@@ -227,6 +240,74 @@ pub enum CommandKind<'a> {
     /// Create a new playback stream.
     CreatePlaybackStream(CreatePlaybackStream<'a>),
 
+    /// Delete a playback stream.
+    DeletePlaybackStream(StreamChannel),
+
+    /// Create a new record stream.
+    CreateRecordStream(CreateRecordStream<'a>),
+
+    /// Delete a record stream.
+    DeleteRecordStream(StreamChannel),
+
+    /// Create a stream for uploading a sample into the sample cache.
+    CreateUploadStream(CreateUploadStream<'a>),
+
+    /// Delete an upload stream without adding its sample to the cache.
+    DeleteUploadStream(StreamChannel),
+
+    /// Finish an upload stream and add its sample to the cache.
+    FinishUploadStream(StreamChannel),
+
+    /// Remove a sample from the sample cache.
+    RemoveSample(RemoveSample<'a>),
+
+    /// Ask the server to shut down.
+    Exit,
+
+    /// Wait until all data written to a playback stream has been played.
+    ///
+    /// The reply is sent once the stream's buffer has run empty.
+    DrainPlaybackStream(StreamChannel),
+
+    /// Pause or resume a playback stream.
+    CorkPlaybackStream(CorkStream),
+
+    /// Pause or resume a record stream.
+    CorkRecordStream(CorkStream),
+
+    /// Discard all buffered data of a playback stream.
+    FlushPlaybackStream(StreamChannel),
+
+    /// Discard all buffered data of a record stream.
+    FlushRecordStream(StreamChannel),
+
+    /// Query timing information of a playback stream.
+    GetPlaybackLatency(GetLatency),
+
+    /// Query timing information of a record stream.
+    GetRecordLatency(GetLatency),
+
+    /// Server request for more data on a playback stream.
+    Request(Request),
+
+    /// A record stream's buffer overflowed (server to client).
+    Overflow(StreamChannel),
+
+    /// A playback stream's buffer ran empty (server to client).
+    Underflow(Underflow),
+
+    /// A playback stream was destroyed by the server (server to client).
+    PlaybackStreamKilled(StreamChannel),
+
+    /// A record stream was destroyed by the server (server to client).
+    RecordStreamKilled(StreamChannel),
+
+    /// A playback stream started playing (server to client).
+    Started(StreamChannel),
+
+    /// Query general information about the server.
+    GetServerInfo,
+
     // TODO: Payload for forwards-compatibility
     GetSinkInfoList,
     GetSourceInfoList,
@@ -237,6 +318,93 @@ pub enum CommandKind<'a> {
     GetSourceOutputInfoList,
     GetSampleInfoList,
 
+    /// Query information about a single sink.
+    GetSinkInfo(GetDeviceInfo<'a>),
+
+    /// Query information about a single source.
+    GetSourceInfo(GetDeviceInfo<'a>),
+
+    /// Query information about a single card (proto>=15).
+    GetCardInfo(GetDeviceInfo<'a>),
+
+    /// Query information about a single client.
+    GetClientInfo(GetInfo),
+
+    /// Query information about a single sink input.
+    GetSinkInputInfo(GetInfo),
+
+    /// Query information about a single source output.
+    GetSourceOutputInfo(GetInfo),
+
+    /// Query information about a single module.
+    GetModuleInfo(GetModuleInfo),
+
+    /// Query information about a single sample in the sample cache.
+    GetSampleInfo(GetSampleInfo<'a>),
+
+    /// Subscribe to events about server objects.
+    Subscribe(Subscribe),
+
+    /// An object of a subscribed facility was created, changed or removed (server to client).
+    SubscribeEvent(SubscriptionEvent),
+
+    /// Load a module into the server.
+    LoadModule(LoadModule<'a>),
+
+    /// Unload a module from the server.
+    UnloadModule(UnloadModule),
+
+    /// Set the channel volumes of a sink.
+    SetSinkVolume(SetDeviceVolume<'a>),
+
+    /// Set the channel volumes of a source.
+    SetSourceVolume(SetDeviceVolume<'a>),
+
+    /// Set the channel volumes of a sink input.
+    SetSinkInputVolume(SetStreamVolume),
+
+    /// Set the channel volumes of a source output (proto>=22).
+    SetSourceOutputVolume(SetStreamVolume),
+
+    /// Mute or unmute a sink.
+    SetSinkMute(SetDeviceMute<'a>),
+
+    /// Mute or unmute a source.
+    SetSourceMute(SetDeviceMute<'a>),
+
+    /// Mute or unmute a sink input.
+    SetSinkInputMute(SetStreamMute),
+
+    /// Mute or unmute a source output (proto>=22).
+    SetSourceOutputMute(SetStreamMute),
+
+    /// Select the sink new playback streams are connected to.
+    SetDefaultSink(SetDefaultDevice<'a>),
+
+    /// Select the source new record streams are connected to.
+    SetDefaultSource(SetDefaultDevice<'a>),
+
+    /// Disconnect a client from the server.
+    KillClient(Kill),
+
+    /// Destroy a sink input.
+    KillSinkInput(Kill),
+
+    /// Destroy a source output.
+    KillSourceOutput(Kill),
+
+    /// Move a sink input to a different sink.
+    MoveSinkInput(MoveStream<'a>),
+
+    /// Move a source output to a different source.
+    MoveSourceOutput(MoveStream<'a>),
+
+    /// Suspend or resume a sink (or all sinks).
+    SuspendSink(SuspendDevice<'a>),
+
+    /// Suspend or resume a source (or all sources).
+    SuspendSource(SuspendDevice<'a>),
+
     /// Register `memfd`-based shared memory.
     ///
     /// This command can be sent from client to server and from server to
@@ -244,10 +412,21 @@ pub enum CommandKind<'a> {
     /// accompanied by the `memfd` file descriptor to share (see [`unix(7)`]
     /// and the `SCM_RIGHTS` ancillary message).
     ///
+    /// No reply is sent. The receiver attaches the segment and accepts memblocks referencing it
+    /// from then on.
+    ///
     /// [`unix(7)`]: https://linux.die.net/man/7/unix
-    // TODO: Better docs
     RegisterMemfdShmid(RegisterMemfdShmid),
 
+    /// Set up a shared ringbuffer channel (see the `srbchannel` module).
+    ///
+    /// Sent by the server along with the channel's two eventfds, and sent back by the client with
+    /// the same tag once it has attached the channel. No reply is sent.
+    EnableSrbchannel,
+
+    /// Stop using the shared ringbuffer channel.
+    DisableSrbchannel,
+
     /// Reply from server to client.
     Reply {
         params: TagStructReader<'a>,
@@ -269,6 +448,13 @@ pub struct Command<'a> {
 }
 
 impl<'a> Command<'a> {
+    /// Creates a command with the given tag.
+    ///
+    /// Commands that don't expect a reply use a tag of `u32::MAX`.
+    pub fn new(tag: u32, kind: CommandKind<'a>) -> Self {
+        Self { tag, kind }
+    }
+
     /// Parses a command encoded as a tagstruct.
     ///
     /// # Parameters
@@ -287,8 +473,13 @@ impl<'a> Command<'a> {
             .ok_or_else(|| Error::string(format!("invalid command opcode {}", command)))?;
         let kind = match command {
             /* SERVER->CLIENT */
-            /*PA_COMMAND_ERROR |
-            PA_COMMAND_TIMEOUT |*/
+            PA_COMMAND_ERROR => {
+                let code = ts.read_u32()?;
+                CommandKind::Error {
+                    code: PulseError::from_u32(code).unwrap_or(PulseError::Unknown),
+                }
+            }
+            /*PA_COMMAND_TIMEOUT |*/
             PA_COMMAND_REPLY => {
                 let content = ts;
                 ts = TagStructReader::from_raw(&[]);
@@ -299,11 +490,16 @@ impl<'a> Command<'a> {
             PA_COMMAND_CREATE_PLAYBACK_STREAM => {
                 CommandKind::CreatePlaybackStream(CreatePlaybackStream::from_tag_struct(&mut ts, protocol_version)?)
             }
-            /*
-            PA_COMMAND_DELETE_PLAYBACK_STREAM |
-            PA_COMMAND_CREATE_RECORD_STREAM |          /* Payload changed in v9, v12 (0.9.0, 0.9.8) */
-            PA_COMMAND_DELETE_RECORD_STREAM |
-            PA_COMMAND_EXIT |*/
+            PA_COMMAND_DELETE_PLAYBACK_STREAM => {
+                CommandKind::DeletePlaybackStream(StreamChannel::from_tag_struct(&mut ts, protocol_version)?)
+            }
+            PA_COMMAND_CREATE_RECORD_STREAM => {
+                CommandKind::CreateRecordStream(CreateRecordStream::from_tag_struct(&mut ts, protocol_version)?)
+            }
+            PA_COMMAND_DELETE_RECORD_STREAM => {
+                CommandKind::DeleteRecordStream(StreamChannel::from_tag_struct(&mut ts, protocol_version)?)
+            }
+            PA_COMMAND_EXIT => CommandKind::Exit,
             PA_COMMAND_AUTH => {
                 CommandKind::Auth(Auth::from_tag_struct(&mut ts, protocol_version)?)
             }
@@ -311,90 +507,175 @@ impl<'a> Command<'a> {
                 CommandKind::SetClientName(SetClientName::from_tag_struct(&mut ts, protocol_version)?)
             }
             /*PA_COMMAND_LOOKUP_SINK |
-            PA_COMMAND_LOOKUP_SOURCE |
-            PA_COMMAND_DRAIN_PLAYBACK_STREAM |
-            PA_COMMAND_STAT |
-            PA_COMMAND_GET_PLAYBACK_LATENCY |
-            PA_COMMAND_CREATE_UPLOAD_STREAM |
-            PA_COMMAND_DELETE_UPLOAD_STREAM |
-            PA_COMMAND_FINISH_UPLOAD_STREAM |
-            PA_COMMAND_PLAY_SAMPLE |
-            PA_COMMAND_REMOVE_SAMPLE |
-
-            PA_COMMAND_GET_SERVER_INFO |*/
-            PA_COMMAND_GET_SINK_INFO => unimplemented!(),
+            PA_COMMAND_LOOKUP_SOURCE |*/
+            PA_COMMAND_DRAIN_PLAYBACK_STREAM => {
+                CommandKind::DrainPlaybackStream(StreamChannel::from_tag_struct(&mut ts, protocol_version)?)
+            }
+            /*PA_COMMAND_STAT |*/
+            PA_COMMAND_GET_PLAYBACK_LATENCY => {
+                CommandKind::GetPlaybackLatency(GetLatency::from_tag_struct(&mut ts, protocol_version)?)
+            }
+            PA_COMMAND_CREATE_UPLOAD_STREAM => {
+                CommandKind::CreateUploadStream(CreateUploadStream::from_tag_struct(&mut ts, protocol_version)?)
+            }
+            PA_COMMAND_DELETE_UPLOAD_STREAM => {
+                CommandKind::DeleteUploadStream(StreamChannel::from_tag_struct(&mut ts, protocol_version)?)
+            }
+            PA_COMMAND_FINISH_UPLOAD_STREAM => {
+                CommandKind::FinishUploadStream(StreamChannel::from_tag_struct(&mut ts, protocol_version)?)
+            }
+            /*PA_COMMAND_PLAY_SAMPLE |*/
+            PA_COMMAND_REMOVE_SAMPLE => {
+                CommandKind::RemoveSample(RemoveSample::from_tag_struct(&mut ts, protocol_version)?)
+            }
+
+            PA_COMMAND_GET_SERVER_INFO => CommandKind::GetServerInfo,
+            PA_COMMAND_GET_SINK_INFO => {
+                CommandKind::GetSinkInfo(GetDeviceInfo::from_tag_struct(&mut ts, protocol_version)?)
+            }
             PA_COMMAND_GET_SINK_INFO_LIST => CommandKind::GetSinkInfoList,
-            PA_COMMAND_GET_SOURCE_INFO => unimplemented!(),
+            PA_COMMAND_GET_SOURCE_INFO => {
+                CommandKind::GetSourceInfo(GetDeviceInfo::from_tag_struct(&mut ts, protocol_version)?)
+            }
             PA_COMMAND_GET_SOURCE_INFO_LIST => CommandKind::GetSourceInfoList,
-            PA_COMMAND_GET_MODULE_INFO => unimplemented!(),
+            PA_COMMAND_GET_MODULE_INFO => {
+                CommandKind::GetModuleInfo(GetModuleInfo::from_tag_struct(&mut ts, protocol_version)?)
+            }
             PA_COMMAND_GET_MODULE_INFO_LIST => CommandKind::GetModuleInfoList,
-            PA_COMMAND_GET_CLIENT_INFO => unimplemented!(),
+            PA_COMMAND_GET_CLIENT_INFO => {
+                CommandKind::GetClientInfo(GetInfo::from_tag_struct(&mut ts, protocol_version)?)
+            }
             PA_COMMAND_GET_CLIENT_INFO_LIST => CommandKind::GetClientInfoList,
-            PA_COMMAND_GET_SINK_INPUT_INFO => unimplemented!(),
+            PA_COMMAND_GET_SINK_INPUT_INFO => {
+                CommandKind::GetSinkInputInfo(GetInfo::from_tag_struct(&mut ts, protocol_version)?)
+            }
             PA_COMMAND_GET_SINK_INPUT_INFO_LIST => CommandKind::GetSinkInputInfoList,
-            PA_COMMAND_GET_SOURCE_OUTPUT_INFO => unimplemented!(),
+            PA_COMMAND_GET_SOURCE_OUTPUT_INFO => {
+                CommandKind::GetSourceOutputInfo(GetInfo::from_tag_struct(&mut ts, protocol_version)?)
+            }
             PA_COMMAND_GET_SOURCE_OUTPUT_INFO_LIST => CommandKind::GetSourceOutputInfoList,
-            PA_COMMAND_GET_SAMPLE_INFO => unimplemented!(),
+            PA_COMMAND_GET_SAMPLE_INFO => {
+                CommandKind::GetSampleInfo(GetSampleInfo::from_tag_struct(&mut ts, protocol_version)?)
+            }
             PA_COMMAND_GET_SAMPLE_INFO_LIST => CommandKind::GetSampleInfoList,
-            /*PA_COMMAND_SUBSCRIBE |
+            PA_COMMAND_SUBSCRIBE => {
+                CommandKind::Subscribe(Subscribe::from_tag_struct(&mut ts, protocol_version)?)
+            }
 
-            PA_COMMAND_SET_SINK_VOLUME |
-            PA_COMMAND_SET_SINK_INPUT_VOLUME |
-            PA_COMMAND_SET_SOURCE_VOLUME |
+            PA_COMMAND_SET_SINK_VOLUME => {
+                CommandKind::SetSinkVolume(SetDeviceVolume::from_tag_struct(&mut ts, protocol_version)?)
+            }
+            PA_COMMAND_SET_SINK_INPUT_VOLUME => {
+                CommandKind::SetSinkInputVolume(SetStreamVolume::from_tag_struct(&mut ts, protocol_version)?)
+            }
+            PA_COMMAND_SET_SOURCE_VOLUME => {
+                CommandKind::SetSourceVolume(SetDeviceVolume::from_tag_struct(&mut ts, protocol_version)?)
+            }
 
-            PA_COMMAND_SET_SINK_MUTE |
-            PA_COMMAND_SET_SOURCE_MUTE |
+            PA_COMMAND_SET_SINK_MUTE => {
+                CommandKind::SetSinkMute(SetDeviceMute::from_tag_struct(&mut ts, protocol_version)?)
+            }
+            PA_COMMAND_SET_SOURCE_MUTE => {
+                CommandKind::SetSourceMute(SetDeviceMute::from_tag_struct(&mut ts, protocol_version)?)
+            }
 
-            PA_COMMAND_CORK_PLAYBACK_STREAM |
-            PA_COMMAND_FLUSH_PLAYBACK_STREAM |
-            PA_COMMAND_TRIGGER_PLAYBACK_STREAM |
+            PA_COMMAND_CORK_PLAYBACK_STREAM => {
+                CommandKind::CorkPlaybackStream(CorkStream::from_tag_struct(&mut ts, protocol_version)?)
+            }
+            PA_COMMAND_FLUSH_PLAYBACK_STREAM => {
+                CommandKind::FlushPlaybackStream(StreamChannel::from_tag_struct(&mut ts, protocol_version)?)
+            }
+            /*PA_COMMAND_TRIGGER_PLAYBACK_STREAM |*/
 
-            PA_COMMAND_SET_DEFAULT_SINK |
-            PA_COMMAND_SET_DEFAULT_SOURCE |
+            PA_COMMAND_SET_DEFAULT_SINK => {
+                CommandKind::SetDefaultSink(SetDefaultDevice::from_tag_struct(&mut ts, protocol_version)?)
+            }
+            PA_COMMAND_SET_DEFAULT_SOURCE => {
+                CommandKind::SetDefaultSource(SetDefaultDevice::from_tag_struct(&mut ts, protocol_version)?)
+            }
 
-            PA_COMMAND_SET_PLAYBACK_STREAM_NAME |
-            PA_COMMAND_SET_RECORD_STREAM_NAME |
+            /*PA_COMMAND_SET_PLAYBACK_STREAM_NAME |
+            PA_COMMAND_SET_RECORD_STREAM_NAME |*/
 
-            PA_COMMAND_KILL_CLIENT |
-            PA_COMMAND_KILL_SINK_INPUT |
-            PA_COMMAND_KILL_SOURCE_OUTPUT |
+            PA_COMMAND_KILL_CLIENT => {
+                CommandKind::KillClient(Kill::from_tag_struct(&mut ts, protocol_version)?)
+            }
+            PA_COMMAND_KILL_SINK_INPUT => {
+                CommandKind::KillSinkInput(Kill::from_tag_struct(&mut ts, protocol_version)?)
+            }
+            PA_COMMAND_KILL_SOURCE_OUTPUT => {
+                CommandKind::KillSourceOutput(Kill::from_tag_struct(&mut ts, protocol_version)?)
+            }
 
-            PA_COMMAND_LOAD_MODULE |
-            PA_COMMAND_UNLOAD_MODULE |
+            PA_COMMAND_LOAD_MODULE => {
+                CommandKind::LoadModule(LoadModule::from_tag_struct(&mut ts, protocol_version)?)
+            }
+            PA_COMMAND_UNLOAD_MODULE => {
+                CommandKind::UnloadModule(UnloadModule::from_tag_struct(&mut ts, protocol_version)?)
+            }
 
             /* Obsolete */
+            /*
             PA_COMMAND_ADD_AUTOLOAD___OBSOLETE |
             PA_COMMAND_REMOVE_AUTOLOAD___OBSOLETE |
             PA_COMMAND_GET_AUTOLOAD_INFO___OBSOLETE |
-            PA_COMMAND_GET_AUTOLOAD_INFO_LIST___OBSOLETE |
+            PA_COMMAND_GET_AUTOLOAD_INFO_LIST___OBSOLETE |*/
 
-            PA_COMMAND_GET_RECORD_LATENCY |
-            PA_COMMAND_CORK_RECORD_STREAM |
-            PA_COMMAND_FLUSH_RECORD_STREAM |
-            PA_COMMAND_PREBUF_PLAYBACK_STREAM |
+            PA_COMMAND_GET_RECORD_LATENCY => {
+                CommandKind::GetRecordLatency(GetLatency::from_tag_struct(&mut ts, protocol_version)?)
+            }
+            PA_COMMAND_CORK_RECORD_STREAM => {
+                CommandKind::CorkRecordStream(CorkStream::from_tag_struct(&mut ts, protocol_version)?)
+            }
+            PA_COMMAND_FLUSH_RECORD_STREAM => {
+                CommandKind::FlushRecordStream(StreamChannel::from_tag_struct(&mut ts, protocol_version)?)
+            }
+            /*PA_COMMAND_PREBUF_PLAYBACK_STREAM |*/
 
             /* SERVER->CLIENT */
-            PA_COMMAND_REQUEST |
-            PA_COMMAND_OVERFLOW |
-            PA_COMMAND_UNDERFLOW |
-            PA_COMMAND_PLAYBACK_STREAM_KILLED |
-            PA_COMMAND_RECORD_STREAM_KILLED |
-            PA_COMMAND_SUBSCRIBE_EVENT |
+            PA_COMMAND_REQUEST => {
+                CommandKind::Request(Request::from_tag_struct(&mut ts, protocol_version)?)
+            }
+            PA_COMMAND_OVERFLOW => {
+                CommandKind::Overflow(StreamChannel::from_tag_struct(&mut ts, protocol_version)?)
+            }
+            PA_COMMAND_UNDERFLOW => {
+                CommandKind::Underflow(Underflow::from_tag_struct(&mut ts, protocol_version)?)
+            }
+            PA_COMMAND_PLAYBACK_STREAM_KILLED => {
+                CommandKind::PlaybackStreamKilled(StreamChannel::from_tag_struct(&mut ts, protocol_version)?)
+            }
+            PA_COMMAND_RECORD_STREAM_KILLED => {
+                CommandKind::RecordStreamKilled(StreamChannel::from_tag_struct(&mut ts, protocol_version)?)
+            }
+            PA_COMMAND_SUBSCRIBE_EVENT => {
+                CommandKind::SubscribeEvent(SubscriptionEvent::from_tag_struct(&mut ts, protocol_version)?)
+            }
 
             /* A few more client->server commands */
 
             /* Supported since protocol v10 (0.9.5) */
-            PA_COMMAND_MOVE_SINK_INPUT |
-            PA_COMMAND_MOVE_SOURCE_OUTPUT |
+            PA_COMMAND_MOVE_SINK_INPUT => {
+                CommandKind::MoveSinkInput(MoveStream::from_tag_struct(&mut ts, protocol_version)?)
+            }
+            PA_COMMAND_MOVE_SOURCE_OUTPUT => {
+                CommandKind::MoveSourceOutput(MoveStream::from_tag_struct(&mut ts, protocol_version)?)
+            }
 
             /* Supported since protocol v11 (0.9.7) */
-            PA_COMMAND_SET_SINK_INPUT_MUTE |
+            PA_COMMAND_SET_SINK_INPUT_MUTE => {
+                CommandKind::SetSinkInputMute(SetStreamMute::from_tag_struct(&mut ts, protocol_version)?)
+            }
 
-            PA_COMMAND_SUSPEND_SINK |
-            PA_COMMAND_SUSPEND_SOURCE |
+            PA_COMMAND_SUSPEND_SINK => {
+                CommandKind::SuspendSink(SuspendDevice::from_tag_struct(&mut ts, protocol_version)?)
+            }
+            PA_COMMAND_SUSPEND_SOURCE => {
+                CommandKind::SuspendSource(SuspendDevice::from_tag_struct(&mut ts, protocol_version)?)
+            }
 
             /* Supported since protocol v12 (0.9.8) */
-            PA_COMMAND_SET_PLAYBACK_STREAM_BUFFER_ATTR |
+            /*PA_COMMAND_SET_PLAYBACK_STREAM_BUFFER_ATTR |
             PA_COMMAND_SET_RECORD_STREAM_BUFFER_ATTR |
 
             PA_COMMAND_UPDATE_PLAYBACK_STREAM_SAMPLE_RATE |
@@ -412,18 +693,22 @@ impl<'a> Command<'a> {
             PA_COMMAND_UPDATE_CLIENT_PROPLIST |
             PA_COMMAND_REMOVE_RECORD_STREAM_PROPLIST |
             PA_COMMAND_REMOVE_PLAYBACK_STREAM_PROPLIST |
-            PA_COMMAND_REMOVE_CLIENT_PROPLIST |
+            PA_COMMAND_REMOVE_CLIENT_PROPLIST |*/
 
             /* SERVER->CLIENT */
-            PA_COMMAND_STARTED |
+            PA_COMMAND_STARTED => {
+                CommandKind::Started(StreamChannel::from_tag_struct(&mut ts, protocol_version)?)
+            }
 
             /* Supported since protocol v14 (0.9.12) */
-            PA_COMMAND_EXTENSION |
+            /*PA_COMMAND_EXTENSION |*/
 
             /* Supported since protocol v15 (0.9.15) */
-            PA_COMMAND_GET_CARD_INFO |
-            PA_COMMAND_GET_CARD_INFO_LIST |
-            PA_COMMAND_SET_CARD_PROFILE |
+            PA_COMMAND_GET_CARD_INFO => {
+                CommandKind::GetCardInfo(GetDeviceInfo::from_tag_struct(&mut ts, protocol_version)?)
+            }
+            PA_COMMAND_GET_CARD_INFO_LIST => CommandKind::GetCardInfoList,
+            /*PA_COMMAND_SET_CARD_PROFILE |
 
             PA_COMMAND_CLIENT_EVENT |
             PA_COMMAND_PLAYBACK_STREAM_EVENT |
@@ -435,26 +720,30 @@ impl<'a> Command<'a> {
 
             /* Supported since protocol v16 (0.9.16) */
             PA_COMMAND_SET_SINK_PORT |
-            PA_COMMAND_SET_SOURCE_PORT |
+            PA_COMMAND_SET_SOURCE_PORT |*/
 
             /* Supported since protocol v22 (1.0) */
-            PA_COMMAND_SET_SOURCE_OUTPUT_VOLUME |
-            PA_COMMAND_SET_SOURCE_OUTPUT_MUTE |
+            PA_COMMAND_SET_SOURCE_OUTPUT_VOLUME => {
+                CommandKind::SetSourceOutputVolume(SetStreamVolume::from_tag_struct(&mut ts, protocol_version)?)
+            }
+            PA_COMMAND_SET_SOURCE_OUTPUT_MUTE => {
+                CommandKind::SetSourceOutputMute(SetStreamMute::from_tag_struct(&mut ts, protocol_version)?)
+            }
 
             /* Supported since protocol v27 (3.0) */
-            PA_COMMAND_SET_PORT_LATENCY_OFFSET |*/
+            /*PA_COMMAND_SET_PORT_LATENCY_OFFSET |*/
 
             /* Supported since protocol v30 (6.0) */
             /* BOTH DIRECTIONS */
-            //PA_COMMAND_ENABLE_SRBCHANNEL
-            //PA_COMMAND_DISABLE_SRBCHANNEL
+            PA_COMMAND_ENABLE_SRBCHANNEL => CommandKind::EnableSrbchannel,
+            PA_COMMAND_DISABLE_SRBCHANNEL => CommandKind::DisableSrbchannel,
 
             /* Supported since protocol v31 (9.0)
              * BOTH DIRECTIONS */
             PA_COMMAND_REGISTER_MEMFD_SHMID => {
                 CommandKind::RegisterMemfdShmid(RegisterMemfdShmid::from_tag_struct(&mut ts, protocol_version)?)
             }
-            _ => unimplemented!("command {:?}", command),
+            _ => return Err(Error::string(format!("unsupported command {:?}", command))),
         };
 
         // ensure that all parameters are consumed
@@ -479,6 +768,9 @@ impl<'a> Command<'a> {
 
     pub fn kind(&self) -> &CommandKind { &self.kind }
 
+    /// The tag used to match replies to this command.
+    pub fn tag(&self) -> u32 { self.tag }
+
     /// Creates a reply command containing a tagstruct.
     pub fn reply_packet<'c, T>(&self, buffer: &'c mut Vec<u8>, protocol_version: u16, reply: T) -> Packet
     where
@@ -549,6 +841,114 @@ impl<'a> ToTagStruct for Command<'a> {
                 w.write(self.tag);
                 params.to_tag_struct(w, protocol_version)?;
             }
+            DeletePlaybackStream(ref params) => {
+                w.write(PA_COMMAND_DELETE_PLAYBACK_STREAM as u32);
+                w.write(self.tag);
+                params.to_tag_struct(w, protocol_version)?;
+            }
+            CreateRecordStream(ref params) => {
+                w.write(PA_COMMAND_CREATE_RECORD_STREAM as u32);
+                w.write(self.tag);
+                params.to_tag_struct(w, protocol_version)?;
+            }
+            DeleteRecordStream(ref params) => {
+                w.write(PA_COMMAND_DELETE_RECORD_STREAM as u32);
+                w.write(self.tag);
+                params.to_tag_struct(w, protocol_version)?;
+            }
+            CreateUploadStream(ref params) => {
+                w.write(PA_COMMAND_CREATE_UPLOAD_STREAM as u32);
+                w.write(self.tag);
+                params.to_tag_struct(w, protocol_version)?;
+            }
+            DeleteUploadStream(ref params) => {
+                w.write(PA_COMMAND_DELETE_UPLOAD_STREAM as u32);
+                w.write(self.tag);
+                params.to_tag_struct(w, protocol_version)?;
+            }
+            FinishUploadStream(ref params) => {
+                w.write(PA_COMMAND_FINISH_UPLOAD_STREAM as u32);
+                w.write(self.tag);
+                params.to_tag_struct(w, protocol_version)?;
+            }
+            RemoveSample(ref params) => {
+                w.write(PA_COMMAND_REMOVE_SAMPLE as u32);
+                w.write(self.tag);
+                params.to_tag_struct(w, protocol_version)?;
+            }
+            Exit => {
+                w.write(PA_COMMAND_EXIT as u32);
+                w.write(self.tag);
+            }
+            DrainPlaybackStream(ref params) => {
+                w.write(PA_COMMAND_DRAIN_PLAYBACK_STREAM as u32);
+                w.write(self.tag);
+                params.to_tag_struct(w, protocol_version)?;
+            }
+            CorkPlaybackStream(ref params) => {
+                w.write(PA_COMMAND_CORK_PLAYBACK_STREAM as u32);
+                w.write(self.tag);
+                params.to_tag_struct(w, protocol_version)?;
+            }
+            CorkRecordStream(ref params) => {
+                w.write(PA_COMMAND_CORK_RECORD_STREAM as u32);
+                w.write(self.tag);
+                params.to_tag_struct(w, protocol_version)?;
+            }
+            FlushPlaybackStream(ref params) => {
+                w.write(PA_COMMAND_FLUSH_PLAYBACK_STREAM as u32);
+                w.write(self.tag);
+                params.to_tag_struct(w, protocol_version)?;
+            }
+            FlushRecordStream(ref params) => {
+                w.write(PA_COMMAND_FLUSH_RECORD_STREAM as u32);
+                w.write(self.tag);
+                params.to_tag_struct(w, protocol_version)?;
+            }
+            GetPlaybackLatency(ref params) => {
+                w.write(PA_COMMAND_GET_PLAYBACK_LATENCY as u32);
+                w.write(self.tag);
+                params.to_tag_struct(w, protocol_version)?;
+            }
+            GetRecordLatency(ref params) => {
+                w.write(PA_COMMAND_GET_RECORD_LATENCY as u32);
+                w.write(self.tag);
+                params.to_tag_struct(w, protocol_version)?;
+            }
+            Request(ref params) => {
+                w.write(PA_COMMAND_REQUEST as u32);
+                w.write(self.tag);
+                params.to_tag_struct(w, protocol_version)?;
+            }
+            Overflow(ref params) => {
+                w.write(PA_COMMAND_OVERFLOW as u32);
+                w.write(self.tag);
+                params.to_tag_struct(w, protocol_version)?;
+            }
+            Underflow(ref params) => {
+                w.write(PA_COMMAND_UNDERFLOW as u32);
+                w.write(self.tag);
+                params.to_tag_struct(w, protocol_version)?;
+            }
+            PlaybackStreamKilled(ref params) => {
+                w.write(PA_COMMAND_PLAYBACK_STREAM_KILLED as u32);
+                w.write(self.tag);
+                params.to_tag_struct(w, protocol_version)?;
+            }
+            RecordStreamKilled(ref params) => {
+                w.write(PA_COMMAND_RECORD_STREAM_KILLED as u32);
+                w.write(self.tag);
+                params.to_tag_struct(w, protocol_version)?;
+            }
+            Started(ref params) => {
+                w.write(PA_COMMAND_STARTED as u32);
+                w.write(self.tag);
+                params.to_tag_struct(w, protocol_version)?;
+            }
+            GetServerInfo => {
+                w.write(PA_COMMAND_GET_SERVER_INFO as u32);
+                w.write(self.tag);
+            }
             GetSinkInfoList => {
                 w.write(PA_COMMAND_GET_SINK_INFO_LIST as u32);
                 w.write(self.tag);
@@ -581,12 +981,166 @@ impl<'a> ToTagStruct for Command<'a> {
                 w.write(PA_COMMAND_GET_SAMPLE_INFO_LIST as u32);
                 w.write(self.tag);
             }
+            GetSinkInfo(ref params) => {
+                w.write(PA_COMMAND_GET_SINK_INFO as u32);
+                w.write(self.tag);
+                params.to_tag_struct(w, protocol_version)?;
+            }
+            GetSourceInfo(ref params) => {
+                w.write(PA_COMMAND_GET_SOURCE_INFO as u32);
+                w.write(self.tag);
+                params.to_tag_struct(w, protocol_version)?;
+            }
+            GetCardInfo(ref params) => {
+                w.write(PA_COMMAND_GET_CARD_INFO as u32);
+                w.write(self.tag);
+                params.to_tag_struct(w, protocol_version)?;
+            }
+            GetSampleInfo(ref params) => {
+                w.write(PA_COMMAND_GET_SAMPLE_INFO as u32);
+                w.write(self.tag);
+                params.to_tag_struct(w, protocol_version)?;
+            }
+            GetClientInfo(ref params) => {
+                w.write(PA_COMMAND_GET_CLIENT_INFO as u32);
+                w.write(self.tag);
+                params.to_tag_struct(w, protocol_version)?;
+            }
+            GetSinkInputInfo(ref params) => {
+                w.write(PA_COMMAND_GET_SINK_INPUT_INFO as u32);
+                w.write(self.tag);
+                params.to_tag_struct(w, protocol_version)?;
+            }
+            GetSourceOutputInfo(ref params) => {
+                w.write(PA_COMMAND_GET_SOURCE_OUTPUT_INFO as u32);
+                w.write(self.tag);
+                params.to_tag_struct(w, protocol_version)?;
+            }
+            GetModuleInfo(ref params) => {
+                w.write(PA_COMMAND_GET_MODULE_INFO as u32);
+                w.write(self.tag);
+                params.to_tag_struct(w, protocol_version)?;
+            }
+            Subscribe(ref params) => {
+                w.write(PA_COMMAND_SUBSCRIBE as u32);
+                w.write(self.tag);
+                params.to_tag_struct(w, protocol_version)?;
+            }
+            SubscribeEvent(ref params) => {
+                w.write(PA_COMMAND_SUBSCRIBE_EVENT as u32);
+                w.write(self.tag);
+                params.to_tag_struct(w, protocol_version)?;
+            }
+            LoadModule(ref params) => {
+                w.write(PA_COMMAND_LOAD_MODULE as u32);
+                w.write(self.tag);
+                params.to_tag_struct(w, protocol_version)?;
+            }
+            UnloadModule(ref params) => {
+                w.write(PA_COMMAND_UNLOAD_MODULE as u32);
+                w.write(self.tag);
+                params.to_tag_struct(w, protocol_version)?;
+            }
+            SetSinkVolume(ref params) => {
+                w.write(PA_COMMAND_SET_SINK_VOLUME as u32);
+                w.write(self.tag);
+                params.to_tag_struct(w, protocol_version)?;
+            }
+            SetSourceVolume(ref params) => {
+                w.write(PA_COMMAND_SET_SOURCE_VOLUME as u32);
+                w.write(self.tag);
+                params.to_tag_struct(w, protocol_version)?;
+            }
+            SetSinkInputVolume(ref params) => {
+                w.write(PA_COMMAND_SET_SINK_INPUT_VOLUME as u32);
+                w.write(self.tag);
+                params.to_tag_struct(w, protocol_version)?;
+            }
+            SetSourceOutputVolume(ref params) => {
+                w.write(PA_COMMAND_SET_SOURCE_OUTPUT_VOLUME as u32);
+                w.write(self.tag);
+                params.to_tag_struct(w, protocol_version)?;
+            }
+            SetSinkMute(ref params) => {
+                w.write(PA_COMMAND_SET_SINK_MUTE as u32);
+                w.write(self.tag);
+                params.to_tag_struct(w, protocol_version)?;
+            }
+            SetSourceMute(ref params) => {
+                w.write(PA_COMMAND_SET_SOURCE_MUTE as u32);
+                w.write(self.tag);
+                params.to_tag_struct(w, protocol_version)?;
+            }
+            SetSinkInputMute(ref params) => {
+                w.write(PA_COMMAND_SET_SINK_INPUT_MUTE as u32);
+                w.write(self.tag);
+                params.to_tag_struct(w, protocol_version)?;
+            }
+            SetSourceOutputMute(ref params) => {
+                w.write(PA_COMMAND_SET_SOURCE_OUTPUT_MUTE as u32);
+                w.write(self.tag);
+                params.to_tag_struct(w, protocol_version)?;
+            }
+            SetDefaultSink(ref params) => {
+                w.write(PA_COMMAND_SET_DEFAULT_SINK as u32);
+                w.write(self.tag);
+                params.to_tag_struct(w, protocol_version)?;
+            }
+            SetDefaultSource(ref params) => {
+                w.write(PA_COMMAND_SET_DEFAULT_SOURCE as u32);
+                w.write(self.tag);
+                params.to_tag_struct(w, protocol_version)?;
+            }
+            KillClient(ref params) => {
+                w.write(PA_COMMAND_KILL_CLIENT as u32);
+                w.write(self.tag);
+                params.to_tag_struct(w, protocol_version)?;
+            }
+            KillSinkInput(ref params) => {
+                w.write(PA_COMMAND_KILL_SINK_INPUT as u32);
+                w.write(self.tag);
+                params.to_tag_struct(w, protocol_version)?;
+            }
+            KillSourceOutput(ref params) => {
+                w.write(PA_COMMAND_KILL_SOURCE_OUTPUT as u32);
+                w.write(self.tag);
+                params.to_tag_struct(w, protocol_version)?;
+            }
+            MoveSinkInput(ref params) => {
+                w.write(PA_COMMAND_MOVE_SINK_INPUT as u32);
+                w.write(self.tag);
+                params.to_tag_struct(w, protocol_version)?;
+            }
+            MoveSourceOutput(ref params) => {
+                w.write(PA_COMMAND_MOVE_SOURCE_OUTPUT as u32);
+                w.write(self.tag);
+                params.to_tag_struct(w, protocol_version)?;
+            }
+            SuspendSink(ref params) => {
+                w.write(PA_COMMAND_SUSPEND_SINK as u32);
+                w.write(self.tag);
+                params.to_tag_struct(w, protocol_version)?;
+            }
+            SuspendSource(ref params) => {
+                w.write(PA_COMMAND_SUSPEND_SOURCE as u32);
+                w.write(self.tag);
+                params.to_tag_struct(w, protocol_version)?;
+            }
             RegisterMemfdShmid(ref params) => {
-                // XXX This needs to pass the memfd too!
+                // The memfd is attached to the resulting packet by the caller
                 w.write(PA_COMMAND_REGISTER_MEMFD_SHMID as u32);
                 w.write(self.tag);
                 params.to_tag_struct(w, protocol_version)?;
             }
+            EnableSrbchannel => {
+                // The eventfds are attached to the resulting packet by the caller
+                w.write(PA_COMMAND_ENABLE_SRBCHANNEL as u32);
+                w.write(self.tag);
+            }
+            DisableSrbchannel => {
+                w.write(PA_COMMAND_DISABLE_SRBCHANNEL as u32);
+                w.write(self.tag);
+            }
 
             Reply { ref params } => {
                 w.write(PA_COMMAND_REPLY as u32);
@@ -1,12 +1,26 @@
 use super::prelude::*;
 
-// FIXME: Figure out exact semantics of this command
-// FIXME: PA ignores this (doesn't attach the received memfd) at proto<31
+/// Announces the ID of a memfd-backed shared memory segment.
+///
+/// The memfd itself isn't part of the tagstruct. It has to be attached to the packet carrying the
+/// command (see `Packet::attach_fd`). PulseAudio ignores this command for protocol versions < 31.
 #[derive(Debug)]
 pub struct RegisterMemfdShmid {
     shmid: u32,
 }
 
+impl RegisterMemfdShmid {
+    /// Creates a command registering the segment `shmid`.
+    pub fn new(shmid: u32) -> Self {
+        Self { shmid }
+    }
+
+    /// The ID of the segment backed by the memfd sent along with the command.
+    pub fn shmid(&self) -> u32 {
+        self.shmid
+    }
+}
+
 impl<'a> FromTagStruct<'a> for RegisterMemfdShmid {
     fn from_tag_struct(ts: &mut TagStructReader<'a>, _protocol_version: u16) -> Result<Self, Error> {
         Ok(Self {
@@ -7,6 +7,11 @@ pub struct SetClientName {
 }
 
 impl SetClientName {
+    /// Creates a command that merges `props` into the client's properties.
+    pub fn new(props: PropList) -> Self {
+        Self { props }
+    }
+
     /// The properties to set.
     ///
     /// The contained proplist should be merged into the existing client properties, overwriting
@@ -32,6 +37,7 @@ impl ToTagStruct for SetClientName {
     }
 }
 
+/// Server reply to `SetClientName`, containing the client's index.
 #[derive(Debug)]
 pub struct SetClientNameReply {
     client_id: u32,
@@ -41,6 +47,20 @@ impl SetClientNameReply {
     pub fn new(client_id: u32) -> Self {
         Self { client_id }
     }
+
+    /// Index of the client on the server.
+    pub fn client_index(&self) -> u32 {
+        self.client_id
+    }
+}
+
+impl<'a> FromTagStruct<'a> for SetClientNameReply {
+    fn from_tag_struct(ts: &mut TagStructReader<'a>, _protocol_version: u16) -> Result<Self, Error> {
+        // proto>=13
+        Ok(Self {
+            client_id: ts.read_u32()?,
+        })
+    }
 }
 
 impl ToTagStruct for SetClientNameReply {
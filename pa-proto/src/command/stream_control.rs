@@ -0,0 +1,290 @@
+//! Commands operating on existing playback and record streams, and the notifications sent by the
+//! server about them.
+//!
+//! Streams are referred to by their *channel*, which the server assigns when creating the stream.
+//! The same channel is used in memblock packets carrying the stream's audio data.
+
+use super::prelude::*;
+
+use time::{Microseconds, Timeval};
+
+/// Parameters of commands that only refer to a stream.
+///
+/// Used by `DeletePlaybackStream`, `DrainPlaybackStream`, `FlushPlaybackStream` and their record
+/// counterparts, as well as the `Started`, `Overflow` and `*StreamKilled` notifications.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StreamChannel {
+    channel: u32,
+}
+
+impl StreamChannel {
+    pub fn new(channel: u32) -> Self {
+        Self { channel }
+    }
+
+    /// The channel of the stream.
+    pub fn channel(&self) -> u32 {
+        self.channel
+    }
+}
+
+impl<'a> FromTagStruct<'a> for StreamChannel {
+    fn from_tag_struct(ts: &mut TagStructReader<'a>, _protocol_version: u16) -> Result<Self, Error> {
+        Ok(Self {
+            channel: ts.read_u32()?,
+        })
+    }
+}
+
+impl ToTagStruct for StreamChannel {
+    fn to_tag_struct(&self, w: &mut TagStructWriter, _protocol_version: u16) -> Result<(), Error> {
+        w.write(self.channel);
+        Ok(())
+    }
+}
+
+/// Parameters of `CorkPlaybackStream` and `CorkRecordStream`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CorkStream {
+    channel: u32,
+    cork: bool,
+}
+
+impl CorkStream {
+    pub fn new(channel: u32, cork: bool) -> Self {
+        Self { channel, cork }
+    }
+
+    /// The channel of the stream.
+    pub fn channel(&self) -> u32 {
+        self.channel
+    }
+
+    /// Whether to pause (`true`) or resume (`false`) the stream.
+    pub fn cork(&self) -> bool {
+        self.cork
+    }
+}
+
+impl<'a> FromTagStruct<'a> for CorkStream {
+    fn from_tag_struct(ts: &mut TagStructReader<'a>, _protocol_version: u16) -> Result<Self, Error> {
+        Ok(Self {
+            channel: ts.read_u32()?,
+            cork: ts.read_bool()?,
+        })
+    }
+}
+
+impl ToTagStruct for CorkStream {
+    fn to_tag_struct(&self, w: &mut TagStructWriter, _protocol_version: u16) -> Result<(), Error> {
+        w.write(self.channel);
+        w.write(self.cork);
+        Ok(())
+    }
+}
+
+/// Server request for more playback data.
+///
+/// The client should write `bytes` more bytes to the stream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Request {
+    channel: u32,
+    bytes: u32,
+}
+
+impl Request {
+    pub fn new(channel: u32, bytes: u32) -> Self {
+        Self { channel, bytes }
+    }
+
+    /// The channel of the playback stream.
+    pub fn channel(&self) -> u32 {
+        self.channel
+    }
+
+    /// Number of bytes requested.
+    pub fn bytes(&self) -> u32 {
+        self.bytes
+    }
+}
+
+impl<'a> FromTagStruct<'a> for Request {
+    fn from_tag_struct(ts: &mut TagStructReader<'a>, _protocol_version: u16) -> Result<Self, Error> {
+        Ok(Self {
+            channel: ts.read_u32()?,
+            bytes: ts.read_u32()?,
+        })
+    }
+}
+
+impl ToTagStruct for Request {
+    fn to_tag_struct(&self, w: &mut TagStructWriter, _protocol_version: u16) -> Result<(), Error> {
+        w.write(self.channel);
+        w.write(self.bytes);
+        Ok(())
+    }
+}
+
+/// Notification that a playback stream ran out of data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Underflow {
+    channel: u32,
+    offset: Option<i64>,
+}
+
+impl Underflow {
+    pub fn new(channel: u32, offset: i64) -> Self {
+        Self {
+            channel,
+            offset: Some(offset),
+        }
+    }
+
+    /// The channel of the playback stream.
+    pub fn channel(&self) -> u32 {
+        self.channel
+    }
+
+    /// Read index at which the underflow occurred (proto>=23).
+    pub fn offset(&self) -> Option<i64> {
+        self.offset
+    }
+}
+
+impl<'a> FromTagStruct<'a> for Underflow {
+    fn from_tag_struct(ts: &mut TagStructReader<'a>, protocol_version: u16) -> Result<Self, Error> {
+        Ok(Self {
+            channel: ts.read_u32()?,
+            offset: if protocol_version >= 23 {
+                Some(ts.read_i64()?)
+            } else {
+                None
+            },
+        })
+    }
+}
+
+impl ToTagStruct for Underflow {
+    fn to_tag_struct(&self, w: &mut TagStructWriter, protocol_version: u16) -> Result<(), Error> {
+        w.write(self.channel);
+        if protocol_version >= 23 {
+            w.write(self.offset.unwrap_or(0));
+        }
+        Ok(())
+    }
+}
+
+/// Parameters of `GetPlaybackLatency` and `GetRecordLatency`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GetLatency {
+    channel: u32,
+    local_time: Timeval,
+}
+
+impl GetLatency {
+    /// Creates a latency query for the stream on `channel`, timestamped with the current time.
+    pub fn new(channel: u32) -> Self {
+        Self {
+            channel,
+            local_time: Timeval::now(),
+        }
+    }
+
+    /// The channel of the stream.
+    pub fn channel(&self) -> u32 {
+        self.channel
+    }
+
+    /// Client time at which the query was sent, echoed back in the reply.
+    pub fn local_time(&self) -> Timeval {
+        self.local_time
+    }
+}
+
+impl<'a> FromTagStruct<'a> for GetLatency {
+    fn from_tag_struct(ts: &mut TagStructReader<'a>, _protocol_version: u16) -> Result<Self, Error> {
+        Ok(Self {
+            channel: ts.read_u32()?,
+            local_time: ts.read_timeval()?,
+        })
+    }
+}
+
+impl ToTagStruct for GetLatency {
+    fn to_tag_struct(&self, w: &mut TagStructWriter, _protocol_version: u16) -> Result<(), Error> {
+        w.write(self.channel);
+        w.write(self.local_time);
+        Ok(())
+    }
+}
+
+/// Server reply to `GetPlaybackLatency` and `GetRecordLatency`.
+#[derive(Debug, Clone)]
+pub struct LatencyInfo {
+    /// Latency of the sink (for playback) or the monitored sink (for record streams).
+    pub sink_usec: Microseconds,
+    /// Latency of the source (always 0 for playback streams).
+    pub source_usec: Microseconds,
+    /// Whether the stream is currently playing or recording.
+    pub playing: bool,
+    /// Client time at which the query was sent.
+    pub local_time: Timeval,
+    /// Server time at which the reply was sent.
+    pub remote_time: Timeval,
+    /// Write index into the stream buffer, in bytes.
+    pub write_index: i64,
+    /// Read index into the stream buffer, in bytes.
+    pub read_index: i64,
+    /// Bytes the playback stream has been underrunning for (playback only).
+    pub underrun_for: Option<u64>,
+    /// Bytes the playback stream has been playing for (playback only).
+    pub playing_for: Option<u64>,
+}
+
+impl LatencyInfo {
+    /// Estimates the one-way transport delay from the query's round-trip time.
+    pub fn transport_usec(&self, received: Timeval) -> Microseconds {
+        Microseconds(received.micros_since(self.local_time) / 2)
+    }
+}
+
+impl<'a> FromTagStruct<'a> for LatencyInfo {
+    fn from_tag_struct(ts: &mut TagStructReader<'a>, _protocol_version: u16) -> Result<Self, Error> {
+        let sink_usec = ts.read_usec()?;
+        let source_usec = ts.read_usec()?;
+        let playing = ts.read_bool()?;
+        let local_time = ts.read_timeval()?;
+        let remote_time = ts.read_timeval()?;
+        let write_index = ts.read_i64()?;
+        let read_index = ts.read_i64()?;
+
+        // Only playback latency replies carry these (proto>=13)
+        let (underrun_for, playing_for) = if ts.is_at_end() {
+            (None, None)
+        } else {
+            (Some(ts.read_u64()?), Some(ts.read_u64()?))
+        };
+
+        Ok(Self {
+            sink_usec, source_usec, playing, local_time, remote_time, write_index, read_index,
+            underrun_for, playing_for,
+        })
+    }
+}
+
+impl ToTagStruct for LatencyInfo {
+    fn to_tag_struct(&self, w: &mut TagStructWriter, _protocol_version: u16) -> Result<(), Error> {
+        w.write(self.sink_usec);
+        w.write(self.source_usec);
+        w.write(self.playing);
+        w.write(self.local_time);
+        w.write(self.remote_time);
+        w.write(self.write_index);
+        w.write(self.read_index);
+        if let (Some(underrun_for), Some(playing_for)) = (self.underrun_for, self.playing_for) {
+            w.write(underrun_for);
+            w.write(playing_for);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,167 @@
+//! The `SUBSCRIBE` command and the `SUBSCRIBE_EVENT` notifications sent in response.
+//!
+//! A client subscribes to changes of certain kinds of objects (facilities) by sending a mask. From
+//! then on, the server notifies it whenever an object of a subscribed facility is created, changed
+//! or removed. The notification only contains the object's index; its new state has to be queried
+//! separately.
+
+use super::prelude::*;
+
+use num_traits::FromPrimitive;
+
+bitflags! {
+    /// The kinds of objects to receive events about.
+    pub struct SubscriptionMask: u32 {
+        const SINK = 0x0001;
+        const SOURCE = 0x0002;
+        const SINK_INPUT = 0x0004;
+        const SOURCE_OUTPUT = 0x0008;
+        const MODULE = 0x0010;
+        const CLIENT = 0x0020;
+        const SAMPLE_CACHE = 0x0040;
+        /// Changes to the server itself (eg. the default sink).
+        const SERVER = 0x0080;
+        const CARD = 0x0200;
+        const ALL = 0x02ff;
+    }
+}
+
+/// The kind of object an event refers to.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, FromPrimitive)]
+pub enum Facility {
+    Sink = 0,
+    Source = 1,
+    SinkInput = 2,
+    SourceOutput = 3,
+    Module = 4,
+    Client = 5,
+    SampleCache = 6,
+    Server = 7,
+    Card = 9,
+}
+
+impl Facility {
+    /// Returns the subscription mask selecting events of this facility.
+    pub fn mask(&self) -> SubscriptionMask {
+        SubscriptionMask::from_bits_truncate(1 << *self as u32)
+    }
+}
+
+/// What happened to the object an event refers to.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, FromPrimitive)]
+pub enum EventType {
+    /// The object was created.
+    New = 0x00,
+    /// Some property of the object changed.
+    Change = 0x10,
+    /// The object was removed.
+    Remove = 0x20,
+}
+
+const FACILITY_MASK: u32 = 0x0f;
+const TYPE_MASK: u32 = 0x30;
+
+/// Parameters of the `Subscribe` command.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Subscribe {
+    mask: SubscriptionMask,
+}
+
+impl Subscribe {
+    /// Subscribes to events about the objects selected by `mask`.
+    ///
+    /// This replaces any previous subscription of the client. An empty mask unsubscribes from all
+    /// events.
+    pub fn new(mask: SubscriptionMask) -> Self {
+        Self { mask }
+    }
+
+    pub fn mask(&self) -> SubscriptionMask {
+        self.mask
+    }
+}
+
+impl<'a> FromTagStruct<'a> for Subscribe {
+    fn from_tag_struct(ts: &mut TagStructReader<'a>, _protocol_version: u16) -> Result<Self, Error> {
+        Ok(Self {
+            mask: SubscriptionMask::from_bits_truncate(ts.read_u32()?),
+        })
+    }
+}
+
+impl ToTagStruct for Subscribe {
+    fn to_tag_struct(&self, w: &mut TagStructWriter, _protocol_version: u16) -> Result<(), Error> {
+        w.write(self.mask.bits());
+        Ok(())
+    }
+}
+
+/// Notification about an object of a subscribed facility (server to client).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SubscriptionEvent {
+    facility: Facility,
+    event_type: EventType,
+    index: u32,
+}
+
+impl SubscriptionEvent {
+    pub fn new(facility: Facility, event_type: EventType, index: u32) -> Self {
+        Self { facility, event_type, index }
+    }
+
+    /// The kind of object that changed.
+    pub fn facility(&self) -> Facility {
+        self.facility
+    }
+
+    /// Whether the object was created, changed or removed.
+    pub fn event_type(&self) -> EventType {
+        self.event_type
+    }
+
+    /// Index of the object.
+    ///
+    /// This is meaningless for `Facility::Server`, which only has a single object.
+    pub fn index(&self) -> u32 {
+        self.index
+    }
+}
+
+impl<'a> FromTagStruct<'a> for SubscriptionEvent {
+    fn from_tag_struct(ts: &mut TagStructReader<'a>, _protocol_version: u16) -> Result<Self, Error> {
+        let event = ts.read_u32()?;
+        let facility = Facility::from_u32(event & FACILITY_MASK)
+            .ok_or_else(|| Error::string(format!("invalid subscription event facility in {:#x}", event)))?;
+        let event_type = EventType::from_u32(event & TYPE_MASK)
+            .ok_or_else(|| Error::string(format!("invalid subscription event type in {:#x}", event)))?;
+
+        Ok(Self {
+            facility,
+            event_type,
+            index: ts.read_u32()?,
+        })
+    }
+}
+
+impl ToTagStruct for SubscriptionEvent {
+    fn to_tag_struct(&self, w: &mut TagStructWriter, _protocol_version: u16) -> Result<(), Error> {
+        w.write(self.facility as u32 | self.event_type as u32);
+        w.write(self.index);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn event_roundtrip() {
+        let event = SubscriptionEvent::new(Facility::Card, EventType::Remove, 7);
+        let mut buf = Vec::new();
+        event.to_tag_struct(&mut TagStructWriter::new(&mut buf), 32).unwrap();
+        let parsed = SubscriptionEvent::from_tag_struct(&mut TagStructReader::from_raw(&buf), 32).unwrap();
+        assert_eq!(parsed, event);
+        assert_eq!(parsed.facility().mask(), SubscriptionMask::CARD);
+    }
+}
@@ -0,0 +1,202 @@
+//! The `CREATE_UPLOAD_STREAM`, `FINISH_UPLOAD_STREAM` and `REMOVE_SAMPLE` commands, which manage
+//! the server's sample cache.
+//!
+//! `DELETE_UPLOAD_STREAM` takes a `StreamChannel`.
+
+use super::prelude::*;
+
+use std::ffi::CStr;
+
+/// Parameters of `CreateUploadStream`, which creates a stream for uploading a sample into the
+/// sample cache.
+///
+/// The sample is added to the cache once all `length` bytes were sent and the stream is finished
+/// with `FinishUploadStream`.
+#[derive(Debug)]
+pub struct CreateUploadStream<'a> {
+    name: Option<&'a CStr>,
+    sample_spec: SampleSpec,
+    channel_map: ChannelMap,
+    length: u32,
+    props: PropList,
+}
+
+impl<'a> CreateUploadStream<'a> {
+    /// Creates a request for an upload stream for a sample of `length` bytes.
+    pub fn new(name: &'a PaStr, sample_spec: SampleSpec, channel_map: ChannelMap, length: u32, props: PropList) -> Self {
+        Self {
+            name: Some(name.as_cstr()),
+            sample_spec,
+            channel_map,
+            length,
+            props,
+        }
+    }
+
+    /// Name of the sample.
+    ///
+    /// If the client didn't set a name, it's taken from the `EventId` or `MediaName` property.
+    pub fn name(&self) -> Option<&PaStr> {
+        match self.name {
+            Some(name) => Some(name.into()),
+            None => self.props.get_string(Prop::EventId)
+                .or_else(|| self.props.get_string(Prop::MediaName)),
+        }
+    }
+
+    pub fn sample_spec(&self) -> &SampleSpec {
+        &self.sample_spec
+    }
+
+    pub fn channel_map(&self) -> &ChannelMap {
+        &self.channel_map
+    }
+
+    /// Size of the sample in bytes.
+    pub fn length(&self) -> u32 {
+        self.length
+    }
+
+    /// Properties of the sample.
+    pub fn props(&self) -> &PropList {
+        &self.props
+    }
+}
+
+impl<'a> FromTagStruct<'a> for CreateUploadStream<'a> {
+    fn from_tag_struct(ts: &mut TagStructReader<'a>, _protocol_version: u16) -> Result<Self, Error> {
+        Ok(Self {
+            name: ts.read_string()?,
+            sample_spec: ts.read_sample_spec()?,
+            channel_map: ts.read_channel_map()?,
+            length: ts.read_u32()?,
+            // proto>=13
+            props: ts.read_proplist()?,
+        })
+    }
+}
+
+impl<'a> ToTagStruct for CreateUploadStream<'a> {
+    fn to_tag_struct(&self, w: &mut TagStructWriter, _protocol_version: u16) -> Result<(), Error> {
+        w.write(self.name.map(<&PaStr>::from));
+        w.write(&self.sample_spec);
+        w.write(&self.channel_map);
+        w.write(self.length);
+        w.write(&self.props);
+        Ok(())
+    }
+}
+
+/// Server reply to `CreateUploadStream`.
+#[derive(Debug, Clone)]
+pub struct CreateUploadStreamReply {
+    /// Channel used to refer to the stream in later commands and memblocks.
+    pub channel: u32,
+    /// Number of bytes the server expects.
+    pub length: u32,
+}
+
+impl ToTagStruct for CreateUploadStreamReply {
+    fn to_tag_struct(&self, w: &mut TagStructWriter, _protocol_version: u16) -> Result<(), Error> {
+        w.write(self.channel);
+        w.write(self.length);
+        Ok(())
+    }
+}
+
+impl<'a> FromTagStruct<'a> for CreateUploadStreamReply {
+    fn from_tag_struct(ts: &mut TagStructReader<'a>, _protocol_version: u16) -> Result<Self, Error> {
+        Ok(Self {
+            channel: ts.read_u32()?,
+            length: ts.read_u32()?,
+        })
+    }
+}
+
+/// Server reply to `FinishUploadStream`, containing the index of the new sample (proto>=13).
+#[derive(Debug, Clone)]
+pub struct FinishUploadStreamReply {
+    pub sample_index: u32,
+}
+
+impl ToTagStruct for FinishUploadStreamReply {
+    fn to_tag_struct(&self, w: &mut TagStructWriter, _protocol_version: u16) -> Result<(), Error> {
+        w.write(self.sample_index);
+        Ok(())
+    }
+}
+
+impl<'a> FromTagStruct<'a> for FinishUploadStreamReply {
+    fn from_tag_struct(ts: &mut TagStructReader<'a>, _protocol_version: u16) -> Result<Self, Error> {
+        Ok(Self {
+            sample_index: ts.read_u32()?,
+        })
+    }
+}
+
+/// Parameters of `RemoveSample`, which removes a sample from the sample cache.
+#[derive(Debug)]
+pub struct RemoveSample<'a> {
+    name: &'a CStr,
+}
+
+impl<'a> RemoveSample<'a> {
+    pub fn new(name: &'a PaStr) -> Self {
+        Self { name: name.as_cstr() }
+    }
+
+    /// Name of the sample to remove.
+    pub fn name(&self) -> &'a PaStr {
+        self.name.into()
+    }
+}
+
+impl<'a> FromTagStruct<'a> for RemoveSample<'a> {
+    fn from_tag_struct(ts: &mut TagStructReader<'a>, _protocol_version: u16) -> Result<Self, Error> {
+        Ok(Self {
+            name: ts.read_string_non_null()?,
+        })
+    }
+}
+
+impl<'a> ToTagStruct for RemoveSample<'a> {
+    fn to_tag_struct(&self, w: &mut TagStructWriter, _protocol_version: u16) -> Result<(), Error> {
+        w.write(self.name());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sample_spec::SampleFormat;
+
+    #[test]
+    fn sample_name() {
+        let spec = SampleSpec::new_checked(SampleFormat::S16Le, 1, 44100).unwrap();
+        let map = ChannelMap::default_for(1).unwrap();
+        let name = PaStr::from_bytes_with_nul(b"bell\0").unwrap();
+        let mut props = PropList::new();
+        props.set(Prop::EventId, "bell-window-system\0");
+
+        let mut buf = Vec::new();
+        CreateUploadStream::new(name, spec.clone(), map.clone(), 4410, props.clone())
+            .to_tag_struct(&mut TagStructWriter::new(&mut buf), 32).unwrap();
+        let parsed = CreateUploadStream::from_tag_struct(&mut TagStructReader::from_raw(&buf), 32).unwrap();
+        assert_eq!(parsed.name(), Some(name));
+        assert_eq!(parsed.length(), 4410);
+
+        // Without a name, the sample is named after its event ID
+        let mut buf = Vec::new();
+        {
+            let mut w = TagStructWriter::new(&mut buf);
+            w.write(None::<&PaStr>);
+            w.write(&spec);
+            w.write(&map);
+            w.write(4410u32);
+            w.write(&props);
+        }
+        let parsed = CreateUploadStream::from_tag_struct(&mut TagStructReader::from_raw(&buf), 32).unwrap();
+        assert_eq!(parsed.name().unwrap().to_str().unwrap(), "bell-window-system");
+    }
+}
@@ -0,0 +1,751 @@
+//! Parser for PulseAudio's configuration files (`client.conf`, `daemon.conf`).
+//!
+//! These files consist of `key = value` lines. Empty lines and lines starting with `#` or `;` are
+//! ignored.
+//!
+//! A configuration is assembled from several files, each overriding the values of the previous
+//! ones (see `ConfFile::load_layered`). `ClientConf` and `DaemonConf` interpret the result and
+//! apply the overrides from environment variables.
+
+use channel_map::ChannelMap;
+use error::Error;
+use modargs::parse_bool;
+use paths;
+use sample_spec::{SampleFormat, SampleSpec};
+
+use log::LevelFilter;
+
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+use std::{env, fmt, fs};
+use std::io;
+
+/// Key-value pairs read from a configuration file.
+#[derive(Debug, Clone, Default)]
+pub struct ConfFile {
+    values: BTreeMap<String, String>,
+}
+
+impl ConfFile {
+    /// Parses the contents of a configuration file.
+    ///
+    /// If a key appears more than once, the last value wins.
+    pub fn parse(s: &str) -> Result<Self, Error> {
+        let mut values = BTreeMap::new();
+        for (i, line) in s.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+                continue;
+            }
+
+            let mut split = line.splitn(2, '=');
+            match (split.next().map(str::trim), split.next().map(str::trim)) {
+                (Some(key), Some(value)) if !key.is_empty() => {
+                    values.insert(key.to_string(), value.to_string());
+                }
+                _ => return Err(Error::string(format!("line {}: expected 'key = value'", i + 1))),
+            }
+        }
+
+        Ok(Self { values })
+    }
+
+    /// Reads and parses the configuration file at `path`.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let contents = fs::read_to_string(path)?;
+        Self::parse(&contents)
+            .map_err(|e| Error::string(format!("{}: {}", path.display(), e)))
+    }
+
+    /// Reads the configuration file at `path`, returning `None` if it doesn't exist.
+    fn load_optional(path: &Path) -> Result<Option<Self>, Error> {
+        match fs::metadata(path) {
+            Ok(_) => Self::load(path).map(Some),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Loads the configuration file `name` (eg. `client.conf`) from all standard locations.
+    ///
+    /// The following files are read in order, with values in later files overriding earlier ones:
+    ///
+    /// * `name` in `paths::SYSTEM_CONFIG_DIR`
+    /// * All `*.conf` files in the system-wide `name.d` directory, in lexical order
+    /// * `name` in `paths::config_home_dir()`
+    /// * All `*.conf` files in the user's `name.d` directory, in lexical order
+    ///
+    /// Files that don't exist are skipped. If none exist, an empty configuration is returned.
+    pub fn load_layered(name: &str) -> Result<Self, Error> {
+        let mut conf = Self::default();
+        for dir in &[PathBuf::from(paths::SYSTEM_CONFIG_DIR), paths::config_home_dir()] {
+            let path = dir.join(name);
+            if let Some(file) = Self::load_optional(&path)? {
+                debug!("read {}", path.display());
+                conf.merge(file);
+            }
+
+            for path in drop_ins(&dir.join(format!("{}.d", name)))? {
+                debug!("read {}", path.display());
+                conf.merge(Self::load(&path)?);
+            }
+        }
+
+        Ok(conf)
+    }
+
+    /// Merges the values of `other` into `self`, overriding existing values.
+    pub fn merge(&mut self, other: ConfFile) {
+        self.values.extend(other.values);
+    }
+
+    /// Returns the value of `key`, or `None` if it isn't set.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.values.get(key).map(|s| &**s)
+    }
+
+    /// Returns an iterator over all keys that are set.
+    pub fn keys(&self) -> impl Iterator<Item=&str> {
+        self.values.keys().map(|key| &**key)
+    }
+
+    /// Parses the value of `key` using `parse`.
+    ///
+    /// `expected` describes valid values for the error message returned if `parse` fails.
+    fn get_with<T, F>(&self, key: &str, expected: &str, parse: F) -> Result<Option<T>, Error>
+    where F: FnOnce(&str) -> Option<T> {
+        match self.get(key) {
+            Some(value) => parse(value).map(Some).ok_or_else(|| Error::string(format!(
+                "invalid value '{}' for '{}' (expected {})", value, key, expected
+            ))),
+            None => Ok(None),
+        }
+    }
+
+    fn get_bool(&self, key: &str) -> Result<Option<bool>, Error> {
+        self.get_with(key, "a boolean", parse_bool)
+    }
+
+    fn get_string(&self, key: &str) -> Option<String> {
+        self.get(key).filter(|value| !value.is_empty()).map(String::from)
+    }
+
+    /// Warns about keys that are set but not in `known`.
+    fn warn_unknown(&self, name: &str, known: &[&str]) {
+        for key in self.keys().filter(|key| !known.contains(key)) {
+            warn!("{}: ignoring unknown key '{}'", name, key);
+        }
+    }
+}
+
+/// Returns the `*.conf` files in `dir`, sorted by name.
+///
+/// A missing directory is treated as empty.
+fn drop_ins(dir: &Path) -> Result<Vec<PathBuf>, Error> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+
+    let mut paths = Vec::new();
+    for entry in entries {
+        let path = entry?.path();
+        if path.extension().map_or(false, |ext| ext == "conf") {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+    Ok(paths)
+}
+
+/// Loads the file named by the environment variable `var` if it is set, or the layered
+/// configuration `name` otherwise.
+fn load_conf(var: &str, name: &str) -> Result<ConfFile, Error> {
+    match env::var_os(var) {
+        Some(path) => {
+            debug!("{} is set, only reading {}", var, Path::new(&path).display());
+            ConfFile::load(path)
+        }
+        None => ConfFile::load_layered(name),
+    }
+}
+
+fn env_string(var: &str) -> Option<String> {
+    env::var(var).ok().filter(|value| !value.is_empty())
+}
+
+const CLIENT_KEYS: &[&str] = &[
+    "default-server",
+    "default-sink",
+    "default-source",
+    "cookie",
+    "cookie-file",
+    "autospawn",
+    "daemon-binary",
+    "extra-arguments",
+    "enable-shm",
+    "enable-memfd",
+];
+
+/// Client configuration, read from `client.conf`.
+#[derive(Debug, Clone)]
+pub struct ClientConf {
+    /// Servers to connect to, as a server string (see the `server_string` module).
+    pub default_server: Option<String>,
+    /// Sink to connect playback streams to if the application doesn't choose one.
+    pub default_sink: Option<String>,
+    /// Source to connect record streams to if the application doesn't choose one.
+    pub default_source: Option<String>,
+    /// The hex-encoded auth cookie.
+    pub cookie: Option<String>,
+    /// File to load the auth cookie from. Relative paths are relative to
+    /// `paths::config_home_dir()`.
+    pub cookie_file: Option<PathBuf>,
+    /// Whether to start a server if none is running.
+    pub autospawn: bool,
+    /// The server to start when autospawning. Looked up in `$PATH` if it's just a name.
+    pub daemon_binary: PathBuf,
+    /// Arguments to pass to the server when autospawning, in addition to `--start`.
+    pub extra_arguments: Vec<String>,
+    /// Whether to use shared memory for audio data, if the server allows it.
+    pub enable_shm: bool,
+    /// Whether to use `memfd`-backed shared memory (requires `enable_shm`).
+    pub enable_memfd: bool,
+}
+
+impl Default for ClientConf {
+    fn default() -> Self {
+        Self {
+            default_server: None,
+            default_sink: None,
+            default_source: None,
+            cookie: None,
+            cookie_file: None,
+            autospawn: true,
+            daemon_binary: PathBuf::from("pulsar-server"),
+            extra_arguments: Vec::new(),
+            enable_shm: true,
+            enable_memfd: true,
+        }
+    }
+}
+
+impl ClientConf {
+    /// Loads the client configuration.
+    ///
+    /// If the `PULSE_CLIENTCONFIG` environment variable is set, only the file it points to is
+    /// read. Otherwise, `client.conf` is read from all standard locations (see
+    /// `ConfFile::load_layered`).
+    ///
+    /// Afterwards, the `PULSE_SERVER`, `PULSE_SINK`, `PULSE_SOURCE` and `PULSE_COOKIE` environment
+    /// variables override `default-server`, `default-sink`, `default-source` and `cookie-file`,
+    /// respectively.
+    pub fn load() -> Result<Self, Error> {
+        let mut conf = Self::from_conf(&load_conf("PULSE_CLIENTCONFIG", "client.conf")?)?;
+        conf.apply_env(|var| env::var_os(var));
+        Ok(conf)
+    }
+
+    /// Interprets the values of a `client.conf` file. Unset values keep their defaults.
+    pub fn from_conf(file: &ConfFile) -> Result<Self, Error> {
+        file.warn_unknown("client.conf", CLIENT_KEYS);
+
+        let defaults = Self::default();
+        Ok(Self {
+            default_server: file.get_string("default-server"),
+            default_sink: file.get_string("default-sink"),
+            default_source: file.get_string("default-source"),
+            cookie: file.get_string("cookie"),
+            cookie_file: file.get_string("cookie-file").map(PathBuf::from),
+            autospawn: file.get_bool("autospawn")?.unwrap_or(defaults.autospawn),
+            daemon_binary: file.get_string("daemon-binary").map(PathBuf::from)
+                .unwrap_or(defaults.daemon_binary),
+            extra_arguments: file.get("extra-arguments")
+                .map(|args| args.split_whitespace().map(String::from).collect())
+                .unwrap_or(defaults.extra_arguments),
+            enable_shm: file.get_bool("enable-shm")?.unwrap_or(defaults.enable_shm),
+            enable_memfd: file.get_bool("enable-memfd")?.unwrap_or(defaults.enable_memfd),
+        })
+    }
+
+    /// Applies the overrides from the environment variables, which are looked up using `var`.
+    fn apply_env<F>(&mut self, var: F)
+    where F: Fn(&str) -> Option<OsString> {
+        let string = |name| var(name).and_then(|value| value.into_string().ok()).filter(|value| !value.is_empty());
+        if let Some(server) = string("PULSE_SERVER") {
+            self.default_server = Some(server);
+        }
+        if let Some(sink) = string("PULSE_SINK") {
+            self.default_sink = Some(sink);
+        }
+        if let Some(source) = string("PULSE_SOURCE") {
+            self.default_source = Some(source);
+        }
+        if let Some(path) = var("PULSE_COOKIE") {
+            // Takes precedence over a cookie set in the file
+            self.cookie = None;
+            self.cookie_file = Some(PathBuf::from(path));
+        }
+    }
+}
+
+/// Resampling algorithms selectable with `resample-method`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ResampleMethod {
+    SrcSincBestQuality,
+    SrcSincMediumQuality,
+    SrcSincFastest,
+    SrcZeroOrderHold,
+    SrcLinear,
+    Trivial,
+    /// Speex floating point resampler with the given quality (0-10).
+    SpeexFloat(u8),
+    /// Speex fixed point resampler with the given quality (0-10).
+    SpeexFixed(u8),
+    Ffmpeg,
+    Auto,
+    Copy,
+    Peaks,
+    SoxrMq,
+    SoxrHq,
+    SoxrVhq,
+}
+
+const RESAMPLE_METHODS: &[(&str, ResampleMethod)] = &[
+    ("src-sinc-best-quality", ResampleMethod::SrcSincBestQuality),
+    ("src-sinc-medium-quality", ResampleMethod::SrcSincMediumQuality),
+    ("src-sinc-fastest", ResampleMethod::SrcSincFastest),
+    ("src-zero-order-hold", ResampleMethod::SrcZeroOrderHold),
+    ("src-linear", ResampleMethod::SrcLinear),
+    ("trivial", ResampleMethod::Trivial),
+    ("ffmpeg", ResampleMethod::Ffmpeg),
+    ("auto", ResampleMethod::Auto),
+    ("copy", ResampleMethod::Copy),
+    ("peaks", ResampleMethod::Peaks),
+    ("soxr-mq", ResampleMethod::SoxrMq),
+    ("soxr-hq", ResampleMethod::SoxrHq),
+    ("soxr-vhq", ResampleMethod::SoxrVhq),
+];
+
+/// Highest quality of the Speex resamplers.
+const SPEEX_QUALITY_MAX: u8 = 10;
+
+impl FromStr for ResampleMethod {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, ()> {
+        let speex = |quality: &str| match quality.parse() {
+            Ok(quality) if quality <= SPEEX_QUALITY_MAX => Ok(quality),
+            _ => Err(()),
+        };
+        // Plain `speex-float`/`speex-fixed` use the default quality
+        match s {
+            "speex-float" => return Ok(ResampleMethod::SpeexFloat(1)),
+            "speex-fixed" => return Ok(ResampleMethod::SpeexFixed(1)),
+            _ if s.starts_with("speex-float-") => return speex(&s[12..]).map(ResampleMethod::SpeexFloat),
+            _ if s.starts_with("speex-fixed-") => return speex(&s[12..]).map(ResampleMethod::SpeexFixed),
+            _ => {}
+        }
+
+        RESAMPLE_METHODS.iter()
+            .find(|&&(name, _)| name == s)
+            .map(|&(_, method)| method)
+            .ok_or(())
+    }
+}
+
+impl fmt::Display for ResampleMethod {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ResampleMethod::SpeexFloat(quality) => write!(f, "speex-float-{}", quality),
+            ResampleMethod::SpeexFixed(quality) => write!(f, "speex-fixed-{}", quality),
+            method => {
+                let &(name, _) = RESAMPLE_METHODS.iter().find(|&&(_, m)| m == method).unwrap();
+                f.write_str(name)
+            }
+        }
+    }
+}
+
+/// Parses a PulseAudio log level (`debug`, `info`, `notice`, `warning`, `error` or `0`-`4`).
+///
+/// Since `log` has no equivalent of `notice`, it is treated like `info`.
+pub fn parse_log_level(s: &str) -> Option<LevelFilter> {
+    Some(match &*s.to_ascii_lowercase() {
+        "debug" | "d" | "4" => LevelFilter::Debug,
+        "info" | "i" | "3" | "notice" | "n" | "2" => LevelFilter::Info,
+        "warning" | "warn" | "w" | "1" => LevelFilter::Warn,
+        "error" | "err" | "e" | "0" => LevelFilter::Error,
+        _ => return None,
+    })
+}
+
+const DAEMON_KEYS: &[&str] = &[
+    "default-sample-format",
+    "default-sample-rate",
+    "default-sample-channels",
+    "default-channel-map",
+    "resample-method",
+    "exit-idle-time",
+    "disallow-exit",
+    "max-connections",
+    "max-streams-per-client",
+    "max-sample-cache-per-client",
+    "max-pending-requests",
+    "max-queued-messages",
+    "default-fragments",
+    "default-fragment-size-msec",
+    "flat-volumes",
+    "log-level",
+    "log-filters",
+    "enable-shm",
+    "enable-memfd",
+];
+
+/// `daemon.conf` keys that are accepted for compatibility with PulseAudio, but have no effect yet.
+const UNSUPPORTED_DAEMON_KEYS: &[&str] = &[
+    "resample-method",
+    "default-fragments",
+    "default-fragment-size-msec",
+    "flat-volumes",
+];
+
+/// Smallest allowed `DaemonConf::max_queued_messages`. Even well-behaved clients would be
+/// disconnected by a smaller queue when a few events are posted while their replies are waiting.
+pub const MIN_QUEUED_MESSAGES: usize = 16;
+
+/// Server configuration, read from `daemon.conf`.
+#[derive(Debug, Clone)]
+pub struct DaemonConf {
+    /// Sample spec of sinks and sources that don't configure their own.
+    pub default_sample_spec: SampleSpec,
+    /// Channel map of sinks and sources that don't configure their own. Always matches the
+    /// channel count of `default_sample_spec`.
+    pub default_channel_map: ChannelMap,
+    /// Resampler used for streams whose sample spec differs from their device's.
+    ///
+    /// Not supported yet: streams are never resampled.
+    pub resample_method: ResampleMethod,
+    /// Time after the last client disconnected until the server exits. `None` if the server
+    /// should not exit when idle.
+    pub exit_idle_time: Option<Duration>,
+    /// Whether clients are forbidden from shutting down the server.
+    pub disallow_exit: bool,
+    /// Maximum number of clients connected at the same time. Further connections are refused.
+    pub max_connections: usize,
+    /// Maximum number of playback and record streams a single client may open.
+    pub max_streams_per_client: usize,
+    /// Maximum number of Bytes a single client may upload into the sample cache.
+    pub max_sample_cache_per_client: usize,
+    /// Maximum number of requests of a single client whose replies weren't sent yet. Further
+    /// requests are refused with `PulseError::TooLarge` until the client has read its replies.
+    pub max_pending_requests: usize,
+    /// Maximum number of messages waiting to be sent to a single client. Clients that don't read
+    /// their messages are disconnected once their queue is full.
+    ///
+    /// Smaller values than `MIN_QUEUED_MESSAGES` are treated as `MIN_QUEUED_MESSAGES`.
+    pub max_queued_messages: usize,
+    /// Number of fragments the buffers of devices are split into.
+    ///
+    /// Not supported yet: the null sink and source don't use buffers.
+    pub default_fragments: u32,
+    /// Length of a single fragment.
+    ///
+    /// Not supported yet, like `default_fragments`.
+    pub default_fragment_size: Duration,
+    /// Whether sink volumes follow the loudest connected stream.
+    ///
+    /// Not supported yet: sink and stream volumes are always independent.
+    pub flat_volumes: bool,
+    pub log_level: LevelFilter,
+    /// Log levels of individual modules, in `env_logger` syntax (eg. `pulsar::server=debug`).
+    pub log_filters: Option<String>,
+    /// Whether to offer shared memory to clients.
+    pub enable_shm: bool,
+    /// Whether to offer `memfd`-backed shared memory to clients (requires `enable_shm`).
+    pub enable_memfd: bool,
+}
+
+impl Default for DaemonConf {
+    fn default() -> Self {
+        Self {
+            default_sample_spec: SampleSpec::new_checked(SampleFormat::Float32Le, 2, 48000).unwrap(),
+            default_channel_map: ChannelMap::default_for(2).unwrap(),
+            resample_method: ResampleMethod::SpeexFloat(1),
+            exit_idle_time: Some(Duration::from_secs(20)),
+            disallow_exit: false,
+            max_connections: 64,
+            max_streams_per_client: 32,
+            max_sample_cache_per_client: 16 * 1024 * 1024,
+            max_pending_requests: 64,
+            max_queued_messages: 256,
+            default_fragments: 4,
+            default_fragment_size: Duration::from_millis(25),
+            flat_volumes: false,
+            log_level: LevelFilter::Info,
+            log_filters: None,
+            enable_shm: true,
+            enable_memfd: true,
+        }
+    }
+}
+
+impl DaemonConf {
+    /// Loads the server configuration.
+    ///
+    /// If the `PULSE_CONFIG` environment variable is set, only the file it points to is read.
+    /// Otherwise, `daemon.conf` is read from all standard locations (see
+    /// `ConfFile::load_layered`).
+    ///
+    /// Afterwards, the `PULSE_LOG` environment variable overrides `log-level`.
+    pub fn load() -> Result<Self, Error> {
+        Self::from_conf(&load_conf("PULSE_CONFIG", "daemon.conf")?)?.with_env_overrides()
+    }
+
+    /// Loads the server configuration from the file at `path` only.
+    ///
+    /// Like `load`, the `PULSE_LOG` environment variable overrides `log-level`.
+    pub fn load_from<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        Self::from_conf(&ConfFile::load(path)?)?.with_env_overrides()
+    }
+
+    fn with_env_overrides(mut self) -> Result<Self, Error> {
+        if let Some(level) = env_string("PULSE_LOG") {
+            self.log_level = parse_log_level(&level)
+                .ok_or_else(|| Error::string(format!("invalid log level '{}' in PULSE_LOG", level)))?;
+        }
+        Ok(self)
+    }
+
+    /// Interprets the values of a `daemon.conf` file. Unset values keep their defaults.
+    pub fn from_conf(file: &ConfFile) -> Result<Self, Error> {
+        file.warn_unknown("daemon.conf", DAEMON_KEYS);
+        for key in file.keys().filter(|key| UNSUPPORTED_DAEMON_KEYS.contains(key)) {
+            warn!("daemon.conf: '{}' is not supported yet and has no effect", key);
+        }
+
+        let defaults = Self::default();
+        let spec = defaults.default_sample_spec;
+        let format = file.get_with("default-sample-format", "a sample format", SampleFormat::from_name)?
+            .unwrap_or(spec.format());
+        let rate = file.get_with("default-sample-rate", "a sample rate", |v| v.parse().ok())?
+            .unwrap_or(spec.sample_rate());
+        let channels = file.get_with("default-sample-channels", "a channel count", |v| v.parse().ok())?;
+        let map = file.get_with("default-channel-map", "a channel map", ChannelMap::parse)?;
+
+        // The channel count and map have to agree. If only one of them is set, the other follows.
+        let channels = match (channels, &map) {
+            (Some(channels), Some(map)) if channels != map.len() => {
+                return Err(Error::string(format!(
+                    "default-channel-map has {} channels, but default-sample-channels is {}", map.len(), channels
+                )));
+            }
+            (Some(channels), _) => channels,
+            (None, &Some(ref map)) => map.len(),
+            (None, &None) => spec.channels(),
+        };
+        let default_sample_spec = SampleSpec::new_checked(format, channels, rate)
+            .map_err(|e| Error::string(format!("invalid default sample spec: {}", e)))?;
+        let default_channel_map = match map {
+            Some(map) => map,
+            None => ChannelMap::default_for(channels).ok_or_else(|| Error::string(format!(
+                "no default channel map for {} channels, set default-channel-map", channels
+            )))?,
+        };
+
+        let exit_idle_time = file.get_with("exit-idle-time", "a number of seconds or -1", |v| {
+            match v.parse::<i64>().ok()? {
+                secs if secs < 0 => Some(None),
+                secs => Some(Some(Duration::from_secs(secs as u64))),
+            }
+        })?;
+
+        Ok(Self {
+            default_sample_spec,
+            default_channel_map,
+            resample_method: file.get_with("resample-method", "a resampler name", |v| v.parse().ok())?
+                .unwrap_or(defaults.resample_method),
+            exit_idle_time: exit_idle_time.unwrap_or(defaults.exit_idle_time),
+            disallow_exit: file.get_bool("disallow-exit")?.unwrap_or(defaults.disallow_exit),
+            max_connections: file.get_with("max-connections", "a positive number", parse_limit)?
+                .unwrap_or(defaults.max_connections),
+            max_streams_per_client: file.get_with("max-streams-per-client", "a positive number", parse_limit)?
+                .unwrap_or(defaults.max_streams_per_client),
+            max_sample_cache_per_client: file.get_with("max-sample-cache-per-client", "a positive number", parse_limit)?
+                .unwrap_or(defaults.max_sample_cache_per_client),
+            max_pending_requests: file.get_with("max-pending-requests", "a positive number", parse_limit)?
+                .unwrap_or(defaults.max_pending_requests),
+            max_queued_messages: file.get_with("max-queued-messages", &format!("a number of at least {}", MIN_QUEUED_MESSAGES), |v| {
+                v.parse().ok().filter(|n| *n >= MIN_QUEUED_MESSAGES)
+            })?
+                .unwrap_or(defaults.max_queued_messages),
+            default_fragments: file.get_with("default-fragments", "a number between 2 and 100", |v| {
+                v.parse().ok().filter(|n| *n >= 2 && *n <= 100)
+            })?.unwrap_or(defaults.default_fragments),
+            default_fragment_size: file.get_with("default-fragment-size-msec", "a positive number", |v| {
+                v.parse().ok().filter(|ms| *ms > 0).map(Duration::from_millis)
+            })?.unwrap_or(defaults.default_fragment_size),
+            flat_volumes: file.get_bool("flat-volumes")?.unwrap_or(defaults.flat_volumes),
+            log_level: file.get_with("log-level", "a log level", parse_log_level)?
+                .unwrap_or(defaults.log_level),
+            log_filters: file.get_string("log-filters"),
+            enable_shm: file.get_bool("enable-shm")?.unwrap_or(defaults.enable_shm),
+            enable_memfd: file.get_bool("enable-memfd")?.unwrap_or(defaults.enable_memfd),
+        })
+    }
+}
+
+/// Formats the configuration as a `daemon.conf` file that sets every value.
+impl fmt::Display for DaemonConf {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let spec = &self.default_sample_spec;
+        writeln!(f, "default-sample-format = {}", spec.format().name())?;
+        writeln!(f, "default-sample-rate = {}", spec.sample_rate())?;
+        writeln!(f, "default-sample-channels = {}", spec.channels())?;
+        writeln!(f, "default-channel-map = {}", self.default_channel_map)?;
+        writeln!(f, "resample-method = {}", self.resample_method)?;
+        match self.exit_idle_time {
+            Some(time) => writeln!(f, "exit-idle-time = {}", time.as_secs())?,
+            None => writeln!(f, "exit-idle-time = -1")?,
+        }
+        writeln!(f, "disallow-exit = {}", format_bool(self.disallow_exit))?;
+        writeln!(f, "max-connections = {}", self.max_connections)?;
+        writeln!(f, "max-streams-per-client = {}", self.max_streams_per_client)?;
+        writeln!(f, "max-sample-cache-per-client = {}", self.max_sample_cache_per_client)?;
+        writeln!(f, "max-pending-requests = {}", self.max_pending_requests)?;
+        writeln!(f, "max-queued-messages = {}", self.max_queued_messages)?;
+        writeln!(f, "default-fragments = {}", self.default_fragments)?;
+        let fragment_ms = self.default_fragment_size.as_secs() * 1000
+            + u64::from(self.default_fragment_size.subsec_millis());
+        writeln!(f, "default-fragment-size-msec = {}", fragment_ms)?;
+        writeln!(f, "flat-volumes = {}", format_bool(self.flat_volumes))?;
+        writeln!(f, "log-level = {}", log_level_name(self.log_level))?;
+        writeln!(f, "log-filters = {}", self.log_filters.as_ref().map_or("", |filters| filters))?;
+        writeln!(f, "enable-shm = {}", format_bool(self.enable_shm))?;
+        writeln!(f, "enable-memfd = {}", format_bool(self.enable_memfd))
+    }
+}
+
+fn parse_limit(value: &str) -> Option<usize> {
+    value.parse().ok().filter(|n| *n > 0)
+}
+
+fn format_bool(b: bool) -> &'static str {
+    if b { "yes" } else { "no" }
+}
+
+/// Returns the PulseAudio name of a log level, which `parse_log_level` accepts.
+pub fn log_level_name(level: LevelFilter) -> &'static str {
+    match level {
+        LevelFilter::Off | LevelFilter::Error => "error",
+        LevelFilter::Warn => "warning",
+        LevelFilter::Info => "info",
+        LevelFilter::Debug | LevelFilter::Trace => "debug",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn daemon_conf() {
+        let mut file = ConfFile::parse("default-sample-rate = 44100\nexit-idle-time = 5\n").unwrap();
+        file.merge(ConfFile::parse("; drop-in\ndefault-channel-map = surround-51\nexit-idle-time = -1\n\
+            resample-method = speex-fixed-3\nlog-level = notice\nflat-volumes = yes\n\
+            log-filters = pulsar::server=debug\n").unwrap());
+        let conf = DaemonConf::from_conf(&file).unwrap();
+        assert_eq!(conf.default_sample_spec.sample_rate(), 44100);
+        assert_eq!(conf.default_sample_spec.channels(), 6);
+        assert_eq!(conf.default_channel_map.len(), 6);
+        assert_eq!(conf.exit_idle_time, None);
+        assert_eq!(conf.resample_method, ResampleMethod::SpeexFixed(3));
+        assert_eq!(conf.resample_method.to_string(), "speex-fixed-3");
+        assert_eq!(conf.log_level, LevelFilter::Info);
+        assert!(conf.flat_volumes);
+        assert_eq!(conf.log_filters, Some("pulsar::server=debug".to_string()));
+
+        let mismatch = ConfFile::parse("default-sample-channels = 1\ndefault-channel-map = stereo").unwrap();
+        assert!(DaemonConf::from_conf(&mismatch).is_err());
+        let invalid = ConfFile::parse("default-fragments = 1").unwrap();
+        assert!(DaemonConf::from_conf(&invalid).is_err());
+        let invalid = ConfFile::parse("max-connections = 0").unwrap();
+        assert!(DaemonConf::from_conf(&invalid).is_err());
+        let invalid = ConfFile::parse("max-queued-messages = 15").unwrap();
+        assert!(DaemonConf::from_conf(&invalid).is_err());
+        let minimal = ConfFile::parse("max-queued-messages = 16").unwrap();
+        assert_eq!(DaemonConf::from_conf(&minimal).unwrap().max_queued_messages, MIN_QUEUED_MESSAGES);
+
+        // The dumped configuration reads back as the same configuration
+        let dumped = DaemonConf::from_conf(&ConfFile::parse(&conf.to_string()).unwrap()).unwrap();
+        assert_eq!(dumped.to_string(), conf.to_string());
+        assert_eq!(dumped.default_channel_map.len(), 6);
+    }
+
+    #[test]
+    fn client_conf() {
+        let conf = ClientConf::from_conf(&ConfFile::parse("").unwrap()).unwrap();
+        assert_eq!(conf.default_server, None);
+        assert!(conf.autospawn);
+        assert_eq!(conf.daemon_binary, PathBuf::from("pulsar-server"));
+
+        let file = ConfFile::parse("default-server = tcp:host unix:/run/pulse/native
+            default-sink = speakers
+default-source =
+cookie-file = pulse/cookie
+autospawn = no
+            daemon-binary = /usr/bin/pulsar-server
+extra-arguments =  --log-level=debug  -n 
+            enable-shm = false
+enable-memfd = 0
+").unwrap();
+        let conf = ClientConf::from_conf(&file).unwrap();
+        assert_eq!(conf.default_server, Some("tcp:host unix:/run/pulse/native".to_string()));
+        assert_eq!(conf.default_sink, Some("speakers".to_string()));
+        // Empty values count as unset
+        assert_eq!(conf.default_source, None);
+        assert_eq!(conf.cookie_file, Some(PathBuf::from("pulse/cookie")));
+        assert!(!conf.autospawn);
+        assert_eq!(conf.daemon_binary, PathBuf::from("/usr/bin/pulsar-server"));
+        assert_eq!(conf.extra_arguments, ["--log-level=debug", "-n"]);
+        assert!(!conf.enable_shm);
+        assert!(!conf.enable_memfd);
+
+        assert!(ClientConf::from_conf(&ConfFile::parse("autospawn = maybe").unwrap()).is_err());
+    }
+
+    #[test]
+    fn client_env() {
+        let file = ConfFile::parse("default-server = file-server
+default-sink = file-sink
+            default-source = file-source
+cookie = 00ff
+").unwrap();
+
+        let mut conf = ClientConf::from_conf(&file).unwrap();
+        conf.apply_env(|_| None);
+        assert_eq!(conf.default_server, Some("file-server".to_string()));
+        assert_eq!(conf.cookie, Some("00ff".to_string()));
+        assert_eq!(conf.cookie_file, None);
+
+        let mut env = BTreeMap::new();
+        env.insert("PULSE_SERVER", "env-server");
+        env.insert("PULSE_SINK", "env-sink");
+        // Empty variables are ignored
+        env.insert("PULSE_SOURCE", "");
+        env.insert("PULSE_COOKIE", "/tmp/cookie");
+        let mut conf = ClientConf::from_conf(&file).unwrap();
+        conf.apply_env(|var| env.get(var).map(OsString::from));
+        assert_eq!(conf.default_server, Some("env-server".to_string()));
+        assert_eq!(conf.default_sink, Some("env-sink".to_string()));
+        assert_eq!(conf.default_source, Some("file-source".to_string()));
+        // The cookie file from the environment replaces the cookie from the file
+        assert_eq!(conf.cookie, None);
+        assert_eq!(conf.cookie_file, Some(PathBuf::from("/tmp/cookie")));
+    }
+}
@@ -3,11 +3,15 @@
 use rand::thread_rng;
 use rand::prelude::*;
 
+use conf::ClientConf;
+use paths;
+use runtime_dir;
+
 use std::path::Path;
-use std::fs::OpenOptions;
+use std::fs::{File, OpenOptions};
 use std::io::prelude::*;
 use std::io::{self, Result};
-use std::{fmt, fs};
+use std::{fmt, fs, str};
 use std::os::unix::fs::{OpenOptionsExt, MetadataExt};
 
 const COOKIE_LENGTH: usize = 256;
@@ -18,22 +22,63 @@ pub struct AuthCookie {
 }
 
 impl AuthCookie {
+    /// Creates a cookie from its raw binary representation.
+    ///
+    /// Returns `None` if `data` doesn't have the right length for a cookie (256 bytes).
+    pub fn from_bytes(data: &[u8]) -> Option<Self> {
+        if data.len() != COOKIE_LENGTH {
+            return None;
+        }
+
+        let mut cookie = AuthCookie { data: [0; COOKIE_LENGTH] };
+        cookie.data.copy_from_slice(data);
+        Some(cookie)
+    }
+
+    /// Creates a cookie from a hex-encoded string (as used in `client.conf`).
+    ///
+    /// Returns `None` if `hex` isn't a valid hex encoding of a cookie.
+    pub fn from_hex(hex: &str) -> Option<Self> {
+        let hex = hex.trim();
+        if hex.len() != COOKIE_LENGTH * 2 || !hex.is_ascii() {
+            return None;
+        }
+
+        let mut cookie = AuthCookie { data: [0; COOKIE_LENGTH] };
+        for (i, byte) in cookie.data.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).ok()?;
+        }
+        Some(cookie)
+    }
+
+    /// Returns the raw cookie data, as sent to the server.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.data
+    }
+
     /// Loads an existing cookie from disk or generates one and writes it to disk.
     ///
+    /// A new cookie is only generated if `file` doesn't exist. If it exists but can't be loaded,
+    /// an error is returned instead of overwriting it, since other clients might still be using
+    /// the cookie.
+    ///
     /// # Parameters
     ///
     /// * `file`: Path to the cookie file.
-    #[allow(unused)]
     pub fn load_or_create<P: AsRef<Path>>(file: P) -> Result<Self> {
-        Self::load(file.as_ref())
-            .or_else(|_| Self::create(file.as_ref()))
+        match Self::load(file.as_ref()) {
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Self::create(file.as_ref()),
+            result => result,
+        }
     }
 
     /// Generates a new cookie and stores it to disk, overwriting any existing cookie file.
     pub fn create<P: AsRef<Path>>(file: P) -> Result<Self> {
         info!("generating new auth cookie at {}", file.as_ref().display());
 
-        // TODO check dir access permissions
+        if let Some(dir) = file.as_ref().parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            check_dir(dir)?;
+        }
         fs::remove_file(&file).ok();
 
         const ACCESS_MODE: u32 = 0o600;  // -rw------
@@ -64,9 +109,69 @@ impl AuthCookie {
 
     /// Loads an existing cookie from disk.
     ///
-    /// If the cookie doesn't exist, an error is returned.
-    pub fn load<P: AsRef<Path>>(_file: P) -> Result<Self> {
-        unimplemented!();
+    /// The file may either contain the 256 raw cookie bytes, or their hex encoding. Cookie files
+    /// that can be modified by other users are rejected.
+    ///
+    /// If the cookie doesn't exist, an error of kind `NotFound` is returned.
+    pub fn load<P: AsRef<Path>>(file: P) -> Result<Self> {
+        let path = file.as_ref();
+        let mut file = File::open(path)?;
+
+        let meta = file.metadata()?;
+        if !meta.is_file() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("cookie file {} is not a regular file", path.display())
+            ));
+        }
+
+        let mode = meta.mode() & 0o777;
+        if mode & 0o022 != 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                format!("cookie file {} is writable by other users (mode 0o{:03o})", path.display(), mode)
+            ));
+        }
+        if mode & 0o044 != 0 {
+            warn!("cookie file {} is readable by other users (mode 0o{:03o})", path.display(), mode);
+        }
+
+        // Read a bit more than needed so we can detect files that are too large
+        let mut data = Vec::new();
+        (&mut file).take(COOKIE_LENGTH as u64 * 2 + 2).read_to_end(&mut data)?;
+
+        Self::from_bytes(&data)
+            .or_else(|| str::from_utf8(&data).ok().and_then(Self::from_hex))
+            .ok_or_else(|| io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "cookie file {} is invalid (expected {} bytes or their hex encoding)",
+                    path.display(), COOKIE_LENGTH
+                )
+            ))
+    }
+
+    /// Loads the cookie a client should use to authenticate to the server.
+    ///
+    /// The cookie is located as follows:
+    ///
+    /// * If `conf` sets `cookie`, its value is used as the hex-encoded cookie.
+    /// * If `conf` sets `cookie_file` (which the `PULSE_COOKIE` environment variable overrides),
+    ///   the cookie is loaded from that file. Relative paths are resolved against
+    ///   `paths::config_home_dir()`.
+    /// * Otherwise, the cookie is loaded from `paths::cookie_path()`.
+    pub fn load_client(conf: &ClientConf) -> Result<Self> {
+        if let Some(ref hex) = conf.cookie {
+            return Self::from_hex(hex).ok_or_else(|| io::Error::new(
+                io::ErrorKind::InvalidData,
+                "invalid 'cookie' value in client.conf (expected hex-encoded cookie)"
+            ));
+        }
+        if let Some(ref path) = conf.cookie_file {
+            return Self::load(paths::config_home_file(path));
+        }
+
+        Self::load(paths::cookie_path())
     }
 }
 
@@ -89,3 +194,53 @@ impl<'a> PartialEq<&'a [u8]> for AuthCookie {
 }
 
 impl Eq for AuthCookie {}
+
+/// Makes sure that other users can't replace the cookie file in `dir`, creating `dir` if it
+/// doesn't exist.
+fn check_dir(dir: &Path) -> Result<()> {
+    let meta = match fs::metadata(dir) {
+        Ok(meta) => meta,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return runtime_dir::create_secure_dir(dir),
+        Err(e) => return Err(e),
+    };
+
+    // Directories like `/tmp` are writable by everyone, but the sticky bit prevents others from
+    // deleting our files
+    const STICKY: u32 = 0o1000;
+    if meta.mode() & 0o022 != 0 && meta.mode() & STICKY == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!("refusing to create cookie in {}, which is writable by other users", dir.display())
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hex() {
+        let hex = "0f".repeat(COOKIE_LENGTH);
+        assert_eq!(AuthCookie::from_hex(&hex).unwrap().as_bytes(), &[0x0f; COOKIE_LENGTH][..]);
+        assert!(AuthCookie::from_hex(&hex[1..]).is_none());
+        assert!(AuthCookie::from_hex(&"zz".repeat(COOKIE_LENGTH)).is_none());
+    }
+
+    #[test]
+    fn create_and_load() {
+        let path = ::std::env::temp_dir().join(format!("pa-proto-cookie-test-{}", ::std::process::id()));
+
+        let created = AuthCookie::load_or_create(&path).unwrap();
+        let loaded = AuthCookie::load_or_create(&path).unwrap();
+        assert_eq!(created, loaded);
+
+        fs::write(&path, b"too short").unwrap();
+        assert_eq!(AuthCookie::load(&path).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        // Invalid cookies must not be overwritten
+        assert!(AuthCookie::load_or_create(&path).is_err());
+
+        fs::remove_file(&path).unwrap();
+    }
+}
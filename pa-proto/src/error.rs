@@ -14,12 +14,24 @@ pub struct Error {
 }
 
 impl Error {
-    pub(crate) fn string<S: AsRef<str>>(string: S) -> Self {
+    /// Creates an error from a human-readable message.
+    pub fn string<S: AsRef<str>>(string: S) -> Self {
         Self {
             inner: Inner::Other(string.as_ref().into()),
         }
     }
 
+    /// Returns the PulseAudio error code carried by this error, if any.
+    ///
+    /// Errors sent by the other side of a connection (eg. in reply to a command) carry the code
+    /// sent by the peer.
+    pub fn code(&self) -> Option<PulseError> {
+        match self.inner {
+            Inner::Code(code) => Some(code),
+            Inner::Other(_) => None,
+        }
+    }
+
     /*pub(crate) fn new<S: ToString>(code: PulseError, msg: S) -> Self {
         Self {
             code,
@@ -36,8 +48,18 @@ impl<E: error::Error + Send + Sync + 'static> From<E> for Error {
     }
 }
 
+impl From<PulseError> for Error {
+    fn from(code: PulseError) -> Error {
+        Error {
+            inner: Inner::Code(code),
+        }
+    }
+}
+
 #[derive(Debug, Fail)]
 enum Inner {
+    #[fail(display = "{}", _0)]
+    Code(PulseError),
     #[fail(display = "{}", _0)]
     Other(Box<error::Error + Send + Sync>),
 }
@@ -46,7 +68,7 @@ enum Inner {
 ///
 /// Can be sent to clients to inform them of a specific error.
 #[repr(u32)]
-#[derive(Debug, Copy, Clone, FromPrimitive, Fail)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, FromPrimitive, Fail)]
 // TODO: Rename to `ErrorCode`?
 pub enum PulseError {
     /// Access failure
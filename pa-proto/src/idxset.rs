@@ -114,6 +114,50 @@ impl<T> IdxSet<T> {
         }
     }
 
+    /// Allocates an `Idx` without associating an item with it yet.
+    ///
+    /// The item can be stored later using `insert`. This is useful when creating the item needs
+    /// its `Idx`, but the `IdxSet` can't stay borrowed (eg. locked) in the meantime. Until then,
+    /// the `Idx` isn't considered part of the `IdxSet`.
+    pub fn reserve(&mut self) -> Idx<T> {
+        self.alloc_idx()
+    }
+
+    /// Stores `t` under an `Idx` previously returned by `reserve`.
+    ///
+    /// Panics if `idx` wasn't allocated by this `IdxSet`, or if an item is already associated
+    /// with it.
+    pub fn insert(&mut self, idx: Idx<T>, t: T) -> Entry<T> {
+        assert!(idx.val < self.next_idx, "index {:?} was never allocated", idx);
+        match self.map.entry(idx) {
+            btree_map::Entry::Vacant(entry) => {
+                Entry {
+                    idx,
+                    t: entry.insert(t),
+                }
+            }
+            btree_map::Entry::Occupied(_) => {
+                panic!("index {:?} already allocated", idx);
+            }
+        }
+    }
+
+    /// Looks up the `Idx` corresponding to a raw index value (eg. one received over the wire).
+    ///
+    /// Returns `None` if no item with the given index is currently stored in the `IdxSet`.
+    pub fn idx_of(&self, raw: u32) -> Option<Idx<T>> {
+        let idx = Idx {
+            val: raw,
+            phantom: PhantomData,
+        };
+
+        if self.map.contains_key(&idx) {
+            Some(idx)
+        } else {
+            None
+        }
+    }
+
     /// Looks up the value associated with `idx` and returns a reference to it.
     ///
     /// If the value has been removed from the `IdxSet`, returns `None`. If no value was ever
@@ -139,6 +183,10 @@ impl<T> IdxSet<T> {
     pub fn iter<'a>(&'a self) -> Iter<'a, T> {
         Iter { inner: self.map.iter() }
     }
+
+    pub fn iter_mut<'a>(&'a mut self) -> IterMut<'a, T> {
+        IterMut { inner: self.map.iter_mut() }
+    }
 }
 
 /// A key-value entry (or index-value entry) in an `IdxSet`.
@@ -169,6 +217,19 @@ impl<'a, T: 'a> Iterator for Iter<'a, T> {
     }
 }
 
+#[derive(Debug)]
+pub struct IterMut<'a, T: 'a> {
+    inner: btree_map::IterMut<'a, Idx<T>, T>,
+}
+
+impl<'a, T: 'a> Iterator for IterMut<'a, T> {
+    type Item = &'a mut T;
+
+    fn next(&mut self) -> Option<<Self as Iterator>::Item> {
+        self.inner.next().map(|(_, v)| v)
+    }
+}
+
 impl<'a, T: 'a> Clone for Iter<'a, T> {
     fn clone(&self) -> Self {
         Self { inner: self.inner.clone() }
@@ -15,18 +15,27 @@ extern crate byteorder;
 extern crate rand;
 extern crate tokio_codec;
 extern crate bytes;
+extern crate nix;
 
 pub mod error;
 pub mod idxset;
+pub mod modargs;
 pub mod sink;
+pub mod source;
 pub mod time;
 pub mod packet;
 pub mod command;
+pub mod conf;
 pub mod cookie;
 pub mod paths;
+pub mod runtime_dir;
+pub mod server_string;
+pub mod shm;
+pub mod srbchannel;
 pub mod stream;
 mod types;
 pub mod string;
+pub mod user;
 
 pub use types::*;
 pub use error::*;
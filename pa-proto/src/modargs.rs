@@ -0,0 +1,471 @@
+//! Parser for module arguments.
+//!
+//! Modules are configured with a string of `key=value` pairs separated by whitespace, eg.
+//! `sink_name=foo sink_properties="device.description='My Sink'" rate=44100`. This module
+//! implements the same syntax PulseAudio's `pa_modargs_new` accepts:
+//!
+//! * Values can be unquoted (ending at the next whitespace), or enclosed in `'single'` or
+//!   `"double"` quotes, in which case they may contain whitespace and the other kind of quote.
+//! * A backslash escapes the following character, both inside and outside of quotes.
+//! * An empty value (`key=`) is allowed.
+//! * Every key may only be specified once, and only keys the module declares are accepted.
+
+use string::UnicodeCString;
+use types::{SampleSpec, SampleFormat, ChannelMap, CVolume, Volume, PropList};
+
+use std::collections::BTreeMap;
+
+/// Errors that can occur while parsing module arguments.
+#[derive(Debug, Fail, PartialEq, Eq)]
+pub enum ModArgsError {
+    /// A key was encountered that the module doesn't accept.
+    #[fail(display = "unknown module argument '{}' (valid arguments: {})", key, valid)]
+    UnknownKey {
+        key: String,
+        /// Comma-separated list of the valid keys.
+        valid: String,
+    },
+
+    /// A key was specified more than once.
+    #[fail(display = "module argument '{}' specified more than once", _0)]
+    DuplicateKey(String),
+
+    /// The argument string is malformed.
+    #[fail(display = "syntax error in module arguments at offset {}: {}", offset, msg)]
+    Syntax {
+        /// Byte offset into the argument string at which the error was detected.
+        offset: usize,
+        msg: &'static str,
+    },
+
+    /// The value of an argument could not be converted to the requested type.
+    #[fail(display = "invalid value '{}' for module argument '{}' (expected {})", value, key, expected)]
+    InvalidValue {
+        key: String,
+        value: String,
+        /// Human-readable description of what was expected.
+        expected: &'static str,
+    },
+}
+
+/// Parsed module arguments.
+#[derive(Debug, Clone, Default)]
+pub struct ModArgs {
+    args: BTreeMap<String, String>,
+}
+
+impl ModArgs {
+    /// Parses a module argument string.
+    ///
+    /// Only keys in `valid_keys` are accepted. If `valid_keys` is empty, all keys are accepted.
+    pub fn parse(args: &str, valid_keys: &[&str]) -> Result<Self, ModArgsError> {
+        let mut map = BTreeMap::new();
+        for (key, value) in tokenize(args)? {
+            if !valid_keys.is_empty() && !valid_keys.contains(&&*key) {
+                return Err(ModArgsError::UnknownKey {
+                    key,
+                    valid: valid_keys.join(", "),
+                });
+            }
+
+            if map.contains_key(&key) {
+                return Err(ModArgsError::DuplicateKey(key));
+            }
+
+            map.insert(key, value);
+        }
+
+        Ok(Self { args: map })
+    }
+
+    /// Returns the raw value of `key`, or `None` if it wasn't specified.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.args.get(key).map(|s| &**s)
+    }
+
+    /// Returns the raw value of `key`, or `default` if it wasn't specified.
+    pub fn get_or<'a>(&'a self, key: &str, default: &'a str) -> &'a str {
+        self.get(key).unwrap_or(default)
+    }
+
+    /// Iterates over all specified `(key, value)` pairs in lexicographic key order.
+    pub fn iter<'a>(&'a self) -> impl Iterator<Item=(&'a str, &'a str)> + 'a {
+        self.args.iter().map(|(k, v)| (&**k, &**v))
+    }
+
+    /// Parses the value of `key` as an unsigned integer.
+    pub fn get_u32(&self, key: &str) -> Result<Option<u32>, ModArgsError> {
+        self.get_with(key, "an unsigned integer", |v| v.parse().ok())
+    }
+
+    /// Parses the value of `key` as a signed integer.
+    pub fn get_i32(&self, key: &str) -> Result<Option<i32>, ModArgsError> {
+        self.get_with(key, "an integer", |v| v.parse().ok())
+    }
+
+    /// Parses the value of `key` as a boolean (see `parse_bool`).
+    pub fn get_bool(&self, key: &str) -> Result<Option<bool>, ModArgsError> {
+        self.get_with(key, "a boolean", parse_bool)
+    }
+
+    /// Parses the value of `key` as a volume (see `Volume::parse`).
+    pub fn get_volume(&self, key: &str) -> Result<Option<Volume>, ModArgsError> {
+        self.get_with(key, "a volume", Volume::parse)
+    }
+
+    /// Parses the value of `key` as a channel volume for `channels` channels.
+    ///
+    /// The value is either a single volume that is applied to all channels, or a comma-separated
+    /// list containing one volume per channel.
+    pub fn get_cvolume(&self, key: &str, channels: u8) -> Result<Option<CVolume>, ModArgsError> {
+        self.get_with(key, "a volume or a list of per-channel volumes", |v| {
+            let volumes = v.split(',').map(Volume::parse).collect::<Option<Vec<_>>>()?;
+            let mut cvolume = CVolume::new();
+            match volumes.len() {
+                1 => for _ in 0..channels {
+                    cvolume.push(volumes[0]).ok()?;
+                },
+                n if n == channels as usize => for volume in volumes {
+                    cvolume.push(volume).ok()?;
+                },
+                _ => return None,
+            }
+            Some(cvolume)
+        })
+    }
+
+    /// Parses the value of `key` as a channel map (see `ChannelMap::parse`).
+    pub fn get_channel_map(&self, key: &str) -> Result<Option<ChannelMap>, ModArgsError> {
+        self.get_with(key, "a channel map", ChannelMap::parse)
+    }
+
+    /// Parses the value of `key` as a property list in `key=value` syntax.
+    ///
+    /// Values use the same quoting rules as module arguments, so a property list can be nested
+    /// inside a module argument by quoting it, eg. `sink_properties="device.description='Foo'"`.
+    /// Values prefixed with `hex:` are decoded as raw binary data.
+    pub fn get_proplist(&self, key: &str) -> Result<Option<PropList>, ModArgsError> {
+        self.get_with(key, "a property list", |v| parse_proplist(v).ok())
+    }
+
+    /// Reads a sample spec from the `format`, `rate` and `channels` arguments.
+    ///
+    /// Arguments that aren't specified are taken from `default`.
+    pub fn get_sample_spec(&self, default: &SampleSpec) -> Result<SampleSpec, ModArgsError> {
+        let format = self.get_with("format", "a sample format", SampleFormat::from_name)?
+            .unwrap_or(default.format());
+        let rate = self.get_u32("rate")?.unwrap_or(default.sample_rate());
+        let channels = match self.get_u32("channels")? {
+            Some(channels) if channels > u8::max_value() as u32 => {
+                return Err(self.invalid_value("channels", "a valid channel count"));
+            }
+            Some(channels) => channels as u8,
+            None => default.channels(),
+        };
+
+        SampleSpec::new_checked(format, channels, rate)
+            .map_err(|_| self.invalid_value(
+                if self.get("channels").is_some() { "channels" } else { "rate" },
+                "a valid sample specification",
+            ))
+    }
+
+    /// Reads a sample spec (see `get_sample_spec`) and a matching channel map from the
+    /// `channel_map` argument.
+    ///
+    /// If no channel map is specified, `default_map` is used if its channel count matches the
+    /// sample spec. Otherwise, a default map for the channel count is chosen.
+    ///
+    /// If only `channel_map` is given, the channel count is derived from it, like PulseAudio does.
+    pub fn get_sample_spec_and_channel_map(
+        &self,
+        default_spec: &SampleSpec,
+        default_map: &ChannelMap,
+    ) -> Result<(SampleSpec, ChannelMap), ModArgsError> {
+        let map = self.get_channel_map("channel_map")?;
+        let default_spec = match map {
+            Some(ref map) if self.get("channels").is_none() => {
+                SampleSpec::new_checked(default_spec.format(), map.len(), default_spec.sample_rate())
+                    .map_err(|_| self.invalid_value("channel_map", "a valid channel map"))?
+            }
+            _ => default_spec.clone(),
+        };
+
+        let spec = self.get_sample_spec(&default_spec)?;
+        let map = match map {
+            Some(map) => map,
+            None if default_map.len() == spec.channels() => default_map.clone(),
+            None => ChannelMap::default_for(spec.channels())
+                .ok_or_else(|| self.invalid_value("channels", "a valid channel count"))?,
+        };
+
+        if map.len() != spec.channels() {
+            return Err(self.invalid_value("channel_map", "a channel map matching the channel count"));
+        }
+
+        Ok((spec, map))
+    }
+
+    fn get_with<T, F>(&self, key: &str, expected: &'static str, f: F) -> Result<Option<T>, ModArgsError>
+    where F: FnOnce(&str) -> Option<T> {
+        match self.get(key) {
+            Some(value) => f(value).map(Some).ok_or_else(|| self.invalid_value(key, expected)),
+            None => Ok(None),
+        }
+    }
+
+    fn invalid_value(&self, key: &str, expected: &'static str) -> ModArgsError {
+        ModArgsError::InvalidValue {
+            key: key.to_string(),
+            value: self.get_or(key, "").to_string(),
+            expected,
+        }
+    }
+}
+
+/// Parses a boolean the way PulseAudio does.
+///
+/// Accepts `1`, `y`, `t`, `yes`, `true` and `on` as `true` and `0`, `n`, `f`, `no`, `false` and
+/// `off` as `false` (case-insensitive).
+pub fn parse_bool(s: &str) -> Option<bool> {
+    match &*s.to_ascii_lowercase() {
+        "1" | "y" | "t" | "yes" | "true" | "on" => Some(true),
+        "0" | "n" | "f" | "no" | "false" | "off" => Some(false),
+        _ => None,
+    }
+}
+
+/// Parses a property list given as a string of `key=value` pairs.
+///
+/// This is the format accepted by `pa_proplist_from_string`. String values are stored with a
+/// terminating nul byte, `hex:` values are stored as-is.
+pub fn parse_proplist(s: &str) -> Result<PropList, ModArgsError> {
+    let mut props = PropList::new();
+    for (key, value) in tokenize(s)? {
+        let invalid = |expected| ModArgsError::InvalidValue {
+            key: key.clone(),
+            value: value.clone(),
+            expected,
+        };
+
+        if !key.is_ascii() {
+            return Err(invalid("an ASCII property name"));
+        }
+
+        let data = if value.starts_with("hex:") {
+            decode_hex(&value[4..]).ok_or_else(|| invalid("hexadecimal data"))?
+        } else {
+            if value.contains('\0') {
+                return Err(invalid("a string without nul bytes"));
+            }
+            let mut data = value.clone().into_bytes();
+            data.push(0);
+            data
+        };
+
+        let key = UnicodeCString::from_string(key.clone())
+            .map_err(|_| invalid("a valid property name"))?;
+        props.insert(key, data.into_boxed_slice());
+    }
+
+    Ok(props)
+}
+
+fn decode_hex(s: &str) -> Option<Vec<u8>> {
+    if s.len() % 2 != 0 || !s.is_ascii() {
+        return None;
+    }
+
+    (0..s.len()).step_by(2)
+        .map(|i| u8::from_str_radix(&s[i..i + 2], 16).ok())
+        .collect()
+}
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum State {
+    /// Skipping whitespace between pairs.
+    Whitespace,
+    Key,
+    /// Just read the `=`.
+    ValueStart,
+    /// Unquoted value.
+    Simple,
+    SimpleEscaped,
+    DoubleQuotes,
+    DoubleQuotesEscaped,
+    Ticks,
+    TicksEscaped,
+}
+
+/// Splits an argument string into `(key, value)` pairs, resolving quotes and escapes.
+fn tokenize(s: &str) -> Result<Vec<(String, String)>, ModArgsError> {
+    use self::State::*;
+
+    let mut pairs = Vec::new();
+    let mut state = Whitespace;
+    let mut key = String::new();
+    let mut value = String::new();
+
+    for (offset, c) in s.char_indices() {
+        let syntax = |msg| ModArgsError::Syntax { offset, msg };
+
+        state = match (state, c) {
+            (Whitespace, c) if c.is_whitespace() => Whitespace,
+            (Whitespace, '=') => return Err(syntax("missing key before '='")),
+            (Whitespace, c) | (Key, c) if is_key_char(c) => {
+                key.push(c);
+                Key
+            }
+            (Whitespace, _) => return Err(syntax("invalid character in key")),
+            (Key, '=') => ValueStart,
+            (Key, c) if c.is_whitespace() => return Err(syntax("expected '=' after key")),
+            (Key, _) => return Err(syntax("invalid character in key")),
+
+            (ValueStart, '\'') => Ticks,
+            (ValueStart, '"') => DoubleQuotes,
+            (ValueStart, '\\') | (Simple, '\\') => SimpleEscaped,
+            (ValueStart, c) | (Simple, c) if c.is_whitespace() => {
+                pairs.push((key.split_off(0), value.split_off(0)));
+                Whitespace
+            }
+            (ValueStart, c) | (Simple, c) | (SimpleEscaped, c) => {
+                value.push(c);
+                Simple
+            }
+
+            (DoubleQuotes, '"') | (Ticks, '\'') => {
+                pairs.push((key.split_off(0), value.split_off(0)));
+                Whitespace
+            }
+            (DoubleQuotes, '\\') => DoubleQuotesEscaped,
+            (Ticks, '\\') => TicksEscaped,
+            (DoubleQuotes, c) | (DoubleQuotesEscaped, c) => {
+                value.push(c);
+                DoubleQuotes
+            }
+            (Ticks, c) | (TicksEscaped, c) => {
+                value.push(c);
+                Ticks
+            }
+        };
+    }
+
+    let syntax = |msg| ModArgsError::Syntax { offset: s.len(), msg };
+    match state {
+        Whitespace => {}
+        ValueStart | Simple => pairs.push((key, value)),
+        Key => return Err(syntax("expected '=' after key")),
+        SimpleEscaped | DoubleQuotesEscaped | TicksEscaped => {
+            return Err(syntax("unterminated escape sequence"));
+        }
+        DoubleQuotes | Ticks => return Err(syntax("unterminated quoted value")),
+    }
+
+    Ok(pairs)
+}
+
+fn is_key_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '.'
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use types::{ChannelPosition, Prop};
+
+    fn pairs(s: &str) -> Vec<(String, String)> {
+        tokenize(s).unwrap()
+    }
+
+    fn pair(k: &str, v: &str) -> (String, String) {
+        (k.to_string(), v.to_string())
+    }
+
+    #[test]
+    fn quoting() {
+        assert_eq!(pairs(""), vec![]);
+        assert_eq!(pairs("  a=b   c=d "), vec![pair("a", "b"), pair("c", "d")]);
+        assert_eq!(pairs("a= b="), vec![pair("a", ""), pair("b", "")]);
+        assert_eq!(pairs(r#"a="x y" b='"z"'"#), vec![pair("a", "x y"), pair("b", "\"z\"")]);
+        assert_eq!(pairs(r#"a=x\ y b="\"" c='\''"#), vec![pair("a", "x y"), pair("b", "\""), pair("c", "'")]);
+        assert_eq!(pairs("a=\"\"b=c"), vec![pair("a", ""), pair("b", "c")]);
+        assert_eq!(pairs("a=b=c"), vec![pair("a", "b=c")]);
+    }
+
+    #[test]
+    fn syntax_errors() {
+        assert!(tokenize("a").is_err());
+        assert!(tokenize("a =b").is_err());
+        assert!(tokenize("=b").is_err());
+        assert!(tokenize("a='b").is_err());
+        assert!(tokenize("a=\"b").is_err());
+        assert!(tokenize("a=b\\").is_err());
+        assert!(tokenize("a'=b").is_err());
+    }
+
+    #[test]
+    fn keys() {
+        assert!(ModArgs::parse("a=1 b=2", &["a", "b"]).is_ok());
+        assert_eq!(
+            ModArgs::parse("a=1 a=2", &["a"]).unwrap_err(),
+            ModArgsError::DuplicateKey("a".to_string())
+        );
+        match ModArgs::parse("a=1 c=2", &["a", "b"]).unwrap_err() {
+            ModArgsError::UnknownKey { key, .. } => assert_eq!(key, "c"),
+            e => panic!("unexpected error {:?}", e),
+        }
+    }
+
+    #[test]
+    fn typed() {
+        let args = ModArgs::parse("n=-5 u=7 b=yes v=50% bad=x", &[]).unwrap();
+        assert_eq!(args.get_i32("n").unwrap(), Some(-5));
+        assert!(args.get_u32("n").is_err());
+        assert_eq!(args.get_u32("u").unwrap(), Some(7));
+        assert_eq!(args.get_u32("missing").unwrap(), None);
+        assert_eq!(args.get_bool("b").unwrap(), Some(true));
+        assert!(args.get_bool("bad").is_err());
+        assert_eq!(args.get_volume("v").unwrap().unwrap().as_u32(), 0x8000);
+        assert_eq!(args.get_cvolume("v", 2).unwrap().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn sample_spec_and_map() {
+        let default_spec = SampleSpec::new_checked(SampleFormat::S16Le, 2, 44100).unwrap();
+        let default_map = ChannelMap::default_for(2).unwrap();
+
+        let args = ModArgs::parse("format=float32le rate=48000", &[]).unwrap();
+        let (spec, map) = args.get_sample_spec_and_channel_map(&default_spec, &default_map).unwrap();
+        assert_eq!(spec.format(), SampleFormat::Float32Le);
+        assert_eq!(spec.sample_rate(), 48000);
+        assert_eq!(spec.channels(), 2);
+        assert_eq!(map.len(), 2);
+
+        let args = ModArgs::parse("channels=6", &[]).unwrap();
+        let (spec, map) = args.get_sample_spec_and_channel_map(&default_spec, &default_map).unwrap();
+        assert_eq!(spec.channels(), 6);
+        assert_eq!(map.len(), 6);
+
+        let args = ModArgs::parse("channel_map=mono", &[]).unwrap();
+        let (spec, map) = args.get_sample_spec_and_channel_map(&default_spec, &default_map).unwrap();
+        assert_eq!(spec.channels(), 1);
+        match map.into_iter().next() {
+            Some(ChannelPosition::Mono) => {}
+            pos => panic!("unexpected channel position {:?}", pos),
+        }
+
+        let args = ModArgs::parse("channels=2 channel_map=mono", &[]).unwrap();
+        assert!(args.get_sample_spec_and_channel_map(&default_spec, &default_map).is_err());
+        let args = ModArgs::parse("format=s17le", &[]).unwrap();
+        assert!(args.get_sample_spec(&default_spec).is_err());
+    }
+
+    #[test]
+    fn proplist() {
+        let args = ModArgs::parse(r#"p="device.description='Foo Bar' x=hex:00ff""#, &[]).unwrap();
+        let props = args.get_proplist("p").unwrap().unwrap();
+        assert_eq!(props.get(Prop::DeviceDescription), Some(&b"Foo Bar\0"[..]));
+        let x = props.into_iter().find(|(k, _)| k.as_str() == "x").unwrap();
+        assert_eq!(&*x.1, &[0x00, 0xff]);
+    }
+}
@@ -4,36 +4,49 @@
 //! writing simple, fixed structures, so we make use of it here.
 
 
-/* We piggyback information if audio data blocks are stored in SHM on the seek mode */
-/*
-#define PA_FLAG_SHMDATA     0x80000000LU
-#define PA_FLAG_SHMDATA_MEMFD_BLOCK         0x20000000LU
-#define PA_FLAG_SHMRELEASE  0x40000000LU
-#define PA_FLAG_SHMREVOKE   0xC0000000LU
-#define PA_FLAG_SHMMASK     0xFF000000LU
-#define PA_FLAG_SEEKMASK    0x000000FFLU
-#define PA_FLAG_SHMWRITABLE 0x00800000LU
-*/
-
-
-
 // Types of packets/items:
 // * "Item packet" - control packet - channel=-1 - payload is tagstruct
-// * "SHM release" - set in flags
-// * "SHM revoke"  - set in flags
+// * "SHM release" - channel=-1, set in flags, block ID in offset_hi
+// * "SHM revoke"  - channel=-1, set in flags, block ID in offset_hi
 // * "MEMBLOCK" - chunk of audio data - channel=actual channel lol
 
 use types::tagstruct::TagStructReader;
+use shm::{Fd, ShmBlock};
 use error::Error;
 
 use bincode;
+use byteorder::{BigEndian, ByteOrder};
+use num_traits::FromPrimitive;
 use tokio_codec::{Encoder, Decoder};
 use bytes::{Bytes, BytesMut};
 use std::io::prelude::*;
 use std::{fmt, mem};
 
-const FLAG_SHMRELEASE: u32 = 0x40000000;    // 0b0100
-const FLAG_SHMREVOKE: u32 = 0xC0000000;     // 0b1100 FIXME 2 bits set?
+// We piggyback information if audio data blocks are stored in SHM on the seek mode
+const FLAG_SHMDATA: u32 = 0x80000000;
+const FLAG_SHMDATA_MEMFD_BLOCK: u32 = 0x20000000;
+const FLAG_SHMRELEASE: u32 = 0x40000000;
+const FLAG_SHMREVOKE: u32 = 0xC0000000;
+const FLAG_SHMMASK: u32 = 0xFF000000;
+const FLAG_SEEKMASK: u32 = 0x000000FF;
+const FLAG_SHMWRITABLE: u32 = 0x00800000;
+
+/// Size of the payload of a memblock packet referencing shared memory.
+const SHM_INFO_SIZE: usize = 16;
+
+/// Maximum payload size of received packets (same as PulseAudio).
+///
+/// Packets announcing a larger payload are rejected before any memory is allocated for them.
+pub const MAX_PAYLOAD_SIZE: usize = 16 * 1024 * 1024;
+
+/// Checks the payload length announced in a packet header against `MAX_PAYLOAD_SIZE`.
+pub fn check_payload_length(length: u32) -> Result<usize, Error> {
+    let length = length as usize;
+    if length > MAX_PAYLOAD_SIZE {
+        return Err(Error::string(format!("packet payload of {} Bytes exceeds the maximum of {} Bytes", length, MAX_PAYLOAD_SIZE)));
+    }
+    Ok(length)
+}
 
 // Not sure why PA doesn't just call this "Header" tbh...
 /// Packet descriptor / header.
@@ -59,6 +72,8 @@ pub struct Packet {
     /// Packet header / Descriptor.
     desc: Descriptor,
     payload: Bytes,  // TODO: dont serialize length
+    /// File descriptors sent along with the packet (only possible over Unix sockets).
+    fds: Vec<Fd>,
 }
 
 impl Packet {
@@ -75,9 +90,98 @@ impl Packet {
                 flags: 0x00,
             },
             payload: payload.into(),
+            fds: Vec::new(),
+        }
+    }
+
+    /// Creates a memblock packet carrying audio data for `channel` inline.
+    pub fn new_memblock(channel: u32, offset: i64, seek_mode: SeekMode, data: &[u8]) -> Self {
+        assert!(data.len() <= u32::max_value() as usize, "payload larger than 4 GB");
+
+        Packet {
+            desc: Descriptor {
+                length: data.len() as u32,
+                channel: channel as i32,
+                offset_hi: (offset as u64 >> 32) as u32,
+                offset_lo: offset as u32,
+                flags: seek_mode as u32,
+            },
+            payload: data.into(),
+            fds: Vec::new(),
+        }
+    }
+
+    /// Creates a memblock packet referencing audio data for `channel` stored in shared memory.
+    ///
+    /// `memfd` must be set if the block is stored in a memfd segment, which must have been
+    /// registered with the peer before. `writable` allows the peer to write to the block.
+    pub fn new_shm_memblock(channel: u32, offset: i64, seek_mode: SeekMode, block: &ShmBlock, memfd: bool, writable: bool) -> Self {
+        let mut info = [0; SHM_INFO_SIZE];
+        BigEndian::write_u32_into(&[block.block_id, block.shm_id, block.offset, block.length], &mut info);
+
+        let mut flags = seek_mode as u32 | FLAG_SHMDATA;
+        if memfd {
+            flags |= FLAG_SHMDATA_MEMFD_BLOCK;
+        }
+        if writable {
+            flags |= FLAG_SHMWRITABLE;
+        }
+
+        Packet {
+            desc: Descriptor {
+                length: SHM_INFO_SIZE as u32,
+                channel: channel as i32,
+                offset_hi: (offset as u64 >> 32) as u32,
+                offset_lo: offset as u32,
+                flags,
+            },
+            payload: Bytes::from(&info[..]),
+            fds: Vec::new(),
+        }
+    }
+
+    /// Creates a packet telling the exporting side that we no longer use the block `block_id`.
+    pub fn new_shm_release(block_id: u32) -> Self {
+        Self::new_shm_control(FLAG_SHMRELEASE, block_id)
+    }
+
+    /// Creates a packet telling the importing side that it must stop using the block `block_id`.
+    pub fn new_shm_revoke(block_id: u32) -> Self {
+        Self::new_shm_control(FLAG_SHMREVOKE, block_id)
+    }
+
+    fn new_shm_control(flags: u32, block_id: u32) -> Self {
+        Packet {
+            desc: Descriptor {
+                length: 0,
+                channel: -1,
+                offset_hi: block_id,
+                offset_lo: 0,
+                flags,
+            },
+            payload: Bytes::new(),
+            fds: Vec::new(),
         }
     }
 
+    /// Attaches a file descriptor that will be sent along with the packet.
+    ///
+    /// This only works when the packet is sent over a Unix socket by a transport that supports
+    /// passing file descriptors. `PacketCodec` ignores them.
+    pub fn attach_fd(&mut self, fd: Fd) {
+        self.fds.push(fd);
+    }
+
+    /// Returns the file descriptors attached to the packet.
+    pub fn fds(&self) -> &[Fd] {
+        &self.fds
+    }
+
+    /// Removes and returns the file descriptors attached to the packet.
+    pub fn take_fds(&mut self) -> Vec<Fd> {
+        mem::replace(&mut self.fds, Vec::new())
+    }
+
     /// Decodes a `Packet` from a byte slice.
     ///
     /// Also see `PacketCodec` for another way of decoding packets.
@@ -96,6 +200,7 @@ impl fmt::Debug for Packet {
         f.debug_struct("Packet")
             .field("desc", &self.desc)
             .field("payload", &Message::from_packet(self))
+            .field("fds", &self.fds)
             .finish()
     }
 }
@@ -126,7 +231,9 @@ impl Decoder for PacketCodec {
         } else {
             // at least enough data for the header
             if self.desc.is_none() {
-                self.desc = Some(bincode::config().big_endian().deserialize_from(&mut &src[..])?);
+                let desc: Descriptor = bincode::config().big_endian().deserialize_from(&mut &src[..])?;
+                check_payload_length(desc.length)?;
+                self.desc = Some(desc);
             }
             let payload_len = self.desc.as_mut().unwrap().length as usize;
 
@@ -138,11 +245,12 @@ impl Decoder for PacketCodec {
                 payload.resize(payload_len, 0);
                 payload.copy_from_slice(&src[mem::size_of::<Descriptor>()..][..payload_len]);
 
-                // remember to reset `self` and clear `src` when yielding an item
-                src.clear();
+                // remember to reset `self` and remove the packet from `src` when yielding an item
+                src.advance(mem::size_of::<Descriptor>() + payload_len);
                 Ok(Some(Packet {
                     desc: self.desc.take().unwrap(),
                     payload: payload.freeze(),
+                    fds: Vec::new(),
                 }))
             }
         }
@@ -155,8 +263,10 @@ impl Encoder for PacketCodec {
 
     fn encode(&mut self, item: <Self as Encoder>::Item, dst: &mut BytesMut) -> Result<(), <Self as Encoder>::Error> {
         assert_eq!(item.desc.length as usize, item.payload.len());
-        dst.resize(mem::size_of::<Descriptor>() + item.desc.length as usize, 0);
-        let mut buf = &mut dst[..];
+        // `dst` might still contain earlier packets that weren't written yet
+        let start = dst.len();
+        dst.resize(start + mem::size_of::<Descriptor>() + item.desc.length as usize, 0);
+        let mut buf = &mut dst[start..];
         bincode::config().big_endian().serialize_into(&mut buf, &item.desc)?;
         buf.write_all(&item.payload)?;
         Ok(())
@@ -170,31 +280,139 @@ pub enum Message<'a> {
     Control {
         tagstruct: TagStructReader<'a>,
     },
+    /// A chunk of audio data for a stream.
     Memblock {
-
+        /// The stream channel the data belongs to.
+        channel: u32,
+        /// Offset to seek to before writing the data, interpreted according to `seek_mode`.
+        offset: i64,
+        seek_mode: SeekMode,
+        data: MemblockData<'a>,
     },
+    /// The peer no longer uses a shared memory block we exported.
     ShmRelease {
-
+        block_id: u32,
     },
+    /// The peer took back a shared memory block it exported to us.
     ShmRevoke {
+        block_id: u32,
+    },
+}
 
+/// The audio data carried by a memblock packet.
+#[derive(Debug)]
+pub enum MemblockData<'a> {
+    /// The data is stored in the packet.
+    Inline(&'a [u8]),
+    /// The data is stored in a shared memory segment.
+    Shm {
+        block: ShmBlock,
+        /// Whether the block is stored in a memfd segment (which must have been registered).
+        memfd: bool,
+        /// Whether the exporting side allows us to write to the block.
+        writable: bool,
     },
 }
 
+/// How the offset of a memblock is interpreted.
+#[repr(u8)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, FromPrimitive)]
+pub enum SeekMode {
+    /// Seek relative to the write index.
+    Relative = 0,
+    /// Seek to an absolute position.
+    Absolute = 1,
+    /// Seek relative to the read index.
+    RelativeOnRead = 2,
+    /// Seek relative to the current end of the buffer.
+    RelativeEnd = 3,
+}
+
 impl<'a> Message<'a> {
     /// Try to create a message from a raw packet.
     pub fn from_packet(packet: &'a Packet) -> Result<Self, Error> {
-        if packet.desc.channel == -1 {
-            // Control message containing tagstruct
-            Ok(Message::Control {
-                tagstruct: TagStructReader::from_raw(&packet.payload),
-            })
-        } else if packet.desc.flags == FLAG_SHMRELEASE {
-            unimplemented!("shmrelease messages");
-        } else if packet.desc.flags == FLAG_SHMREVOKE {
-            unimplemented!("shmrevoke messages");
+        let desc = &packet.desc;
+        if desc.channel == -1 {
+            match desc.flags & FLAG_SHMMASK {
+                FLAG_SHMRELEASE => Ok(Message::ShmRelease { block_id: desc.offset_hi }),
+                FLAG_SHMREVOKE => Ok(Message::ShmRevoke { block_id: desc.offset_hi }),
+                // Control message containing tagstruct
+                _ => Ok(Message::Control {
+                    tagstruct: TagStructReader::from_raw(&packet.payload),
+                }),
+            }
         } else {
-            unimplemented!("memblock messages");
+            let seek_mode = SeekMode::from_u32(desc.flags & FLAG_SEEKMASK)
+                .ok_or_else(|| Error::string(format!("invalid seek mode in flags {:#010x}", desc.flags)))?;
+
+            let data = if desc.flags & FLAG_SHMDATA != 0 {
+                if packet.payload.len() != SHM_INFO_SIZE {
+                    return Err(Error::string(format!("invalid SHM memblock payload length {}", packet.payload.len())));
+                }
+
+                let mut info = [0; 4];
+                BigEndian::read_u32_into(&packet.payload, &mut info);
+                MemblockData::Shm {
+                    block: ShmBlock {
+                        block_id: info[0],
+                        shm_id: info[1],
+                        offset: info[2],
+                        length: info[3],
+                    },
+                    memfd: desc.flags & FLAG_SHMDATA_MEMFD_BLOCK != 0,
+                    writable: desc.flags & FLAG_SHMWRITABLE != 0,
+                }
+            } else {
+                MemblockData::Inline(&packet.payload)
+            };
+
+            Ok(Message::Memblock {
+                channel: desc.channel as u32,
+                offset: ((desc.offset_hi as u64) << 32 | desc.offset_lo as u64) as i64,
+                seek_mode,
+                data,
+            })
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode() {
+        let mut buf = BytesMut::new();
+        let mut codec = PacketCodec::new();
+        codec.encode(Packet::new_command(b"abc"), &mut buf).unwrap();
+        codec.encode(Packet::new_command(b""), &mut buf).unwrap();
+
+        // Incomplete packets are only decoded once all data is there
+        let mut partial = BytesMut::from(&buf[..22]);
+        assert!(codec.decode(&mut partial).unwrap().is_none());
+        partial.extend_from_slice(&buf[22..]);
+
+        assert_eq!(&codec.decode(&mut partial).unwrap().unwrap().payload[..], b"abc");
+        assert_eq!(&codec.decode(&mut partial).unwrap().unwrap().payload[..], b"");
+        assert!(codec.decode(&mut partial).unwrap().is_none());
+        assert!(partial.is_empty());
+    }
+
+    #[test]
+    fn oversized_payload() {
+        let header = |length: u32| {
+            let mut buf = BytesMut::from(vec![0; 20]);
+            BigEndian::write_u32(&mut buf[..4], length);
+            BigEndian::write_i32(&mut buf[4..8], -1);
+            buf
+        };
+
+        // The header alone is enough to reject a packet
+        assert!(PacketCodec::new().decode(&mut header(u32::max_value())).is_err());
+        assert!(PacketCodec::new().decode(&mut header(MAX_PAYLOAD_SIZE as u32 + 1)).is_err());
+        assert!(PacketCodec::new().decode(&mut header(MAX_PAYLOAD_SIZE as u32)).unwrap().is_none());
+
+        assert!(check_payload_length(MAX_PAYLOAD_SIZE as u32 + 1).is_err());
+        assert_eq!(check_payload_length(MAX_PAYLOAD_SIZE as u32).unwrap(), MAX_PAYLOAD_SIZE);
+    }
+}
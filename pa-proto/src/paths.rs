@@ -1,7 +1,9 @@
 //! Utilities for finding PulseAudio paths.
 
+use user;
+
 use std::env;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::ffi::OsString;
 
 /// Locates the PulseAudio runtime directory.
@@ -20,12 +22,17 @@ pub fn runtime_dir() -> PathBuf {
         })
 }
 
+/// Locates the home directory of the current user.
+///
+/// This is `$HOME` (or `$USERPROFILE`) if set, or the directory listed in the password database
+/// otherwise. If the user has no entry there either, `/` is returned.
 pub fn user_home() -> OsString {
     env::var_os("HOME")
         .or_else(|| env::var_os("USERPROFILE"))
+        .or_else(|| user::home_dir(user::current_uid()).map(PathBuf::into_os_string))
         .unwrap_or_else(|| {
-            // TODO: Use getpwuid
-            unimplemented!();
+            warn!("couldn't determine home directory of UID {}, using /", user::current_uid());
+            OsString::from("/")
         })
 }
 
@@ -42,6 +49,41 @@ pub fn config_home_dir() -> PathBuf {
     }
 }
 
+/// Resolves `path` relative to `config_home_dir()`.
+///
+/// Absolute paths are returned unchanged.
+pub fn config_home_file<P: AsRef<Path>>(path: P) -> PathBuf {
+    config_home_dir().join(path)
+}
+
+/// Directory containing the system-wide PulseAudio configuration.
+pub const SYSTEM_CONFIG_DIR: &str = "/etc/pulse";
+
+/// Runtime directory of the system-wide server instance.
+pub const SYSTEM_RUNTIME_DIR: &str = "/var/run/pulse";
+
+const SCRIPT_NAME: &str = "default.pa";
+
+/// Locates the startup script the server should execute.
+///
+/// The user's script in `config_home_dir()` takes precedence over the system-wide one in
+/// `SYSTEM_CONFIG_DIR`. Returns `None` if neither exists.
+pub fn startup_script() -> Option<PathBuf> {
+    let mut user_script = config_home_dir();
+    user_script.push(SCRIPT_NAME);
+    if user_script.exists() {
+        return Some(user_script);
+    }
+
+    let mut system_script = PathBuf::from(SYSTEM_CONFIG_DIR);
+    system_script.push(SCRIPT_NAME);
+    if system_script.exists() {
+        return Some(system_script);
+    }
+
+    None
+}
+
 const COOKIE_NAME: &str = "cookie";
 const COOKIE_NAME_FALLBACK: &str = ".pulse-cookie";
 
@@ -0,0 +1,282 @@
+//! Management of the server's runtime directory.
+//!
+//! The runtime directory (see `paths::runtime_dir`) contains the server's sockets, so anyone who
+//! can write to it can impersonate the server. `create_secure_dir` makes sure that only the
+//! current user has access to it. The system-wide instance serves all users, so its directory is
+//! created with `create_shared_dir` instead, which lets everyone enter it, but still only lets the
+//! daemon user write to it.
+//!
+//! The directory also contains the PID file, which is locked by the running server to ensure that
+//! only one server uses the directory at a time. The lock is compatible with the one taken by
+//! PulseAudio, so pulsar and PulseAudio also won't fight over the same directory. Clients starting
+//! a server hold another lock (see `AUTOSPAWN_LOCK_NAME`), so that only one of them does.
+
+use user;
+
+use nix::fcntl::{fcntl, FcntlArg};
+use nix::libc;
+use nix::unistd::{self, Gid, Uid};
+
+use std::fs::{self, DirBuilder, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::os::unix::fs::{DirBuilderExt, MetadataExt, OpenOptionsExt, PermissionsExt};
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::mem;
+
+/// Name of the PID file in the runtime directory.
+pub const PID_FILE_NAME: &str = "pid";
+
+/// Name of the lock file clients hold while starting a server.
+pub const AUTOSPAWN_LOCK_NAME: &str = "autospawn.lock";
+
+const DIR_MODE: u32 = 0o700;  // drwx------
+const SHARED_DIR_MODE: u32 = 0o755;  // drwxr-xr-x
+
+/// Creates the directory `path`, accessible only by the current user.
+///
+/// If the directory already exists, it must be owned by the current user. Any permissions for
+/// other users are revoked. Missing parent directories are created with default permissions.
+pub fn create_secure_dir(path: &Path) -> io::Result<()> {
+    create_dir(path, DIR_MODE, user::current_uid(), None)
+}
+
+/// Creates the runtime directory of the system-wide instance, owned by the user `uid` and
+/// accessible by all users.
+///
+/// A new directory is handed to `uid` and `gid`, which requires root privileges unless they're
+/// the IDs of the current user. If the directory already exists, it must be owned by `uid`. Its
+/// permissions are set to `0755`, so that only its owner can create or replace files in it.
+pub fn create_shared_dir(path: &Path, uid: u32, gid: u32) -> io::Result<()> {
+    create_dir(path, SHARED_DIR_MODE, uid, Some(gid))
+}
+
+/// Creates the directory `path` with permissions `mode`, and makes sure it's owned by `uid`.
+///
+/// If `gid` is set, a newly created directory is handed to `uid` and `gid`.
+fn create_dir(path: &Path, mode: u32, uid: u32, gid: Option<u32>) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    match DirBuilder::new().mode(mode).create(path) {
+        Ok(()) => if let Some(gid) = gid {
+            unistd::chown(path, Some(Uid::from_raw(uid)), Some(Gid::from_raw(gid))).map_err(nix_to_io)?;
+        },
+        Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists => {}
+        Err(e) => return Err(e),
+    }
+
+    // Don't follow symlinks, they could point anywhere
+    let meta = fs::symlink_metadata(path)?;
+    if !meta.file_type().is_dir() {
+        return Err(io::Error::new(io::ErrorKind::AlreadyExists, format!(
+            "{} exists, but is not a directory", path.display()
+        )));
+    }
+    if meta.uid() != uid {
+        return Err(io::Error::new(io::ErrorKind::PermissionDenied, format!(
+            "{} is owned by UID {} instead of {}, refusing to use it", path.display(), meta.uid(), uid
+        )));
+    }
+    if meta.mode() & 0o777 != mode {
+        warn!("{} has mode 0o{:03o}, changing it to 0o{:03o}", path.display(), meta.mode() & 0o777, mode);
+        fs::set_permissions(path, fs::Permissions::from_mode(mode))?;
+    }
+
+    Ok(())
+}
+
+/// A locked PID file.
+///
+/// The lock is held as long as this object is alive. Dropping it deletes the file and releases the
+/// lock.
+#[derive(Debug)]
+pub struct PidFile {
+    path: PathBuf,
+    file: File,
+}
+
+impl PidFile {
+    /// Locks the PID file at `path` and writes the PID of this process into it.
+    ///
+    /// Returns an error of kind `AlreadyExists` if another process holds the lock.
+    pub fn create<P: Into<PathBuf>>(path: P) -> io::Result<Self> {
+        let path = path.into();
+        loop {
+            let file = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .mode(0o644)
+                .open(&path)?;
+
+            if !set_lock(&file, false)? {
+                let pid = lock_owner(&file)?.map_or_else(|| "unknown".to_string(), |pid| pid.to_string());
+                return Err(io::Error::new(io::ErrorKind::AlreadyExists, format!(
+                    "{} is locked by another process (PID {})", path.display(), pid
+                )));
+            }
+
+            // The previous owner might have deleted the file after we opened it, in which case we
+            // locked a file nobody else will ever look at
+            match fs::metadata(&path) {
+                Ok(ref meta) if meta.ino() == file.metadata()?.ino() => {}
+                Ok(_) => continue,
+                Err(ref e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e),
+            }
+
+            let mut pid_file = Self { path, file };
+            pid_file.file.set_len(0)?;
+            writeln!(pid_file.file, "{}", unistd::getpid())?;
+            return Ok(pid_file);
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        // Delete the file before releasing the lock (by closing it)
+        if let Err(e) = fs::remove_file(&self.path) {
+            warn!("couldn't remove PID file {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+/// An exclusive lock on a file.
+///
+/// The lock is released when this object is dropped. Unlike `PidFile`, the file is left in place.
+#[derive(Debug)]
+pub struct Lock {
+    _file: File,
+}
+
+impl Lock {
+    /// Locks the file at `path`, creating it if necessary.
+    ///
+    /// Blocks until no other process holds the lock.
+    pub fn acquire(path: &Path) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .mode(0o600)
+            .open(path)?;
+        set_lock(&file, true)?;
+        Ok(Self { _file: file })
+    }
+}
+
+/// Returns the PID of the process holding the lock on the PID file at `path`.
+///
+/// Returns `None` if the file doesn't exist or isn't locked, which means that no server is
+/// running. Left-over files of crashed servers are ignored this way.
+pub fn running_pid(path: &Path) -> Option<u32> {
+    let file = File::open(path).ok()?;
+    match lock_owner(&file) {
+        Ok(pid) => pid,
+        Err(e) => {
+            warn!("couldn't check lock on {}: {}", path.display(), e);
+            // Fall back to the PID stored in the file
+            let mut contents = String::new();
+            (&file).read_to_string(&mut contents).ok()?;
+            contents.trim().parse().ok()
+        }
+    }
+}
+
+fn new_flock(ty: libc::c_short) -> libc::flock {
+    let mut lock: libc::flock = unsafe { mem::zeroed() };
+    lock.l_type = ty;
+    lock.l_whence = libc::SEEK_SET as libc::c_short;
+    // `l_start` and `l_len` are 0, which locks the whole file
+    lock
+}
+
+/// Locks `file` for writing, waiting for other processes to release their lock if `wait` is set.
+///
+/// Returns `false` if `wait` isn't set and another process holds the lock.
+fn set_lock(file: &File, wait: bool) -> io::Result<bool> {
+    let lock = new_flock(libc::F_WRLCK as libc::c_short);
+    let arg = if wait { FcntlArg::F_SETLKW(&lock) } else { FcntlArg::F_SETLK(&lock) };
+    match fcntl(file.as_raw_fd(), arg) {
+        Ok(_) => Ok(true),
+        Err(e) => match nix_to_io(e) {
+            ref e if e.kind() == io::ErrorKind::WouldBlock || e.raw_os_error() == Some(libc::EACCES) => Ok(false),
+            e => Err(e),
+        },
+    }
+}
+
+/// Returns the PID of the process holding a write lock on `file`.
+fn lock_owner(file: &File) -> io::Result<Option<u32>> {
+    let mut lock = new_flock(libc::F_WRLCK as libc::c_short);
+    fcntl(file.as_raw_fd(), FcntlArg::F_GETLK(&mut lock)).map_err(nix_to_io)?;
+    if lock.l_type == libc::F_UNLCK as libc::c_short {
+        Ok(None)
+    } else {
+        Ok(Some(lock.l_pid as u32))
+    }
+}
+
+fn nix_to_io(e: ::nix::Error) -> io::Error {
+    match e {
+        ::nix::Error::Sys(errno) => errno.into(),
+        e => io::Error::new(io::ErrorKind::Other, e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::process;
+
+    #[test]
+    fn pid_file() {
+        let dir = env::temp_dir().join(format!("pa-proto-runtime-test-{}", process::id()));
+        fs::create_dir(&dir).unwrap();
+        fs::set_permissions(&dir, fs::Permissions::from_mode(0o755)).unwrap();
+        create_secure_dir(&dir).unwrap();
+        assert_eq!(fs::metadata(&dir).unwrap().mode() & 0o777, 0o700);
+
+        let path = dir.join(PID_FILE_NAME);
+        // Locks are per process, so another process would be needed to test the conflict. Our
+        // own lock isn't reported by `F_GETLK`.
+        let pid_file = PidFile::create(&path).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), format!("{}\n", process::id()));
+        assert_eq!(running_pid(&path), None);
+        drop(pid_file);
+        assert!(!path.exists());
+
+        fs::write(&path, "").unwrap();
+        assert!(create_secure_dir(&path).is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn shared_dir() {
+        let dir = env::temp_dir().join(format!("pa-proto-shared-test-{}", process::id()));
+        let (uid, gid) = (user::current_uid(), user::current_gid());
+
+        create_shared_dir(&dir, uid, gid).unwrap();
+        assert_eq!(fs::metadata(&dir).unwrap().mode() & 0o777, 0o755);
+
+        // Existing directories get their permissions fixed, but have to belong to the right user
+        fs::set_permissions(&dir, fs::Permissions::from_mode(0o777)).unwrap();
+        create_shared_dir(&dir, uid, gid).unwrap();
+        assert_eq!(fs::metadata(&dir).unwrap().mode() & 0o777, 0o755);
+        assert!(create_shared_dir(&dir, uid.wrapping_add(1), gid).is_err());
+
+        // The directory of the user instance is private again
+        create_secure_dir(&dir).unwrap();
+        assert_eq!(fs::metadata(&dir).unwrap().mode() & 0o777, 0o700);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,250 @@
+//! Parser for PulseAudio server strings, and the order in which clients try servers.
+//!
+//! A server string is a whitespace-separated list of servers to try in order. Each entry is one of:
+//!
+//! * `unix:/path/to/socket` or just `/path/to/socket`: A Unix domain socket.
+//! * `tcp:host[:port]`, `tcp4:host[:port]` or `tcp6:host[:port]`: A TCP server, optionally
+//!   restricted to IPv4 or IPv6. IPv6 addresses with a port have to be enclosed in brackets
+//!   (`tcp6:[::1]:4713`).
+//! * `host[:port]`: Same as `tcp:host[:port]`.
+//!
+//! Any entry can be prefixed with `{machine-id}`, in which case it is only used on the machine
+//! with that ID (see `machine_id`). This is how PulseAudio advertises its Unix socket in the X11
+//! root window without remote X clients trying to use it.
+
+use conf::ClientConf;
+use error::Error;
+use paths;
+
+use nix::unistd;
+
+use std::ffi::CStr;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::{fmt, fs};
+
+/// The TCP port PulseAudio servers listen on by default.
+pub const DEFAULT_PORT: u16 = 4713;
+
+/// The IP versions a TCP server may be reached with.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum IpFamily {
+    Any,
+    V4,
+    V6,
+}
+
+/// Address of a server.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ServerAddress {
+    /// A Unix domain socket.
+    Unix(PathBuf),
+    /// A TCP server. `host` is a host name or an IP address, which still needs to be resolved.
+    Tcp {
+        host: String,
+        port: u16,
+        family: IpFamily,
+    },
+}
+
+impl fmt::Display for ServerAddress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ServerAddress::Unix(ref path) => write!(f, "unix:{}", path.display()),
+            ServerAddress::Tcp { ref host, port, family } => {
+                let prefix = match family {
+                    IpFamily::Any => "tcp",
+                    IpFamily::V4 => "tcp4",
+                    IpFamily::V6 => "tcp6",
+                };
+                if host.contains(':') {
+                    write!(f, "{}:[{}]:{}", prefix, host, port)
+                } else {
+                    write!(f, "{}:{}:{}", prefix, host, port)
+                }
+            }
+        }
+    }
+}
+
+/// An entry of a server string.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Server {
+    machine_id: Option<String>,
+    address: ServerAddress,
+}
+
+impl Server {
+    /// ID of the machine this entry is restricted to, if any.
+    pub fn machine_id(&self) -> Option<&str> {
+        self.machine_id.as_ref().map(|id| &**id)
+    }
+
+    pub fn address(&self) -> &ServerAddress {
+        &self.address
+    }
+
+    /// Returns whether the entry may be used on this machine.
+    pub fn is_for_this_machine(&self) -> bool {
+        match self.machine_id {
+            Some(ref id) => machine_id().map_or(false, |local| *id == local),
+            None => true,
+        }
+    }
+}
+
+impl FromStr for Server {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        let (machine_id, rest) = if s.starts_with('{') {
+            let end = s.find('}')
+                .ok_or_else(|| Error::string(format!("unterminated machine ID in server '{}'", s)))?;
+            (Some(s[1..end].to_string()), &s[end + 1..])
+        } else {
+            (None, s)
+        };
+
+        let address = if rest.starts_with("unix:") || rest.starts_with('/') {
+            let path = if rest.starts_with("unix:") { &rest[5..] } else { rest };
+            if path.is_empty() {
+                return Err(Error::string(format!("missing socket path in server '{}'", s)));
+            }
+            ServerAddress::Unix(PathBuf::from(path))
+        } else if rest.starts_with("tcp4:") {
+            parse_tcp(&rest[5..], IpFamily::V4, s)?
+        } else if rest.starts_with("tcp6:") {
+            parse_tcp(&rest[5..], IpFamily::V6, s)?
+        } else if rest.starts_with("tcp:") {
+            parse_tcp(&rest[4..], IpFamily::Any, s)?
+        } else {
+            parse_tcp(rest, IpFamily::Any, s)?
+        };
+
+        Ok(Self { machine_id, address })
+    }
+}
+
+/// Parses the `host[:port]` part of a TCP server entry.
+fn parse_tcp(host_port: &str, family: IpFamily, server: &str) -> Result<ServerAddress, Error> {
+    let (host, port) = if host_port.starts_with('[') {
+        let end = host_port.find(']')
+            .ok_or_else(|| Error::string(format!("unterminated IPv6 address in server '{}'", server)))?;
+        let port = &host_port[end + 1..];
+        if !port.is_empty() && !port.starts_with(':') {
+            return Err(Error::string(format!("unexpected '{}' after IPv6 address in server '{}'", port, server)));
+        }
+        (&host_port[1..end], port.get(1..))
+    } else if host_port.matches(':').count() == 1 {
+        let mut split = host_port.splitn(2, ':');
+        (split.next().unwrap(), split.next())
+    } else {
+        // No port, or an IPv6 address without brackets (which can't have a port)
+        (host_port, None)
+    };
+
+    if host.is_empty() {
+        return Err(Error::string(format!("missing host in server '{}'", server)));
+    }
+    let port = match port {
+        Some(port) => match port.parse() {
+            Ok(0) | Err(_) => return Err(Error::string(format!("invalid port '{}' in server '{}'", port, server))),
+            Ok(port) => port,
+        },
+        None => DEFAULT_PORT,
+    };
+
+    Ok(ServerAddress::Tcp {
+        host: host.to_string(),
+        port,
+        family,
+    })
+}
+
+/// Parses a whitespace-separated list of servers.
+pub fn parse_list(s: &str) -> Result<Vec<Server>, Error> {
+    s.split_whitespace().map(str::parse).collect()
+}
+
+/// Parses a server string and returns the addresses usable on this machine, in order.
+pub fn candidates(s: &str) -> Result<Vec<ServerAddress>, Error> {
+    Ok(parse_list(s)?.into_iter()
+        .filter(|server| {
+            let usable = server.is_for_this_machine();
+            if !usable {
+                debug!("skipping server {} meant for machine {:?}", server.address, server.machine_id);
+            }
+            usable
+        })
+        .map(|server| server.address)
+        .collect())
+}
+
+/// Returns the addresses a client should try when no server was specified explicitly, in order.
+///
+/// If `conf` sets `default_server` (which the `PULSE_SERVER` environment variable overrides), only
+/// the servers listed there are used. Otherwise, the user's server (`native` in
+/// `paths::runtime_dir()`) is tried first, followed by the system-wide server in
+/// `paths::SYSTEM_RUNTIME_DIR`.
+pub fn default_candidates(conf: &ClientConf) -> Result<Vec<ServerAddress>, Error> {
+    if let Some(ref servers) = conf.default_server {
+        debug!("using configured servers: {}", servers);
+        return candidates(servers);
+    }
+
+    Ok(vec![
+        ServerAddress::Unix(paths::runtime_dir().join("native")),
+        ServerAddress::Unix(PathBuf::from(paths::SYSTEM_RUNTIME_DIR).join("native")),
+    ])
+}
+
+/// Returns the ID of this machine.
+///
+/// This is the D-Bus machine ID, or the host name if no ID is available.
+pub fn machine_id() -> Option<String> {
+    for path in &["/etc/machine-id", "/var/lib/dbus/machine-id"] {
+        if let Ok(id) = fs::read_to_string(path) {
+            let id = id.trim();
+            if !id.is_empty() {
+                return Some(id.to_string());
+            }
+        }
+    }
+
+    let mut buf = [0; 256];
+    unistd::gethostname(&mut buf).ok()
+        .and_then(|name: &CStr| name.to_str().ok())
+        .map(String::from)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tcp(host: &str, port: u16, family: IpFamily) -> ServerAddress {
+        ServerAddress::Tcp { host: host.to_string(), port, family }
+    }
+
+    #[test]
+    fn parse_server_strings() {
+        let servers = parse_list("unix:/run/pulse/native  /tmp/sock\ttcp:sidecar:4714 \
+            tcp6:[::1]:4713 tcp4:127.0.0.1 myhost ::1 {abc}unix:/x").unwrap();
+        let addresses = servers.iter().map(|s| s.address().clone()).collect::<Vec<_>>();
+        assert_eq!(addresses, vec![
+            ServerAddress::Unix(PathBuf::from("/run/pulse/native")),
+            ServerAddress::Unix(PathBuf::from("/tmp/sock")),
+            tcp("sidecar", 4714, IpFamily::Any),
+            tcp("::1", 4713, IpFamily::V6),
+            tcp("127.0.0.1", DEFAULT_PORT, IpFamily::V4),
+            tcp("myhost", DEFAULT_PORT, IpFamily::Any),
+            tcp("::1", DEFAULT_PORT, IpFamily::Any),
+            ServerAddress::Unix(PathBuf::from("/x")),
+        ]);
+        assert_eq!(servers[7].machine_id(), Some("abc"));
+        assert_eq!(addresses[3].to_string(), "tcp6:[::1]:4713");
+
+        for invalid in &["unix:", "tcp:", "tcp:host:0", "tcp:host:http", "[::1", "[::1]x", "{abc"] {
+            assert!(invalid.parse::<Server>().is_err(), "{} parsed", invalid);
+        }
+    }
+}
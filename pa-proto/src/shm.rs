@@ -0,0 +1,531 @@
+//! Shared memory used to transfer audio data without copying it through the socket.
+//!
+//! PulseAudio supports two kinds of shared memory segments:
+//!
+//! * POSIX shared memory (`shm_open`), named `/pulse-shm-<id>`. The peer opens the segment by
+//!   name when it first sees a memblock referencing it.
+//! * Linux' `memfd`. Since these have no name, the file descriptor is passed to the peer over the
+//!   Unix socket (as `SCM_RIGHTS` ancillary data) along with a `REGISTER_MEMFD_SHMID` command
+//!   that announces the segment's ID.
+//!
+//! Audio data stored in a segment is sent as a memblock packet referencing the block by segment
+//! ID, offset and length (see `ShmBlock`). The importing side sends an `SHMRELEASE` packet when it
+//! no longer needs the block, and the exporting side can take a block back by sending an
+//! `SHMREVOKE` packet.
+//!
+//! The exporting side of this is `ShmPool`, the importing side `ShmImports`.
+
+use error::Error;
+
+use nix::fcntl::OFlag;
+use nix::sys::memfd::{memfd_create, MemFdCreateFlag};
+use nix::sys::mman::{mmap, munmap, shm_open, shm_unlink, MapFlags, ProtFlags};
+use nix::sys::stat::{fstat, Mode};
+use nix::unistd::{close, dup, ftruncate};
+use nix::libc::{c_void, off_t};
+use rand;
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::ffi::CStr;
+use std::os::unix::io::{AsRawFd, FromRawFd, IntoRawFd, RawFd};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::{fmt, ptr, slice};
+
+/// Default size of a `ShmPool`, 64 MiB (same as PulseAudio).
+///
+/// Memory is only allocated by the kernel when it's first touched, so a large pool is cheap.
+pub const DEFAULT_POOL_SIZE: usize = 64 * 1024 * 1024;
+
+/// Size of the slots a `ShmPool` is divided into. Blocks always occupy whole slots.
+pub const SLOT_SIZE: usize = 64 * 1024;
+
+/// Maximum size of a segment we're willing to map.
+const MAX_SEGMENT_SIZE: usize = 1024 * 1024 * 1024;
+
+/// Maximum number of segments imported from a single peer (same as PulseAudio).
+const MAX_IMPORTED_SEGMENTS: usize = 16;
+
+/// Maximum number of blocks a peer may have exported to us at the same time.
+const MAX_IMPORTED_BLOCKS: usize = 160;
+
+/// Source of block IDs. They're unique in the whole process, so that a peer's release can be
+/// matched up with the right pool even if there are several.
+static NEXT_BLOCK_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// An owned file descriptor that is closed when dropped.
+#[derive(Debug)]
+pub struct Fd(RawFd);
+
+impl Fd {
+    /// Creates a new file descriptor referring to the same file.
+    pub fn try_clone(&self) -> Result<Self, Error> {
+        Ok(Fd(dup(self.0)?))
+    }
+}
+
+impl AsRawFd for Fd {
+    fn as_raw_fd(&self) -> RawFd {
+        self.0
+    }
+}
+
+impl IntoRawFd for Fd {
+    fn into_raw_fd(self) -> RawFd {
+        let fd = self.0;
+        ::std::mem::forget(self);
+        fd
+    }
+}
+
+impl FromRawFd for Fd {
+    unsafe fn from_raw_fd(fd: RawFd) -> Self {
+        Fd(fd)
+    }
+}
+
+impl Drop for Fd {
+    fn drop(&mut self) {
+        if let Err(e) = close(self.0) {
+            warn!("couldn't close file descriptor {}: {}", self.0, e);
+        }
+    }
+}
+
+/// The mechanism backing a shared memory segment.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ShmType {
+    /// POSIX shared memory, opened by name.
+    Posix,
+    /// Linux `memfd`, shared by passing the file descriptor.
+    Memfd,
+}
+
+/// Location of a block of audio data in a shared memory segment.
+///
+/// This is what a memblock packet carries instead of the data itself.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ShmBlock {
+    /// ID of the block, assigned by the exporting side. Used to release and revoke the block.
+    pub block_id: u32,
+    /// ID of the segment the block is stored in.
+    pub shm_id: u32,
+    /// Offset of the block inside the segment.
+    pub offset: u32,
+    /// Length of the block in Bytes.
+    pub length: u32,
+}
+
+/// A mapped shared memory segment.
+pub struct ShmSegment {
+    id: u32,
+    kind: ShmType,
+    /// The memfd backing the segment, kept so it can be passed to peers.
+    fd: Option<Fd>,
+    ptr: *mut u8,
+    size: usize,
+    /// Whether we created the segment (and thus need to unlink a POSIX segment on drop).
+    owned: bool,
+}
+
+// The mapping is owned by the `ShmSegment` and only accessed through it.
+unsafe impl Send for ShmSegment {}
+unsafe impl Sync for ShmSegment {}
+
+impl ShmSegment {
+    /// Creates a new, zero-filled, writable segment of `size` Bytes with a random ID.
+    pub fn create(kind: ShmType, size: usize) -> Result<Self, Error> {
+        if size == 0 || size > MAX_SEGMENT_SIZE {
+            return Err(Error::string(format!("invalid shared memory segment size {}", size)));
+        }
+
+        let id = rand::random();
+        let fd = match kind {
+            ShmType::Posix => Fd(shm_open(
+                &*posix_name(id),
+                OFlag::O_RDWR | OFlag::O_CREAT | OFlag::O_EXCL,
+                // Segments carry audio and srbchannel data, which other users must not read
+                Mode::S_IRUSR | Mode::S_IWUSR,
+            )?),
+            ShmType::Memfd => {
+                let name = CStr::from_bytes_with_nul(b"pulseaudio\0").unwrap();
+                Fd(memfd_create(name, MemFdCreateFlag::MFD_CLOEXEC)?)
+            }
+        };
+
+        let result = ftruncate(fd.as_raw_fd(), size as off_t)
+            .map_err(Error::from)
+            .and_then(|_| map(&fd, size, true));
+        let ptr = match result {
+            Ok(ptr) => ptr,
+            Err(e) => {
+                if kind == ShmType::Posix {
+                    shm_unlink(&*posix_name(id)).ok();
+                }
+                return Err(e);
+            }
+        };
+
+        debug!("created {:?} shared memory segment {} ({} Bytes)", kind, id, size);
+        Ok(Self {
+            id,
+            kind,
+            // POSIX segments are shared by name, so there's no need to keep the fd around
+            fd: if kind == ShmType::Memfd { Some(fd) } else { None },
+            ptr,
+            size,
+            owned: true,
+        })
+    }
+
+    /// Maps a read-only view of the segment `id` backed by the memfd `fd` received from a peer.
+    pub fn attach_memfd(id: u32, fd: Fd) -> Result<Self, Error> {
+        let size = segment_size(&fd)?;
+        let ptr = map(&fd, size, false)?;
+        Ok(Self { id, kind: ShmType::Memfd, fd: Some(fd), ptr, size, owned: false })
+    }
+
+    /// Opens and maps a read-only view of the POSIX shared memory segment `id` created by a peer.
+    pub fn attach_posix(id: u32) -> Result<Self, Error> {
+        let fd = Fd(shm_open(&*posix_name(id), OFlag::O_RDONLY, Mode::empty())?);
+        let size = segment_size(&fd)?;
+        let ptr = map(&fd, size, false)?;
+        Ok(Self { id, kind: ShmType::Posix, fd: None, ptr, size, owned: false })
+    }
+
+    /// Returns the ID of the segment.
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    /// Returns the mechanism backing the segment.
+    pub fn kind(&self) -> ShmType {
+        self.kind
+    }
+
+    /// Returns the size of the segment in Bytes.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Returns the memfd backing the segment, or `None` for POSIX segments.
+    pub fn memfd(&self) -> Option<&Fd> {
+        self.fd.as_ref()
+    }
+
+    /// Returns the contents of the segment.
+    pub fn as_slice(&self) -> &[u8] {
+        unsafe { slice::from_raw_parts(self.ptr, self.size) }
+    }
+
+    /// Returns the contents of a segment we created.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the segment was attached from a peer (those are mapped read-only).
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
+        assert!(self.owned, "attempted to write to an imported shared memory segment");
+        unsafe { slice::from_raw_parts_mut(self.ptr, self.size) }
+    }
+}
+
+impl fmt::Debug for ShmSegment {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ShmSegment")
+            .field("id", &self.id)
+            .field("kind", &self.kind)
+            .field("fd", &self.fd)
+            .field("size", &self.size)
+            .field("owned", &self.owned)
+            .finish()
+    }
+}
+
+impl Drop for ShmSegment {
+    fn drop(&mut self) {
+        unsafe {
+            munmap(self.ptr as *mut c_void, self.size).ok();
+        }
+
+        if self.owned && self.kind == ShmType::Posix {
+            if let Err(e) = shm_unlink(&*posix_name(self.id)) {
+                warn!("couldn't unlink shared memory segment {}: {}", self.id, e);
+            }
+        }
+    }
+}
+
+/// Returns the name of the POSIX shared memory segment with ID `id`.
+fn posix_name(id: u32) -> String {
+    format!("/pulse-shm-{}", id)
+}
+
+/// Returns the size of the segment backed by `fd`, making sure it's sane.
+fn segment_size(fd: &Fd) -> Result<usize, Error> {
+    let size = fstat(fd.as_raw_fd())?.st_size;
+    if size <= 0 || size as u64 > MAX_SEGMENT_SIZE as u64 {
+        return Err(Error::string(format!("invalid shared memory segment size {}", size)));
+    }
+    Ok(size as usize)
+}
+
+/// Maps `size` Bytes of `fd` into memory.
+fn map(fd: &Fd, size: usize, writable: bool) -> Result<*mut u8, Error> {
+    let prot = if writable {
+        ProtFlags::PROT_READ | ProtFlags::PROT_WRITE
+    } else {
+        ProtFlags::PROT_READ
+    };
+
+    let ptr = unsafe {
+        mmap(ptr::null_mut(), size, prot, MapFlags::MAP_SHARED, fd.as_raw_fd(), 0)?
+    };
+    Ok(ptr as *mut u8)
+}
+
+/// A shared memory segment from which blocks are allocated and exported to a peer.
+#[derive(Debug)]
+pub struct ShmPool {
+    segment: ShmSegment,
+    /// Which slots are in use.
+    used: Vec<bool>,
+    /// Exported blocks, by block ID.
+    blocks: BTreeMap<u32, ShmBlock>,
+}
+
+impl ShmPool {
+    /// Creates a pool backed by a new segment of `size` Bytes (rounded up to whole slots).
+    pub fn new(kind: ShmType, size: usize) -> Result<Self, Error> {
+        let slots = (size + SLOT_SIZE - 1) / SLOT_SIZE;
+        let segment = ShmSegment::create(kind, slots * SLOT_SIZE)?;
+        Ok(Self {
+            segment,
+            used: vec![false; slots],
+            blocks: BTreeMap::new(),
+        })
+    }
+
+    /// Returns the segment backing the pool.
+    ///
+    /// For memfd pools, its file descriptor has to be registered with the peer (using
+    /// `REGISTER_MEMFD_SHMID`) before the first block is sent.
+    pub fn segment(&self) -> &ShmSegment {
+        &self.segment
+    }
+
+    /// Allocates a block of `length` Bytes.
+    ///
+    /// Returns `None` if the pool doesn't have enough contiguous free space left.
+    pub fn alloc(&mut self, length: usize) -> Option<ShmBlock> {
+        if length == 0 || length > self.segment.size() {
+            return None;
+        }
+
+        let needed = (length + SLOT_SIZE - 1) / SLOT_SIZE;
+        let mut start = 0;
+        while start + needed <= self.used.len() {
+            match self.used[start..start + needed].iter().rposition(|&used| used) {
+                Some(pos) => start += pos + 1,
+                None => {
+                    for slot in &mut self.used[start..start + needed] {
+                        *slot = true;
+                    }
+
+                    let block = ShmBlock {
+                        block_id: NEXT_BLOCK_ID.fetch_add(1, Ordering::Relaxed) as u32,
+                        shm_id: self.segment.id(),
+                        offset: (start * SLOT_SIZE) as u32,
+                        length: length as u32,
+                    };
+                    self.blocks.insert(block.block_id, block);
+                    return Some(block);
+                }
+            }
+        }
+
+        None
+    }
+
+    /// Returns the contents of an allocated block.
+    pub fn block_mut(&mut self, block: &ShmBlock) -> &mut [u8] {
+        assert_eq!(self.blocks.get(&block.block_id), Some(block), "block not allocated from this pool");
+        &mut self.segment.as_mut_slice()[block.offset as usize..][..block.length as usize]
+    }
+
+    /// Frees the block `block_id` after the peer released it.
+    ///
+    /// Returns an error if no such block is allocated (ie. the peer sent a bogus release).
+    pub fn release(&mut self, block_id: u32) -> Result<(), Error> {
+        match self.blocks.remove(&block_id) {
+            Some(block) => {
+                self.free_slots(&block);
+                Ok(())
+            }
+            None => Err(Error::string(format!("release of unknown shared memory block {}", block_id))),
+        }
+    }
+
+    /// Takes back the block `block_id` without waiting for the peer to release it.
+    ///
+    /// The caller has to inform the peer by sending an `SHMREVOKE` packet. Returns `false` if no
+    /// such block was allocated.
+    pub fn revoke(&mut self, block_id: u32) -> bool {
+        match self.blocks.remove(&block_id) {
+            Some(block) => {
+                self.free_slots(&block);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Revokes all allocated blocks and returns their IDs.
+    pub fn revoke_all(&mut self) -> Vec<u32> {
+        let ids = self.blocks.keys().cloned().collect::<Vec<_>>();
+        for &id in &ids {
+            self.revoke(id);
+        }
+        ids
+    }
+
+    fn free_slots(&mut self, block: &ShmBlock) {
+        let start = block.offset as usize / SLOT_SIZE;
+        let count = (block.length as usize + SLOT_SIZE - 1) / SLOT_SIZE;
+        for slot in &mut self.used[start..start + count] {
+            *slot = false;
+        }
+    }
+}
+
+/// Segments and blocks imported from a peer.
+#[derive(Debug, Default)]
+pub struct ShmImports {
+    segments: HashMap<u32, ShmSegment>,
+    /// IDs of blocks the peer exported to us that haven't been released or revoked yet.
+    blocks: HashSet<u32>,
+}
+
+impl ShmImports {
+    /// Creates an empty set of imports.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers the memfd-backed segment `shm_id` sent by the peer.
+    pub fn register_memfd(&mut self, shm_id: u32, fd: Fd) -> Result<(), Error> {
+        if self.segments.contains_key(&shm_id) {
+            return Err(Error::string(format!("shared memory segment {} registered twice", shm_id)));
+        }
+        self.check_segment_limit()?;
+
+        let segment = ShmSegment::attach_memfd(shm_id, fd)?;
+        debug!("attached memfd segment {} ({} Bytes)", shm_id, segment.size());
+        self.segments.insert(shm_id, segment);
+        Ok(())
+    }
+
+    /// Returns the data of a block the peer sent us.
+    ///
+    /// `memfd` is the corresponding flag of the memblock packet. Memfd segments have to be
+    /// registered first, POSIX segments are attached on first use. The block counts as in use
+    /// until `release` or `revoke` is called.
+    pub fn get(&mut self, block: &ShmBlock, memfd: bool) -> Result<&[u8], Error> {
+        if !self.segments.contains_key(&block.shm_id) {
+            if memfd {
+                return Err(Error::string(format!("memblock references unregistered memfd segment {}", block.shm_id)));
+            }
+            self.check_segment_limit()?;
+
+            let segment = ShmSegment::attach_posix(block.shm_id)?;
+            debug!("attached POSIX segment {} ({} Bytes)", block.shm_id, segment.size());
+            self.segments.insert(block.shm_id, segment);
+        }
+
+        if !self.blocks.contains(&block.block_id) && self.blocks.len() >= MAX_IMPORTED_BLOCKS {
+            return Err(Error::string("too many shared memory blocks in use"));
+        }
+
+        let segment = &self.segments[&block.shm_id];
+        let end = block.offset as u64 + block.length as u64;
+        if end > segment.size() as u64 {
+            return Err(Error::string(format!(
+                "memblock {}+{} exceeds size of segment {} ({} Bytes)",
+                block.offset, block.length, block.shm_id, segment.size()
+            )));
+        }
+
+        self.blocks.insert(block.block_id);
+        Ok(&segment.as_slice()[block.offset as usize..end as usize])
+    }
+
+    /// Marks the block `block_id` as no longer used by us.
+    ///
+    /// Returns `true` if the block was in use, in which case the caller has to send an
+    /// `SHMRELEASE` packet to the peer.
+    pub fn release(&mut self, block_id: u32) -> bool {
+        self.blocks.remove(&block_id)
+    }
+
+    /// Handles the peer revoking the block `block_id`.
+    ///
+    /// The block must not be used anymore and must not be released.
+    pub fn revoke(&mut self, block_id: u32) {
+        self.blocks.remove(&block_id);
+    }
+
+    fn check_segment_limit(&self) -> Result<(), Error> {
+        if self.segments.len() >= MAX_IMPORTED_SEGMENTS {
+            Err(Error::string("too many shared memory segments imported"))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pool_alloc_release() {
+        let mut pool = ShmPool::new(ShmType::Memfd, 4 * SLOT_SIZE).unwrap();
+        let a = pool.alloc(SLOT_SIZE + 1).unwrap();
+        let b = pool.alloc(SLOT_SIZE).unwrap();
+        assert_eq!(a.offset, 0);
+        assert_eq!(b.offset as usize, 2 * SLOT_SIZE);
+        assert_eq!(pool.alloc(2 * SLOT_SIZE), None);
+
+        pool.block_mut(&a)[..4].copy_from_slice(b"test");
+        pool.release(a.block_id).unwrap();
+        assert!(pool.release(a.block_id).is_err());
+        assert_eq!(pool.alloc(2 * SLOT_SIZE).unwrap().offset, 0);
+
+        assert_eq!(pool.revoke_all().len(), 2);
+        assert!(!pool.revoke(b.block_id));
+    }
+
+    #[test]
+    fn posix_segment_mode() {
+        let segment = ShmSegment::create(ShmType::Posix, SLOT_SIZE).unwrap();
+        let fd = Fd(shm_open(&*posix_name(segment.id()), OFlag::O_RDONLY, Mode::empty()).unwrap());
+        assert_eq!(fstat(fd.as_raw_fd()).unwrap().st_mode & 0o777, 0o600);
+    }
+
+    #[test]
+    fn import_memfd() {
+        let mut pool = ShmPool::new(ShmType::Memfd, SLOT_SIZE).unwrap();
+        let block = pool.alloc(5).unwrap();
+        pool.block_mut(&block).copy_from_slice(b"hello");
+
+        let mut imports = ShmImports::new();
+        let fd = pool.segment().memfd().unwrap().try_clone().unwrap();
+        assert!(imports.get(&block, true).is_err());
+        imports.register_memfd(block.shm_id, fd).unwrap();
+        assert_eq!(imports.get(&block, true).unwrap(), b"hello");
+
+        let too_long = ShmBlock { length: SLOT_SIZE as u32, offset: 1, ..block };
+        assert!(imports.get(&too_long, true).is_err());
+
+        assert!(imports.release(block.block_id));
+        assert!(!imports.release(block.block_id));
+    }
+}
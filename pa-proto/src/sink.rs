@@ -49,7 +49,7 @@ bitflags! {
     }
 }
 
-#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+#[derive(Debug, Eq, PartialEq, Copy, Clone, FromPrimitive)]
 pub enum SinkState {
     /// Sink is playing samples: The sink is used by at least one non-paused input.
     Running = 0,
@@ -72,7 +72,7 @@ pub enum Direction {
 /// Port availability / jack detection status.
 /// \since 2.0
 // TODO: Clarify if this means "port available for playback/recording"
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, FromPrimitive)]
 pub enum Available {
     /// This port does not support jack detection.
     Unknown = 0,
@@ -147,6 +147,8 @@ pub struct Sink {
     active_port: usize,
     /// Supported sample formats.
     formats: Vec<FormatInfo>,
+    /// Index of the module that created this sink, if any.
+    owner_module: Option<u32>,
     /// The actual sink implementation.
     kind: Box<SinkImpl>,
 }
@@ -156,24 +158,45 @@ impl Sink {
     ///
     /// The server will create a dummy sink on startup if no other sinks can be found.
     pub fn new_dummy(index: u32) -> Self {
+        let channel_map = {
+            let mut map = ChannelMap::new();
+            map.push(ChannelPosition::FrontLeft).unwrap();
+            map.push(ChannelPosition::FrontRight).unwrap();
+            map
+        };
+
+        Self::new_null(
+            index,
+            PaString::new("Dummy Sink").unwrap(),
+            PropList::new(),
+            SampleSpec::new_checked(SampleFormat::Float32Le, 2, 48000).unwrap(),
+            channel_map,
+        )
+    }
+
+    /// Creates a sink with the given name and format that drops all samples sent to it.
+    ///
+    /// The sink's volume is initialized to `Volume::NORM` on all channels of `channel_map`.
+    pub fn new_null(
+        index: u32,
+        name: PaString,
+        props: PropList,
+        sample_spec: SampleSpec,
+        channel_map: ChannelMap,
+    ) -> Self {
+        let mut cvolume = CVolume::new();
+        for _ in &channel_map {
+            cvolume.push(Volume::NORM).unwrap();
+        }
+
         Self {
             index,
-            name: PaString::new("Dummy Sink").unwrap(),
-            props: PropList::new(),
+            name,
+            props,
             state: SinkState::Idle,
-            sample_spec: SampleSpec::new_checked(SampleFormat::Float32Le, 2, 48000).unwrap(),
-            channel_map: {
-                let mut map = ChannelMap::new();
-                map.push(ChannelPosition::FrontLeft).unwrap();
-                map.push(ChannelPosition::FrontRight).unwrap();
-                map
-            },
-            cvolume: {
-                let mut vol = CVolume::new();
-                vol.push(Volume::from_linear(1.0)).unwrap();
-                vol.push(Volume::from_linear(1.0)).unwrap();
-                vol
-            },
+            sample_spec,
+            channel_map,
+            cvolume,
             muted: false,
             flags: SinkFlags::empty(),
             ports: vec![
@@ -183,6 +206,7 @@ impl Sink {
             formats: vec![
                 FormatInfo::new(FormatEncoding::Pcm),
             ],
+            owner_module: None,
             kind: Box::new(DummySink),
         }
     }
@@ -207,6 +231,25 @@ impl Sink {
 
     pub fn cvolume(&self) -> &CVolume { &self.cvolume }
 
+    /// Sets the volume of all channels of this sink to `volume`.
+    pub fn set_volume(&mut self, volume: Volume) {
+        let mut cvolume = CVolume::new();
+        for _ in 0..self.cvolume.len() {
+            cvolume.push(volume).unwrap();
+        }
+        self.cvolume = cvolume;
+    }
+
+    /// Sets the volume of each channel of this sink.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `cvolume` doesn't have a volume for every channel.
+    pub fn set_cvolume(&mut self, cvolume: CVolume) {
+        assert_eq!(cvolume.len(), self.cvolume.len(), "wrong number of channels");
+        self.cvolume = cvolume;
+    }
+
     pub fn muted(&self) -> bool { self.muted }
 
     pub fn actual_latency(&self) -> Microseconds { Microseconds(0) }   // TODO
@@ -235,6 +278,16 @@ impl Sink {
     /// Most commonly used sinks of consumer hardware will only have support for a single format,
     /// PCM.
     pub fn formats(&self) -> &[FormatInfo] { &self.formats }
+
+    /// Index of the module that owns this sink.
+    ///
+    /// Sinks created by the server itself (such as the fallback dummy sink) have no owner module.
+    pub fn owner_module(&self) -> Option<u32> { self.owner_module }
+
+    /// Sets the module that owns this sink.
+    pub fn set_owner_module(&mut self, module: Option<u32>) {
+        self.owner_module = module;
+    }
 }
 
 pub trait SinkImpl: Debug + Send + Sync {
@@ -0,0 +1,203 @@
+//! Defines source data and utilities.
+
+use string::{PaStr, PaString};
+use sink::Port;
+use types::{
+    PropList, SampleSpec, SampleFormat, ChannelMap, ChannelPosition, CVolume, Volume, FormatInfo,
+    FormatEncoding
+};
+use time::Microseconds;
+
+use std::fmt::Debug;
+
+bitflags! {
+    pub struct SourceFlags: u32 {
+        /// Supports hardware volume control. This is a dynamic flag and may
+        /// change at runtime after the source has initialized.
+        const HW_VOLUME_CTRL = 0x0001;
+
+        /// Supports latency querying.
+        const LATENCY = 0x0002;
+
+        /// Is a hardware source of some kind, in contrast to
+        /// "virtual"/software source. \since 0.9.3
+        const HARDWARE = 0x0004;
+
+        /// Is a networked source of some kind. \since 0.9.7
+        const NETWORK = 0x0008;
+
+        /// Supports hardware mute control. This is a dynamic flag and may
+        /// change at runtime after the source has initialized. \since 0.9.11
+        const HW_MUTE_CTRL = 0x0010;
+
+        /// Volume can be translated to dB with pa_sw_volume_to_dB(). This is a
+        /// dynamic flag and may change at runtime after the source has initialized.
+        /// \since 0.9.11
+        const DECIBEL_VOLUME = 0x0020;
+
+        /// The latency can be adjusted dynamically depending on the
+        /// needs of the connected streams. \since 0.9.15
+        const DYNAMIC_LATENCY = 0x0040;
+
+        /// This source is in flat volume mode, i.e.\ always the maximum of
+        /// the volume of all connected outputs. \since 1.0
+        const FLAT_VOLUME = 0x0080;
+    }
+}
+
+#[derive(Debug, Eq, PartialEq, Copy, Clone, FromPrimitive)]
+pub enum SourceState {
+    /// Source is recording samples: The source is used by at least one non-paused output.
+    Running = 0,
+    /// Source is recording but has no connected outputs.
+    Idle,
+    /// Source is not currently recording and can be closed.
+    Suspended,
+}
+
+/// A source connected to a PulseAudio server.
+///
+/// Every source can have any number of Source Outputs, or streams recording from it.
+///
+/// Like a sink, a source always has a single configured sample spec, and all source outputs are
+/// converted from that format.
+#[derive(Debug)]
+pub struct Source {
+    index: u32,
+    name: PaString,
+    props: PropList,
+    state: SourceState,
+    sample_spec: SampleSpec,
+    channel_map: ChannelMap,    // make sure channel map length == sample spec channels
+    cvolume: CVolume,
+    /// Overrides `cvolume`.
+    muted: bool,
+    flags: SourceFlags,
+    ports: Vec<Port>,   // TODO: Vec1
+    active_port: usize,
+    /// Supported sample formats.
+    formats: Vec<FormatInfo>,
+    /// Index of the module that created this source, if any.
+    owner_module: Option<u32>,
+    /// The actual source implementation.
+    kind: Box<SourceImpl>,
+}
+
+impl Source {
+    /// Creates a dummy source that produces silence.
+    pub fn new_dummy(index: u32) -> Self {
+        let channel_map = {
+            let mut map = ChannelMap::new();
+            map.push(ChannelPosition::FrontLeft).unwrap();
+            map.push(ChannelPosition::FrontRight).unwrap();
+            map
+        };
+
+        Self::new_null(
+            index,
+            PaString::new("Dummy Source").unwrap(),
+            PropList::new(),
+            SampleSpec::new_checked(SampleFormat::Float32Le, 2, 48000).unwrap(),
+            channel_map,
+        )
+    }
+
+    /// Creates a source with the given name and format that produces silence.
+    ///
+    /// The source's volume is initialized to `Volume::NORM` on all channels of `channel_map`.
+    pub fn new_null(
+        index: u32,
+        name: PaString,
+        props: PropList,
+        sample_spec: SampleSpec,
+        channel_map: ChannelMap,
+    ) -> Self {
+        let mut cvolume = CVolume::new();
+        for _ in &channel_map {
+            cvolume.push(Volume::NORM).unwrap();
+        }
+
+        Self {
+            index,
+            name,
+            props,
+            state: SourceState::Idle,
+            sample_spec,
+            channel_map,
+            cvolume,
+            muted: false,
+            flags: SourceFlags::empty(),
+            ports: vec![
+                Port::new_input(PaString::new("Stereo Input").unwrap(), PaString::new("").unwrap(), 0),
+            ],
+            active_port: 0,
+            formats: vec![
+                FormatInfo::new(FormatEncoding::Pcm),
+            ],
+            owner_module: None,
+            kind: Box::new(DummySource),
+        }
+    }
+
+    /// Server-internal source ID.
+    pub fn index(&self) -> u32 { self.index }
+
+    /// The human readable name of the source.
+    pub fn name(&self) -> &PaStr { &self.name }
+
+    /// Gets the property list storing the properties associated with this source.
+    pub fn props(&self) -> &PropList { &self.props }
+
+    /// Current source state (eg. whether the source is actively recording).
+    pub fn state(&self) -> SourceState { self.state }
+
+    pub fn sample_spec(&self) -> &SampleSpec { &self.sample_spec }
+
+    pub fn channel_map(&self) -> &ChannelMap { &self.channel_map }
+
+    pub fn cvolume(&self) -> &CVolume { &self.cvolume }
+
+    pub fn muted(&self) -> bool { self.muted }
+
+    pub fn actual_latency(&self) -> Microseconds { Microseconds(0) }   // TODO
+
+    pub fn requested_latency(&self) -> Microseconds { Microseconds(0) } // TODO
+
+    pub fn flags(&self) -> SourceFlags { self.flags }
+
+    pub fn base_volume(&self) -> Volume { Volume::from_linear(1.0) }    // TODO
+    pub fn volume_steps(&self) -> u32 { 100 }   // TODO
+
+    /// Get the ports of this source.
+    ///
+    /// Like sinks, sources have at least one port and exactly one active port.
+    pub fn ports(&self) -> &[Port] { &self.ports }
+
+    /// Get a reference to the currently active port of this source.
+    pub fn active_port(&self) -> &Port {
+        &self.ports[self.active_port]
+    }
+
+    /// Get the list of supported sample formats.
+    pub fn formats(&self) -> &[FormatInfo] { &self.formats }
+
+    /// Index of the module that owns this source.
+    pub fn owner_module(&self) -> Option<u32> { self.owner_module }
+
+    /// Sets the module that owns this source.
+    pub fn set_owner_module(&mut self, module: Option<u32>) {
+        self.owner_module = module;
+    }
+}
+
+pub trait SourceImpl: Debug + Send + Sync {
+
+}
+
+/// A source that produces nothing but silence.
+#[derive(Debug)]
+pub struct DummySource;
+
+impl SourceImpl for DummySource {
+
+}
@@ -0,0 +1,356 @@
+//! Shared ringbuffer channel ("srbchannel"), supported since protocol version 30.
+//!
+//! Once set up, all packets that don't carry file descriptors are exchanged through a pair of
+//! ringbuffers in shared memory instead of the socket. Each side signals the other through an
+//! eventfd when it has written data (or made room in a previously full buffer), and only does so
+//! when the other side is actually waiting, so a busy stream needs no syscalls at all.
+//!
+//! The server creates the channel and announces it with an `ENABLE_SRBCHANNEL` command carrying
+//! both eventfds, followed by a writable memblock containing the ringbuffers. The client
+//! acknowledges with another `ENABLE_SRBCHANNEL` command using the same tag, after which both
+//! sides send packets through the channel.
+//!
+//! The memory layout matches PulseAudio's, so stock clients can use the channel.
+
+use error::Error;
+use shm::{Fd, ShmBlock, ShmPool, ShmType, SLOT_SIZE};
+
+use nix::sys::eventfd::{eventfd, EfdFlags};
+use nix::libc;
+use nix::unistd;
+use byteorder::{NativeEndian, ByteOrder};
+
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::{mem, ptr};
+
+/// Shared state of an fd semaphore (`pa_fdsem_data`).
+#[repr(C)]
+struct FdSemData {
+    waiting: AtomicI32,
+    signalled: AtomicI32,
+    in_pipe: AtomicI32,
+}
+
+/// Layout of the start of the shared memory block (`struct srbheader`).
+#[repr(C)]
+struct SrbHeader {
+    read_count: AtomicI32,
+    write_count: AtomicI32,
+    read_semdata: FdSemData,
+    write_semdata: FdSemData,
+    capacity: i32,
+    readbuf_offset: i32,
+    writebuf_offset: i32,
+}
+
+/// Rounds `n` up to the pointer size, like PulseAudio's `PA_ALIGN`.
+fn align(n: usize) -> usize {
+    let a = mem::size_of::<usize>();
+    (n + a - 1) / a * a
+}
+
+/// One direction of the channel. The fill level is shared, the indices are local.
+#[derive(Debug)]
+struct RingBuffer {
+    /// Offset of the fill level counter from the start of the header.
+    count_offset: usize,
+    /// Offset of the buffer memory from the start of the header.
+    offset: usize,
+    capacity: usize,
+    index: usize,
+}
+
+/// The server side of an srbchannel.
+///
+/// Reading and writing never block. The owner is responsible for waiting on `read_sem` (see
+/// `before_poll` and `after_poll`) when there's nothing to read or no room to write.
+#[derive(Debug)]
+pub struct SrbChannel {
+    /// The pool holding the ringbuffer block. Keeps the mapping alive.
+    pool: ShmPool,
+    block: ShmBlock,
+    header: *mut u8,
+    rb_read: RingBuffer,
+    rb_write: RingBuffer,
+    /// We wait on this eventfd for data to read, or room to write.
+    read_sem: Fd,
+    /// We signal the peer through this eventfd.
+    write_sem: Fd,
+    /// Whether we announced that we're waiting on `read_sem` (`before_poll` returned `true`).
+    waiting: bool,
+    /// Whether this is the client's view of the channel, which has the directions swapped.
+    swapped: bool,
+}
+
+// The block is only accessed through the `SrbChannel` (and the peer).
+unsafe impl Send for SrbChannel {}
+
+impl SrbChannel {
+    /// Creates a channel in a new shared memory pool of type `kind`.
+    ///
+    /// For memfd pools, the pool's segment needs to be registered with the client before the
+    /// ringbuffer block is sent.
+    pub fn new(kind: ShmType) -> Result<Self, Error> {
+        let mut pool = ShmPool::new(kind, SLOT_SIZE)?;
+        let block = pool.alloc(SLOT_SIZE).expect("couldn't allocate srbchannel block");
+        let header = pool.block_mut(&block).as_mut_ptr();
+
+        // Same layout calculation as PulseAudio
+        let readbuf_offset = align(mem::size_of::<SrbHeader>());
+        let capacity = (block.length as usize - readbuf_offset) / 2;
+        let writebuf_offset = align(readbuf_offset + capacity);
+        let capacity = capacity.min(writebuf_offset - readbuf_offset);
+
+        unsafe {
+            ptr::write_bytes(header, 0, mem::size_of::<SrbHeader>());
+            let h = &mut *(header as *mut SrbHeader);
+            h.capacity = capacity as i32;
+            h.readbuf_offset = readbuf_offset as i32;
+            h.writebuf_offset = writebuf_offset as i32;
+        }
+
+        let new_sem = || -> Result<Fd, Error> {
+            Ok(unsafe { Fd::from_raw_fd(eventfd(0, EfdFlags::EFD_CLOEXEC)?) })
+        };
+
+        debug!("srbchannel block is {} Bytes, ringbuffer capacity is 2 * {} Bytes", block.length, capacity);
+        Ok(Self {
+            pool,
+            block,
+            header,
+            rb_read: RingBuffer { count_offset: 0, offset: readbuf_offset, capacity, index: 0 },
+            rb_write: RingBuffer { count_offset: mem::size_of::<AtomicI32>(), offset: writebuf_offset, capacity, index: 0 },
+            read_sem: new_sem()?,
+            write_sem: new_sem()?,
+            waiting: false,
+            swapped: false,
+        })
+    }
+
+    /// Returns the pool containing the ringbuffer block.
+    pub fn pool(&self) -> &ShmPool {
+        &self.pool
+    }
+
+    /// Returns the block to send to the client (as a writable memblock).
+    pub fn block(&self) -> &ShmBlock {
+        &self.block
+    }
+
+    /// Returns the eventfds to send to the client with `ENABLE_SRBCHANNEL`, in protocol order.
+    pub fn sems(&self) -> [&Fd; 2] {
+        [&self.read_sem, &self.write_sem]
+    }
+
+    /// Returns the eventfd signalled by the client. Wait for it to become readable after
+    /// `before_poll` returned `true`.
+    pub fn read_sem(&self) -> &Fd {
+        &self.read_sem
+    }
+
+    fn header(&self) -> &SrbHeader {
+        unsafe { &*(self.header as *const SrbHeader) }
+    }
+
+    /// Returns the shared state of the semaphore we wait on.
+    fn read_semdata(&self) -> &FdSemData {
+        let h = self.header();
+        if self.swapped { &h.write_semdata } else { &h.read_semdata }
+    }
+
+    /// Returns the shared state of the semaphore the peer waits on.
+    fn write_semdata(&self) -> &FdSemData {
+        let h = self.header();
+        if self.swapped { &h.read_semdata } else { &h.write_semdata }
+    }
+
+    fn count(&self, rb: &RingBuffer) -> &AtomicI32 {
+        unsafe { &*(self.header.add(rb.count_offset) as *const AtomicI32) }
+    }
+
+    /// Reads up to `buf.len()` Bytes from the channel. Returns the number of Bytes read.
+    pub fn read(&mut self, buf: &mut [u8]) -> usize {
+        let mut read = 0;
+        while read < buf.len() {
+            let count = self.count(&self.rb_read).load(Ordering::SeqCst).max(0) as usize;
+            let rb = &self.rb_read;
+            let n = count.min(rb.capacity - rb.index).min(buf.len() - read);
+            if n == 0 {
+                break;
+            }
+
+            unsafe {
+                ptr::copy_nonoverlapping(self.header.add(rb.offset + rb.index), buf[read..].as_mut_ptr(), n);
+            }
+
+            // If the buffer was full, the peer might be waiting for room to write
+            let was_full = self.count(rb).fetch_sub(n as i32, Ordering::SeqCst) as usize >= rb.capacity;
+            self.rb_read.index = (self.rb_read.index + n) % self.rb_read.capacity;
+            if was_full {
+                self.post();
+            }
+            read += n;
+        }
+
+        read
+    }
+
+    /// Writes as much of `data` to the channel as fits and signals the peer.
+    ///
+    /// Returns the number of Bytes written.
+    pub fn write(&mut self, data: &[u8]) -> usize {
+        let mut written = 0;
+        while written < data.len() {
+            let count = self.count(&self.rb_write).load(Ordering::SeqCst).max(0) as usize;
+            let rb = &self.rb_write;
+            let n = (rb.capacity - rb.index).min(rb.capacity - count).min(data.len() - written);
+            if n == 0 {
+                break;
+            }
+
+            unsafe {
+                ptr::copy_nonoverlapping(data[written..].as_ptr(), self.header.add(rb.offset + rb.index), n);
+            }
+
+            self.count(rb).fetch_add(n as i32, Ordering::SeqCst);
+            self.rb_write.index = (self.rb_write.index + n) % self.rb_write.capacity;
+            written += n;
+        }
+
+        self.post();
+        written
+    }
+
+    /// Signals the peer's semaphore (`pa_fdsem_post`).
+    fn post(&self) {
+        let sem = self.write_semdata();
+        if sem.signalled.compare_exchange(0, 1, Ordering::SeqCst, Ordering::SeqCst).is_ok() && sem.waiting.load(Ordering::SeqCst) != 0 {
+            sem.in_pipe.fetch_add(1, Ordering::SeqCst);
+            let mut buf = [0; 8];
+            NativeEndian::write_u64(&mut buf, 1);
+            if let Err(e) = unistd::write(self.write_sem.as_raw_fd(), &buf) {
+                warn!("couldn't signal srbchannel peer: {}", e);
+            }
+        }
+    }
+
+    /// Announces that we're about to wait on `read_sem` (`pa_fdsem_before_poll`).
+    ///
+    /// Returns `false` if the semaphore was already signalled. In that case, there's no need to
+    /// wait and the buffers should be checked again instead. Calling this again before
+    /// `after_poll` has no effect.
+    pub fn before_poll(&mut self) -> bool {
+        if self.waiting {
+            return true;
+        }
+
+        let sem = self.read_semdata();
+        sem.waiting.fetch_add(1, Ordering::SeqCst);
+        if sem.signalled.compare_exchange(1, 0, Ordering::SeqCst, Ordering::SeqCst).is_ok() {
+            sem.waiting.fetch_sub(1, Ordering::SeqCst);
+            return false;
+        }
+
+        self.waiting = true;
+        true
+    }
+
+    /// Finishes waiting on `read_sem` (`pa_fdsem_after_poll`).
+    ///
+    /// Call this when `read_sem` became readable. Does nothing if we weren't waiting.
+    pub fn after_poll(&mut self) {
+        if !self.waiting {
+            return;
+        }
+        self.waiting = false;
+
+        let sem = self.read_semdata();
+        sem.waiting.fetch_sub(1, Ordering::SeqCst);
+
+        // Drain the eventfd. It's shared with the client, so we can't make it non-blocking and
+        // have to check whether the signal actually arrived before reading.
+        while sem.in_pipe.load(Ordering::SeqCst) > 0 && is_readable(&self.read_sem) {
+            let mut buf = [0; 8];
+            match unistd::read(self.read_sem.as_raw_fd(), &mut buf) {
+                Ok(8) => {
+                    sem.in_pipe.fetch_sub(NativeEndian::read_u64(&buf) as i32, Ordering::SeqCst);
+                }
+                Ok(_) | Err(_) => break,
+            }
+        }
+
+        sem.signalled.compare_exchange(1, 0, Ordering::SeqCst, Ordering::SeqCst).ok();
+    }
+}
+
+impl Drop for SrbChannel {
+    fn drop(&mut self) {
+        self.after_poll();
+    }
+}
+
+/// Checks whether `fd` can be read without blocking.
+fn is_readable(fd: &Fd) -> bool {
+    let mut pollfd = libc::pollfd {
+        fd: fd.as_raw_fd(),
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    unsafe { libc::poll(&mut pollfd, 1, 0) == 1 && pollfd.revents & libc::POLLIN != 0 }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Swaps the directions of a channel, giving the client's view of it.
+    fn client_view(server: &SrbChannel) -> SrbChannel {
+        let pool = ShmPool::new(ShmType::Memfd, SLOT_SIZE).unwrap();
+        SrbChannel {
+            pool,
+            block: server.block,
+            header: server.header,
+            rb_read: RingBuffer { index: 0, ..server.rb_write },
+            rb_write: RingBuffer { index: 0, ..server.rb_read },
+            read_sem: server.write_sem.try_clone().unwrap(),
+            write_sem: server.read_sem.try_clone().unwrap(),
+            waiting: false,
+            swapped: true,
+        }
+    }
+
+    #[test]
+    fn roundtrip() {
+        let mut server = SrbChannel::new(ShmType::Memfd).unwrap();
+        let mut client = client_view(&server);
+        let capacity = server.rb_write.capacity;
+
+        assert_eq!(server.write(b"hello"), 5);
+        let mut buf = [0; 16];
+        assert_eq!(client.read(&mut buf), 5);
+        assert_eq!(&buf[..5], b"hello");
+        assert_eq!(client.read(&mut buf), 0);
+
+        // Fill the buffer, wrapping around
+        let data = vec![0xAB; capacity + 10];
+        assert_eq!(server.write(&data), capacity);
+        assert_eq!(server.write(&data), 0);
+
+        // The client waits, then the server signals it by writing
+        let mut big = vec![0; capacity];
+        assert_eq!(client.read(&mut big), capacity);
+        assert!(big.iter().all(|&b| b == 0xAB));
+        // The earlier writes left the semaphore signalled
+        assert!(!client.before_poll());
+        assert!(client.before_poll());
+        server.write(b"x");
+        assert!(is_readable(client.read_sem()));
+        client.after_poll();
+        assert!(!is_readable(client.read_sem()));
+        assert_eq!(client.read(&mut buf), 1);
+
+        // Stop the client's `Drop` from messing with the server's state
+        mem::forget(client);
+    }
+}
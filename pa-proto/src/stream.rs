@@ -5,12 +5,19 @@
 
 #![allow(unused)]   // TODO remove
 
+use channel_map::ChannelMap;
+use command::{CreatePlaybackStream, CreateRecordStream, SinkSpec, SourceSpec};
+use error::Error;
+use proplist::PropList;
+use sample_spec::SampleSpec;
 use sink::Sink;
-use std::rc::Rc;
 use time::Microseconds;
 
+use std::rc::Rc;
+use std::u32;
+
 /// The direction of a stream.
-#[derive(Debug)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum StreamDirection {
     /// Playback stream.
     Playback,
@@ -183,7 +190,7 @@ bitflags! {
 }
 
 /// Playback and record buffer metrics.
-#[derive(Default, Debug)]
+#[derive(Default, Debug, Clone)]
 pub struct BufferAttr {
     /// Maximum length of the buffer in bytes. Setting this to `u32::MAX`
     /// will initialize this to the maximum value supported by server,
@@ -260,6 +267,19 @@ pub struct BufferAttr {
     pub fragsize: u32,
 }
 
+impl BufferAttr {
+    /// Buffer metrics that leave every value up to the server.
+    pub fn server_default() -> Self {
+        BufferAttr {
+            maxlength: u32::MAX,
+            tlength: u32::MAX,
+            prebuf: u32::MAX,
+            minreq: u32::MAX,
+            fragsize: u32::MAX,
+        }
+    }
+}
+
 /// A playback stream connecting a source with a sink.
 ///
 /// A stream always has a fixed sample format that's not necessarily equivalent with the current
@@ -277,15 +297,32 @@ impl Stream {
     pub fn sink(&self) -> &Sink { &self.sink }
 }
 
-#[derive(Debug)]
+/// Collects the configuration of a stream to create.
+///
+/// The resulting `CreatePlaybackStream` or `CreateRecordStream` command asks the server to create
+/// the stream.
+#[derive(Debug, Clone)]
 pub struct StreamBuilder {
+    sample_spec: SampleSpec,
+    channel_map: Option<ChannelMap>,
+    buffer_attr: BufferAttr,
     flags: StreamFlags,
+    props: PropList,
 }
 
 impl StreamBuilder {
-    pub fn new() -> Self {
+    /// Creates a builder for a stream transporting audio in the format described by
+    /// `sample_spec`.
+    ///
+    /// By default, the default channel map for the number of channels is used and the buffer
+    /// metrics are left up to the server.
+    pub fn new(sample_spec: SampleSpec) -> Self {
         Self {
+            sample_spec,
+            channel_map: None,
+            buffer_attr: BufferAttr::server_default(),
             flags: StreamFlags::empty(),
+            props: PropList::new(),
         }
     }
 
@@ -293,4 +330,87 @@ impl StreamBuilder {
         self.flags |= flags;
         self
     }
+
+    /// Sets the meaning of the stream's channels.
+    pub fn channel_map(&mut self, channel_map: ChannelMap) -> &mut Self {
+        self.channel_map = Some(channel_map);
+        self
+    }
+
+    /// Sets the buffer metrics to request.
+    pub fn buffer_attr(&mut self, buffer_attr: BufferAttr) -> &mut Self {
+        self.buffer_attr = buffer_attr;
+        self
+    }
+
+    /// Sets the stream properties (such as `Prop::MediaName`).
+    pub fn props(&mut self, props: PropList) -> &mut Self {
+        self.props = props;
+        self
+    }
+
+    pub fn flags(&self) -> StreamFlags {
+        self.flags
+    }
+
+    pub fn sample_spec(&self) -> &SampleSpec {
+        &self.sample_spec
+    }
+
+    /// Builds the command creating a playback stream connected to `sink` (or the default sink).
+    pub fn playback<'a>(&self, sink: Option<SinkSpec<'a>>) -> Result<CreatePlaybackStream<'a>, Error> {
+        Ok(CreatePlaybackStream::new(
+            self.props.clone(),
+            self.sample_spec.clone(),
+            self.resolve_channel_map()?,
+            self.buffer_attr.clone(),
+            self.flags,
+            sink,
+        ))
+    }
+
+    /// Builds the command creating a record stream connected to `source` (or the default source).
+    pub fn record<'a>(&self, source: Option<SourceSpec<'a>>) -> Result<CreateRecordStream<'a>, Error> {
+        Ok(CreateRecordStream::new(
+            self.props.clone(),
+            self.sample_spec.clone(),
+            self.resolve_channel_map()?,
+            self.buffer_attr.clone(),
+            self.flags,
+            source,
+        ))
+    }
+
+    fn resolve_channel_map(&self) -> Result<ChannelMap, Error> {
+        let channels = self.sample_spec.channels();
+        match self.channel_map {
+            Some(ref map) if map.len() != channels => Err(Error::string(format!(
+                "channel map has {} channels, but the sample spec has {}", map.len(), channels
+            ))),
+            Some(ref map) => Ok(map.clone()),
+            None => ChannelMap::default_for(channels)
+                .ok_or_else(|| Error::string(format!("no default channel map for {} channels", channels))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sample_spec::SampleFormat;
+
+    #[test]
+    fn builder_channel_map() {
+        let spec = SampleSpec::new_checked(SampleFormat::S16Le, 2, 44100).unwrap();
+        let mut builder = StreamBuilder::new(spec);
+        builder.add_flags(StreamFlags::INTERPOLATE_TIMING);
+
+        let params = builder.playback(None).unwrap();
+        assert_eq!(params.channel_map().len(), 2);
+        assert_eq!(params.stream_flags(), StreamFlags::INTERPOLATE_TIMING);
+
+        builder.channel_map(ChannelMap::default_for(1).unwrap());
+        assert!(builder.playback(None).is_err());
+        assert!(builder.record(None).is_err());
+    }
 }
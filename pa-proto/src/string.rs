@@ -12,6 +12,7 @@ use std::string::FromUtf8Error;
 /// Contains no interior nul bytes, but a nul terminator, and might not be valid UTF-8. It
 /// implements `Display` like normal and will replace invalid code points with the replacement
 /// character.
+#[derive(Clone, PartialEq, Eq)]
 pub struct PaString {
     inner: CString,
 }
@@ -89,6 +90,11 @@ impl PaStr {
     pub fn to_str(&self) -> Result<&str, Utf8Error> {
         self.inner.to_str()
     }
+
+    /// Returns the underlying `CStr`.
+    pub fn as_cstr(&self) -> &CStr {
+        &self.inner
+    }
 }
 
 impl fmt::Display for PaStr {
@@ -103,6 +109,28 @@ impl fmt::Debug for PaStr {
     }
 }
 
+impl PartialEq for PaStr {
+    fn eq(&self, other: &Self) -> bool {
+        self.to_bytes() == other.to_bytes()
+    }
+}
+
+impl Eq for PaStr {}
+
+impl Borrow<PaStr> for PaString {
+    fn borrow(&self) -> &PaStr {
+        self
+    }
+}
+
+impl ToOwned for PaStr {
+    type Owned = PaString;
+
+    fn to_owned(&self) -> PaString {
+        PaString::from(self.inner.to_owned())
+    }
+}
+
 impl<'a> From<&'a CStr> for &'a PaStr {
     fn from(cstr: &'a CStr) -> Self {
         unsafe { PaStr::new_unchecked(cstr.to_bytes_with_nul()) }
@@ -1,7 +1,38 @@
 //! Defines custom time units for use with PulseAudio.
 
+use std::time::{SystemTime, UNIX_EPOCH};
+
 /// A (whole) number of microseconds.
 ///
 /// Used for specifying latencies.
 #[derive(Debug, Clone, Copy, Ord, PartialOrd, Eq, PartialEq)]
 pub struct Microseconds(pub u64);
+
+/// A point in time, transmitted as seconds and microseconds since the Unix epoch.
+///
+/// Used by latency queries to let clients estimate the transport delay.
+#[derive(Debug, Clone, Copy, Ord, PartialOrd, Eq, PartialEq)]
+pub struct Timeval {
+    pub sec: u32,
+    pub usec: u32,
+}
+
+impl Timeval {
+    /// Returns the current wall-clock time.
+    pub fn now() -> Self {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        Timeval {
+            sec: now.as_secs() as u32,
+            usec: now.subsec_micros(),
+        }
+    }
+
+    /// Returns the number of microseconds from `earlier` to `self`, or 0 if `earlier` is later.
+    pub fn micros_since(&self, earlier: Timeval) -> u64 {
+        self.as_micros().saturating_sub(earlier.as_micros())
+    }
+
+    fn as_micros(&self) -> u64 {
+        u64::from(self.sec) * 1_000_000 + u64::from(self.usec)
+    }
+}
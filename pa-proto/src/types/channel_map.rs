@@ -2,6 +2,7 @@
 
 use types::sample_spec::CHANNELS_MAX;
 
+use num_traits::FromPrimitive;
 use std::fmt;
 
 /// Channel position labels.
@@ -78,6 +79,82 @@ pub enum ChannelPosition {
     TopRearCenter,
 }
 
+impl ChannelPosition {
+    /// Parses a channel position name as understood by PulseAudio (eg. `front-left`).
+    ///
+    /// Also accepts the aliases `left`, `right`, `center` and `subwoofer`. Returns `None` if
+    /// `name` isn't a known channel position.
+    pub fn from_name(name: &str) -> Option<Self> {
+        use self::ChannelPosition::*;
+
+        Some(match name {
+            "mono" => Mono,
+            "front-left" | "left" => FrontLeft,
+            "front-right" | "right" => FrontRight,
+            "front-center" | "center" => FrontCenter,
+            "rear-center" => RearCenter,
+            "rear-left" => RearLeft,
+            "rear-right" => RearRight,
+            "lfe" | "subwoofer" => Lfe,
+            "front-left-of-center" => FrontLeftOfCenter,
+            "front-right-of-center" => FrontRightOfCenter,
+            "side-left" => SideLeft,
+            "side-right" => SideRight,
+            "top-center" => TopCenter,
+            "top-front-left" => TopFrontLeft,
+            "top-front-right" => TopFrontRight,
+            "top-front-center" => TopFrontCenter,
+            "top-rear-left" => TopRearLeft,
+            "top-rear-right" => TopRearRight,
+            "top-rear-center" => TopRearCenter,
+            _ if name.starts_with("aux") => {
+                let n: u8 = name[3..].parse().ok()?;
+                if n >= 32 {
+                    return None;
+                }
+                // `Aux0` to `Aux31` are contiguous
+                return ChannelPosition::from_u8(Aux0 as u8 + n);
+            }
+            _ => return None,
+        })
+    }
+
+    /// Returns the PulseAudio name of this channel position (eg. `front-left`).
+    pub fn name(&self) -> &'static str {
+        use self::ChannelPosition::*;
+
+        const AUX_NAMES: [&str; 32] = [
+            "aux0", "aux1", "aux2", "aux3", "aux4", "aux5", "aux6", "aux7", "aux8", "aux9",
+            "aux10", "aux11", "aux12", "aux13", "aux14", "aux15", "aux16", "aux17", "aux18",
+            "aux19", "aux20", "aux21", "aux22", "aux23", "aux24", "aux25", "aux26", "aux27",
+            "aux28", "aux29", "aux30", "aux31",
+        ];
+
+        match *self {
+            Mono => "mono",
+            FrontLeft => "front-left",
+            FrontRight => "front-right",
+            FrontCenter => "front-center",
+            RearCenter => "rear-center",
+            RearLeft => "rear-left",
+            RearRight => "rear-right",
+            Lfe => "lfe",
+            FrontLeftOfCenter => "front-left-of-center",
+            FrontRightOfCenter => "front-right-of-center",
+            SideLeft => "side-left",
+            SideRight => "side-right",
+            TopCenter => "top-center",
+            TopFrontLeft => "top-front-left",
+            TopFrontRight => "top-front-right",
+            TopFrontCenter => "top-front-center",
+            TopRearLeft => "top-rear-left",
+            TopRearRight => "top-rear-right",
+            TopRearCenter => "top-rear-center",
+            aux => AUX_NAMES[(aux as u8 - Aux0 as u8) as usize],
+        }
+    }
+}
+
 /// A map from stream channels to speaker positions.
 ///
 /// These values are relevant for conversion and mixing of streams.
@@ -113,6 +190,75 @@ impl ChannelMap {
     pub fn len(&self) -> u8 {
         self.channels
     }
+
+    /// Creates a sensible default channel map for the given number of channels.
+    ///
+    /// Mono, stereo, 4.0, 5.0, 5.1 and 7.1 layouts are used for 1, 2, 4, 5, 6 and 8 channels,
+    /// respectively. Other channel counts are mapped to auxiliary channels. Returns `None` if
+    /// `channels` is 0 or larger than `CHANNELS_MAX`.
+    pub fn default_for(channels: u8) -> Option<Self> {
+        let name = match channels {
+            0 => return None,
+            1 => "mono",
+            2 => "stereo",
+            4 => "surround-40",
+            5 => "surround-50",
+            6 => "surround-51",
+            8 => "surround-71",
+            _ if channels > CHANNELS_MAX => return None,
+            _ => {
+                let mut map = ChannelMap::new();
+                for i in 0..channels {
+                    map.push(ChannelPosition::from_u8(ChannelPosition::Aux0 as u8 + i).unwrap())
+                        .unwrap();
+                }
+                return Some(map);
+            }
+        };
+
+        Self::parse(name)
+    }
+
+    /// Parses a channel map in PulseAudio's syntax.
+    ///
+    /// This is either a comma-separated list of channel positions (eg. `front-left,front-right`),
+    /// or the name of a well-known layout (`mono`, `stereo`, `surround-21`, `surround-40`,
+    /// `surround-41`, `surround-50`, `surround-51` or `surround-71`).
+    ///
+    /// Returns `None` if `s` is not a valid channel map.
+    pub fn parse(s: &str) -> Option<Self> {
+        let list = match s {
+            "stereo" => "front-left,front-right",
+            "surround-21" => "front-left,front-right,lfe",
+            "surround-40" => "front-left,front-right,rear-left,rear-right",
+            "surround-41" => "front-left,front-right,rear-left,rear-right,lfe",
+            "surround-50" => "front-left,front-right,rear-left,rear-right,front-center",
+            "surround-51" => "front-left,front-right,rear-left,rear-right,front-center,lfe",
+            "surround-71" => "front-left,front-right,rear-left,rear-right,front-center,lfe,side-left,side-right",
+            other => other,
+        };
+
+        let mut map = ChannelMap::new();
+        for name in list.split(',') {
+            map.push(ChannelPosition::from_name(name.trim())?).ok()?;
+        }
+
+        Some(map)
+    }
+}
+
+/// Formats the channel map as a comma-separated list of channel positions, which can be read back
+/// with `ChannelMap::parse`.
+impl fmt::Display for ChannelMap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, pos) in self.into_iter().enumerate() {
+            if i != 0 {
+                f.write_str(",")?;
+            }
+            f.write_str(pos.name())?;
+        }
+        Ok(())
+    }
 }
 
 impl fmt::Debug for ChannelMap {
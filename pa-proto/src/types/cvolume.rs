@@ -52,6 +52,39 @@ impl Volume {
         f*f*f
     }
 
+    /// Convert from an amplification/attenuation in decibel (dB).
+    ///
+    /// Volumes outside the valid range will be clamped.
+    pub fn from_db(db: f32) -> Self {
+        Self::from_linear(10.0f32.powf(db / 20.0))
+    }
+
+    /// Parses a volume in one of the formats understood by PulseAudio.
+    ///
+    /// The volume can be specified as a raw integer (`65536` is 100%), as a percentage of the
+    /// normal volume (eg. `50%`) or in decibel (eg. `-6dB`). Returns `None` if `s` is not a valid
+    /// volume.
+    pub fn parse(s: &str) -> Option<Self> {
+        let s = s.trim();
+        if s.ends_with('%') {
+            let percent: f64 = s[..s.len() - 1].trim().parse().ok()?;
+            let raw = percent / 100.0 * VOLUME_NORM as f64;
+            if raw < 0.0 || raw > VOLUME_MAX as f64 {
+                return None;
+            }
+            Some(Volume(raw as u32))
+        } else if s.len() > 2 && s[s.len() - 2..].eq_ignore_ascii_case("db") {
+            let db: f32 = s[..s.len() - 2].trim().parse().ok()?;
+            Some(Self::from_db(db))
+        } else {
+            let raw: u32 = s.parse().ok()?;
+            if raw > VOLUME_MAX {
+                return None;
+            }
+            Some(Volume(raw))
+        }
+    }
+
     /// Convert from a linear volume.
     ///
     /// Volumes outside the valid range will be clamped.
@@ -1,12 +1,16 @@
 //! Sample specification data type.
 
+use time::Microseconds;
+
+use std::fmt;
+
 /// Maximum number of channels.
 pub const CHANNELS_MAX: u8 = 32;
 
 const RATE_MAX: u32 = 48000 * 8;
 
 /// Describes how individual samples are encoded.
-#[derive(Debug, Copy, Clone, FromPrimitive)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, FromPrimitive)]
 pub enum SampleFormat {
     /// Unsigned 8 Bit PCM
     U8 = 0,
@@ -36,6 +40,77 @@ pub enum SampleFormat {
     S24In32Be,
 }
 
+impl SampleFormat {
+    /// Size of a single sample in bytes.
+    pub fn sample_size(&self) -> usize {
+        use self::SampleFormat::*;
+
+        match self {
+            U8 | Alaw | Ulaw => 1,
+            S16Le | S16Be => 2,
+            S24Le | S24Be => 3,
+            Float32Le | Float32Be | S32Le | S32Be | S24In32Le | S24In32Be => 4,
+        }
+    }
+
+    /// Parses a sample format name as understood by PulseAudio (eg. `s16le` or `float32`).
+    ///
+    /// Formats without explicit endianness (eg. `s16` or `float32ne`) refer to the native
+    /// endianness of the machine. Returns `None` if `name` isn't a known format.
+    pub fn from_name(name: &str) -> Option<Self> {
+        use self::SampleFormat::*;
+
+        let name = name.to_ascii_lowercase();
+        Some(match &*name {
+            "u8" | "8" => U8,
+            "alaw" => Alaw,
+            "ulaw" | "mulaw" => Ulaw,
+            "s16le" => S16Le,
+            "s16be" => S16Be,
+            "s16" | "s16ne" | "16" => if cfg!(target_endian = "big") { S16Be } else { S16Le },
+            "s16re" => if cfg!(target_endian = "big") { S16Le } else { S16Be },
+            "float32le" => Float32Le,
+            "float32be" => Float32Be,
+            "float32" | "float32ne" | "float" => if cfg!(target_endian = "big") { Float32Be } else { Float32Le },
+            "float32re" => if cfg!(target_endian = "big") { Float32Le } else { Float32Be },
+            "s32le" => S32Le,
+            "s32be" => S32Be,
+            "s32" | "s32ne" | "32" => if cfg!(target_endian = "big") { S32Be } else { S32Le },
+            "s32re" => if cfg!(target_endian = "big") { S32Le } else { S32Be },
+            "s24le" => S24Le,
+            "s24be" => S24Be,
+            "s24" | "s24ne" | "24" => if cfg!(target_endian = "big") { S24Be } else { S24Le },
+            "s24re" => if cfg!(target_endian = "big") { S24Le } else { S24Be },
+            "s24-32le" => S24In32Le,
+            "s24-32be" => S24In32Be,
+            "s24-32" | "s24-32ne" => if cfg!(target_endian = "big") { S24In32Be } else { S24In32Le },
+            "s24-32re" => if cfg!(target_endian = "big") { S24In32Le } else { S24In32Be },
+            _ => return None,
+        })
+    }
+
+    /// Returns the canonical PulseAudio name of this sample format (eg. `s16le`).
+    pub fn name(&self) -> &'static str {
+        use self::SampleFormat::*;
+
+        match *self {
+            U8 => "u8",
+            Alaw => "aLaw",
+            Ulaw => "uLaw",
+            S16Le => "s16le",
+            S16Be => "s16be",
+            Float32Le => "float32le",
+            Float32Be => "float32be",
+            S32Le => "s32le",
+            S32Be => "s32be",
+            S24Le => "s24le",
+            S24Be => "s24be",
+            S24In32Le => "s24-32le",
+            S24In32Be => "s24-32be",
+        }
+    }
+}
+
 /// A sample specification that fully describes the format of a sample stream between 2 endpoints.
 #[derive(Debug, Clone)]
 pub struct SampleSpec {
@@ -82,6 +157,19 @@ impl SampleSpec {
         self.sample_rate
     }
 
+    /// Size of a single frame (one sample for every channel) in bytes.
+    pub fn frame_size(&self) -> usize {
+        self.format.sample_size() * usize::from(self.channels)
+    }
+
+    /// Returns the playback duration of `bytes` bytes of audio in this format.
+    ///
+    /// Incomplete frames are ignored.
+    pub fn bytes_to_usec(&self, bytes: u64) -> Microseconds {
+        let frames = bytes / self.frame_size() as u64;
+        Microseconds(frames * 1_000_000 / u64::from(self.sample_rate))
+    }
+
     /// Modifies a `SampleSpec` to be compatible with a different `protocol_version` so that older
     /// clients can understand it.
     pub fn protocol_downgrade(&self, protocol_version: u16) -> SampleSpec {
@@ -103,6 +191,13 @@ impl SampleSpec {
     }
 }
 
+/// Formats the sample spec like PulseAudio does (eg. `s16le 2ch 44100Hz`).
+impl fmt::Display for SampleSpec {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {}ch {}Hz", self.format.name(), self.channels, self.sample_rate)
+    }
+}
+
 /// An error returned by `SampleSpec::new_checked` when the parameters are invalid.
 #[derive(Debug, Fail)]
 #[fail(display = "{}", s)]
@@ -9,7 +9,7 @@ use types::channel_map::{ChannelMap, ChannelPosition};
 use types::cvolume::{CVolume, Volume};
 use types::FormatInfo;
 use error::Error;
-use time::Microseconds;
+use time::{Microseconds, Timeval};
 
 use byteorder::{NetworkEndian, ReadBytesExt, WriteBytesExt};
 use num_traits::FromPrimitive;
@@ -48,13 +48,6 @@ enum Tag {
 }
 
 
-// TODO: implement these:
-
-/// tv_sec: u32
-/// tv_usec: u32
-#[derive(Debug, Copy, Clone)]
-pub enum Timeval {}
-
 /// Enum of the different values that can be stored in a tagstruct.
 #[derive(Debug, Clone)]
 pub enum Value<'a> {
@@ -189,6 +182,11 @@ impl<'a> TagStructReader<'a> {
         }
     }
 
+    /// Returns whether all values have been read.
+    pub fn is_at_end(&self) -> bool {
+        self.data.position() as usize >= self.data.get_ref().len()
+    }
+
     /// Read a given number of bytes from the data stream.
     ///
     /// Returns an error if the stream ends prematurely.
@@ -343,7 +341,10 @@ impl<'a> TagStructReader<'a> {
                 Value::FormatInfo(FormatInfo::from_raw(encoding, props)
                     .map_err(|e| Error::string(e.to_string()))?)
             }
-            TIMEVAL => unimplemented!("tagstruct tag {:?}", tag),
+            TIMEVAL => {
+                let (sec, usec) = (self.data.read_u32::<NetworkEndian>()?, self.data.read_u32::<NetworkEndian>()?);
+                Value::Timeval(Timeval { sec, usec })
+            }
         }))
     }
 
@@ -366,6 +367,9 @@ impl<'a> TagStructReader<'a> {
     read_typed!(read_channel_map = Value::ChannelMap -> ChannelMap);
     read_typed!(read_cvolume = Value::CVolume -> CVolume);
     read_typed!(read_format_info = Value::FormatInfo -> FormatInfo);
+    read_typed!(read_usec = Value::Usec -> Microseconds);
+    read_typed!(read_timeval = Value::Timeval -> Timeval);
+    read_typed!(read_volume = Value::Volume -> Volume);
 
     /// Reads a `Value::Arbitrary` with an expected size.
     ///
@@ -488,7 +492,7 @@ impl<'a> TagStructWriter<'a> {
             SampleSpec(_) => unimplemented!(),
             Arbitrary(bytes) => bytes.to_tag_struct(self, 0),
             Boolean(b) => b.to_tag_struct(self, 0),
-            Timeval(_) => unimplemented!(),
+            Timeval(tv) => tv.to_tag_struct(self, 0),
             Usec(n) => n.to_tag_struct(self, 0),
             ChannelMap(_) |
             CVolume(_) |
@@ -589,6 +593,27 @@ pub trait FromTagStruct<'a>: Sized {
     fn from_tag_struct(ts: &mut TagStructReader<'a>, protocol_version: u16) -> Result<Self, Error>;
 }
 
+/// Empty replies.
+impl<'a> FromTagStruct<'a> for () {
+    fn from_tag_struct(_ts: &mut TagStructReader<'a>, _protocol_version: u16) -> Result<Self, Error> {
+        Ok(())
+    }
+}
+
+/// List replies (eg. to `GET_SINK_INFO_LIST`).
+///
+/// The entries of a list are simply concatenated without a count or separator, so this reads
+/// entries until the end of the tagstruct is reached.
+impl<'a, T> FromTagStruct<'a> for Vec<T> where T: FromTagStruct<'a> {
+    fn from_tag_struct(ts: &mut TagStructReader<'a>, protocol_version: u16) -> Result<Self, Error> {
+        let mut list = Vec::new();
+        while !ts.is_at_end() {
+            list.push(T::from_tag_struct(ts, protocol_version)?);
+        }
+        Ok(list)
+    }
+}
+
 /// Marker trait for tagstruct-serializable types that do not depend on the protocol version.
 ///
 /// Implemented mostly by the primitive types used in tagstructs.
@@ -693,6 +718,15 @@ impl ToTagStruct for Microseconds {
     }
 }
 
+impl ToTagStruct for Timeval {
+    fn to_tag_struct(&self, w: &mut TagStructWriter, _protocol_version: u16) -> Result<(), Error> {
+        w.buf.write_u8(Tag::TIMEVAL as u8)?;
+        w.buf.write_u32::<NetworkEndian>(self.sec)?;
+        w.buf.write_u32::<NetworkEndian>(self.usec)?;
+        Ok(())
+    }
+}
+
 impl<'a> ToTagStruct for &'a [u8] {
     fn to_tag_struct(&self, w: &mut TagStructWriter, _protocol_version: u16) -> Result<(), Error> {
         assert!(self.len() <= u32::MAX as usize);
@@ -774,6 +808,7 @@ impl VersionIndependent for u32 {}
 impl VersionIndependent for u64 {}
 impl VersionIndependent for i64 {}
 impl VersionIndependent for Microseconds {}
+impl VersionIndependent for Timeval {}
 impl VersionIndependent for PaString {}
 impl<'a> VersionIndependent for &'a CStr {}
 impl<'a> VersionIndependent for &'a PaStr {}
@@ -0,0 +1,133 @@
+//! Looks up information about users and groups in the system's user database.
+
+use nix::libc::{self, c_char};
+
+use std::ffi::{CStr, CString, OsStr};
+use std::os::unix::ffi::OsStrExt;
+use std::path::PathBuf;
+use std::{mem, ptr};
+
+/// Initial size of the buffer passed to the `get*_r` functions. Grown as needed.
+const INITIAL_BUF_SIZE: usize = 1024;
+/// Give up when a user database entry needs more memory than this.
+const MAX_BUF_SIZE: usize = 1024 * 1024;
+
+/// Calls a reentrant user database lookup function, growing the buffer until the entry fits.
+///
+/// `f` receives the buffer and returns the error code and whether an entry was found.
+fn lookup<F>(mut f: F) -> bool
+where F: FnMut(&mut [c_char]) -> (i32, bool) {
+    let mut buf = vec![0; INITIAL_BUF_SIZE];
+    loop {
+        match f(&mut buf) {
+            (libc::ERANGE, _) if buf.len() < MAX_BUF_SIZE => {
+                let len = buf.len() * 2;
+                buf.resize(len, 0);
+            }
+            (0, found) => return found,
+            (_, _) => return false,
+        }
+    }
+}
+
+/// Calls `f` with the `passwd` entry of the user with ID `uid`.
+fn with_passwd<F, R>(uid: u32, f: F) -> Option<R>
+where F: FnOnce(&libc::passwd) -> R {
+    let mut f = Some(f);
+    let mut result = None;
+    lookup(|buf| unsafe {
+        let mut pwd: libc::passwd = mem::zeroed();
+        let mut entry = ptr::null_mut();
+        let ret = libc::getpwuid_r(uid, &mut pwd, buf.as_mut_ptr(), buf.len(), &mut entry);
+        if ret == 0 && !entry.is_null() {
+            result = f.take().map(|f| f(&pwd));
+        }
+        (ret, !entry.is_null())
+    });
+    result
+}
+
+/// Returns the ID of the user running this process.
+pub fn current_uid() -> u32 {
+    unsafe { libc::getuid() }
+}
+
+/// Returns the primary group ID of the process.
+pub fn current_gid() -> u32 {
+    unsafe { libc::getgid() }
+}
+
+/// Returns the login name of the user with ID `uid`.
+pub fn name(uid: u32) -> Option<String> {
+    with_passwd(uid, |pwd| unsafe {
+        CStr::from_ptr(pwd.pw_name).to_str().ok().map(String::from)
+    }).and_then(|name| name)
+}
+
+/// Returns the home directory of the user with ID `uid`.
+pub fn home_dir(uid: u32) -> Option<PathBuf> {
+    with_passwd(uid, |pwd| unsafe {
+        PathBuf::from(OsStr::from_bytes(CStr::from_ptr(pwd.pw_dir).to_bytes()))
+    })
+}
+
+/// Returns the user ID and primary group ID of the user called `name`.
+pub fn user_ids(name: &str) -> Option<(u32, u32)> {
+    let name = CString::new(name).ok()?;
+    let mut ids = None;
+    lookup(|buf| unsafe {
+        let mut pwd: libc::passwd = mem::zeroed();
+        let mut entry = ptr::null_mut();
+        let ret = libc::getpwnam_r(name.as_ptr(), &mut pwd, buf.as_mut_ptr(), buf.len(), &mut entry);
+        if ret == 0 && !entry.is_null() {
+            ids = Some((pwd.pw_uid, pwd.pw_gid));
+        }
+        (ret, !entry.is_null())
+    });
+    ids
+}
+
+/// Returns the ID of the group called `name`.
+pub fn group_id(name: &str) -> Option<u32> {
+    let name = CString::new(name).ok()?;
+    let mut gid = None;
+    lookup(|buf| unsafe {
+        let mut grp: libc::group = mem::zeroed();
+        let mut entry = ptr::null_mut();
+        let ret = libc::getgrnam_r(name.as_ptr(), &mut grp, buf.as_mut_ptr(), buf.len(), &mut entry);
+        if ret == 0 && !entry.is_null() {
+            gid = Some(grp.gr_gid);
+        }
+        (ret, !entry.is_null())
+    });
+    gid
+}
+
+/// Returns whether the user with ID `uid` is a member of the group with ID `gid`.
+///
+/// This only checks the group's member list, not the user's primary group.
+pub fn is_group_member(uid: u32, gid: u32) -> bool {
+    let user = match name(uid) {
+        Some(user) => user,
+        None => return false,
+    };
+
+    let mut member = false;
+    lookup(|buf| unsafe {
+        let mut grp: libc::group = mem::zeroed();
+        let mut entry = ptr::null_mut();
+        let ret = libc::getgrgid_r(gid, &mut grp, buf.as_mut_ptr(), buf.len(), &mut entry);
+        if ret == 0 && !entry.is_null() {
+            let mut mem = grp.gr_mem;
+            while !(*mem).is_null() {
+                if CStr::from_ptr(*mem).to_bytes() == user.as_bytes() {
+                    member = true;
+                    break;
+                }
+                mem = mem.offset(1);
+            }
+        }
+        (ret, !entry.is_null())
+    });
+    member
+}
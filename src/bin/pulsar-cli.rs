@@ -0,0 +1,90 @@
+//! Command line client for the server's text interface, similar to PulseAudio's `pacmd`.
+//!
+//! When invoked with arguments, they are sent to the server as a single command and its output is
+//! printed. Without arguments, commands are read from stdin and the server's responses (including
+//! its prompts) are copied to stdout.
+
+extern crate pa_proto;
+
+use pa_proto::paths;
+
+use std::io::{self, Read, Write};
+use std::net::Shutdown;
+use std::os::unix::net::UnixStream;
+use std::process::exit;
+use std::{env, thread};
+
+/// The prompt the server sends when it's ready to accept a command.
+const PROMPT: &[u8] = b">>> ";
+
+fn main() {
+    let args = env::args().skip(1).collect::<Vec<_>>();
+
+    let mut path = paths::runtime_dir();
+    path.push("cli");
+    let stream = match UnixStream::connect(&path) {
+        Ok(stream) => stream,
+        Err(e) => {
+            eprintln!("could not connect to {}: {}", path.display(), e);
+            exit(1);
+        }
+    };
+
+    let result = if args.is_empty() {
+        interactive(stream)
+    } else {
+        command(stream, &args.join(" "))
+    };
+
+    if let Err(e) = result {
+        eprintln!("error: {}", e);
+        exit(1);
+    }
+}
+
+/// Sends a single command and prints its output.
+fn command(mut stream: UnixStream, cmd: &str) -> io::Result<()> {
+    // Skip the welcome message
+    read_until_prompt(&mut stream)?;
+
+    stream.write_all(cmd.as_bytes())?;
+    stream.write_all(b"\n")?;
+    let output = read_until_prompt(&mut stream)?;
+
+    let stdout = io::stdout();
+    stdout.lock().write_all(&output)
+}
+
+/// Forwards stdin to the server and the server's responses to stdout.
+fn interactive(stream: UnixStream) -> io::Result<()> {
+    let mut writer = stream.try_clone()?;
+    thread::spawn(move || {
+        let stdin = io::stdin();
+        // Errors will be noticed by the reading side
+        let _ = io::copy(&mut stdin.lock(), &mut writer);
+        let _ = writer.shutdown(Shutdown::Write);
+    });
+
+    let stdout = io::stdout();
+    io::copy(&mut &stream, &mut stdout.lock())?;
+    Ok(())
+}
+
+/// Reads data from `stream` until the server sends a prompt.
+///
+/// Returns the data received before the prompt.
+fn read_until_prompt(stream: &mut UnixStream) -> io::Result<Vec<u8>> {
+    let mut data = Vec::new();
+    let mut buf = [0; 4096];
+    while !data.ends_with(PROMPT) {
+        let n = stream.read(&mut buf)?;
+        if n == 0 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "server closed the connection"));
+        }
+        data.extend_from_slice(&buf[..n]);
+    }
+
+    let len = data.len() - PROMPT.len();
+    data.truncate(len);
+    Ok(data)
+}
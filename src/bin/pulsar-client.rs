@@ -1,11 +1,24 @@
 extern crate pulsar;
+extern crate pa_proto;
 extern crate env_logger;
+extern crate tokio;
 
 use pulsar::client::Client;
+use pa_proto::proplist::{Prop, PropList};
+
+use tokio::prelude::*;
 
 fn main() {
     env_logger::init();
 
-    let mut client = Client::connect_default().unwrap();
-    client.test().unwrap();
+    let mut props = PropList::new();
+    props.set(Prop::ApplicationName, "pulsar-client\0");
+
+    let task = Client::connect_default(props).map(|client| {
+        println!("connected as client #{} using protocol version {}", client.client_index(), client.protocol_version());
+    }).map_err(|e| {
+        eprintln!("error: {}", e);
+    });
+
+    tokio::run(task);
 }
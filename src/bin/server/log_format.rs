@@ -0,0 +1,66 @@
+//! Formats of the server's log lines besides `env_logger`'s own.
+
+use log::{Level, Record};
+
+use std::fmt::Display;
+use std::io::{self, Write};
+
+/// Facility code of system daemons in syslog messages.
+const SYSLOG_FACILITY_DAEMON: u8 = 3;
+
+/// Writes `record` as an RFC 5424 syslog message of the process `pid` on `host`.
+pub fn write_syslog<W: Write, T: Display>(out: &mut W, timestamp: T, host: &str, pid: u32, record: &Record) -> io::Result<()> {
+    writeln!(out, "<{}>1 {} {} pulsar-server {} - - {}: {}",
+        SYSLOG_FACILITY_DAEMON * 8 + severity(record.level()), timestamp, host, pid, record.target(),
+        record.args())
+}
+
+/// Writes `record` with a severity prefix understood by journald (see `sd-daemon(3)`).
+pub fn write_journal<W: Write>(out: &mut W, record: &Record) -> io::Result<()> {
+    writeln!(out, "<{}>{}: {}", severity(record.level()), record.target(), record.args())
+}
+
+/// Returns the syslog severity of a log level, which journald also uses.
+fn severity(level: Level) -> u8 {
+    match level {
+        Level::Error => 3,
+        Level::Warn => 4,
+        Level::Info => 6,
+        Level::Debug | Level::Trace => 7,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn format<F>(level: Level, write: F) -> String
+    where F: FnOnce(&mut Vec<u8>, &Record) -> io::Result<()> {
+        let mut out = Vec::new();
+        write(&mut out, &Record::builder()
+            .args(format_args!("new client 3 (firefox, pid 1234) connected"))
+            .level(level)
+            .target("pulsar::server")
+            .build()).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn syslog() {
+        let line = format(Level::Info, |out, record| {
+            write_syslog(out, "2018-06-01T12:00:00Z", "host", 42, record)
+        });
+        assert_eq!(line, "<30>1 2018-06-01T12:00:00Z host pulsar-server 42 - - \
+            pulsar::server: new client 3 (firefox, pid 1234) connected\n");
+        let line = format(Level::Error, |out, record| write_syslog(out, "-", "-", 1, record));
+        assert!(line.starts_with("<27>1 "), "{}", line);
+    }
+
+    #[test]
+    fn journal() {
+        let line = format(Level::Warn, |out, record| write_journal(out, record));
+        assert_eq!(line, "<4>pulsar::server: new client 3 (firefox, pid 1234) connected\n");
+        assert!(format(Level::Trace, |out, record| write_journal(out, record)).starts_with("<7>"));
+        assert!(format(Level::Debug, |out, record| write_journal(out, record)).starts_with("<7>"));
+    }
+}
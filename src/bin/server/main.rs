@@ -0,0 +1,384 @@
+//! The pulsar sound server daemon.
+//!
+//! Run with `--help` for a list of options. The options are modeled after those of `pulseaudio`,
+//! so scripts starting, checking or killing the daemon work the same for both.
+
+extern crate pulsar;
+extern crate pa_proto;
+
+#[macro_use] extern crate log;
+extern crate env_logger;
+extern crate nix;
+extern crate tokio;
+
+mod log_format;
+mod options;
+
+use options::{Command, LogFormat, LogTarget, Options, USAGE};
+
+use pulsar::server::Server;
+use pa_proto::conf::DaemonConf;
+use pa_proto::paths;
+use pa_proto::runtime_dir::{self, PID_FILE_NAME};
+use pa_proto::user;
+
+use env_logger::fmt::WriteStyle;
+use nix::sys::signal::{self, SigSet, Signal};
+use nix::sys::stat;
+use nix::unistd::{self, ForkResult, Gid, Pid, Uid};
+
+use std::ffi::CString;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::path::{Path, PathBuf};
+use std::process::{self, exit};
+use std::{env, thread};
+
+fn main() {
+    let mut options = match Options::parse(env::args().skip(1)) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{}", e);
+            eprintln!("Try '--help' for more information.");
+            exit(1);
+        }
+    };
+
+    match options.command {
+        Command::Help => {
+            print!("{}", USAGE);
+            return;
+        }
+        Command::Version => {
+            println!("pulsar {}", env!("CARGO_PKG_VERSION"));
+            return;
+        }
+        _ => {}
+    }
+
+    // The daemon changes its working directory, so relative paths have to be resolved first
+    if let Ok(cwd) = env::current_dir() {
+        for path in options.runtime_dir.iter_mut().chain(&mut options.scripts) {
+            *path = cwd.join(&*path);
+        }
+        if let LogTarget::File(ref mut path) = options.log_target {
+            *path = cwd.join(&*path);
+        }
+    }
+
+    let conf = match load_conf(&options) {
+        Ok(conf) => conf,
+        Err(e) => {
+            eprintln!("could not load configuration: {}", e);
+            exit(1);
+        }
+    };
+
+    let runtime_dir = options.runtime_dir.clone().unwrap_or_else(|| if options.system {
+        PathBuf::from(paths::SYSTEM_RUNTIME_DIR)
+    } else {
+        paths::runtime_dir()
+    });
+    let pid_file = runtime_dir.join(PID_FILE_NAME);
+
+    match options.command {
+        Command::DumpConf => {
+            print!("{}", conf);
+            return;
+        }
+        Command::Check => exit(if running_daemon(&pid_file).is_some() { 0 } else { 1 }),
+        Command::Kill => match running_daemon(&pid_file) {
+            Some(pid) => if let Err(e) = signal::kill(pid, Signal::SIGTERM) {
+                eprintln!("failed to kill daemon: {}", e);
+                exit(1);
+            },
+            None => {
+                eprintln!("no daemon running");
+                exit(1);
+            }
+        },
+        Command::Start if running_daemon(&pid_file).is_some() => {
+            eprintln!("daemon already running");
+        }
+        Command::Start | Command::Run => {
+            // Daemonize before anything spawns threads
+            let mut readiness = if options.command == Command::Start {
+                daemonize()
+            } else {
+                None
+            };
+
+            let result = run(&options, conf, &runtime_dir, &mut readiness);
+            if let Err(e) = result {
+                eprintln!("{}", e);
+                if let Some(mut readiness) = readiness {
+                    // Tell the parent why we failed, since our stderr is gone
+                    readiness.write_all(e.as_bytes()).ok();
+                }
+                exit(1);
+            }
+        }
+        Command::Help | Command::Version => unreachable!(),
+    }
+}
+
+/// Loads `daemon.conf` (or the file passed with `--config-file`) and applies the overrides from
+/// the command line.
+fn load_conf(options: &Options) -> Result<DaemonConf, pa_proto::error::Error> {
+    let mut conf = match options.config_file {
+        Some(ref path) => DaemonConf::load_from(path)?,
+        None => DaemonConf::load()?,
+    };
+
+    if let Some(level) = options.log_level {
+        conf.log_level = level;
+    }
+    if let Some(ref filters) = options.log_filters {
+        conf.log_filters = Some(filters.clone());
+    }
+    for _ in 0..options.verbosity {
+        conf.log_level = match conf.log_level {
+            log::LevelFilter::Off => log::LevelFilter::Error,
+            log::LevelFilter::Error => log::LevelFilter::Warn,
+            log::LevelFilter::Warn => log::LevelFilter::Info,
+            _ => log::LevelFilter::Debug,
+        };
+    }
+    if let Some(exit_idle_time) = options.exit_idle_time {
+        conf.exit_idle_time = exit_idle_time;
+    }
+    if options.disallow_exit {
+        conf.disallow_exit = true;
+    }
+    if options.system {
+        // The system instance serves all users, so it must not go away when idle
+        conf.exit_idle_time = None;
+    }
+
+    Ok(conf)
+}
+
+/// Runs the server until it is stopped by a signal.
+///
+/// `readiness` is the pipe to the parent process if the server was daemonized. A zero byte is
+/// written to it once the server accepts connections.
+fn run(options: &Options, conf: DaemonConf, runtime_dir: &Path, readiness: &mut Option<File>) -> Result<(), String> {
+    init_logging(options, &conf, readiness.is_some())?;
+
+    // Block the signals in all threads, so that only the signal thread receives them
+    let mut signals = SigSet::empty();
+    signals.add(Signal::SIGINT);
+    signals.add(Signal::SIGTERM);
+    signals.thread_block().map_err(|e| format!("couldn't block signals: {}", e))?;
+
+    info!("using runtime directory {}", runtime_dir.display());
+
+    if options.system {
+        if user::current_uid() == 0 {
+            drop_privileges(runtime_dir)?;
+        } else {
+            // Presumably started by a service manager that already switched users
+            info!("system mode: not started as root, running as UID {}", user::current_uid());
+        }
+    }
+
+    let server = if options.system {
+        Server::new_system_with_conf(runtime_dir, conf)
+    } else {
+        Server::new_unix_with_conf(runtime_dir, conf)
+    };
+    let mut server = server.map_err(|e| format!("could not start server: {}", e))?;
+    if options.system {
+        if let Err(e) = server.set_auth_group(SYSTEM_ACCESS_GROUP) {
+            warn!("system mode: {}, only clients with the cookie will be accepted", e);
+        }
+    }
+
+    let mut runtime = tokio::runtime::Runtime::new()
+        .map_err(|e| format!("couldn't start runtime: {}", e))?;
+
+    if !options.no_default_script {
+        let system_script = Path::new(paths::SYSTEM_CONFIG_DIR).join("system.pa");
+        let result = if options.system && system_script.exists() {
+            runtime.block_on(server.run_script_file(system_script))
+        } else {
+            runtime.block_on(server.run_startup_script())
+        };
+        result.map_err(|e| format!("failed to initialize server: {}", e))?;
+    }
+    for script in &options.scripts {
+        runtime.block_on(server.run_script_file(script.clone()))
+            .map_err(|e| format!("failed to run {}: {}", script.display(), e))?;
+    }
+
+    let shutdown = server.shutdown_handle();
+    thread::Builder::new().name("signals".to_string()).spawn(move || {
+        match signals.wait() {
+            Ok(signal) => info!("got {:?}, exiting", signal),
+            Err(e) => error!("waiting for signals failed: {}", e),
+        }
+        shutdown.shutdown();
+    }).map_err(|e| format!("couldn't spawn signal thread: {}", e))?;
+
+    info!("server started");
+    if let Some(mut readiness) = readiness.take() {
+        readiness.write_all(&[0]).ok();
+    }
+
+    runtime.block_on(server.listen())
+        .map_err(|e| format!("server encountered error: {}", e))
+}
+
+/// User the system instance runs as.
+const SYSTEM_USER: &str = "pulse";
+
+/// Group whose members may use the system instance without the auth cookie.
+const SYSTEM_ACCESS_GROUP: &str = "pulse-access";
+
+/// Switches from root to `SYSTEM_USER`, after handing it the runtime directory.
+///
+/// Like PulseAudio, the runtime directory becomes the user's home directory, so the auth cookie is
+/// stored there as well.
+fn drop_privileges(runtime_dir: &Path) -> Result<(), String> {
+    let (uid, gid) = user::user_ids(SYSTEM_USER)
+        .ok_or_else(|| format!("system mode: user '{}' doesn't exist", SYSTEM_USER))?;
+    if uid == 0 {
+        return Err(format!("system mode: user '{}' must not be root", SYSTEM_USER));
+    }
+
+    runtime_dir::create_shared_dir(runtime_dir, uid, gid)
+        .map_err(|e| format!("couldn't create runtime directory {}: {}", runtime_dir.display(), e))?;
+
+    // Supplementary groups first, since that requires root privileges
+    let name = CString::new(SYSTEM_USER).unwrap();
+    unistd::initgroups(&name, Gid::from_raw(gid))
+        .and_then(|()| unistd::setgid(Gid::from_raw(gid)))
+        .and_then(|()| unistd::setuid(Uid::from_raw(uid)))
+        .map_err(|e| format!("system mode: couldn't switch to user '{}': {}", SYSTEM_USER, e))?;
+    // Make sure that root privileges can't be regained
+    if unistd::setuid(Uid::from_raw(0)).is_ok() {
+        return Err("system mode: still able to regain root privileges".to_string());
+    }
+
+    env::set_var("HOME", runtime_dir);
+    env::remove_var("XDG_CONFIG_HOME");
+    info!("system mode: dropped privileges to user '{}' (UID {}, GID {})", SYSTEM_USER, uid, gid);
+    Ok(())
+}
+
+/// Sets up `env_logger` according to the configured log level and target.
+///
+/// `RUST_LOG` takes precedence over the configured log level.
+fn init_logging(options: &Options, conf: &DaemonConf, daemonized: bool) -> Result<(), String> {
+    match options.log_target {
+        LogTarget::Auto if daemonized => {
+            // stderr already points to /dev/null
+        }
+        LogTarget::Auto | LogTarget::Stderr => {}
+        LogTarget::File(ref path) => {
+            let file = OpenOptions::new().create(true).append(true).open(path)
+                .map_err(|e| format!("couldn't open log file {}: {}", path.display(), e))?;
+            unistd::dup2(file.as_raw_fd(), io::stderr().as_raw_fd())
+                .map_err(|e| format!("couldn't redirect log to {}: {}", path.display(), e))?;
+        }
+        LogTarget::Fd(fd) => {
+            unistd::dup2(fd, io::stderr().as_raw_fd())
+                .map_err(|e| format!("couldn't redirect log to fd {}: {}", fd, e))?;
+        }
+    }
+
+    let format = options.log_format.unwrap_or_else(|| {
+        if stderr_is_journal() { LogFormat::Journal } else { LogFormat::Plain }
+    });
+
+    let mut logger = env_logger::Builder::new();
+    logger.filter_level(conf.log_level);
+    if let Some(ref filters) = conf.log_filters {
+        logger.parse(filters);
+    }
+    if let Ok(filters) = env::var("RUST_LOG") {
+        logger.parse(&filters);
+    }
+    match format {
+        LogFormat::Plain => {}
+        LogFormat::Syslog => {
+            let mut buf = [0; 256];
+            let host = unistd::gethostname(&mut buf)
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_else(|_| "-".to_string());
+            logger.write_style(WriteStyle::Never).format(move |buf, record| {
+                let timestamp = buf.timestamp();
+                log_format::write_syslog(buf, timestamp, &host, process::id(), record)
+            });
+        }
+        LogFormat::Journal => {
+            logger.write_style(WriteStyle::Never).format(|buf, record| log_format::write_journal(buf, record));
+        }
+    }
+    logger.try_init().map_err(|e| e.to_string())
+}
+
+/// Returns whether stderr is connected to the systemd journal (see `systemd.exec(5)`).
+fn stderr_is_journal() -> bool {
+    let stream = match env::var("JOURNAL_STREAM") {
+        Ok(stream) => stream,
+        Err(_) => return false,
+    };
+    match stat::fstat(io::stderr().as_raw_fd()) {
+        Ok(st) => stream == format!("{}:{}", st.st_dev, st.st_ino),
+        Err(_) => false,
+    }
+}
+
+/// Returns the PID of the daemon owning `pid_file`, or `None` if no daemon is running.
+fn running_daemon(pid_file: &Path) -> Option<Pid> {
+    runtime_dir::running_pid(pid_file).map(|pid| Pid::from_raw(pid as i32))
+}
+
+/// Forks into the background, detaching from the terminal.
+///
+/// The parent process waits until the daemon reports that it is ready (or failed) and then exits.
+/// In the daemon, this returns the pipe on which to report readiness.
+fn daemonize() -> Option<File> {
+    let (read_fd, write_fd) = match unistd::pipe() {
+        Ok(fds) => fds,
+        Err(e) => {
+            eprintln!("couldn't create pipe: {}", e);
+            exit(1);
+        }
+    };
+    let (mut read_end, write_end) = unsafe { (File::from_raw_fd(read_fd), File::from_raw_fd(write_fd)) };
+
+    match unistd::fork() {
+        Ok(ForkResult::Parent { .. }) => {
+            drop(write_end);
+            let mut status = Vec::new();
+            read_end.read_to_end(&mut status).ok();
+            match &*status {
+                [0] => exit(0),
+                [] => eprintln!("daemon startup failed"),
+                message => eprintln!("daemon startup failed: {}", String::from_utf8_lossy(message)),
+            }
+            exit(1);
+        }
+        Ok(ForkResult::Child) => {
+            drop(read_end);
+            if let Err(e) = unistd::setsid() {
+                eprintln!("setsid failed: {}", e);
+                exit(1);
+            }
+            unistd::chdir("/").ok();
+            if let Ok(null) = OpenOptions::new().read(true).write(true).open("/dev/null") {
+                for fd in 0..3 {
+                    unistd::dup2(null.as_raw_fd(), fd).ok();
+                }
+            }
+            Some(write_end)
+        }
+        Err(e) => {
+            eprintln!("fork failed: {}", e);
+            exit(1);
+        }
+    }
+}
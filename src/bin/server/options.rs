@@ -0,0 +1,299 @@
+//! Command line options of the server.
+
+use pa_proto::conf;
+
+use log::LevelFilter;
+
+use std::os::unix::io::RawFd;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Text printed by `--help`.
+pub const USAGE: &str = "\
+Usage: pulsar-server [OPTION]...
+
+Starts the pulsar sound server.
+
+Commands:
+  -h, --help                Show this help and exit
+      --version             Show the version and exit
+      --dump-conf           Print the effective daemon.conf and exit
+      --check               Exit with status 0 if a daemon is running, 1 otherwise
+  -k, --kill                Stop the running daemon
+      --start               Start a daemon in the background if none is running
+
+Options:
+      --system              Run as the system-wide instance (as the 'pulse' user if
+                            started as root)
+      --runtime-dir=DIR     Put the sockets and PID file into DIR
+      --config-file=FILE    Read the configuration from FILE instead of daemon.conf
+      --log-level=LEVEL     Set the log level (error, warning, notice, info or debug)
+  -v, --verbose             Increase the log level
+      --log-target=TARGET   Log to TARGET (auto, stderr, file:PATH or fd:N)
+      --log-format=FORMAT   Format log lines as FORMAT (plain, syslog or journal)
+      --log-filters=FILTERS Set the log level per module (eg. pulsar::server=debug)
+      --exit-idle-time=SECS Exit after being idle for SECS seconds (-1 to never exit)
+      --disallow-exit       Don't let clients shut down the server
+  -n                        Don't run the default startup script
+  -F, --file=FILE           Run the script FILE after the startup script
+
+The RUST_LOG environment variable takes the same filters as --log-filters and overrides them.
+";
+
+/// What the invocation is supposed to do.
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    /// Run the server in the foreground.
+    Run,
+    /// Daemonize and run the server, unless one is already running.
+    Start,
+    Check,
+    Kill,
+    DumpConf,
+    Help,
+    Version,
+}
+
+/// Where log messages go.
+#[derive(Debug, PartialEq)]
+pub enum LogTarget {
+    /// stderr when running in the foreground, discarded when daemonized.
+    Auto,
+    Stderr,
+    /// Append to a file.
+    File(PathBuf),
+    /// Write to an inherited file descriptor.
+    Fd(RawFd),
+}
+
+/// How log messages are formatted.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LogFormat {
+    /// Human-readable lines with a timestamp.
+    Plain,
+    /// RFC 5424 syslog messages, one per line.
+    Syslog,
+    /// Lines prefixed with their priority, as understood by journald (see `sd-daemon(3)`).
+    Journal,
+}
+
+/// Options passed on the command line.
+#[derive(Debug)]
+pub struct Options {
+    pub command: Command,
+    pub system: bool,
+    pub runtime_dir: Option<PathBuf>,
+    pub config_file: Option<PathBuf>,
+    pub log_level: Option<LevelFilter>,
+    /// Number of `-v` flags.
+    pub verbosity: usize,
+    pub log_target: LogTarget,
+    /// `None` to pick the format based on the log target.
+    pub log_format: Option<LogFormat>,
+    pub log_filters: Option<String>,
+    pub exit_idle_time: Option<Option<Duration>>,
+    pub disallow_exit: bool,
+    /// Whether to skip the startup script.
+    pub no_default_script: bool,
+    pub scripts: Vec<PathBuf>,
+}
+
+impl Options {
+    /// Parses the command line arguments (without the program name).
+    pub fn parse<I: Iterator<Item=String>>(mut args: I) -> Result<Self, String> {
+        let mut options = Options {
+            command: Command::Run,
+            system: false,
+            runtime_dir: None,
+            config_file: None,
+            log_level: None,
+            verbosity: 0,
+            log_target: LogTarget::Auto,
+            log_format: None,
+            log_filters: None,
+            exit_idle_time: None,
+            disallow_exit: false,
+            no_default_script: false,
+            scripts: Vec::new(),
+        };
+
+        while let Some(arg) = args.next() {
+            // Accept both `--opt=value` and `--opt value`
+            let (name, inline_value) = match arg.find('=') {
+                Some(i) if arg.starts_with("--") => (arg[..i].to_string(), Some(arg[i + 1..].to_string())),
+                _ => (arg.clone(), None),
+            };
+            let mut inline_value = inline_value;
+            let mut value = || inline_value.take().or_else(|| args.next())
+                .ok_or_else(|| format!("option '{}' requires a value", name));
+
+            let command = match &*name {
+                "-h" | "--help" => Some(Command::Help),
+                "--version" => Some(Command::Version),
+                "--dump-conf" => Some(Command::DumpConf),
+                "--check" => Some(Command::Check),
+                "-k" | "--kill" => Some(Command::Kill),
+                "--start" => Some(Command::Start),
+                _ => None,
+            };
+            if let Some(command) = command {
+                if inline_value.is_some() {
+                    return Err(format!("option '{}' doesn't take a value", name));
+                }
+                if options.command != Command::Run {
+                    return Err(format!("'{}' can't be combined with another command", arg));
+                }
+                options.command = command;
+                continue;
+            }
+
+            match &*name {
+                "--system" => options.system = true,
+                "--runtime-dir" => options.runtime_dir = Some(PathBuf::from(value()?)),
+                "--config-file" => options.config_file = Some(PathBuf::from(value()?)),
+                "--log-level" => {
+                    let level = value()?;
+                    options.log_level = Some(conf::parse_log_level(&level)
+                        .ok_or_else(|| format!("invalid log level '{}'", level))?);
+                }
+                "-v" | "--verbose" => options.verbosity += 1,
+                "--log-target" => {
+                    let target = value()?;
+                    options.log_target = match &*target {
+                        "auto" => LogTarget::Auto,
+                        "stderr" => LogTarget::Stderr,
+                        _ if target.starts_with("file:") && target.len() > 5 => {
+                            LogTarget::File(PathBuf::from(&target[5..]))
+                        }
+                        _ if target.starts_with("fd:") => match target[3..].parse() {
+                            Ok(fd) if fd >= 0 => LogTarget::Fd(fd),
+                            _ => return Err(format!("invalid log target '{}'", target)),
+                        },
+                        _ => return Err(format!("invalid log target '{}'", target)),
+                    };
+                }
+                "--log-format" => {
+                    let format = value()?;
+                    options.log_format = Some(match &*format {
+                        "plain" => LogFormat::Plain,
+                        "syslog" => LogFormat::Syslog,
+                        "journal" => LogFormat::Journal,
+                        _ => return Err(format!("invalid log format '{}'", format)),
+                    });
+                }
+                "--log-filters" => options.log_filters = Some(value()?),
+                "--exit-idle-time" => {
+                    let secs = value()?;
+                    options.exit_idle_time = Some(match secs.parse::<i64>() {
+                        Ok(secs) if secs < 0 => None,
+                        Ok(secs) => Some(Duration::from_secs(secs as u64)),
+                        Err(_) => return Err(format!("invalid exit idle time '{}'", secs)),
+                    });
+                }
+                "--disallow-exit" => options.disallow_exit = true,
+                "-n" => options.no_default_script = true,
+                "-F" | "--file" => options.scripts.push(PathBuf::from(value()?)),
+                _ => return Err(format!("unknown option '{}'", arg)),
+            }
+
+            if let Some(value) = inline_value {
+                return Err(format!("option '{}' doesn't take a value (got '{}')", name, value));
+            }
+        }
+
+        Ok(options)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Options, String> {
+        Options::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn defaults() {
+        let options = parse(&[]).unwrap();
+        assert_eq!(options.command, Command::Run);
+        assert!(!options.system);
+        assert_eq!(options.log_target, LogTarget::Auto);
+        assert_eq!(options.log_format, None);
+        assert_eq!(options.exit_idle_time, None);
+        assert!(!options.no_default_script);
+        assert!(options.scripts.is_empty());
+    }
+
+    #[test]
+    fn flags() {
+        let options = parse(&[
+            "--system", "--runtime-dir=/run/pulsar", "--config-file", "daemon.conf", "--log-level=debug",
+            "-v", "--verbose", "--log-filters", "pulsar::server=trace", "--exit-idle-time=30",
+            "--disallow-exit", "-n", "-F", "a.pa", "--file=b.pa",
+        ]).unwrap();
+        assert_eq!(options.command, Command::Run);
+        assert!(options.system);
+        assert_eq!(options.runtime_dir, Some(PathBuf::from("/run/pulsar")));
+        assert_eq!(options.config_file, Some(PathBuf::from("daemon.conf")));
+        assert_eq!(options.log_level, Some(LevelFilter::Debug));
+        assert_eq!(options.verbosity, 2);
+        assert_eq!(options.log_filters, Some("pulsar::server=trace".to_string()));
+        assert_eq!(options.exit_idle_time, Some(Some(Duration::from_secs(30))));
+        assert!(options.disallow_exit);
+        assert!(options.no_default_script);
+        assert_eq!(options.scripts, [PathBuf::from("a.pa"), PathBuf::from("b.pa")]);
+
+        assert_eq!(parse(&["--exit-idle-time", "-1"]).unwrap().exit_idle_time, Some(None));
+        // Later values override earlier ones
+        assert_eq!(parse(&["--log-level=error", "--log-level=info"]).unwrap().log_level, Some(LevelFilter::Info));
+    }
+
+    #[test]
+    fn commands() {
+        assert_eq!(parse(&["--start"]).unwrap().command, Command::Start);
+        assert_eq!(parse(&["--check"]).unwrap().command, Command::Check);
+        assert_eq!(parse(&["-k"]).unwrap().command, Command::Kill);
+        assert_eq!(parse(&["--kill"]).unwrap().command, Command::Kill);
+        assert_eq!(parse(&["--dump-conf"]).unwrap().command, Command::DumpConf);
+        assert_eq!(parse(&["-h"]).unwrap().command, Command::Help);
+        assert_eq!(parse(&["--version"]).unwrap().command, Command::Version);
+
+        // Commands combine with options, but not with each other
+        let options = parse(&["--system", "--start", "--runtime-dir", "/run/pulsar"]).unwrap();
+        assert_eq!(options.command, Command::Start);
+        assert!(options.system);
+        assert!(parse(&["--start", "--kill"]).is_err());
+        assert!(parse(&["--check", "--check"]).is_err());
+        assert!(parse(&["--start=yes"]).is_err());
+    }
+
+    #[test]
+    fn log_targets() {
+        let target = |target: &str| parse(&["--log-target", target]).map(|options| options.log_target);
+        assert_eq!(target("auto").unwrap(), LogTarget::Auto);
+        assert_eq!(target("stderr").unwrap(), LogTarget::Stderr);
+        assert_eq!(target("file:/var/log/pulsar.log").unwrap(), LogTarget::File(PathBuf::from("/var/log/pulsar.log")));
+        assert_eq!(target("fd:3").unwrap(), LogTarget::Fd(3));
+        for &invalid in &["file:", "fd:", "fd:-1", "fd:x", "syslog", ""] {
+            assert!(target(invalid).is_err(), "{}", invalid);
+        }
+
+        let format = |format: &str| parse(&["--log-format", format]).map(|options| options.log_format);
+        assert_eq!(format("plain").unwrap(), Some(LogFormat::Plain));
+        assert_eq!(format("syslog").unwrap(), Some(LogFormat::Syslog));
+        assert_eq!(format("journal").unwrap(), Some(LogFormat::Journal));
+        assert!(format("json").is_err());
+    }
+
+    #[test]
+    fn invalid() {
+        assert!(parse(&["--bogus"]).is_err());
+        assert!(parse(&["run"]).is_err());
+        assert!(parse(&["--runtime-dir"]).is_err());
+        assert!(parse(&["--system=yes"]).is_err());
+        assert!(parse(&["-v=2"]).is_err());
+        assert!(parse(&["--log-level=loud"]).is_err());
+        assert!(parse(&["--exit-idle-time=soon"]).is_err());
+    }
+}
@@ -0,0 +1,129 @@
+//! Starting a server when none is running.
+//!
+//! Like libpulse, `Client::connect_default` starts the user's server itself if it can't connect to
+//! any. This runs `daemon-binary` from `client.conf` with `--start`, which returns once the server
+//! accepts connections. Clients hold a lock in the runtime directory while doing so, so that
+//! clients starting at the same time don't spawn several servers.
+
+use pa_proto::conf::ClientConf;
+use pa_proto::error::Error;
+use pa_proto::paths;
+use pa_proto::runtime_dir::{self, Lock, AUTOSPAWN_LOCK_NAME, PID_FILE_NAME};
+use pa_proto::user;
+
+use futures::sync::oneshot;
+use tokio::prelude::*;
+
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How long to wait for the socket to appear after the server was started.
+const SOCKET_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Returns whether a server may be started according to `conf`.
+///
+/// Servers are only started when connecting to the default servers, and never for root (a server
+/// running as root is a system-wide instance, which should be started by the system).
+pub fn allowed(conf: &ClientConf) -> bool {
+    if !conf.autospawn {
+        debug!("not starting a server, autospawn is disabled");
+        false
+    } else if conf.default_server.is_some() {
+        debug!("not starting a server, a server was configured explicitly");
+        false
+    } else if user::current_uid() == 0 {
+        debug!("not starting a server for root");
+        false
+    } else {
+        true
+    }
+}
+
+/// Starts the user's server unless one is already running, and returns the path of its socket.
+///
+/// The blocking work happens on a separate thread, so this doesn't stall the runtime.
+pub fn spawn(conf: &ClientConf) -> impl Future<Item=PathBuf, Error=Error> + Send {
+    let binary = conf.daemon_binary.clone();
+    let args = conf.extra_arguments.clone();
+    let (tx, rx) = oneshot::channel();
+    let thread = thread::Builder::new().name("autospawn".to_string()).spawn(move || {
+        tx.send(spawn_blocking(&paths::runtime_dir(), binary, args)).ok();
+    });
+
+    future::result(thread.map_err(Error::from))
+        .and_then(move |_| rx.map_err(|_| Error::string("autospawn thread panicked")))
+        .and_then(|result| result)
+}
+
+/// Starts a server using the runtime directory `dir`.
+fn spawn_blocking(dir: &Path, binary: PathBuf, args: Vec<String>) -> Result<PathBuf, Error> {
+    runtime_dir::create_secure_dir(dir)?;
+    let _lock = Lock::acquire(&dir.join(AUTOSPAWN_LOCK_NAME))?;
+
+    // Another client might have started the server while we were waiting for the lock
+    let pid_file = dir.join(PID_FILE_NAME);
+    if runtime_dir::running_pid(&pid_file).is_none() {
+        let mut command = Command::new(&binary);
+        command.arg("--start").args(&args);
+        info!("starting server: {:?}", command);
+        let status = command.status()
+            .map_err(|e| Error::string(format!("couldn't run {}: {}", binary.display(), e)))?;
+        if !status.success() {
+            return Err(Error::string(format!("{} failed to start ({})", binary.display(), status)));
+        }
+    }
+
+    // The server creates the PID file before binding its socket, so if the PID file isn't locked
+    // (anymore), the server has exited and the socket won't appear
+    let socket = dir.join("native");
+    let start = Instant::now();
+    while !socket.exists() {
+        if runtime_dir::running_pid(&pid_file).is_none() {
+            return Err(Error::string(format!("server exited before creating its socket {}", socket.display())));
+        }
+        if start.elapsed() > SOCKET_TIMEOUT {
+            return Err(Error::string(format!("server socket {} didn't appear", socket.display())));
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+
+    Ok(socket)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, fs, process};
+    use std::os::unix::net::UnixListener;
+
+    #[test]
+    fn allowed() {
+        let conf = ClientConf::default();
+        assert_eq!(super::allowed(&conf), user::current_uid() != 0);
+        assert!(!super::allowed(&ClientConf { autospawn: false, ..conf.clone() }));
+        assert!(!super::allowed(&ClientConf { default_server: Some("unix:/tmp/native".to_string()), ..conf }));
+    }
+
+    #[test]
+    fn spawn_failures() {
+        let dir = env::temp_dir().join(format!("pulsar-autospawn-{}", process::id()));
+
+        let err = spawn_blocking(&dir, dir.join("missing"), vec![]).unwrap_err();
+        assert!(err.to_string().contains("couldn't run"), "{}", err);
+        let err = spawn_blocking(&dir, "false".into(), vec![]).unwrap_err();
+        assert!(err.to_string().contains("failed to start"), "{}", err);
+
+        // A server that exits without creating its socket is noticed before the timeout expires
+        let start = Instant::now();
+        let err = spawn_blocking(&dir, "true".into(), vec![]).unwrap_err();
+        assert!(err.to_string().contains("exited before creating its socket"), "{}", err);
+        assert!(start.elapsed() < SOCKET_TIMEOUT);
+
+        let _listener = UnixListener::bind(dir.join("native")).unwrap();
+        assert_eq!(spawn_blocking(&dir, "true".into(), vec![]).unwrap(), dir.join("native"));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
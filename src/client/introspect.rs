@@ -0,0 +1,186 @@
+//! Querying and modifying the server's sinks, sources, streams, clients, modules and cards.
+//!
+//! This is the equivalent of PulseAudio's introspection API (and what `pactl` is built on). The
+//! methods are implemented on `Client`, and each returns a `ReplyFuture` resolving to owned
+//! information structs that stay valid independent of the connection.
+//!
+//! Sinks and sources are selected by index or by name using `SinkSpec` and `SourceSpec`. Sink
+//! inputs, source outputs and clients are always selected by their index.
+
+use super::{Client, ReplyFuture};
+
+use pa_proto::command::{CommandKind, GetDeviceInfo, GetInfo, GetModuleInfo, Kill, MoveStream};
+use pa_proto::command::{SetDefaultDevice, SetDeviceMute};
+use pa_proto::command::{SetDeviceVolume, SetStreamMute, SetStreamVolume, SuspendDevice};
+use pa_proto::cvolume::CVolume;
+use pa_proto::string::PaStr;
+
+pub use pa_proto::command::{CardInfo, CardPortInfo, CardProfileInfo, ClientInfo, ModuleInfo, PortInfo};
+pub use pa_proto::command::{ServerInfo, SinkInfo, SinkInputInfo, SourceInfo, SourceOutputInfo};
+pub use pa_proto::command::{SinkSpec, SourceSpec};
+
+impl Client {
+    /// Queries general information about the server, such as the default sink and source.
+    pub fn server_info(&self) -> ReplyFuture<ServerInfo> {
+        self.request(CommandKind::GetServerInfo)
+    }
+
+    /// Lists all sinks.
+    pub fn sinks(&self) -> ReplyFuture<Vec<SinkInfo>> {
+        self.request(CommandKind::GetSinkInfoList)
+    }
+
+    /// Lists all sources, including monitor sources.
+    pub fn sources(&self) -> ReplyFuture<Vec<SourceInfo>> {
+        self.request(CommandKind::GetSourceInfoList)
+    }
+
+    /// Lists all sink inputs (playback streams).
+    pub fn sink_inputs(&self) -> ReplyFuture<Vec<SinkInputInfo>> {
+        self.request(CommandKind::GetSinkInputInfoList)
+    }
+
+    /// Lists all source outputs (record streams).
+    pub fn source_outputs(&self) -> ReplyFuture<Vec<SourceOutputInfo>> {
+        self.request(CommandKind::GetSourceOutputInfoList)
+    }
+
+    /// Lists all clients connected to the server.
+    pub fn clients(&self) -> ReplyFuture<Vec<ClientInfo>> {
+        self.request(CommandKind::GetClientInfoList)
+    }
+
+    /// Lists all loaded modules.
+    pub fn modules(&self) -> ReplyFuture<Vec<ModuleInfo>> {
+        self.request(CommandKind::GetModuleInfoList)
+    }
+
+    /// Lists all cards.
+    pub fn cards(&self) -> ReplyFuture<Vec<CardInfo>> {
+        self.request(CommandKind::GetCardInfoList)
+    }
+
+    /// Queries a single sink.
+    pub fn sink_info(&self, sink: SinkSpec) -> ReplyFuture<SinkInfo> {
+        self.request(CommandKind::GetSinkInfo(GetDeviceInfo::sink(sink)))
+    }
+
+    /// Queries a single source.
+    pub fn source_info(&self, source: SourceSpec) -> ReplyFuture<SourceInfo> {
+        self.request(CommandKind::GetSourceInfo(GetDeviceInfo::source(source)))
+    }
+
+    /// Queries the sink input with index `index`.
+    pub fn sink_input_info(&self, index: u32) -> ReplyFuture<SinkInputInfo> {
+        self.request(CommandKind::GetSinkInputInfo(GetInfo::new(index)))
+    }
+
+    /// Queries the source output with index `index`.
+    pub fn source_output_info(&self, index: u32) -> ReplyFuture<SourceOutputInfo> {
+        self.request(CommandKind::GetSourceOutputInfo(GetInfo::new(index)))
+    }
+
+    /// Queries the client with index `index`.
+    pub fn client_info(&self, index: u32) -> ReplyFuture<ClientInfo> {
+        self.request(CommandKind::GetClientInfo(GetInfo::new(index)))
+    }
+
+    /// Queries the module with index `index`.
+    pub fn module_info(&self, index: u32) -> ReplyFuture<ModuleInfo> {
+        self.request(CommandKind::GetModuleInfo(GetModuleInfo::new(index)))
+    }
+
+    /// Queries the card with index `index`.
+    pub fn card_info(&self, index: u32) -> ReplyFuture<CardInfo> {
+        self.request(CommandKind::GetCardInfo(GetDeviceInfo::card(index)))
+    }
+
+    /// Sets the channel volumes of a sink.
+    pub fn set_sink_volume(&self, sink: SinkSpec, volume: CVolume) -> ReplyFuture<()> {
+        self.request(CommandKind::SetSinkVolume(SetDeviceVolume::sink(sink, volume)))
+    }
+
+    /// Sets the channel volumes of a source.
+    pub fn set_source_volume(&self, source: SourceSpec, volume: CVolume) -> ReplyFuture<()> {
+        self.request(CommandKind::SetSourceVolume(SetDeviceVolume::source(source, volume)))
+    }
+
+    /// Sets the channel volumes of the sink input with index `index`.
+    pub fn set_sink_input_volume(&self, index: u32, volume: CVolume) -> ReplyFuture<()> {
+        self.request(CommandKind::SetSinkInputVolume(SetStreamVolume::new(index, volume)))
+    }
+
+    /// Sets the channel volumes of the source output with index `index`.
+    ///
+    /// Requires protocol version 22 or newer.
+    pub fn set_source_output_volume(&self, index: u32, volume: CVolume) -> ReplyFuture<()> {
+        self.request(CommandKind::SetSourceOutputVolume(SetStreamVolume::new(index, volume)))
+    }
+
+    /// Mutes or unmutes a sink.
+    pub fn set_sink_mute(&self, sink: SinkSpec, mute: bool) -> ReplyFuture<()> {
+        self.request(CommandKind::SetSinkMute(SetDeviceMute::sink(sink, mute)))
+    }
+
+    /// Mutes or unmutes a source.
+    pub fn set_source_mute(&self, source: SourceSpec, mute: bool) -> ReplyFuture<()> {
+        self.request(CommandKind::SetSourceMute(SetDeviceMute::source(source, mute)))
+    }
+
+    /// Mutes or unmutes the sink input with index `index`.
+    pub fn set_sink_input_mute(&self, index: u32, mute: bool) -> ReplyFuture<()> {
+        self.request(CommandKind::SetSinkInputMute(SetStreamMute::new(index, mute)))
+    }
+
+    /// Mutes or unmutes the source output with index `index`.
+    ///
+    /// Requires protocol version 22 or newer.
+    pub fn set_source_output_mute(&self, index: u32, mute: bool) -> ReplyFuture<()> {
+        self.request(CommandKind::SetSourceOutputMute(SetStreamMute::new(index, mute)))
+    }
+
+    /// Makes the sink called `name` the default sink.
+    pub fn set_default_sink(&self, name: &PaStr) -> ReplyFuture<()> {
+        self.request(CommandKind::SetDefaultSink(SetDefaultDevice::new(name)))
+    }
+
+    /// Makes the source called `name` the default source.
+    pub fn set_default_source(&self, name: &PaStr) -> ReplyFuture<()> {
+        self.request(CommandKind::SetDefaultSource(SetDefaultDevice::new(name)))
+    }
+
+    /// Moves the sink input with index `index` to a different sink.
+    pub fn move_sink_input(&self, index: u32, sink: SinkSpec) -> ReplyFuture<()> {
+        self.request(CommandKind::MoveSinkInput(MoveStream::to_sink(index, sink)))
+    }
+
+    /// Moves the source output with index `index` to a different source.
+    pub fn move_source_output(&self, index: u32, source: SourceSpec) -> ReplyFuture<()> {
+        self.request(CommandKind::MoveSourceOutput(MoveStream::to_source(index, source)))
+    }
+
+    /// Disconnects the client with index `index` from the server.
+    pub fn kill_client(&self, index: u32) -> ReplyFuture<()> {
+        self.request(CommandKind::KillClient(Kill::new(index)))
+    }
+
+    /// Destroys the sink input with index `index`.
+    pub fn kill_sink_input(&self, index: u32) -> ReplyFuture<()> {
+        self.request(CommandKind::KillSinkInput(Kill::new(index)))
+    }
+
+    /// Destroys the source output with index `index`.
+    pub fn kill_source_output(&self, index: u32) -> ReplyFuture<()> {
+        self.request(CommandKind::KillSourceOutput(Kill::new(index)))
+    }
+
+    /// Suspends (`suspend = true`) or resumes a sink, or all sinks if `sink` is `None`.
+    pub fn suspend_sink(&self, sink: Option<SinkSpec>, suspend: bool) -> ReplyFuture<()> {
+        self.request(CommandKind::SuspendSink(SuspendDevice::sink(sink, suspend)))
+    }
+
+    /// Suspends (`suspend = true`) or resumes a source, or all sources if `source` is `None`.
+    pub fn suspend_source(&self, source: Option<SourceSpec>, suspend: bool) -> ReplyFuture<()> {
+        self.request(CommandKind::SuspendSource(SuspendDevice::source(source, suspend)))
+    }
+}
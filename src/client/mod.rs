@@ -0,0 +1,770 @@
+//! Client-Side interface for connecting to a running server.
+//!
+//! `Client` talks the native protocol to a pulsar or PulseAudio server. Connecting performs the
+//! authentication and `SET_CLIENT_NAME` handshake; afterwards, every command sent through the
+//! client returns a `ReplyFuture` that resolves to the typed reply (or the error sent by the
+//! server).
+//!
+//! Playback and record streams configured with a `StreamBuilder` are created with
+//! `connect_playback` and `connect_record`. Sinks, sources and other server objects can be listed
+//! and modified using the methods in the `introspect` module, and changes to them can be watched
+//! using the event streams of the `subscribe` module.
+//!
+//! The connection is driven by tasks spawned onto the tokio runtime, so all of this must be used
+//! from within one. The connection is closed once all clones of the `Client` and all pending
+//! `ReplyFuture`s are dropped.
+
+mod autospawn;
+pub mod introspect;
+mod stream;
+pub mod subscribe;
+mod timing;
+
+pub use self::stream::{Fill, PlaybackStream, RecordStream};
+
+use self::stream::{StreamConn, StreamEvent, Streams};
+use self::subscribe::Subscribers;
+
+use pa_proto::command::{Auth, AuthReply, Command, CommandKind, LoadModule, LoadModuleReply};
+use pa_proto::command::{SetClientName, SetClientNameReply, UnloadModule};
+use pa_proto::command::{CreatePlaybackStreamReply, CreateRecordStreamReply, SinkSpec, SourceSpec};
+use pa_proto::command::{PROTOCOL_MIN_VERSION, PROTOCOL_VERSION};
+use pa_proto::conf::ClientConf;
+use pa_proto::cookie::AuthCookie;
+use pa_proto::error::Error;
+use pa_proto::packet::{MemblockData, Message, Packet, PacketCodec};
+use pa_proto::proplist::{Prop, PropList};
+use pa_proto::server_string::{self, ServerAddress};
+use pa_proto::stream::{StreamBuilder, StreamDirection};
+use pa_proto::string::PaStr;
+use pa_proto::tagstruct::FromTagStruct;
+use pa_proto::user;
+use transport::Transport;
+
+use futures::future::{self, Either, Loop};
+use futures::sync::{mpsc, oneshot};
+use tokio::prelude::*;
+use tokio::net::TcpStream;
+use tokio_codec::Decoder;
+use tokio_uds::UnixStream;
+
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::marker::PhantomData;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::{env, process, u32};
+
+/// A PulseAudio client connected to a server.
+///
+/// Clones of a `Client` share the same connection.
+#[derive(Debug, Clone)]
+pub struct Client {
+    /// Packets to send to the server.
+    tx: mpsc::UnboundedSender<Packet>,
+    shared: Arc<Shared>,
+    /// Index of this client on the server.
+    client_index: u32,
+}
+
+/// State shared between client handles and the task reading from the connection.
+#[derive(Debug)]
+struct Shared {
+    next_tag: AtomicUsize,
+    /// Negotiated protocol version.
+    protocol_version: AtomicUsize,
+    /// Requests waiting for a reply, by tag.
+    ///
+    /// Set to `None` when the connection is closed.
+    pending: Mutex<Option<HashMap<u32, Pending>>>,
+    /// Streams that receive notifications and audio data.
+    streams: Mutex<Streams>,
+    /// Subscriptions that receive events about server objects.
+    subscribers: Mutex<Subscribers>,
+    /// The client configuration used for this connection.
+    conf: ClientConf,
+}
+
+/// A request waiting for its reply.
+#[derive(Debug)]
+struct Pending {
+    reply: oneshot::Sender<Packet>,
+    /// Event queue to register for the stream created by the request.
+    ///
+    /// This happens before the reply is passed on, so that no events sent right after the reply
+    /// are lost.
+    stream: Option<(StreamDirection, mpsc::UnboundedSender<StreamEvent>)>,
+}
+
+impl Client {
+    /// Attempts to connect to the user's server, falling back to other servers if that fails.
+    ///
+    /// The servers listed in the `PULSE_SERVER` environment variable or in `client.conf`'s
+    /// `default-server` are tried in order. If neither is set, the user instance is tried first,
+    /// followed by the system instance (see `server_string::default_candidates`).
+    ///
+    /// If no server accepts the connection and neither is set, the user's server is started as
+    /// configured by `autospawn` and `daemon-binary` in `client.conf`, and connected to once it's
+    /// running.
+    ///
+    /// # Parameters
+    ///
+    /// * `props`: Client properties to send to the server. This should at least contain
+    ///   `Prop::ApplicationName`. Properties describing the client process are added automatically.
+    pub fn connect_default(props: PropList) -> impl Future<Item=Self, Error=Error> + Send {
+        let conf = load_conf();
+        let spawn_conf = conf.clone();
+        future::result(server_string::default_candidates(&conf))
+            .and_then(Self::open_any)
+            .or_else(move |e| {
+                if !autospawn::allowed(&spawn_conf) {
+                    return Either::A(future::err(e));
+                }
+                info!("{}, starting a server", e);
+                Either::B(autospawn::spawn(&spawn_conf).and_then(|socket| Self::open(Transport::Unix(socket))))
+            })
+            .and_then(move |stream| Self::handshake_any(stream, conf, props))
+    }
+
+    /// Connects to the first reachable server listed in the server string `servers`.
+    ///
+    /// See the `server_string` module for the format (eg. `"unix:/run/pulse/native tcp:host"`).
+    pub fn connect_server(servers: &str, props: PropList) -> impl Future<Item=Self, Error=Error> + Send {
+        future::result(server_string::candidates(servers))
+            .and_then(move |servers| Self::connect_any(servers, load_conf(), props))
+    }
+
+    /// Connects to a server listening on a TCP socket.
+    pub fn connect_network(addr: SocketAddr, props: PropList) -> impl Future<Item=Self, Error=Error> + Send {
+        // TODO: `ToSocketAddrs` instead?
+        Self::connect(Transport::Network(addr), load_conf(), props)
+    }
+
+    /// Connects to a server listening on a Unix socket at `path`.
+    pub fn connect_unix<P: AsRef<Path>>(path: P, props: PropList) -> impl Future<Item=Self, Error=Error> + Send {
+        Self::connect(Transport::Unix(path.as_ref().to_path_buf()), load_conf(), props)
+    }
+
+    fn connect(transport: Transport, conf: ClientConf, props: PropList) -> impl Future<Item=Self, Error=Error> + Send {
+        Self::open(transport).and_then(move |stream| Self::handshake_any(stream, conf, props))
+    }
+
+    /// Tries to connect to each server in turn, and authenticates to the first one that accepts
+    /// the connection.
+    ///
+    /// Only failures to establish the connection cause the next server to be tried. If all
+    /// servers fail, the error lists why each of them did.
+    fn connect_any(servers: Vec<ServerAddress>, conf: ClientConf, props: PropList) -> impl Future<Item=Self, Error=Error> + Send {
+        Self::open_any(servers).and_then(move |stream| Self::handshake_any(stream, conf, props))
+    }
+
+    /// Opens a connection to the first server in `servers` that accepts it (see `connect_any`).
+    fn open_any(servers: Vec<ServerAddress>) -> impl Future<Item=Either<TcpStream, UnixStream>, Error=Error> + Send {
+        // A host name can resolve to several addresses, which are all tried
+        let mut attempts = Vec::new();
+        for server in servers {
+            match Transport::resolve(&server) {
+                Ok(transports) => attempts.extend(transports.into_iter().map(|transport| {
+                    let name = match transport {
+                        Transport::Network(addr) => format!("{} ({})", server, addr),
+                        Transport::Unix(_) => server.to_string(),
+                    };
+                    (name, Ok(transport))
+                })),
+                Err(e) => attempts.push((server.to_string(), Err(e))),
+            }
+        }
+
+        future::loop_fn((attempts.into_iter(), Vec::new()), |(mut attempts, mut errors)| {
+            match attempts.next() {
+                None if errors.is_empty() => Either::A(future::err(Error::string("no server to connect to"))),
+                None => Either::A(future::err(Error::string(format!(
+                    "couldn't connect to any server: {}", errors.join("; ")
+                )))),
+                Some((name, Err(e))) => {
+                    errors.push(format!("{}: {}", name, e));
+                    Either::A(future::ok(Loop::Continue((attempts, errors))))
+                }
+                Some((name, Ok(transport))) => Either::B(Self::open(transport).then(move |result| match result {
+                    Ok(stream) => Ok(Loop::Break(stream)),
+                    Err(e) => {
+                        debug!("couldn't connect to {}: {}", name, e);
+                        errors.push(format!("{}: {}", name, e));
+                        Ok(Loop::Continue((attempts, errors)))
+                    }
+                })),
+            }
+        })
+    }
+
+    /// Opens a connection to a server without performing the handshake.
+    fn open(transport: Transport) -> impl Future<Item=Either<TcpStream, UnixStream>, Error=Error> + Send {
+        info!("connecting to {:?}", transport);
+
+        match transport {
+            Transport::Network(addr) => Either::A(TcpStream::connect(&addr).map(Either::A).map_err(Error::from)),
+            Transport::Unix(path) => Either::B(UnixStream::connect(path).map(Either::B).map_err(Error::from)),
+        }
+    }
+
+    fn handshake_any(stream: Either<TcpStream, UnixStream>, conf: ClientConf, props: PropList)
+        -> impl Future<Item=Self, Error=Error> + Send {
+        match stream {
+            Either::A(stream) => Either::A(Self::handshake(PacketCodec::new().framed(stream), conf, props)),
+            Either::B(stream) => Either::B(Self::handshake(PacketCodec::new().framed(stream), conf, props)),
+        }
+    }
+
+    /// Starts the connection tasks and authenticates to the server.
+    fn handshake<T>(transport: T, conf: ClientConf, mut props: PropList) -> impl Future<Item=Self, Error=Error> + Send
+    where T: Stream<Item=Packet, Error=Error> + Sink<SinkItem=Packet, SinkError=Error> + Send + 'static {
+        let mut client = Self::start(transport, conf);
+
+        let cookie = AuthCookie::load_client(&client.shared.conf).unwrap_or_else(|e| {
+            // Servers might still accept us, eg. based on our credentials
+            info!("couldn't load auth cookie, sending an empty one: {}", e);
+            AuthCookie::from_bytes(&[0; 256]).unwrap()
+        });
+        // The client can't receive audio data via shared memory yet, so `enable-shm` and
+        // `enable-memfd` don't matter
+        let auth = Auth::new(PROTOCOL_VERSION, false, false, cookie.as_bytes());
+
+        set_process_props(&mut props);
+
+        client.request::<AuthReply>(CommandKind::Auth(auth)).and_then(move |reply| {
+            let version = reply.server_protocol_version();
+            if version < PROTOCOL_MIN_VERSION {
+                return Either::A(future::err(Error::string(format!(
+                    "server protocol version {} unsupported (minimum supported version is {})",
+                    version, PROTOCOL_MIN_VERSION,
+                ))));
+            }
+
+            let version = version.min(PROTOCOL_VERSION);
+            info!("authenticated, using protocol version {}", version);
+            client.shared.protocol_version.store(version as usize, Ordering::SeqCst);
+
+            Either::B(client.set_client_name(props).map(move |reply| {
+                client.client_index = reply.client_index();
+                client
+            }))
+        })
+    }
+
+    /// Starts the connection tasks, without authenticating.
+    fn start<T>(transport: T, conf: ClientConf) -> Self
+    where T: Stream<Item=Packet, Error=Error> + Sink<SinkItem=Packet, SinkError=Error> + Send + 'static {
+        let shared = Arc::new(Shared {
+            next_tag: AtomicUsize::new(0),
+            protocol_version: AtomicUsize::new(PROTOCOL_VERSION as usize),
+            pending: Mutex::new(Some(HashMap::new())),
+            streams: Mutex::new(Streams::default()),
+            subscribers: Mutex::new(Subscribers::default()),
+            conf,
+        });
+        Client {
+            tx: spawn_connection(transport, shared.clone()),
+            shared,
+            client_index: u32::MAX,
+        }
+    }
+
+    /// Returns the protocol version negotiated with the server.
+    pub fn protocol_version(&self) -> u16 {
+        self.shared.protocol_version.load(Ordering::SeqCst) as u16
+    }
+
+    /// Returns the index assigned to this client by the server.
+    pub fn client_index(&self) -> u32 {
+        self.client_index
+    }
+
+    /// Sends a command to the server and returns a future resolving to its reply.
+    ///
+    /// The command is sent immediately, even if the returned future is never polled. Its tag is
+    /// chosen by the client.
+    ///
+    /// If the server replies with an error, the future resolves to an `Error` carrying the error
+    /// code (see `Error::code`).
+    pub fn request<R>(&self, kind: CommandKind) -> ReplyFuture<R>
+    where R: for<'a> FromTagStruct<'a> {
+        self.request_inner(kind, None)
+    }
+
+    fn request_inner<R>(&self, kind: CommandKind, stream: Option<(StreamDirection, mpsc::UnboundedSender<StreamEvent>)>) -> ReplyFuture<R>
+    where R: for<'a> FromTagStruct<'a> {
+        let protocol_version = self.protocol_version();
+        let tag = self.shared.allocate_tag();
+        let packet = Command::new(tag, kind).to_packet(&mut Vec::new(), protocol_version);
+        let (reply_tx, reply_rx) = oneshot::channel();
+
+        let result = match *self.shared.pending.lock().unwrap() {
+            Some(ref mut pending) => {
+                pending.insert(tag, Pending { reply: reply_tx, stream });
+                self.tx.unbounded_send(packet).map_err(|_| connection_closed())
+            }
+            None => Err(connection_closed()),
+        };
+
+        ReplyFuture {
+            state: match result {
+                Ok(()) => Ok(reply_rx),
+                Err(e) => Err(Some(e)),
+            },
+            _conn: self.tx.clone(),
+            protocol_version,
+            _reply: PhantomData,
+        }
+    }
+
+    /// Updates the client's properties on the server.
+    ///
+    /// The properties in `props` are merged into the existing properties.
+    pub fn set_client_name(&self, props: PropList) -> ReplyFuture<SetClientNameReply> {
+        self.request(CommandKind::SetClientName(SetClientName::new(props)))
+    }
+
+    /// Loads a module into the server.
+    pub fn load_module(&self, name: &PaStr, argument: Option<&PaStr>) -> ReplyFuture<LoadModuleReply> {
+        self.request(CommandKind::LoadModule(LoadModule::new(name, argument)))
+    }
+
+    /// Unloads the module with index `module_index`.
+    pub fn unload_module(&self, module_index: u32) -> ReplyFuture<()> {
+        self.request(CommandKind::UnloadModule(UnloadModule::new(module_index)))
+    }
+
+    /// Asks the server to shut down.
+    ///
+    /// Fails with `PulseError::Access` if the server doesn't allow clients to do this.
+    pub fn exit_daemon(&self) -> ReplyFuture<()> {
+        self.request(CommandKind::Exit)
+    }
+
+    /// Creates a playback stream configured by `builder`.
+    ///
+    /// The stream is connected to the sink named `sink`. If `None`, the sink configured in
+    /// `client.conf` or `PULSE_SINK` is used, or the server's default sink if neither is set.
+    pub fn connect_playback(&self, builder: &StreamBuilder, sink: Option<&PaStr>)
+        -> impl Future<Item=PlaybackStream, Error=Error> + Send {
+        let (events_tx, events_rx) = mpsc::unbounded();
+        let direction = StreamDirection::Playback;
+        let flags = builder.flags();
+        let client = self.clone();
+
+        let default_sink = conf_device(&self.shared.conf.default_sink);
+        let sink = sink.map(PaStr::as_cstr).or_else(|| default_sink.as_ref().map(|name| &**name));
+        let reply = builder.playback(sink.map(SinkSpec::Name))
+            .map(|params| self.request_inner::<CreatePlaybackStreamReply>(
+                CommandKind::CreatePlaybackStream(params),
+                Some((direction, events_tx)),
+            ));
+        future::result(reply).flatten().map(move |reply| {
+            let conn = StreamConn::new(client, direction, reply.stream_index, events_rx);
+            PlaybackStream::new(conn, reply, flags)
+        })
+    }
+
+    /// Creates a record stream configured by `builder`.
+    ///
+    /// The stream is connected to the source named `source`. If `None`, the source configured in
+    /// `client.conf` or `PULSE_SOURCE` is used, or the server's default source if neither is set.
+    pub fn connect_record(&self, builder: &StreamBuilder, source: Option<&PaStr>)
+        -> impl Future<Item=RecordStream, Error=Error> + Send {
+        let (events_tx, events_rx) = mpsc::unbounded();
+        let direction = StreamDirection::Record;
+        let flags = builder.flags();
+        let client = self.clone();
+
+        let default_source = conf_device(&self.shared.conf.default_source);
+        let source = source.map(PaStr::as_cstr).or_else(|| default_source.as_ref().map(|name| &**name));
+        let reply = builder.record(source.map(SourceSpec::Name))
+            .map(|params| self.request_inner::<CreateRecordStreamReply>(
+                CommandKind::CreateRecordStream(params),
+                Some((direction, events_tx)),
+            ));
+        future::result(reply).flatten().map(move |reply| {
+            let conn = StreamConn::new(client, direction, reply.channel, events_rx);
+            RecordStream::new(conn, reply, flags)
+        })
+    }
+
+    /// Queues a raw packet for sending.
+    ///
+    /// Fails if the connection is closed.
+    fn send(&self, packet: Packet) -> Result<(), ()> {
+        self.tx.unbounded_send(packet).map_err(|_| ())
+    }
+}
+
+impl Shared {
+    fn allocate_tag(&self) -> u32 {
+        loop {
+            // `u32::MAX` is used for commands that don't want a reply
+            let tag = self.next_tag.fetch_add(1, Ordering::SeqCst) as u32;
+            if tag != u32::MAX {
+                return tag;
+            }
+        }
+    }
+
+    /// Passes a packet received from the server to the request it replies to, or the stream or
+    /// subscriptions it concerns.
+    fn dispatch(&self, packet: Packet) {
+        // Tag of the reply, and the channel of the stream it might have created
+        let (tag, channel) = match Message::from_packet(&packet) {
+            Ok(Message::Control { tagstruct }) => {
+                let protocol_version = self.protocol_version.load(Ordering::SeqCst) as u16;
+                let command = match Command::from_tagstruct(tagstruct, protocol_version) {
+                    Ok(command) => command,
+                    Err(e) => {
+                        warn!("couldn't parse command from server: {}", e);
+                        return;
+                    }
+                };
+
+                let mut streams = self.streams.lock().unwrap();
+                match command.kind() {
+                    CommandKind::Reply { params } => (command.tag(), params.clone().read_u32().ok()),
+                    CommandKind::Error { .. } => (command.tag(), None),
+                    CommandKind::Request(request) => {
+                        streams.send(StreamDirection::Playback, request.channel(), StreamEvent::Request(request.bytes()));
+                        return;
+                    }
+                    CommandKind::Started(params) => {
+                        streams.send(StreamDirection::Playback, params.channel(), StreamEvent::Started);
+                        return;
+                    }
+                    CommandKind::Underflow(params) => {
+                        streams.send(StreamDirection::Playback, params.channel(), StreamEvent::Underflow);
+                        return;
+                    }
+                    CommandKind::Overflow(params) => {
+                        streams.send(StreamDirection::Record, params.channel(), StreamEvent::Overflow);
+                        return;
+                    }
+                    CommandKind::PlaybackStreamKilled(params) => {
+                        streams.send(StreamDirection::Playback, params.channel(), StreamEvent::Killed);
+                        streams.remove(StreamDirection::Playback, params.channel());
+                        return;
+                    }
+                    CommandKind::RecordStreamKilled(params) => {
+                        streams.send(StreamDirection::Record, params.channel(), StreamEvent::Killed);
+                        streams.remove(StreamDirection::Record, params.channel());
+                        return;
+                    }
+                    CommandKind::SubscribeEvent(event) => {
+                        self.subscribers.lock().unwrap().send(*event);
+                        return;
+                    }
+                    _ => {
+                        debug!("ignoring command from server: {:?}", command);
+                        return;
+                    }
+                }
+            }
+            Ok(Message::Memblock { channel, data: MemblockData::Inline(data), .. }) => {
+                let event = StreamEvent::Data(data.to_vec());
+                self.streams.lock().unwrap().send(StreamDirection::Record, channel, event);
+                return;
+            }
+            Ok(message) => {
+                debug!("ignoring message from server: {:?}", message);
+                return;
+            }
+            Err(e) => {
+                warn!("invalid packet from server: {}", e);
+                return;
+            }
+        };
+
+        let pending = self.pending.lock().unwrap().as_mut().and_then(|pending| pending.remove(&tag));
+        match pending {
+            Some(pending) => {
+                if let (Some((direction, events)), Some(channel)) = (pending.stream, channel) {
+                    self.streams.lock().unwrap().insert(direction, channel, events);
+                }
+                // The receiver might have been dropped, which is fine
+                let _ = pending.reply.send(packet);
+            }
+            None => warn!("server replied with unknown tag {}", tag),
+        }
+    }
+
+    /// Fails all outstanding and future requests.
+    fn close(&self) {
+        self.pending.lock().unwrap().take();
+        self.streams.lock().unwrap().clear();
+        self.subscribers.lock().unwrap().clear();
+    }
+}
+
+/// Spawns the tasks exchanging packets with the server.
+///
+/// Returns the queue of packets to send. The connection is closed when all senders are dropped.
+fn spawn_connection<T>(transport: T, shared: Arc<Shared>) -> mpsc::UnboundedSender<Packet>
+where T: Stream<Item=Packet, Error=Error> + Sink<SinkItem=Packet, SinkError=Error> + Send + 'static {
+    let (sink, stream) = transport.split();
+    let (tx, rx) = mpsc::unbounded();
+
+    let writer = sink
+        .send_all(rx.map_err(|()| Error::string("packet queue failed")))
+        .map(|_| debug!("client dropped, closing connection"));
+    let reader = {
+        let shared = shared.clone();
+        stream.for_each(move |packet| {
+            shared.dispatch(packet);
+            Ok(())
+        }).map(|()| info!("server closed the connection"))
+    };
+
+    let task = reader.select(writer).then(move |result| {
+        if let Err((e, _)) = result {
+            error!("connection to server failed: {}", e);
+        }
+        shared.close();
+        Ok(())
+    });
+
+    tokio::spawn(task);
+    tx
+}
+
+/// Adds the properties describing the client process, unless they're already set.
+fn set_process_props(props: &mut PropList) {
+    if props.get(Prop::ApplicationProcessId).is_none() {
+        props.set(Prop::ApplicationProcessId, format!("{}\0", process::id()));
+    }
+    if props.get(Prop::ApplicationProcessUser).is_none() {
+        if let Some(name) = user::name(user::current_uid()) {
+            props.set(Prop::ApplicationProcessUser, format!("{}\0", name));
+        }
+    }
+    if props.get(Prop::ApplicationProcessBinary).is_none() {
+        let binary = env::current_exe().ok()
+            .and_then(|path| path.file_name().map(|name| name.to_string_lossy().into_owned()));
+        if let Some(binary) = binary {
+            props.set(Prop::ApplicationProcessBinary, format!("{}\0", binary));
+        }
+    }
+}
+
+/// Loads the client configuration, falling back to the defaults if that fails.
+fn load_conf() -> ClientConf {
+    ClientConf::load().unwrap_or_else(|e| {
+        warn!("couldn't load client configuration, using defaults: {}", e);
+        ClientConf::default()
+    })
+}
+
+/// Converts a device name from the client configuration for use in a command.
+fn conf_device(name: &Option<String>) -> Option<CString> {
+    name.as_ref().and_then(|name| CString::new(name.as_str()).ok())
+}
+
+fn connection_closed() -> Error {
+    Error::string("connection to server closed")
+}
+
+/// Future resolving to the server's reply to a command.
+///
+/// Returned by `Client::request` and the command-specific methods on `Client`.
+#[must_use = "futures do nothing unless polled"]
+#[derive(Debug)]
+pub struct ReplyFuture<R> {
+    /// The reply packet, or the error that occurred when sending the command.
+    state: Result<oneshot::Receiver<Packet>, Option<Error>>,
+    /// Keeps the connection open until the reply arrives.
+    _conn: mpsc::UnboundedSender<Packet>,
+    protocol_version: u16,
+    _reply: PhantomData<fn() -> R>,
+}
+
+impl<R> Future for ReplyFuture<R>
+where R: for<'a> FromTagStruct<'a> {
+    type Item = R;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<R, Error> {
+        let packet = match self.state {
+            Ok(ref mut rx) => try_ready!(rx.poll().map_err(|_| connection_closed())),
+            Err(ref mut e) => return Err(e.take().expect("ReplyFuture polled after completion")),
+        };
+
+        let tagstruct = match Message::from_packet(&packet)? {
+            Message::Control { tagstruct } => tagstruct,
+            _ => unreachable!("only control packets are dispatched to requests"),
+        };
+
+        let command = Command::from_tagstruct(tagstruct, self.protocol_version)?;
+        match command.kind() {
+            CommandKind::Reply { params } => {
+                let mut params = params.clone();
+                let reply = R::from_tag_struct(&mut params, self.protocol_version)?;
+                if let Some(val) = params.read()? {
+                    return Err(Error::string(format!("extra reply parameter: {:?}", val)));
+                }
+                Ok(Async::Ready(reply))
+            }
+            CommandKind::Error { code } => Err(Error::from(*code)),
+            _ => unreachable!("only replies are dispatched to requests"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pa_proto::error::PulseError;
+    use pa_proto::command::ServerInfo;
+    use pa_proto::tagstruct::ToTagStruct;
+    use futures::StartSend;
+    use std::fmt;
+    use tokio::runtime::current_thread::Runtime;
+
+    /// A transport that passes packets through channels instead of a socket.
+    struct MemoryTransport {
+        incoming: mpsc::UnboundedReceiver<Packet>,
+        outgoing: mpsc::UnboundedSender<Packet>,
+    }
+
+    impl Stream for MemoryTransport {
+        type Item = Packet;
+        type Error = Error;
+
+        fn poll(&mut self) -> Poll<Option<Packet>, Error> {
+            Ok(self.incoming.poll().unwrap())
+        }
+    }
+
+    impl Sink for MemoryTransport {
+        type SinkItem = Packet;
+        type SinkError = Error;
+
+        fn start_send(&mut self, item: Packet) -> StartSend<Packet, Error> {
+            self.outgoing.start_send(item).map_err(|_| connection_closed())
+        }
+
+        fn poll_complete(&mut self) -> Poll<(), Error> {
+            Ok(Async::Ready(()))
+        }
+    }
+
+    /// The server side of a `MemoryTransport`.
+    struct TestServer {
+        /// Packets to send to the client.
+        tx: mpsc::UnboundedSender<Packet>,
+        /// Packets sent by the client.
+        rx: mpsc::UnboundedReceiver<Packet>,
+    }
+
+    impl TestServer {
+        /// Waits for the next command sent by the client and returns its tag.
+        ///
+        /// Panics unless `expected` returns `true` for the command.
+        fn receive(&mut self, runtime: &mut Runtime, expected: fn(&CommandKind) -> bool) -> u32 {
+            let packet = runtime.block_on(future::poll_fn(|| self.rx.poll())).unwrap().unwrap();
+            let tagstruct = match Message::from_packet(&packet).unwrap() {
+                Message::Control { tagstruct } => tagstruct,
+                message => panic!("unexpected message {:?}", message),
+            };
+            let command = Command::from_tagstruct(tagstruct, PROTOCOL_VERSION).unwrap();
+            assert!(expected(command.kind()), "unexpected command {:?}", command);
+            command.tag()
+        }
+
+        fn send(&self, packet: Packet) {
+            self.tx.unbounded_send(packet).unwrap();
+        }
+
+        /// Sends a reply to the command with tag `tag`.
+        fn reply<T: ToTagStruct + fmt::Debug>(&self, tag: u32, reply: T) {
+            let command = Command::new(tag, CommandKind::Exit);
+            self.send(command.reply_packet(&mut Vec::new(), PROTOCOL_VERSION, reply));
+        }
+
+        fn empty_reply(&self, tag: u32) {
+            self.send(Command::new(tag, CommandKind::Exit).empty_reply_packet(&mut Vec::new()));
+        }
+
+        fn error_reply(&self, tag: u32, code: PulseError) {
+            let error = Command::new(tag, CommandKind::Exit).error_reply(code);
+            self.send(error.to_packet(&mut Vec::new(), PROTOCOL_VERSION));
+        }
+    }
+
+    /// Starts a client connected to a `TestServer`, without performing the handshake.
+    fn connect(runtime: &mut Runtime) -> (Client, TestServer) {
+        let (client_tx, server_rx) = mpsc::unbounded();
+        let (server_tx, client_rx) = mpsc::unbounded();
+        let transport = MemoryTransport { incoming: client_rx, outgoing: client_tx };
+        let client = runtime.block_on(future::lazy(|| {
+            Ok::<_, ()>(Client::start(transport, ClientConf::default()))
+        })).unwrap();
+        (client, TestServer { tx: server_tx, rx: server_rx })
+    }
+
+    #[test]
+    fn replies_out_of_order() {
+        let mut runtime = Runtime::new().unwrap();
+        let (client, mut server) = connect(&mut runtime);
+
+        let name = PaStr::from_bytes_with_nul(b"module-null-sink\0").unwrap();
+        let load = client.load_module(name, None);
+        let unload = client.unload_module(3);
+        let exit = client.exit_daemon();
+
+        let load_tag = server.receive(&mut runtime, |kind| match kind { CommandKind::LoadModule(_) => true, _ => false });
+        let unload_tag = server.receive(&mut runtime, |kind| match kind { CommandKind::UnloadModule(_) => true, _ => false });
+        let exit_tag = server.receive(&mut runtime, |kind| match kind { CommandKind::Exit => true, _ => false });
+        assert!(load_tag != unload_tag && unload_tag != exit_tag && load_tag != exit_tag);
+
+        // Replies to unknown tags are ignored
+        server.empty_reply(1000);
+        server.error_reply(exit_tag, PulseError::Access);
+        server.empty_reply(unload_tag);
+        server.reply(load_tag, LoadModuleReply::new(7));
+
+        runtime.block_on(unload).unwrap();
+        assert_eq!(runtime.block_on(load).unwrap().module_index(), 7);
+        assert_eq!(runtime.block_on(exit).unwrap_err().code(), Some(PulseError::Access));
+    }
+
+    #[test]
+    fn malformed_reply() {
+        let mut runtime = Runtime::new().unwrap();
+        let (client, mut server) = connect(&mut runtime);
+
+        let unload = client.unload_module(3);
+        let info = client.request::<ServerInfo>(CommandKind::GetServerInfo);
+        let unload_tag = server.receive(&mut runtime, |_| true);
+        let info_tag = server.receive(&mut runtime, |_| true);
+
+        // An empty reply is expected, and a `ServerInfo` can't be parsed from an empty one
+        server.reply(unload_tag, LoadModuleReply::new(7));
+        server.empty_reply(info_tag);
+        assert!(runtime.block_on(unload).unwrap_err().code().is_none());
+        assert!(runtime.block_on(info).unwrap_err().code().is_none());
+
+        // The connection is still usable
+        let unload = client.unload_module(4);
+        let tag = server.receive(&mut runtime, |_| true);
+        server.empty_reply(tag);
+        runtime.block_on(unload).unwrap();
+    }
+
+    #[test]
+    fn closed_connection() {
+        let mut runtime = Runtime::new().unwrap();
+        let (client, mut server) = connect(&mut runtime);
+
+        let unload = client.unload_module(3);
+        server.receive(&mut runtime, |_| true);
+        drop(server);
+
+        assert!(runtime.block_on(unload).is_err());
+        assert!(runtime.block_on(client.unload_module(3)).is_err());
+    }
+}
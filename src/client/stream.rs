@@ -0,0 +1,463 @@
+//! Client-side playback and record streams.
+//!
+//! Streams are configured with a `StreamBuilder` and created with `Client::connect_playback` or
+//! `Client::connect_record`. The connection task routes stream notifications (`REQUEST`,
+//! `STARTED`, etc.) and recorded audio data to the stream owning the channel.
+
+use super::{Client, ReplyFuture};
+use super::timing::{self, Timing};
+
+use pa_proto::command::{CommandKind, CorkStream, CreatePlaybackStreamReply, CreateRecordStreamReply};
+use pa_proto::command::StreamChannel;
+use pa_proto::error::Error;
+use pa_proto::packet::{Packet, SeekMode};
+use pa_proto::sample_spec::SampleSpec;
+use pa_proto::stream::{BufferAttr, StreamDirection, StreamFlags};
+use pa_proto::time::Microseconds;
+
+use futures::sync::mpsc;
+use tokio::prelude::*;
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+/// Maximum amount of audio data to put into a single memblock packet.
+const MAX_MEMBLOCK_SIZE: usize = 64 * 1024;
+
+/// Something that happened to a stream.
+#[derive(Debug)]
+pub(crate) enum StreamEvent {
+    /// The server requests this many bytes of playback data.
+    Request(u32),
+    /// Recorded audio data.
+    Data(Vec<u8>),
+    /// Playback has started.
+    Started,
+    /// The playback buffer ran empty.
+    Underflow,
+    /// The record buffer overflowed and data was lost.
+    Overflow,
+    /// The server destroyed the stream.
+    Killed,
+}
+
+/// Event queues of the streams open on a connection.
+#[derive(Debug, Default)]
+pub(crate) struct Streams {
+    /// Playback and upload streams, which share a channel namespace.
+    playback: HashMap<u32, mpsc::UnboundedSender<StreamEvent>>,
+    record: HashMap<u32, mpsc::UnboundedSender<StreamEvent>>,
+}
+
+impl Streams {
+    fn channels(&mut self, direction: StreamDirection) -> &mut HashMap<u32, mpsc::UnboundedSender<StreamEvent>> {
+        match direction {
+            StreamDirection::Playback | StreamDirection::Upload => &mut self.playback,
+            StreamDirection::Record => &mut self.record,
+        }
+    }
+
+    pub fn insert(&mut self, direction: StreamDirection, channel: u32, events: mpsc::UnboundedSender<StreamEvent>) {
+        self.channels(direction).insert(channel, events);
+    }
+
+    pub fn remove(&mut self, direction: StreamDirection, channel: u32) {
+        self.channels(direction).remove(&channel);
+    }
+
+    /// Passes an event to the stream on `channel`.
+    pub fn send(&mut self, direction: StreamDirection, channel: u32, event: StreamEvent) {
+        let channels = self.channels(direction);
+        let closed = match channels.get(&channel) {
+            Some(events) => events.unbounded_send(event).is_err(),
+            None => {
+                debug!("{:?} event for unknown stream {}: {:?}", direction, channel, event);
+                false
+            }
+        };
+
+        if closed {
+            channels.remove(&channel);
+        }
+    }
+
+    /// Drops all event queues, signaling the end of every stream.
+    pub fn clear(&mut self) {
+        self.playback.clear();
+        self.record.clear();
+    }
+}
+
+/// The client end of a stream created on the server.
+///
+/// Dropping it deletes the stream.
+#[derive(Debug)]
+pub(crate) struct StreamConn {
+    client: Client,
+    direction: StreamDirection,
+    channel: u32,
+    events: mpsc::UnboundedReceiver<StreamEvent>,
+}
+
+impl StreamConn {
+    pub fn new(
+        client: Client,
+        direction: StreamDirection,
+        channel: u32,
+        events: mpsc::UnboundedReceiver<StreamEvent>,
+    ) -> Self {
+        Self { client, direction, channel, events }
+    }
+
+    pub fn client(&self) -> &Client {
+        &self.client
+    }
+
+    pub fn channel(&self) -> u32 {
+        self.channel
+    }
+
+    /// Polls for the next event.
+    ///
+    /// Resolves to `None` once the connection is closed.
+    pub fn poll_event(&mut self) -> Poll<Option<StreamEvent>, ()> {
+        self.events.poll()
+    }
+
+    /// Sends audio data to the stream.
+    ///
+    /// `offset` and `seek_mode` determine where the server puts the data.
+    pub fn write(&self, data: &[u8], offset: i64, seek_mode: SeekMode) -> Result<(), ()> {
+        let mut chunks = data.chunks(MAX_MEMBLOCK_SIZE);
+        if let Some(first) = chunks.next() {
+            self.client.send(Packet::new_memblock(self.channel, offset, seek_mode, first))?;
+        }
+        // The remaining chunks directly follow the first one
+        for chunk in chunks {
+            self.client.send(Packet::new_memblock(self.channel, 0, SeekMode::Relative, chunk))?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for StreamConn {
+    fn drop(&mut self) {
+        self.client.shared.streams.lock().unwrap().remove(self.direction, self.channel);
+
+        let params = StreamChannel::new(self.channel);
+        let kind = match self.direction {
+            StreamDirection::Playback => CommandKind::DeletePlaybackStream(params),
+            StreamDirection::Record => CommandKind::DeleteRecordStream(params),
+            StreamDirection::Upload => return,
+        };
+        // Nobody's interested in the reply
+        drop(self.client.request::<()>(kind));
+    }
+}
+
+/// A stream playing audio data on a sink.
+///
+/// The server requests data by sending `REQUEST` messages, which are processed by
+/// `poll_writable`. The amount of data the server wants is returned by `writable_size`. Data can
+/// be written at any time, though.
+///
+/// Dropping the stream deletes it on the server.
+#[derive(Debug)]
+pub struct PlaybackStream {
+    conn: StreamConn,
+    reply: CreatePlaybackStreamReply,
+    /// Bytes requested by the server and not yet written.
+    requested: usize,
+    timing: Arc<Mutex<Timing>>,
+}
+
+impl PlaybackStream {
+    pub(crate) fn new(conn: StreamConn, reply: CreatePlaybackStreamReply, flags: StreamFlags) -> Self {
+        let timing = Arc::new(Mutex::new(Timing::new(StreamDirection::Playback, flags, reply.sample_spec.clone())));
+        if flags.contains(StreamFlags::AUTO_TIMING_UPDATE) {
+            timing::spawn_auto_update(conn.client().clone(), conn.channel(), Arc::downgrade(&timing));
+        }
+
+        Self {
+            requested: reply.missing as usize,
+            conn, reply, timing,
+        }
+    }
+
+    /// Channel identifying the stream on the connection.
+    pub fn channel(&self) -> u32 {
+        self.conn.channel()
+    }
+
+    /// Index of the stream's sink input on the server.
+    pub fn sink_input_index(&self) -> u32 {
+        self.reply.sink_input_index
+    }
+
+    /// Index of the sink the stream is connected to.
+    pub fn sink_index(&self) -> u32 {
+        self.reply.sink_index
+    }
+
+    /// The sample format chosen by the server.
+    pub fn sample_spec(&self) -> &SampleSpec {
+        &self.reply.sample_spec
+    }
+
+    /// The buffer metrics chosen by the server.
+    pub fn buffer_attr(&self) -> &BufferAttr {
+        &self.reply.buffer_metrics
+    }
+
+    /// Number of bytes the server has requested that haven't been written yet.
+    pub fn writable_size(&self) -> usize {
+        self.requested
+    }
+
+    /// Processes messages from the server, resolving once it has requested data.
+    ///
+    /// Resolves to the number of bytes requested (see `writable_size`).
+    pub fn poll_writable(&mut self) -> Poll<usize, Error> {
+        loop {
+            // Process everything that's already there, so `requested` is up to date
+            let event = match self.conn.poll_event() {
+                Ok(Async::Ready(Some(event))) => event,
+                Ok(Async::Ready(None)) | Err(()) => return Err(Error::string("connection to server closed")),
+                Ok(Async::NotReady) => break,
+            };
+
+            match event {
+                StreamEvent::Request(bytes) => self.requested += bytes as usize,
+                StreamEvent::Killed => return Err(Error::string("stream was killed by the server")),
+                StreamEvent::Underflow => debug!("playback stream {} underflow", self.channel()),
+                event => debug!("playback stream {}: {:?}", self.channel(), event),
+            }
+        }
+
+        if self.requested == 0 {
+            Ok(Async::NotReady)
+        } else {
+            Ok(Async::Ready(self.requested))
+        }
+    }
+
+    /// Appends `data` to the stream.
+    pub fn write(&mut self, data: &[u8]) -> Result<(), Error> {
+        self.write_with(data, 0, SeekMode::Relative)
+    }
+
+    /// Writes `data` to the stream at a position given by `offset` and `seek_mode`.
+    ///
+    /// With `SeekMode::Relative`, `offset` is relative to the end of the data written before. A
+    /// negative offset overwrites data that hasn't been played yet.
+    pub fn write_with(&mut self, data: &[u8], offset: i64, seek_mode: SeekMode) -> Result<(), Error> {
+        self.conn.write(data, offset, seek_mode).map_err(|()| Error::string("connection to server closed"))?;
+        self.requested = self.requested.saturating_sub(data.len());
+
+        let mut timing = self.timing.lock().unwrap();
+        let len = data.len() as i64;
+        let write_index = match seek_mode {
+            SeekMode::Relative => timing.write_index().map(|index| index + offset + len),
+            SeekMode::Absolute => Some(offset + len),
+            SeekMode::RelativeOnRead => timing.server_read_index().map(|index| index + offset + len),
+            // We don't know where the buffer ends
+            SeekMode::RelativeEnd => None,
+        };
+        timing.set_write_index(write_index);
+        Ok(())
+    }
+
+    /// Returns a future that feeds the stream with data produced by `f`.
+    ///
+    /// Whenever the server requests data, `f` is called with the number of bytes requested and
+    /// returns the data to write (which may be shorter or longer). When `f` returns `None`, the
+    /// future resolves to the stream.
+    pub fn fill<F>(self, f: F) -> Fill<F>
+    where F: FnMut(usize) -> Option<Vec<u8>> {
+        Fill {
+            stream: Some(self),
+            f,
+        }
+    }
+
+    /// Pauses (`cork = true`) or resumes (`cork = false`) playback.
+    pub fn cork(&mut self, cork: bool) -> ReplyFuture<()> {
+        self.timing.lock().unwrap().set_corked(cork);
+        self.conn.client().request(CommandKind::CorkPlaybackStream(CorkStream::new(self.channel(), cork)))
+    }
+
+    /// Whether the stream is currently corked.
+    pub fn is_corked(&self) -> bool {
+        self.timing.lock().unwrap().corked()
+    }
+
+    /// Resolves once all data written so far has been played.
+    pub fn drain(&self) -> ReplyFuture<()> {
+        self.conn.client().request(CommandKind::DrainPlaybackStream(StreamChannel::new(self.channel())))
+    }
+
+    /// Discards all data that hasn't been played yet.
+    pub fn flush(&self) -> ReplyFuture<()> {
+        self.conn.client().request(CommandKind::FlushPlaybackStream(StreamChannel::new(self.channel())))
+    }
+
+    /// Requests up-to-date timing info from the server.
+    ///
+    /// This is done periodically if the stream was created with `AUTO_TIMING_UPDATE`.
+    pub fn update_timing(&self) -> impl Future<Item=(), Error=Error> + Send {
+        timing::update(self.conn.client(), self.channel(), self.timing.clone())
+    }
+
+    /// Returns the current playback time: the position of the sample currently being played.
+    ///
+    /// With `INTERPOLATE_TIMING`, this is estimated using the local clock, otherwise it's the time
+    /// at the last timing update. Returns `None` if no timing info has been received yet.
+    pub fn time(&self) -> Option<Microseconds> {
+        self.timing.lock().unwrap().time()
+    }
+
+    /// Returns the time until data written now will be played.
+    ///
+    /// Returns `None` if no timing info has been received yet.
+    pub fn latency(&self) -> Option<Microseconds> {
+        self.timing.lock().unwrap().latency()
+    }
+}
+
+/// Future feeding a `PlaybackStream` with data, returned by `PlaybackStream::fill`.
+#[must_use = "futures do nothing unless polled"]
+#[derive(Debug)]
+pub struct Fill<F> {
+    stream: Option<PlaybackStream>,
+    f: F,
+}
+
+impl<F> Future for Fill<F>
+where F: FnMut(usize) -> Option<Vec<u8>> {
+    type Item = PlaybackStream;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<PlaybackStream, Error> {
+        loop {
+            let requested = {
+                let stream = self.stream.as_mut().expect("Fill polled after completion");
+                try_ready!(stream.poll_writable())
+            };
+
+            match (self.f)(requested) {
+                Some(data) => self.stream.as_mut().unwrap().write(&data)?,
+                None => return Ok(Async::Ready(self.stream.take().unwrap())),
+            }
+        }
+    }
+}
+
+/// A stream recording audio data from a source.
+///
+/// Recorded data is obtained through the `futures::Stream` implementation.
+///
+/// Dropping the stream deletes it on the server.
+#[derive(Debug)]
+pub struct RecordStream {
+    conn: StreamConn,
+    reply: CreateRecordStreamReply,
+    timing: Arc<Mutex<Timing>>,
+}
+
+impl RecordStream {
+    pub(crate) fn new(conn: StreamConn, reply: CreateRecordStreamReply, flags: StreamFlags) -> Self {
+        let timing = Arc::new(Mutex::new(Timing::new(StreamDirection::Record, flags, reply.sample_spec.clone())));
+        if flags.contains(StreamFlags::AUTO_TIMING_UPDATE) {
+            timing::spawn_auto_update(conn.client().clone(), conn.channel(), Arc::downgrade(&timing));
+        }
+
+        Self { conn, reply, timing }
+    }
+
+    /// Channel identifying the stream on the connection.
+    pub fn channel(&self) -> u32 {
+        self.conn.channel()
+    }
+
+    /// Index of the stream's source output on the server.
+    pub fn source_output_index(&self) -> u32 {
+        self.reply.source_output_index
+    }
+
+    /// Index of the source the stream is connected to.
+    pub fn source_index(&self) -> u32 {
+        self.reply.source_index
+    }
+
+    /// The sample format chosen by the server.
+    pub fn sample_spec(&self) -> &SampleSpec {
+        &self.reply.sample_spec
+    }
+
+    /// The buffer metrics chosen by the server.
+    pub fn buffer_attr(&self) -> &BufferAttr {
+        &self.reply.buffer_metrics
+    }
+
+    /// Pauses (`cork = true`) or resumes (`cork = false`) recording.
+    pub fn cork(&mut self, cork: bool) -> ReplyFuture<()> {
+        self.timing.lock().unwrap().set_corked(cork);
+        self.conn.client().request(CommandKind::CorkRecordStream(CorkStream::new(self.channel(), cork)))
+    }
+
+    /// Whether the stream is currently corked.
+    pub fn is_corked(&self) -> bool {
+        self.timing.lock().unwrap().corked()
+    }
+
+    /// Discards all recorded data the server hasn't sent yet.
+    pub fn flush(&self) -> ReplyFuture<()> {
+        self.conn.client().request(CommandKind::FlushRecordStream(StreamChannel::new(self.channel())))
+    }
+
+    /// Requests up-to-date timing info from the server.
+    ///
+    /// This is done periodically if the stream was created with `AUTO_TIMING_UPDATE`.
+    pub fn update_timing(&self) -> impl Future<Item=(), Error=Error> + Send {
+        timing::update(self.conn.client(), self.channel(), self.timing.clone())
+    }
+
+    /// Returns the current record time: the position of the sample currently being recorded.
+    ///
+    /// Returns `None` if no timing info has been received yet.
+    pub fn time(&self) -> Option<Microseconds> {
+        self.timing.lock().unwrap().time()
+    }
+
+    /// Returns the time since the data that will be yielded next was recorded.
+    ///
+    /// Returns `None` if no timing info has been received yet.
+    pub fn latency(&self) -> Option<Microseconds> {
+        self.timing.lock().unwrap().latency()
+    }
+}
+
+impl Stream for RecordStream {
+    type Item = Vec<u8>;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<Vec<u8>>, Error> {
+        loop {
+            let event = match self.conn.poll_event() {
+                Ok(Async::Ready(Some(event))) => event,
+                Ok(Async::Ready(None)) | Err(()) => return Err(Error::string("connection to server closed")),
+                Ok(Async::NotReady) => return Ok(Async::NotReady),
+            };
+
+            match event {
+                StreamEvent::Data(data) => {
+                    self.timing.lock().unwrap().advance_read_index(data.len());
+                    return Ok(Async::Ready(Some(data)));
+                }
+                StreamEvent::Killed => return Err(Error::string("stream was killed by the server")),
+                StreamEvent::Overflow => warn!("record stream {} overflow, data was lost", self.channel()),
+                event => debug!("record stream {}: {:?}", self.channel(), event),
+            }
+        }
+    }
+}
@@ -0,0 +1,191 @@
+//! Receiving events about server objects.
+//!
+//! `Client::subscribe` returns a `Subscription`, a stream of events telling which object was
+//! created, changed or removed. The events only carry the object's index, so
+//! `Subscription::with_info` can be used to fetch the object's current state along with each
+//! event.
+//!
+//! A connection only has a single subscription mask on the server, so the masks of all
+//! `Subscription`s created from the same `Client` are combined, and each `Subscription` only
+//! receives the events it asked for.
+
+use super::{connection_closed, Client};
+use super::introspect::{CardInfo, ClientInfo, ModuleInfo, ServerInfo, SinkInfo, SinkInputInfo};
+use super::introspect::{SourceInfo, SourceOutputInfo};
+
+use pa_proto::command::{CommandKind, SinkSpec, SourceSpec, Subscribe};
+use pa_proto::error::{Error, PulseError};
+
+use futures::future;
+use futures::sync::mpsc;
+use tokio::prelude::*;
+
+use std::fmt;
+
+pub use pa_proto::command::{EventType, Facility, SubscriptionEvent, SubscriptionMask};
+
+impl Client {
+    /// Subscribes to events about the objects selected by `mask`.
+    ///
+    /// The returned future resolves once the server has acknowledged the subscription. No events
+    /// are lost between then and polling the `Subscription` for the first time.
+    pub fn subscribe(&self, mask: SubscriptionMask) -> impl Future<Item=Subscription, Error=Error> + Send {
+        let (events_tx, events_rx) = mpsc::unbounded();
+        // Register first so that events sent right after the reply are queued
+        let mask = self.shared.subscribers.lock().unwrap().insert(mask, events_tx);
+        let client = self.clone();
+
+        self.request::<()>(CommandKind::Subscribe(Subscribe::new(mask)))
+            .map(move |()| Subscription { events: events_rx, client })
+    }
+}
+
+/// Event queues of the subscriptions on a connection.
+#[derive(Debug, Default)]
+pub(crate) struct Subscribers {
+    subscribers: Vec<(SubscriptionMask, mpsc::UnboundedSender<SubscriptionEvent>)>,
+}
+
+impl Subscribers {
+    /// Adds a subscriber and returns the combined mask of all subscribers.
+    pub fn insert(&mut self, mask: SubscriptionMask, events: mpsc::UnboundedSender<SubscriptionEvent>) -> SubscriptionMask {
+        self.subscribers.push((mask, events));
+        self.subscribers.iter().fold(SubscriptionMask::empty(), |all, &(mask, _)| all | mask)
+    }
+
+    /// Passes an event to all subscribers interested in it, dropping the ones that went away.
+    pub fn send(&mut self, event: SubscriptionEvent) {
+        let facility = event.facility().mask();
+        self.subscribers.retain(|&(mask, ref events)| {
+            !mask.contains(facility) || events.unbounded_send(event).is_ok()
+        });
+    }
+
+    /// Drops all event queues, ending every subscription.
+    pub fn clear(&mut self) {
+        self.subscribers.clear();
+    }
+}
+
+/// A stream of events about server objects.
+///
+/// Created by `Client::subscribe`. The stream ends when the connection is closed.
+#[must_use = "streams do nothing unless polled"]
+#[derive(Debug)]
+pub struct Subscription {
+    events: mpsc::UnboundedReceiver<SubscriptionEvent>,
+    client: Client,
+}
+
+impl Subscription {
+    /// Fetches the current state of the object each event refers to.
+    ///
+    /// The info is `None` for removed objects, objects that were removed again before the query
+    /// reached the server, and sample cache entries. Events are still yielded in order, so each
+    /// event waits for the previous one's query to finish.
+    pub fn with_info(self) -> WithInfo {
+        WithInfo {
+            events: self,
+            fetching: None,
+        }
+    }
+}
+
+impl Stream for Subscription {
+    type Item = SubscriptionEvent;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<SubscriptionEvent>, Error> {
+        self.events.poll().map_err(|()| connection_closed())
+    }
+}
+
+/// Information about an object that was created or changed.
+#[derive(Debug, Clone)]
+pub enum ObjectInfo {
+    Sink(SinkInfo),
+    Source(SourceInfo),
+    SinkInput(SinkInputInfo),
+    SourceOutput(SourceOutputInfo),
+    Module(ModuleInfo),
+    Client(ClientInfo),
+    Card(CardInfo),
+    Server(ServerInfo),
+}
+
+type InfoFuture = Box<Future<Item=Option<ObjectInfo>, Error=Error> + Send>;
+
+/// A stream of events along with the current state of the object they refer to.
+///
+/// Created by `Subscription::with_info`.
+#[must_use = "streams do nothing unless polled"]
+pub struct WithInfo {
+    events: Subscription,
+    /// The event whose object is being queried.
+    fetching: Option<(SubscriptionEvent, InfoFuture)>,
+}
+
+impl WithInfo {
+    fn fetch(&self, event: SubscriptionEvent) -> InfoFuture {
+        if event.event_type() == EventType::Remove {
+            return Box::new(future::ok(None));
+        }
+
+        let client = &self.events.client;
+        let index = event.index();
+        let info: Box<Future<Item=ObjectInfo, Error=Error> + Send> = match event.facility() {
+            Facility::Sink => Box::new(client.sink_info(SinkSpec::Index(index)).map(ObjectInfo::Sink)),
+            Facility::Source => Box::new(client.source_info(SourceSpec::Index(index)).map(ObjectInfo::Source)),
+            Facility::SinkInput => Box::new(client.sink_input_info(index).map(ObjectInfo::SinkInput)),
+            Facility::SourceOutput => Box::new(client.source_output_info(index).map(ObjectInfo::SourceOutput)),
+            Facility::Module => Box::new(client.module_info(index).map(ObjectInfo::Module)),
+            Facility::Client => Box::new(client.client_info(index).map(ObjectInfo::Client)),
+            Facility::Card => Box::new(client.card_info(index).map(ObjectInfo::Card)),
+            Facility::Server => Box::new(client.server_info().map(ObjectInfo::Server)),
+            Facility::SampleCache => return Box::new(future::ok(None)),
+        };
+
+        Box::new(info.map(Some).or_else(|e| match e.code() {
+            // The object is already gone again
+            Some(PulseError::NoEntity) => Ok(None),
+            _ => Err(e),
+        }))
+    }
+}
+
+impl Stream for WithInfo {
+    type Item = (SubscriptionEvent, Option<ObjectInfo>);
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Error> {
+        if self.fetching.is_none() {
+            let event = match try_ready!(self.events.poll()) {
+                Some(event) => event,
+                None => return Ok(Async::Ready(None)),
+            };
+            self.fetching = Some((event, self.fetch(event)));
+        }
+
+        let result = self.fetching.as_mut().unwrap().1.poll();
+        match result {
+            Ok(Async::NotReady) => Ok(Async::NotReady),
+            Ok(Async::Ready(info)) => {
+                let (event, _) = self.fetching.take().unwrap();
+                Ok(Async::Ready(Some((event, info))))
+            }
+            Err(e) => {
+                self.fetching = None;
+                Err(e)
+            }
+        }
+    }
+}
+
+impl fmt::Debug for WithInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("WithInfo")
+            .field("events", &self.events)
+            .field("fetching", &self.fetching.as_ref().map(|&(event, _)| event))
+            .finish()
+    }
+}
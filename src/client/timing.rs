@@ -0,0 +1,193 @@
+//! Stream timing: tracking the playback/record position and latency of a stream.
+//!
+//! The server reports the read and write indices of a stream's buffer along with the latencies of
+//! the device in `LatencyInfo` replies. Between two such updates, the stream time can be
+//! interpolated from the local clock (`StreamFlags::INTERPOLATE_TIMING`), and updates can be
+//! requested periodically in the background (`StreamFlags::AUTO_TIMING_UPDATE`).
+
+use super::Client;
+
+use pa_proto::command::{CommandKind, GetLatency, LatencyInfo};
+use pa_proto::error::Error;
+use pa_proto::sample_spec::SampleSpec;
+use pa_proto::stream::{StreamDirection, StreamFlags};
+use pa_proto::time::{Microseconds, Timeval};
+
+use futures::future::{self, Loop};
+use tokio::prelude::*;
+use tokio::timer::Delay;
+
+use std::cmp;
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, Instant};
+
+/// Interval of the first automatic timing update.
+const AUTO_UPDATE_MIN: Duration = Duration::from_millis(10);
+
+/// The automatic update interval doubles up to this value.
+const AUTO_UPDATE_MAX: Duration = Duration::from_millis(1500);
+
+/// Timing state of a stream, shared with the automatic update task.
+#[derive(Debug)]
+pub(crate) struct Timing {
+    direction: StreamDirection,
+    flags: StreamFlags,
+    sample_spec: SampleSpec,
+    /// Last timing info received, along with the time it arrived and the transport latency.
+    info: Option<(LatencyInfo, Instant, Microseconds)>,
+    corked: bool,
+    /// Last time returned, to keep the time monotonic.
+    previous_time: Microseconds,
+    /// Local write index of playback streams, or `None` if it's unknown after a seek relative to
+    /// the server's read index or buffer end.
+    write_index: Option<i64>,
+    /// Local read index of record streams.
+    read_index: i64,
+}
+
+impl Timing {
+    pub fn new(direction: StreamDirection, flags: StreamFlags, sample_spec: SampleSpec) -> Self {
+        Self {
+            direction,
+            flags,
+            sample_spec,
+            info: None,
+            corked: flags.contains(StreamFlags::START_CORKED),
+            previous_time: Microseconds(0),
+            write_index: Some(0),
+            read_index: 0,
+        }
+    }
+
+    pub fn set_corked(&mut self, corked: bool) {
+        self.corked = corked;
+    }
+
+    pub fn corked(&self) -> bool {
+        self.corked
+    }
+
+    /// Sets the local write index of a playback stream.
+    pub fn set_write_index(&mut self, write_index: Option<i64>) {
+        self.write_index = write_index;
+    }
+
+    pub fn write_index(&self) -> Option<i64> {
+        self.write_index
+    }
+
+    /// Advances the local read index of a record stream.
+    pub fn advance_read_index(&mut self, bytes: usize) {
+        self.read_index += bytes as i64;
+    }
+
+    /// Last read index reported by the server.
+    pub fn server_read_index(&self) -> Option<i64> {
+        self.info.as_ref().map(|&(ref info, _, _)| info.read_index)
+    }
+
+    fn update(&mut self, info: LatencyInfo, received: Instant) {
+        let transport = info.transport_usec(Timeval::now());
+        if self.write_index.is_none() {
+            // Recover from seeks we couldn't track (the server's index might lag a bit behind)
+            self.write_index = Some(info.write_index);
+        }
+        self.info = Some((info, received, transport));
+    }
+
+    /// Returns the current stream time: the position of the sample currently being played or
+    /// recorded, relative to the start of the stream.
+    ///
+    /// Returns `None` if no timing info has been received yet.
+    pub fn time(&mut self) -> Option<Microseconds> {
+        let usec = {
+            let (ref info, received, transport) = *self.info.as_ref()?;
+            let running = !self.corked;
+
+            let mut usec = match self.direction {
+                StreamDirection::Record => {
+                    let mut usec = self.bytes_to_usec(info.write_index);
+                    if running {
+                        // The data the server has was recorded this long ago
+                        usec = (usec + transport.0 + info.source_usec.0).saturating_sub(info.sink_usec.0);
+                    }
+                    usec
+                }
+                StreamDirection::Playback | StreamDirection::Upload => {
+                    let mut usec = self.bytes_to_usec(info.read_index);
+                    if running {
+                        // The reply took a while to arrive, and the device plays the data later
+                        usec = (usec + transport.0).saturating_sub(info.sink_usec.0);
+                    }
+                    usec
+                }
+            };
+
+            if self.flags.contains(StreamFlags::INTERPOLATE_TIMING) && running && info.playing {
+                let age = received.elapsed();
+                usec += age.as_secs() * 1_000_000 + u64::from(age.subsec_micros());
+            }
+
+            usec
+        };
+
+        if self.flags.contains(StreamFlags::NOT_MONOTONIC) {
+            Some(Microseconds(usec))
+        } else {
+            self.previous_time = cmp::max(self.previous_time, Microseconds(usec));
+            Some(self.previous_time)
+        }
+    }
+
+    /// Returns the stream latency.
+    ///
+    /// For playback streams, this is the time until data written now is played. For record
+    /// streams, this is the time since the data read next was recorded.
+    pub fn latency(&mut self) -> Option<Microseconds> {
+        let time = self.time()?.0;
+        Some(Microseconds(match self.direction {
+            StreamDirection::Record => time.saturating_sub(self.bytes_to_usec(self.read_index)),
+            StreamDirection::Playback | StreamDirection::Upload => {
+                let write_index = match self.write_index {
+                    Some(index) => index,
+                    None => self.info.as_ref()?.0.write_index,
+                };
+                self.bytes_to_usec(write_index).saturating_sub(time)
+            }
+        }))
+    }
+
+    fn bytes_to_usec(&self, bytes: i64) -> u64 {
+        self.sample_spec.bytes_to_usec(cmp::max(bytes, 0) as u64).0
+    }
+}
+
+/// Requests a timing update for the stream on `channel`.
+pub(crate) fn update(client: &Client, channel: u32, timing: Arc<Mutex<Timing>>) -> impl Future<Item=(), Error=Error> + Send {
+    let params = GetLatency::new(channel);
+    let kind = match timing.lock().unwrap().direction {
+        StreamDirection::Record => CommandKind::GetRecordLatency(params),
+        StreamDirection::Playback | StreamDirection::Upload => CommandKind::GetPlaybackLatency(params),
+    };
+
+    client.request::<LatencyInfo>(kind).map(move |info| {
+        timing.lock().unwrap().update(info, Instant::now());
+    })
+}
+
+/// Spawns a task periodically updating the timing info until the stream is dropped.
+pub(crate) fn spawn_auto_update(client: Client, channel: u32, timing: Weak<Mutex<Timing>>) {
+    let task = future::loop_fn(AUTO_UPDATE_MIN, move |interval| {
+        let timing = match timing.upgrade() {
+            Some(timing) => timing,
+            None => return future::Either::A(future::ok(Loop::Break(()))),
+        };
+
+        let next = cmp::min(interval * 2, AUTO_UPDATE_MAX);
+        future::Either::B(update(&client, channel, timing)
+            .and_then(move |()| Delay::new(Instant::now() + interval).map_err(Error::from))
+            .map(move |()| Loop::Continue(next)))
+    }).map_err(|e| debug!("automatic timing update failed: {}", e));
+
+    tokio::spawn(task);
+}
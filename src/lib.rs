@@ -1,10 +1,19 @@
 extern crate pa_proto;
 
 #[macro_use] extern crate log;
+extern crate byteorder;
+extern crate bytes;
+#[macro_use] extern crate futures;
+extern crate mio;
+extern crate mio_uds;
+extern crate nix;
+extern crate rand;
 extern crate tokio;
 extern crate tokio_codec;
 extern crate tokio_uds;
 
 pub mod client;
 pub mod server;
+pub mod simple;
 pub mod transport;
+mod unix_framed;
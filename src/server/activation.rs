@@ -0,0 +1,193 @@
+//! systemd socket activation.
+//!
+//! When started by systemd (or any other service manager implementing the same protocol), the
+//! server inherits already-bound listening sockets instead of binding its own. The socket exists
+//! before the server runs, so the first client connecting to it starts the server, and its
+//! connection is accepted once the server is up.
+//!
+//! The protocol is described in `sd_listen_fds(3)`: `LISTEN_PID` is the PID of the process the
+//! sockets are meant for, `LISTEN_FDS` the number of sockets (passed as file descriptors 3 and
+//! up), and `LISTEN_FDNAMES` an optional colon-separated list of their names.
+
+use nix::fcntl::{fcntl, FcntlArg, FdFlag};
+use nix::unistd::{self, Pid};
+
+use std::env;
+use std::io;
+use std::os::unix::io::{FromRawFd, RawFd};
+use std::os::unix::net::UnixListener;
+
+/// File descriptor of the first passed socket.
+const LISTEN_FDS_START: RawFd = 3;
+
+/// Maximum number of sockets accepted from the service manager. The server only listens on two
+/// sockets, so anything beyond this is a misconfiguration.
+const MAX_LISTEN_FDS: usize = 16;
+
+/// Listening sockets passed to this process by the service manager.
+#[derive(Debug, Default)]
+pub struct Activation {
+    sockets: Vec<(Option<String>, UnixListener)>,
+}
+
+impl Activation {
+    /// Takes the sockets passed to this process, if any.
+    ///
+    /// The environment variables are removed, so that they aren't inherited by child processes.
+    /// Returns an error if the variables are malformed or a passed file descriptor isn't a Unix
+    /// socket.
+    pub fn from_env() -> io::Result<Self> {
+        let pid = env::var("LISTEN_PID").ok();
+        let fds = env::var("LISTEN_FDS").ok();
+        let names = env::var("LISTEN_FDNAMES").ok();
+        for var in &["LISTEN_PID", "LISTEN_FDS", "LISTEN_FDNAMES"] {
+            env::remove_var(var);
+        }
+
+        fn as_str(var: &Option<String>) -> Option<&str> {
+            var.as_ref().map(|s| &**s)
+        }
+        let names = match parse_env(as_str(&pid), as_str(&fds), as_str(&names), unistd::getpid())? {
+            Some(names) => names,
+            None => return Ok(Self::default()),
+        };
+
+        let mut sockets = Vec::new();
+        for (fd, name) in (LISTEN_FDS_START..).zip(names) {
+            fcntl(fd, FcntlArg::F_SETFD(FdFlag::FD_CLOEXEC))
+                .map_err(|e| io::Error::new(io::ErrorKind::Other, format!("passed fd {}: {}", fd, e)))?;
+            let listener = unsafe { UnixListener::from_raw_fd(fd) };
+            let addr = listener.local_addr()
+                .map_err(|e| io::Error::new(e.kind(), format!("passed fd {} is not a Unix socket: {}", fd, e)))?;
+            debug!("got socket {:?} ({:?}) from service manager", addr, name);
+            sockets.push((name, listener));
+        }
+
+        Ok(Self { sockets })
+    }
+
+    /// Removes and returns the socket called `name`.
+    ///
+    /// A socket matches if its name in `LISTEN_FDNAMES` (`FileDescriptorName=` in systemd) is
+    /// `name`, or if it is bound to a file called `name`.
+    pub fn take(&mut self, name: &str) -> Option<UnixListener> {
+        let pos = self.sockets.iter().position(|&(ref fd_name, ref listener)| {
+            fd_name.as_ref().map_or(false, |fd_name| fd_name == name) || listener.local_addr().ok()
+                .and_then(|addr| addr.as_pathname().and_then(|path| path.file_name()).map(|file| file == name))
+                .unwrap_or(false)
+        })?;
+        Some(self.sockets.remove(pos).1)
+    }
+}
+
+impl Drop for Activation {
+    fn drop(&mut self) {
+        for &(ref name, ref listener) in &self.sockets {
+            warn!("ignoring unknown socket {:?} ({:?}) passed by the service manager", listener.local_addr(), name);
+        }
+    }
+}
+
+/// Interprets the values of `LISTEN_PID`, `LISTEN_FDS` and `LISTEN_FDNAMES`.
+///
+/// Returns the names of the passed sockets (`None` for unnamed ones), or `None` if no sockets were
+/// passed to the process `own_pid`.
+fn parse_env(pid: Option<&str>, fds: Option<&str>, names: Option<&str>, own_pid: Pid) -> io::Result<Option<Vec<Option<String>>>> {
+    let (pid, fds) = match (pid, fds) {
+        (Some(pid), Some(fds)) => (pid, fds),
+        _ => return Ok(None),
+    };
+    if Pid::from_raw(pid.parse().map_err(invalid("LISTEN_PID"))?) != own_pid {
+        debug!("ignoring sockets passed to PID {}", pid);
+        return Ok(None);
+    }
+
+    // Parsing as `usize` rejects negative counts
+    let count = fds.parse::<usize>().map_err(invalid("LISTEN_FDS"))?;
+    if count > MAX_LISTEN_FDS {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, format!(
+            "LISTEN_FDS passes {} sockets, at most {} are supported", count, MAX_LISTEN_FDS
+        )));
+    }
+
+    match names {
+        Some(names) => {
+            let names = names.split(':').map(|name| Some(name.to_string())).collect::<Vec<_>>();
+            if names.len() != count {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, format!(
+                    "LISTEN_FDNAMES has {} names for {} sockets", names.len(), count
+                )));
+            }
+            Ok(Some(names))
+        }
+        None => Ok(Some(vec![None; count])),
+    }
+}
+
+fn invalid<E>(var: &'static str) -> impl FnOnce(E) -> io::Error {
+    move |_| io::Error::new(io::ErrorKind::InvalidInput, format!("invalid value for {}", var))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{fs, process};
+
+    #[test]
+    fn parse() {
+        let own = Pid::from_raw(42);
+        assert_eq!(parse_env(None, None, None, own).unwrap(), None);
+        assert_eq!(parse_env(Some("42"), None, None, own).unwrap(), None);
+        assert_eq!(parse_env(None, Some("2"), None, own).unwrap(), None);
+        // Sockets meant for another process (eg. our parent) are left alone
+        assert_eq!(parse_env(Some("41"), Some("2"), None, own).unwrap(), None);
+
+        assert_eq!(parse_env(Some("42"), Some("0"), None, own).unwrap(), Some(vec![]));
+        assert_eq!(parse_env(Some("42"), Some("2"), None, own).unwrap(), Some(vec![None, None]));
+        assert_eq!(
+            parse_env(Some("42"), Some("2"), Some("native:cli"), own).unwrap(),
+            Some(vec![Some("native".to_string()), Some("cli".to_string())])
+        );
+
+        for &(pid, fds, names) in &[
+            ("x", "1", None),
+            ("42", "-1", None),
+            ("42", "x", None),
+            ("42", "", None),
+            ("42", "17", None),
+            ("42", "4294967296000", None),
+            ("42", "2", Some("native")),
+            ("42", "1", Some("native:cli")),
+        ] {
+            assert!(parse_env(Some(pid), Some(fds), names, own).is_err(), "{} {} {:?}", pid, fds, names);
+        }
+    }
+
+    #[test]
+    fn take() {
+        let dir = env::temp_dir().join(format!("pulsar-activation-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let bind = |name: &str| UnixListener::bind(dir.join(name)).unwrap();
+
+        let mut activation = Activation {
+            sockets: vec![
+                (Some("native".to_string()), bind("pulse-native")),
+                (None, bind("cli")),
+                (Some("other".to_string()), bind("native")),
+            ],
+        };
+
+        // The first socket matching either by name or by file name is taken
+        let native = activation.take("native").unwrap();
+        assert_eq!(native.local_addr().unwrap().as_pathname(), Some(&*dir.join("pulse-native")));
+        let cli = activation.take("cli").unwrap();
+        assert_eq!(cli.local_addr().unwrap().as_pathname(), Some(&*dir.join("cli")));
+        assert!(activation.take("cli").is_none());
+        assert!(activation.take("pulse-native").is_none());
+        // The socket bound to a file called `native` is still there
+        assert!(activation.take("native").is_some());
+        assert!(activation.sockets.is_empty());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
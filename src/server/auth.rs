@@ -0,0 +1,305 @@
+//! Client authentication policies.
+//!
+//! Every listening socket has an associated `AuthOptions` that decides which clients are allowed to
+//! use the server. The checks are applied when a client sends the `AUTH` command.
+
+use pa_proto::cookie::AuthCookie;
+use pa_proto::error::Error;
+use pa_proto::proplist::{Prop, PropList};
+use pa_proto::user;
+
+use nix::sys::socket::{getsockopt, sockopt::PeerCredentials};
+use std::net::{IpAddr, SocketAddr};
+use std::os::unix::io::AsRawFd;
+use std::sync::Arc;
+use std::io;
+
+/// Determines which clients connecting via a socket are granted access.
+#[derive(Debug, Clone)]
+pub(crate) struct AuthOptions {
+    /// Accept every client without checking any credentials.
+    pub anonymous: bool,
+    /// Accept clients that present this auth cookie. `None` disables cookie authentication.
+    pub cookie: Option<Arc<AuthCookie>>,
+    /// Accept clients whose address is in one of these networks, without checking the cookie.
+    pub ip_acl: Option<IpAcl>,
+    /// Accept local clients running as the same user as the server.
+    pub same_user: bool,
+    /// Accept local clients whose user is a member of this group.
+    pub group: Option<u32>,
+}
+
+impl AuthOptions {
+    /// Only accept clients that know `cookie`.
+    pub fn cookie_only(cookie: Arc<AuthCookie>) -> Self {
+        Self {
+            anonymous: false,
+            cookie: Some(cookie),
+            ip_acl: None,
+            same_user: false,
+            group: None,
+        }
+    }
+
+    /// Checks whether a client may access the server.
+    ///
+    /// `peer` is the client's address (if connected via the network), `creds` its credentials (if
+    /// connected via a Unix socket) and `cookie` the auth cookie it sent. If access is granted,
+    /// returns a description of the method used to authenticate the client.
+    pub fn check(&self, peer: Option<&SocketAddr>, creds: Option<&PeerCreds>, cookie: &[u8]) -> Option<&'static str> {
+        if self.anonymous {
+            return Some("anonymous access");
+        }
+
+        if let Some(creds) = creds {
+            if self.same_user && creds.uid == user::current_uid() {
+                return Some("peer credentials (same user)");
+            }
+
+            if let Some(gid) = self.group {
+                if creds.gid == gid || user::is_group_member(creds.uid, gid) {
+                    return Some("peer credentials (group membership)");
+                }
+            }
+        }
+
+        if let (Some(acl), Some(peer)) = (&self.ip_acl, peer) {
+            if acl.contains(peer.ip()) {
+                return Some("IP ACL");
+            }
+        }
+
+        if let Some(ref expected) = self.cookie {
+            if **expected == cookie {
+                return Some("cookie");
+            }
+        }
+
+        None
+    }
+}
+
+/// Credentials of the process on the other end of a Unix socket.
+#[derive(Debug, Copy, Clone)]
+pub(crate) struct PeerCreds {
+    pub pid: i32,
+    pub uid: u32,
+    pub gid: u32,
+}
+
+impl PeerCreds {
+    /// Queries the credentials of the process connected to the Unix socket `sock`.
+    ///
+    /// The credentials are those of the process at the time it connected (`SO_PEERCRED`), so they
+    /// can't be forged by passing the socket to another process.
+    pub fn of<S: AsRawFd>(sock: &S) -> io::Result<Self> {
+        let creds = getsockopt(sock.as_raw_fd(), PeerCredentials)
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+
+        Ok(Self {
+            pid: creds.pid(),
+            uid: creds.uid(),
+            gid: creds.gid(),
+        })
+    }
+
+    /// Stores the credentials in the `application.process.*` properties of `props`.
+    ///
+    /// This overwrites any values the client claimed for itself.
+    pub fn set_props(&self, props: &mut PropList) {
+        props.set(Prop::ApplicationProcessId, format!("{}\0", self.pid));
+        match user::name(self.uid) {
+            Some(name) => props.set(Prop::ApplicationProcessUser, format!("{}\0", name)),
+            None => props.set(Prop::ApplicationProcessUser, format!("{}\0", self.uid)),
+        }
+    }
+}
+
+/// A list of IP networks, as accepted by PulseAudio's `auth-ip-acl` module argument.
+#[derive(Debug, Clone)]
+pub(crate) struct IpAcl {
+    /// Network addresses and prefix lengths.
+    networks: Vec<(IpAddr, u8)>,
+}
+
+impl IpAcl {
+    /// Parses a `;`-separated list of addresses with optional prefix lengths, eg.
+    /// `127.0.0.1;192.168.0.0/16;fd00::/8`.
+    ///
+    /// Addresses without a prefix length match only that exact address.
+    pub fn parse(s: &str) -> Result<Self, Error> {
+        let networks = s.split(';').map(str::trim).filter(|entry| !entry.is_empty()).map(|entry| {
+            let invalid = || Error::string(format!("invalid IP ACL entry '{}'", entry));
+
+            let mut split = entry.splitn(2, '/');
+            let addr: IpAddr = split.next().unwrap().parse().map_err(|_| invalid())?;
+            let max_bits = if addr.is_ipv4() { 32 } else { 128 };
+            let bits = match split.next() {
+                Some(bits) => bits.parse().map_err(|_| invalid())?,
+                None => max_bits,
+            };
+            if bits > max_bits {
+                return Err(invalid());
+            }
+
+            Ok((addr, bits))
+        }).collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Self { networks })
+    }
+
+    /// Returns whether `addr` is part of any network in this list.
+    ///
+    /// IPv4-mapped IPv6 addresses (as reported for IPv4 clients of dual-stack sockets) are treated
+    /// as IPv4 addresses.
+    pub fn contains(&self, addr: IpAddr) -> bool {
+        let addr = match addr {
+            IpAddr::V6(v6) => match v6.to_ipv4() {
+                Some(v4) if v6.segments()[..6] == [0, 0, 0, 0, 0, 0xffff] => IpAddr::V4(v4),
+                _ => IpAddr::V6(v6),
+            },
+            v4 => v4,
+        };
+
+        self.networks.iter().any(|&(network, bits)| match (network, addr) {
+            (IpAddr::V4(network), IpAddr::V4(addr)) => {
+                prefix_matches(&network.octets(), &addr.octets(), bits)
+            }
+            (IpAddr::V6(network), IpAddr::V6(addr)) => {
+                prefix_matches(&network.octets(), &addr.octets(), bits)
+            }
+            _ => false,
+        })
+    }
+}
+
+/// Compares the first `bits` bits of two addresses.
+fn prefix_matches(network: &[u8], addr: &[u8], bits: u8) -> bool {
+    let full_bytes = (bits / 8) as usize;
+    if network[..full_bytes] != addr[..full_bytes] {
+        return false;
+    }
+
+    let rest = bits % 8;
+    if rest == 0 {
+        return true;
+    }
+
+    let mask = 0xffu8 << (8 - rest);
+    network[full_bytes] & mask == addr[full_bytes] & mask
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn acl(s: &str) -> IpAcl {
+        IpAcl::parse(s).unwrap()
+    }
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn parse_acl() {
+        assert_eq!(acl(" 127.0.0.1 ; 10.0.0.0/8;;fd00::/8 ").networks, [
+            (ip("127.0.0.1"), 32),
+            (ip("10.0.0.0"), 8),
+            (ip("fd00::"), 8),
+        ]);
+        assert!(acl("").networks.is_empty());
+        assert_eq!(acl("0.0.0.0/0;::/0;::1/128").networks, [(ip("0.0.0.0"), 0), (ip("::"), 0), (ip("::1"), 128)]);
+
+        for &invalid in &["10.0.0.0/33", "::/129", "10.0.0.0/", "10.0.0.0/-1", "10.0.0.0/8/8", "10.0.0/8", "localhost", "10.0.0.0/a"] {
+            assert!(IpAcl::parse(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn contains() {
+        let any = acl("0.0.0.0/0");
+        assert!(any.contains(ip("1.2.3.4")));
+        assert!(any.contains(ip("255.255.255.255")));
+        assert!(!any.contains(ip("::1")));
+        assert!(acl("::/0").contains(ip("2001:db8::1")));
+
+        let host = acl("192.168.1.10/32;::1/128");
+        assert!(host.contains(ip("192.168.1.10")));
+        assert!(!host.contains(ip("192.168.1.11")));
+        assert!(host.contains(ip("::1")));
+        assert!(!host.contains(ip("::2")));
+        // Without a prefix length, only the address itself matches
+        assert!(acl("192.168.1.10").contains(ip("192.168.1.10")));
+        assert!(!acl("192.168.1.10").contains(ip("192.168.1.11")));
+
+        // Prefixes that don't end at a byte boundary
+        let net = acl("172.16.0.0/12;2001:db8::/33");
+        assert!(net.contains(ip("172.16.0.1")));
+        assert!(net.contains(ip("172.31.255.255")));
+        assert!(!net.contains(ip("172.32.0.0")));
+        assert!(!net.contains(ip("172.15.255.255")));
+        assert!(net.contains(ip("2001:db8:7fff::1")));
+        assert!(!net.contains(ip("2001:db8:8000::1")));
+        // Host bits set in the network address are ignored
+        assert!(acl("10.1.2.3/8").contains(ip("10.200.0.1")));
+    }
+
+    #[test]
+    fn ipv4_mapped() {
+        let v4 = acl("10.0.0.0/8");
+        assert!(v4.contains(ip("::ffff:10.1.2.3")));
+        assert!(!v4.contains(ip("::ffff:11.1.2.3")));
+        // IPv4-compatible addresses (without the `ffff` marker) aren't mapped
+        assert!(!v4.contains(ip("::10.1.2.3")));
+
+        let v6 = acl("::ffff:0:0/96");
+        assert!(!v6.contains(ip("::ffff:10.1.2.3")));
+        assert!(!v6.contains(ip("10.1.2.3")));
+    }
+
+    #[test]
+    fn prefix() {
+        assert!(prefix_matches(&[0xff, 0xff], &[0x00, 0x00], 0));
+        assert!(prefix_matches(&[0xab, 0xcd], &[0xab, 0xcd], 16));
+        assert!(!prefix_matches(&[0xab, 0xcd], &[0xab, 0xcc], 16));
+        assert!(prefix_matches(&[0xab, 0xcd], &[0xab, 0xcc], 15));
+        assert!(prefix_matches(&[0b1010_0000], &[0b1011_1111], 3));
+        assert!(!prefix_matches(&[0b1010_0000], &[0b1011_1111], 4));
+        assert!(prefix_matches(&[0x80], &[0xff], 1));
+        assert!(!prefix_matches(&[0x80], &[0x7f], 1));
+    }
+
+    #[test]
+    fn check_precedence() {
+        let cookie = Arc::new(AuthCookie::from_bytes(&[7; 256]).unwrap());
+        let wrong_cookie = [0; 256];
+        let local = PeerCreds { pid: 1, uid: user::current_uid(), gid: 0 };
+        let other = PeerCreds { pid: 1, uid: user::current_uid().wrapping_add(1), gid: 0 };
+        let inside: SocketAddr = "10.0.0.1:1234".parse().unwrap();
+        let outside: SocketAddr = "11.0.0.1:1234".parse().unwrap();
+
+        let opts = AuthOptions {
+            same_user: true,
+            ip_acl: Some(acl("10.0.0.0/8")),
+            ..AuthOptions::cookie_only(cookie.clone())
+        };
+        // Credentials are checked before the ACL, which is checked before the cookie
+        assert_eq!(opts.check(Some(&inside), Some(&local), cookie.as_bytes()), Some("peer credentials (same user)"));
+        assert_eq!(opts.check(Some(&inside), Some(&other), cookie.as_bytes()), Some("IP ACL"));
+        assert_eq!(opts.check(Some(&outside), Some(&other), cookie.as_bytes()), Some("cookie"));
+        assert_eq!(opts.check(Some(&outside), None, cookie.as_bytes()), Some("cookie"));
+        assert_eq!(opts.check(Some(&outside), Some(&other), &wrong_cookie), None);
+        assert_eq!(opts.check(None, None, &[]), None);
+
+        // Anonymous access overrides everything else
+        let anonymous = AuthOptions { anonymous: true, ..opts.clone() };
+        assert_eq!(anonymous.check(Some(&inside), Some(&local), cookie.as_bytes()), Some("anonymous access"));
+        assert_eq!(anonymous.check(None, None, &[]), Some("anonymous access"));
+
+        // Without a cookie, even an empty one isn't accepted
+        let no_cookie = AuthOptions { cookie: None, ..opts };
+        assert_eq!(no_cookie.check(Some(&outside), Some(&other), cookie.as_bytes()), None);
+        assert_eq!(no_cookie.check(None, None, &[]), None);
+    }
+}
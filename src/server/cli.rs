@@ -0,0 +1,180 @@
+//! Text-based command line interface served on the `cli` socket.
+//!
+//! Clients send one command per line, using the same language as startup scripts (see the
+//! `script` module). The server answers with the command's output (or an error message), followed
+//! by a `>>> ` prompt that signals that the server is ready for the next command. Like in
+//! PulseAudio, a welcome message followed by a prompt is sent right after connecting.
+//!
+//! The CLI gives full control over the server without any further authentication, so only
+//! processes running as the same user as the server or as root may connect (checked using the
+//! socket's peer credentials).
+
+use super::ServerData;
+use super::auth::PeerCreds;
+use super::script::Interpreter;
+
+use pa_proto::user;
+
+use bytes::BytesMut;
+use tokio;
+use tokio::prelude::*;
+use tokio_codec::{Decoder, Encoder, LinesCodec};
+use tokio_uds::UnixStream;
+use std::sync::Arc;
+use std::io;
+
+/// The prompt sent after every command.
+const PROMPT: &str = ">>> ";
+
+/// Decodes input lines and encodes raw output text.
+#[derive(Debug)]
+struct CliCodec {
+    lines: LinesCodec,
+}
+
+impl Decoder for CliCodec {
+    type Item = String;
+    type Error = io::Error;
+
+    fn decode(&mut self, buf: &mut BytesMut) -> Result<Option<String>, io::Error> {
+        self.lines.decode(buf)
+    }
+
+    fn decode_eof(&mut self, buf: &mut BytesMut) -> Result<Option<String>, io::Error> {
+        self.lines.decode_eof(buf)
+    }
+}
+
+impl Encoder for CliCodec {
+    type Item = String;
+    type Error = io::Error;
+
+    fn encode(&mut self, item: String, dst: &mut BytesMut) -> Result<(), io::Error> {
+        dst.extend_from_slice(item.as_bytes());
+        Ok(())
+    }
+}
+
+/// Returns whether a process with the credentials `creds` may use the CLI of a server running as
+/// the user `server_uid`.
+fn authorized(creds: &PeerCreds, server_uid: u32) -> bool {
+    creds.uid == server_uid || creds.uid == 0
+}
+
+/// Process an incoming CLI connection.
+pub(crate) fn process(stream: UnixStream, data: Arc<ServerData>) {
+    match PeerCreds::of(&stream) {
+        Ok(ref creds) if authorized(creds, user::current_uid()) => {
+            info!("new CLI connection from PID {} (UID {})", creds.pid, creds.uid);
+        }
+        Ok(creds) => {
+            warn!("refusing CLI connection from PID {} (UID {})", creds.pid, creds.uid);
+            return;
+        }
+        Err(e) => {
+            warn!("refusing CLI connection, couldn't get peer credentials: {}", e);
+            return;
+        }
+    }
+
+    let shutdown = data.shutdown.clone();
+    match shutdown.guard(session(stream, data)) {
+        Some(task) => {
+            tokio::spawn(task);
+        }
+        None => debug!("server is shutting down, dropping new CLI connection"),
+    }
+}
+
+/// Returns a future that runs a CLI session on `stream` until the client disconnects.
+fn session<S>(stream: S, data: Arc<ServerData>) -> impl Future<Item=(), Error=()>
+where S: AsyncRead + AsyncWrite {
+    let (tx, rx) = CliCodec { lines: LinesCodec::new() }.framed(stream).split();
+    let mut interp = Interpreter::new(data);
+
+    let welcome = format!(
+        "Welcome to pulsar {}! Use \"help\" for usage information.\n{}",
+        env!("CARGO_PKG_VERSION"), PROMPT,
+    );
+
+    let replies = rx.map(move |line| {
+        let mut out = String::new();
+        if let Err(e) = interp.execute_line(&line, &mut out) {
+            out.push_str(&e.to_string());
+            out.push('\n');
+        }
+        out.push_str(PROMPT);
+        out
+    });
+
+    tx.send_all(stream::once(Ok(welcome)).chain(replies)).then(|result| {
+        match result {
+            Ok(_) => info!("CLI connection closed"),
+            Err(e) => error!("CLI connection encountered error: {}", e),
+        }
+
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pa_proto::conf::DaemonConf;
+    use pa_proto::cookie::AuthCookie;
+
+    use tokio::reactor::Handle;
+    use tokio::runtime::current_thread::Runtime;
+    use std::os::unix::net;
+    use std::net::Shutdown;
+
+    #[test]
+    fn authorization() {
+        let creds = |uid| PeerCreds { pid: 1, uid, gid: 100 };
+        assert!(authorized(&creds(1000), 1000));
+        assert!(authorized(&creds(0), 1000));
+        assert!(authorized(&creds(0), 0));
+        assert!(!authorized(&creds(1001), 1000));
+        assert!(!authorized(&creds(1000), 0));
+    }
+
+    /// Runs a CLI session receiving `input` and returns everything the server sent.
+    fn run_session(data: Arc<ServerData>, input: &str) -> String {
+        let (server, mut client) = net::UnixStream::pair().unwrap();
+        client.write_all(input.as_bytes()).unwrap();
+        client.shutdown(Shutdown::Write).unwrap();
+
+        let mut runtime = Runtime::new().unwrap();
+        runtime.block_on(future::lazy(|| {
+            let server = UnixStream::from_std(server, &Handle::default()).unwrap();
+            session(server, data)
+        })).unwrap();
+
+        let mut output = String::new();
+        client.read_to_string(&mut output).unwrap();
+        output
+    }
+
+    #[test]
+    fn commands() {
+        let data = Arc::new(ServerData::new(AuthCookie::from_bytes(&[0; 256]).unwrap(), DaemonConf::default()));
+        let output = run_session(data.clone(), "help
+load-module module-null-sink sink_name=cli
+
+bogus
+list-sinks
+");
+
+        let mut replies = output.split(PROMPT);
+        assert!(replies.next().unwrap().starts_with("Welcome to pulsar"));
+        assert!(replies.next().unwrap().contains("load-module <name> [<arguments>]"));
+        assert_eq!(replies.next(), Some(""));
+        assert_eq!(replies.next(), Some(""));
+        assert_eq!(replies.next(), Some("unknown command 'bogus'\n"));
+        assert!(replies.next().unwrap().starts_with("1 sink(s) available.\n"));
+        assert_eq!(replies.next(), Some(""));
+        assert_eq!(replies.next(), None);
+
+        assert_eq!(data.sinks().iter().next().unwrap().name().to_string(), "cli");
+    }
+}
@@ -0,0 +1,2465 @@
+mod activation;
+mod auth;
+mod cli;
+mod module;
+mod script;
+mod sample_cache;
+mod shutdown;
+mod streams;
+
+use self::activation::Activation;
+use self::auth::{AuthOptions, PeerCreds};
+use self::module::LoadedModule;
+use self::script::Interpreter;
+use self::sample_cache::{Sample, Upload};
+use self::shutdown::Shutdown;
+use self::streams::{SinkInput, SourceOutput};
+
+use pa_proto::error::{PulseError, Error};
+use pa_proto::command::{self, Command, CommandKind, ClientInfo, ServerInfo, PROTOCOL_MIN_VERSION};
+use pa_proto::command::{EventType, Facility, SubscriptionEvent, SubscriptionMask};
+use pa_proto::command::{CreatePlaybackStream, CreateRecordStream, SinkSpec, SourceSpec, StreamChannel};
+use pa_proto::command::{CreateUploadStream, LatencyInfo};
+use pa_proto::packet::{Packet, PacketCodec, Message, MemblockData, SeekMode};
+use pa_proto::proplist::{Prop, PropList};
+use pa_proto::conf::{DaemonConf, MIN_QUEUED_MESSAGES};
+use pa_proto::cookie::AuthCookie;
+use pa_proto::paths::{self, cookie_path};
+use pa_proto::runtime_dir::{create_secure_dir, create_shared_dir, PidFile, PID_FILE_NAME};
+use pa_proto::idxset::{Idx, IdxSet};
+use pa_proto::sample_spec::SampleSpec;
+use pa_proto::channel_map::ChannelMap;
+use pa_proto::source::Source;
+use pa_proto::shm::{Fd, ShmImports, ShmPool, ShmType, DEFAULT_POOL_SIZE};
+use pa_proto::srbchannel::SrbChannel;
+use pa_proto::string::PaString;
+use pa_proto::time::{Microseconds, Timeval};
+use pa_proto::user;
+use pa_proto::{self, CVolume};
+
+use nix::libc;
+use nix::unistd;
+use futures::future::Either;
+use futures::sync::{mpsc, oneshot};
+use futures::StartSend;
+use rand;
+use tokio;
+use tokio::prelude::*;
+use tokio::reactor::Handle;
+use tokio::timer::{Delay, Interval};
+use tokio_codec::Decoder;
+use tokio_uds::{UnixListener, UnixStream};
+use unix_framed::{SrbControl, UnixFramed};
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::{cmp, fmt, fs, io};
+use std::ops::{Deref, DerefMut};
+use std::time::{Duration, Instant};
+
+/// Minimum protocol version of clients that can use memfd-backed shared memory.
+const MEMFD_MIN_VERSION: u16 = 31;
+
+/// Minimum protocol version of clients that can use an srbchannel.
+const SRBCHANNEL_MIN_VERSION: u16 = 30;
+
+/// How often to check whether the server is idle (see `DaemonConf::exit_idle_time`).
+const IDLE_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How long a refused client has to send its first command, which is answered with the error.
+const REFUSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Maximum number of refused connections that are kept open to tell the client why. Further
+/// refused connections are closed right away, so a flood of them can't use up all file descriptors.
+const MAX_PENDING_REFUSALS: usize = 16;
+
+/// How long to wait before accepting connections again after running out of resources, eg. file
+/// descriptors.
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
+/// Number of messages handed to a client's transport before waiting for them to be written.
+const MAX_UNSENT_MESSAGES: usize = 64;
+
+#[derive(Debug)]
+pub struct Server {
+    sock: UnixListener,
+    /// Socket accepting connections using the text-based CLI protocol.
+    cli_sock: UnixListener,
+    /// Accept local clients running as the same user as the server without checking the cookie.
+    same_user_auth: bool,
+    /// Accept local clients belonging to this group without checking the cookie.
+    auth_group: Option<u32>,
+    runtime_files: RuntimeFiles,
+    data: Arc<ServerData>,
+}
+
+/// Files in the runtime directory owned by a server, which are removed when it goes away.
+#[derive(Debug)]
+struct RuntimeFiles {
+    sockets: Vec<PathBuf>,
+    /// Dropped after removing the sockets, so another server can't bind them in the meantime.
+    _pid_file: PidFile,
+}
+
+impl Drop for RuntimeFiles {
+    fn drop(&mut self) {
+        for socket in &self.sockets {
+            if let Err(e) = fs::remove_file(socket) {
+                warn!("couldn't remove socket {}: {}", socket.display(), e);
+            }
+        }
+    }
+}
+
+impl Server {
+    /// Creates a server listening in `runtime_dir`, configured by `daemon.conf` (see
+    /// `DaemonConf::load`).
+    pub fn new_unix<P: AsRef<Path>>(runtime_dir: P) -> io::Result<Self> {
+        let conf = DaemonConf::load()
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?;
+        Self::new_unix_with_conf(runtime_dir, conf)
+    }
+
+    /// Creates a server listening in `runtime_dir`, using the configuration `conf`.
+    ///
+    /// The runtime directory is created if necessary and must only be accessible by the current
+    /// user. The server locks the PID file in it, so only one server can use the directory at a
+    /// time. The sockets and the PID file are removed when the server is dropped.
+    ///
+    /// If the server was started via socket activation (see the `activation` module), the passed
+    /// sockets are used instead of binding new ones. They are left alone when the server exits,
+    /// so the service manager can start the server again on the next connection.
+    pub fn new_unix_with_conf<P: AsRef<Path>>(runtime_dir: P, conf: DaemonConf) -> io::Result<Self> {
+        Self::new_unix_inner(runtime_dir.as_ref(), conf, false)
+    }
+
+    /// Creates the system-wide server instance listening in `runtime_dir`, using the
+    /// configuration `conf`.
+    ///
+    /// Unlike `new_unix_with_conf`, the runtime directory is made accessible to all users (see
+    /// `runtime_dir::create_shared_dir`), and so is the native protocol socket. The directory must
+    /// be owned by the current user, so this should be called after dropping privileges.
+    pub fn new_system_with_conf<P: AsRef<Path>>(runtime_dir: P, conf: DaemonConf) -> io::Result<Self> {
+        Self::new_unix_inner(runtime_dir.as_ref(), conf, true)
+    }
+
+    fn new_unix_inner(runtime_dir: &Path, conf: DaemonConf, system: bool) -> io::Result<Self> {
+        if system {
+            create_shared_dir(runtime_dir, user::current_uid(), user::current_gid())?;
+        } else {
+            create_secure_dir(runtime_dir)?;
+        }
+        let mut runtime_files = RuntimeFiles {
+            sockets: Vec::new(),
+            _pid_file: PidFile::create(runtime_dir.join(PID_FILE_NAME))?,
+        };
+
+        let mut activation = Activation::from_env()?;
+        let sock = listener(runtime_dir, "native", &mut activation, &mut runtime_files)?;
+        if system && runtime_files.sockets.contains(&runtime_dir.join("native")) {
+            // Clients need write access to connect. The CLI socket stays private.
+            fs::set_permissions(runtime_dir.join("native"), fs::Permissions::from_mode(0o777))?;
+        }
+        let cli_sock = listener(runtime_dir, "cli", &mut activation, &mut runtime_files)?;
+
+        // Reuse the existing cookie so that clients which already know it keep working
+        let cookie = AuthCookie::load_or_create(cookie_path())?;
+
+        Ok(Server {
+            sock,
+            cli_sock,
+            same_user_auth: true,
+            auth_group: None,
+            runtime_files,
+            data: Arc::new(ServerData::new(cookie, conf)),
+        })
+    }
+
+    /// Sets whether local clients running as the same user as the server are accepted without
+    /// checking their auth cookie.
+    ///
+    /// This is enabled by default.
+    pub fn set_same_user_auth(&mut self, enabled: bool) {
+        self.same_user_auth = enabled;
+    }
+
+    /// Accepts local clients whose user is a member of the group `name` without checking their
+    /// auth cookie.
+    ///
+    /// Returns an error if no such group exists.
+    pub fn set_auth_group(&mut self, name: &str) -> Result<(), Error> {
+        let gid = user::group_id(name)
+            .ok_or_else(|| Error::string(format!("unknown group '{}'", name)))?;
+        self.auth_group = Some(gid);
+        Ok(())
+    }
+
+    /// Executes the startup script.
+    ///
+    /// This runs the user's or the system-wide `default.pa` (see `paths::startup_script`). If
+    /// neither exists, a built-in script that sets up a null sink and source is used instead.
+    ///
+    /// The script is executed when the returned future is polled. Since modules may spawn tasks,
+    /// it must run on the same runtime as the server.
+    pub fn run_startup_script(&self) -> impl Future<Item=(), Error=Error> + Send {
+        let data = self.data.clone();
+        future::lazy(move || match paths::startup_script() {
+            Some(path) => {
+                info!("executing script {}", path.display());
+                Interpreter::new(data).run_file(&path)
+            }
+            None => {
+                info!("no startup script found, using built-in default configuration");
+                Interpreter::new(data).run_str(script::BUILTIN_SCRIPT, "<built-in>")
+            }
+        })
+    }
+
+    /// Executes the `default.pa`-style script stored at `path`.
+    ///
+    /// Like `run_startup_script`, the returned future must run on the server's runtime.
+    pub fn run_script_file<P: Into<PathBuf>>(&self, path: P) -> impl Future<Item=(), Error=Error> + Send {
+        let data = self.data.clone();
+        let path = path.into();
+        future::lazy(move || {
+            info!("executing script {}", path.display());
+            Interpreter::new(data).run_file(&path)
+        })
+    }
+
+    /// Returns a handle that can be used to shut down the server once it's running.
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        ShutdownHandle {
+            shutdown: self.data.shutdown.clone(),
+        }
+    }
+
+    /// Turn the server instance to a runnable `Future` that will accept clients and process
+    /// communication.
+    ///
+    /// The future completes when the server is shut down, either via a `ShutdownHandle`, by a
+    /// client sending the `EXIT` command, or because no client was connected for the configured
+    /// `exit_idle_time`. The server then stops accepting connections, disconnects all clients,
+    /// unloads all modules and removes its files from the runtime directory. The future resolves
+    /// once all connections are closed.
+    ///
+    /// Returns an error if something related to the listening socket goes wrong. This normally
+    /// shouldn't happen. In particular, this won't return an error when a client messes up - the
+    /// client will simply be disconnected and the error logged. The server is shut down in this
+    /// case, too.
+    ///
+    /// If the future is dropped before completing, the files in the runtime directory are
+    /// removed, but connected clients and modules stay around until the runtime is shut down.
+    pub fn listen(self) -> impl Future<Item=(), Error=io::Error> {
+        let runtime_files = self.runtime_files;
+        let data = self.data;
+        let native_data = data.clone();
+        let cli_data = data.clone();
+        let auth = Arc::new(AuthOptions {
+            same_user: self.same_user_auth,
+            group: self.auth_group,
+            ..AuthOptions::cookie_only(data.cookie.clone())
+        });
+
+        // Accept `std` sockets, since `UnixFramed` needs to do its own I/O to pass file descriptors
+        let sock = self.sock;
+        let native = incoming(move || sock.poll_accept_std()).for_each(move |stream| {
+            // Without credentials, the client can still authenticate using the cookie
+            let creds = PeerCreds::of(&stream)
+                .map_err(|e| warn!("couldn't get peer credentials of client: {}", e))
+                .ok();
+            let transport = match UnixFramed::new(stream) {
+                Ok(transport) => transport,
+                Err(e) => {
+                    error!("couldn't register client socket: {}", e);
+                    return Ok(());
+                }
+            };
+            let srb = transport.srb_control();
+            process(transport, None, creds, Some(srb), auth.clone(), native_data.clone());
+            Ok(())
+        });
+        let cli_sock = self.cli_sock;
+        let cli = incoming(move || cli_sock.poll_accept()).for_each(move |stream| {
+            cli::process(stream, cli_data.clone());
+            Ok(())
+        });
+
+        let accept = native.select(cli).map(|_| ()).map_err(|(e, _)| e);
+
+        let stop = data.shutdown.triggered();
+        let idle = match data.conf().exit_idle_time {
+            Some(timeout) => Either::A(exit_when_idle(data.clone(), timeout)),
+            None => Either::B(future::empty()),
+        };
+        accept.select2(stop.select2(idle)).then(move |result| {
+            // Dropping the listeners stops accepting connections
+            let result = match result {
+                Ok(_) => Ok(()),
+                Err(Either::A((e, _))) => {
+                    error!("server encountered error: {}", e);
+                    Err(e)
+                }
+                Err(Either::B(_)) => Ok(()),
+            };
+
+            data.shutdown.trigger();
+            module::unload_all(&data);
+            data.shutdown.finished().then(move |_| {
+                drop(runtime_files);
+                info!("server stopped");
+                result
+            })
+        })
+    }
+}
+
+/// Handle for shutting down a running server.
+///
+/// Created by `Server::shutdown_handle`.
+#[derive(Debug, Clone)]
+pub struct ShutdownHandle {
+    shutdown: Arc<Shutdown>,
+}
+
+impl ShutdownHandle {
+    /// Shuts down the server.
+    ///
+    /// This returns immediately. The future returned by `Server::listen` completes once the
+    /// server has stopped. Does nothing if the server is already shutting down.
+    pub fn shutdown(&self) {
+        self.shutdown.trigger();
+    }
+}
+
+/// Returns the stream of connections accepted by `accept`, eg. `UnixListener::poll_accept`.
+///
+/// Errors that only affect a single connection or that go away on their own, like running out of
+/// file descriptors, are logged and don't end the stream. After running out of resources,
+/// accepting is paused for `ACCEPT_BACKOFF`, since the pending connection would fail right away
+/// again.
+fn incoming<S, A, F>(mut accept: F) -> impl Stream<Item=S, Error=io::Error>
+where F: FnMut() -> Poll<(S, A), io::Error> {
+    let mut backoff: Option<Delay> = None;
+    stream::poll_fn(move || loop {
+        if let Some(mut delay) = backoff.take() {
+            // A timer error only ends the pause early
+            if let Ok(Async::NotReady) = delay.poll() {
+                backoff = Some(delay);
+                return Ok(Async::NotReady);
+            }
+        }
+
+        match accept() {
+            Ok(Async::Ready((stream, _))) => return Ok(Async::Ready(Some(stream))),
+            Ok(Async::NotReady) => return Ok(Async::NotReady),
+            Err(ref e) if e.kind() == io::ErrorKind::ConnectionAborted || e.kind() == io::ErrorKind::Interrupted => {
+                debug!("couldn't accept connection: {}", e);
+            }
+            Err(ref e) if is_resource_error(e) => {
+                warn!("couldn't accept connection, pausing for {} ms: {}", ACCEPT_BACKOFF.subsec_millis(), e);
+                backoff = Some(Delay::new(Instant::now() + ACCEPT_BACKOFF));
+            }
+            Err(e) => return Err(e),
+        }
+    })
+}
+
+/// Returns whether `error` means that the system or the process ran out of resources.
+fn is_resource_error(error: &io::Error) -> bool {
+    match error.raw_os_error() {
+        Some(libc::EMFILE) | Some(libc::ENFILE) | Some(libc::ENOBUFS) | Some(libc::ENOMEM) => true,
+        _ => false,
+    }
+}
+
+/// Returns a future that resolves once no client was connected to the server for `timeout`.
+fn exit_when_idle(data: Arc<ServerData>, timeout: Duration) -> impl Future<Item=(), Error=()> {
+    // The server is idle initially, so it also exits if no client ever connects
+    let mut idle_since = Instant::now();
+    Interval::new(Instant::now() + IDLE_CHECK_INTERVAL, IDLE_CHECK_INTERVAL)
+        .skip_while(move |&now| {
+            if data.shutdown.connections() != 0 {
+                idle_since = now;
+            }
+            Ok(now.duration_since(idle_since) < timeout)
+        })
+        .into_future()
+        .then(move |result| match result {
+            Ok(_) => {
+                info!("no clients connected for {} seconds, exiting", timeout.as_secs());
+                Either::A(future::ok(()))
+            }
+            // Shutting down is up to the user then
+            Err((e, _)) => {
+                error!("idle timer failed, the server won't exit when idle: {}", e);
+                Either::B(future::empty())
+            }
+        })
+}
+
+/// Returns the listening socket `name` (`native` or `cli`).
+///
+/// This is the socket passed by the service manager if there is one, or a new socket bound in
+/// `runtime_dir` otherwise.
+fn listener(runtime_dir: &Path, name: &str, activation: &mut Activation, runtime_files: &mut RuntimeFiles) -> io::Result<UnixListener> {
+    if let Some(listener) = activation.take(name) {
+        info!("using {} socket passed by the service manager", name);
+        return UnixListener::from_std(listener, &Handle::default());
+    }
+
+    let socket_file = runtime_dir.join(name);
+    let listener = bind_unix(&socket_file)?;
+    runtime_files.sockets.push(socket_file);
+    Ok(listener)
+}
+
+/// Binds a listening Unix socket to `socket_file`.
+fn bind_unix(socket_file: &Path) -> io::Result<UnixListener> {
+    // `socket_file` might already exist. In that case, it's either a left-over from the last
+    // server (in which case we can delete the socket) or a server is already running (in which
+    // case we bail).
+    if socket_file.exists() {
+        // If a connection attempt succeeds, there's still someone on the other side.
+        match UnixStream::connect(socket_file).wait() {
+            Ok(_) => return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("server socket '{}' already exists and a server is still running", socket_file.display())
+            )),
+            Err(ref e) if e.kind() == io::ErrorKind::ConnectionRefused => {
+                // This is the expected error when nobody is listening on the socket.
+                // Try to remove the file. We ignore any errors since someone else might've
+                // deleted it while we were checking. If a legit error occurs, the bind below
+                // will fail.
+                if let Err(e) = fs::remove_file(socket_file) {
+                    error!("couldn't remove abandoned socket file '{}': {}", socket_file.display(), e);
+                }
+            }
+            Err(e) => {
+                // Unexpected error, better bail
+                return Err(e);
+            }
+        }
+    }
+
+    UnixListener::bind(socket_file)
+}
+
+/// Process an incoming connection.
+///
+/// `transport` exchanges packets with the client (see `framed` for plain byte streams). `peer` is
+/// the address of the client if it connected via the network, `creds` are its credentials if it
+/// connected via a Unix socket, and `srb` is set if the transport supports srbchannels. `auth`
+/// determines which clients are accepted.
+fn process<T>(transport: T, peer: Option<SocketAddr>, creds: Option<PeerCreds>, srb: Option<Arc<SrbControl>>, auth: Arc<AuthOptions>, data: Arc<ServerData>)
+where T: Stream<Item=Packet, Error=Error> + Sink<SinkItem=Packet, SinkError=Error> + Send + 'static {
+    let (queue_tx, queue_rx) = data.client_queue();
+    let (kill_tx, kill_rx) = oneshot::channel();
+    let mut handler = match ClientHandler::new(data.clone(), peer, creds, srb, auth, queue_tx.clone(), kill_tx) {
+        Ok(handler) => handler,
+        Err(e) => return refuse(transport, ClientLabel::new(None, peer, creds), e, &data),
+    };
+    let client = handler.client;
+    let pending = handler.pending.clone();
+
+    let (tx, rx) = transport.split();
+
+    // Replies wait for room in the queue, so a client that doesn't read them isn't read from
+    // either. Messages sent by the rest of the server can't wait (see `Client::send`).
+    let reader_data = data.clone();
+    let reader = rx.map_err(move |e| {
+        error!("{} will be disconnected: {}", reader_data.client_label(client), e);
+    }).fold(queue_tx, move |queue, packet| {
+        let replies = match handler.handle_packet(packet) {
+            Ok(replies) => replies,
+            Err(e) => {
+                error!("{} will be disconnected: {}", handler.label(), e);
+                return Either::A(future::err(()));
+            }
+        };
+        debug!("{}: replies: {:?}", handler.label(), replies);
+        Either::B(queue.send_all(stream::iter_ok(replies.into_iter().map(Outgoing::Reply)))
+            .map(|(queue, _)| queue)
+            .map_err(|_| ()))
+    });
+
+    let tx = UnsentLimit {
+        sink: tx,
+        unsent: 0,
+        unsent_replies: 0,
+        pending,
+        data: data.clone(),
+        client,
+    };
+    let writer_data = data.clone();
+    let writer = tx.send_all(queue_rx.map_err(|()| Error::string("client queue failed"))).map_err(move |e| {
+        error!("couldn't send to {}, disconnecting it: {}", writer_data.client_label(client), e);
+    });
+
+    let task_data = data.clone();
+    let task = reader.map(|_| ()).select(writer.map(|_| ())).map_err(|_| ())
+        .select2(kill_rx)
+        .then(move |result| {
+            match result {
+                // The client is still registered while the connection is alive
+                Ok(Either::B(_)) | Err(Either::B(_)) => info!("disconnected {}", task_data.client_label(client)),
+                // The client closed the connection, or the error was logged already
+                Ok(Either::A(_)) | Err(Either::A(_)) => {}
+            }
+
+            Ok(())
+        });
+
+    match data.shutdown.guard(task) {
+        Some(task) => {
+            tokio::spawn(task);
+        }
+        None => debug!("server is shutting down, dropping new connection"),
+    }
+}
+
+/// Rejects a connection that `ClientHandler::new` refused. `label` identifies the client.
+///
+/// The client's first command (normally `AUTH`) is answered with `error`, so that the client
+/// learns why it can't connect, and the connection is closed afterwards.
+fn refuse<T>(transport: T, label: ClientLabel, error: PulseError, data: &ServerData)
+where T: Stream<Item=Packet, Error=Error> + Sink<SinkItem=Packet, SinkError=Error> + Send + 'static {
+    let refusal = match Refusal::start(data) {
+        Some(refusal) => refusal,
+        None => {
+            // Dropping the transport closes the connection
+            debug!("closing connection of refused {} ({})", label, error);
+            return;
+        }
+    };
+
+    let task = transport.into_future().map_err(|(e, _)| e).and_then(move |(packet, transport)| {
+        let mut buf = Vec::new();
+        let reply = packet.as_ref()
+            .and_then(|packet| match Message::from_packet(packet) {
+                Ok(Message::Control { tagstruct }) => Command::from_tagstruct(tagstruct, PROTOCOL_MIN_VERSION).ok(),
+                _ => None,
+            })
+            .map(|cmd| cmd.error_reply(error).to_packet(&mut buf, PROTOCOL_MIN_VERSION));
+        transport.send_all(stream::iter_ok::<_, Error>(reply))
+    }).timeout(REFUSE_TIMEOUT).then(move |result| {
+        drop(refusal);
+        match result {
+            Ok(_) => debug!("refused {}: {}", label, error),
+            Err(e) => debug!("couldn't send error to refused {} ({}): {}", label, error, e),
+        }
+        Ok(())
+    });
+
+    if let Some(task) = data.shutdown.guard(task) {
+        tokio::spawn(task);
+    }
+}
+
+/// A refused connection that is kept open to tell the client why (see `ServerData::refusals`).
+struct Refusal(Arc<AtomicUsize>);
+
+impl Refusal {
+    /// Counts a new refused connection. Returns `None` if `MAX_PENDING_REFUSALS` are pending.
+    fn start(data: &ServerData) -> Option<Self> {
+        let refusal = Refusal(data.refusals.clone());
+        if refusal.0.fetch_add(1, Ordering::SeqCst) < MAX_PENDING_REFUSALS {
+            Some(refusal)
+        } else {
+            None
+        }
+    }
+}
+
+impl Drop for Refusal {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Limits the number of messages handed to a client's transport that weren't written yet.
+///
+/// Once `MAX_UNSENT_MESSAGES` messages are unsent, no more are accepted until they were written,
+/// so the rest stay in the client's bounded queue instead of piling up in the transport. Written
+/// replies are subtracted from the client's pending requests.
+struct UnsentLimit<S> {
+    sink: S,
+    unsent: usize,
+    /// Number of unsent messages that are replies.
+    unsent_replies: usize,
+    /// The client's pending requests (see `ClientHandler::pending`).
+    pending: Arc<AtomicUsize>,
+    data: Arc<ServerData>,
+    client: Idx<Client>,
+}
+
+impl<S> UnsentLimit<S> {
+    /// Called once all messages handed to the transport were written.
+    fn written(&mut self) {
+        self.pending.fetch_sub(self.unsent_replies, Ordering::SeqCst);
+        self.unsent = 0;
+        self.unsent_replies = 0;
+    }
+}
+
+impl<S: Sink<SinkItem=Packet>> Sink for UnsentLimit<S> {
+    type SinkItem = Outgoing;
+    type SinkError = S::SinkError;
+
+    fn start_send(&mut self, item: Outgoing) -> StartSend<Outgoing, S::SinkError> {
+        if self.unsent >= MAX_UNSENT_MESSAGES {
+            if self.sink.poll_complete()?.is_not_ready() {
+                debug!("{} has {} unsent messages, waiting for it to catch up",
+                    self.data.client_label(self.client), self.unsent);
+                return Ok(AsyncSink::NotReady(item));
+            }
+            self.written();
+        }
+
+        let (packet, reply) = match item {
+            Outgoing::Reply(packet) => (packet, true),
+            Outgoing::Event(packet) => (packet, false),
+        };
+        match self.sink.start_send(packet)? {
+            AsyncSink::Ready => {
+                self.unsent += 1;
+                if reply {
+                    self.unsent_replies += 1;
+                }
+                Ok(AsyncSink::Ready)
+            }
+            AsyncSink::NotReady(packet) if reply => Ok(AsyncSink::NotReady(Outgoing::Reply(packet))),
+            AsyncSink::NotReady(packet) => Ok(AsyncSink::NotReady(Outgoing::Event(packet))),
+        }
+    }
+
+    fn poll_complete(&mut self) -> Poll<(), S::SinkError> {
+        try_ready!(self.sink.poll_complete());
+        self.written();
+        Ok(Async::Ready(()))
+    }
+
+    fn close(&mut self) -> Poll<(), S::SinkError> {
+        self.sink.close()
+    }
+}
+
+/// Wraps a byte stream (eg. a TCP connection) in a packet transport for `process`.
+///
+/// Since file descriptors can't be passed over such streams, clients connected this way can't
+/// use shared memory.
+fn framed<S>(stream: S) -> impl Stream<Item=Packet, Error=Error> + Sink<SinkItem=Packet, SinkError=Error>
+where S: AsyncRead + AsyncWrite {
+    PacketCodec::new().framed(stream)
+}
+
+/// Server data used by server and client handlers (potentially from different threads). Shared via
+/// `Arc<RwLock<_>>`.
+#[derive(Debug)]
+pub(crate) struct ServerData {
+    /// The server's auth cookie, used by sockets that don't configure their own.
+    cookie: Arc<AuthCookie>,
+    /// Currently registered clients.
+    ///
+    /// A client will be added to this list just by opening the control socket, so there might be
+    /// bogus clients in here.
+    clients: RwLock<IdxSet<Client>>,
+    /// Sinks connected to the server.
+    sinks: RwLock<IdxSet<pa_proto::sink::Sink>>,
+    /// Sources connected to the server.
+    sources: RwLock<IdxSet<Source>>,
+    /// Playback streams connected to sinks.
+    sink_inputs: RwLock<IdxSet<SinkInput>>,
+    /// Record streams connected to sources.
+    source_outputs: RwLock<IdxSet<SourceOutput>>,
+    /// The sample cache.
+    samples: RwLock<IdxSet<Sample>>,
+    /// Name of the sink new playback streams are connected to, if configured.
+    default_sink: RwLock<Option<PaString>>,
+    /// Name of the source new record streams are connected to, if configured.
+    default_source: RwLock<Option<PaString>>,
+    /// Loaded modules.
+    modules: RwLock<IdxSet<LoadedModule>>,
+    /// Random number identifying this server instance, reported to clients in the server info.
+    instance_cookie: u32,
+    /// The server configuration.
+    conf: DaemonConf,
+    /// Used to shut down the server and its connections.
+    shutdown: Arc<Shutdown>,
+    /// Number of refused connections that are still open (see `refuse`).
+    refusals: Arc<AtomicUsize>,
+}
+
+impl ServerData {
+    pub fn new(auth_cookie: AuthCookie, conf: DaemonConf) -> Self {
+        Self {
+            cookie: Arc::new(auth_cookie),
+            clients: RwLock::new(IdxSet::new()),
+            sinks: RwLock::new(IdxSet::new()),
+            sources: RwLock::new(IdxSet::new()),
+            sink_inputs: RwLock::new(IdxSet::new()),
+            source_outputs: RwLock::new(IdxSet::new()),
+            samples: RwLock::new(IdxSet::new()),
+            default_sink: RwLock::new(None),
+            default_source: RwLock::new(None),
+            modules: RwLock::new(IdxSet::new()),
+            instance_cookie: rand::random(),
+            conf,
+            shutdown: Arc::new(Shutdown::new()),
+            refusals: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+}
+
+impl ServerData {
+    fn clients<'a>(&'a self) -> impl Deref<Target=IdxSet<Client>> + 'a {
+        self.clients.read().unwrap()
+    }
+
+    fn clients_mut<'a>(&'a self) -> impl DerefMut<Target=IdxSet<Client>> + 'a {
+        self.clients.write().unwrap()
+    }
+
+    /// Returns the label identifying the client `idx` in log messages.
+    ///
+    /// Must not be called while the client list is locked.
+    fn client_label(&self, idx: Idx<Client>) -> ClientLabel {
+        self.clients().get(idx)
+            .map(|client| client.label.clone())
+            .unwrap_or_else(|| ClientLabel::new(Some(idx.value()), None, None))
+    }
+
+    fn sinks<'a>(&'a self) -> impl Deref<Target=IdxSet<pa_proto::sink::Sink>> + 'a {
+        self.sinks.read().unwrap()
+    }
+
+    fn sinks_mut<'a>(&'a self) -> impl DerefMut<Target=IdxSet<pa_proto::sink::Sink>> + 'a {
+        self.sinks.write().unwrap()
+    }
+
+    fn sources<'a>(&'a self) -> impl Deref<Target=IdxSet<Source>> + 'a {
+        self.sources.read().unwrap()
+    }
+
+    fn sources_mut<'a>(&'a self) -> impl DerefMut<Target=IdxSet<Source>> + 'a {
+        self.sources.write().unwrap()
+    }
+
+    fn sink_inputs<'a>(&'a self) -> impl Deref<Target=IdxSet<SinkInput>> + 'a {
+        self.sink_inputs.read().unwrap()
+    }
+
+    fn sink_inputs_mut<'a>(&'a self) -> impl DerefMut<Target=IdxSet<SinkInput>> + 'a {
+        self.sink_inputs.write().unwrap()
+    }
+
+    fn source_outputs<'a>(&'a self) -> impl Deref<Target=IdxSet<SourceOutput>> + 'a {
+        self.source_outputs.read().unwrap()
+    }
+
+    fn source_outputs_mut<'a>(&'a self) -> impl DerefMut<Target=IdxSet<SourceOutput>> + 'a {
+        self.source_outputs.write().unwrap()
+    }
+
+    fn samples<'a>(&'a self) -> impl Deref<Target=IdxSet<Sample>> + 'a {
+        self.samples.read().unwrap()
+    }
+
+    fn samples_mut<'a>(&'a self) -> impl DerefMut<Target=IdxSet<Sample>> + 'a {
+        self.samples.write().unwrap()
+    }
+
+    /// Removes the playback streams connected to the sink `sink`, which is going away.
+    ///
+    /// The clients owning the streams are told that they were killed.
+    pub(crate) fn kill_sink_inputs(&self, sink: u32) {
+        let killed = {
+            let mut sink_inputs = self.sink_inputs_mut();
+            let killed = sink_inputs.iter()
+                .filter(|input| input.sink() == sink)
+                .map(|input| (input.index(), input.client(), input.channel()))
+                .collect::<Vec<_>>();
+            for &(index, _, _) in &killed {
+                let idx = sink_inputs.idx_of(index).unwrap();
+                sink_inputs.remove(idx);
+            }
+            killed
+        };
+
+        for (index, client, channel) in killed {
+            self.send_to_client(client, CommandKind::PlaybackStreamKilled(StreamChannel::new(channel)));
+            self.post_event(Facility::SinkInput, EventType::Remove, index);
+        }
+    }
+
+    /// Removes the record streams connected to the source `source`, which is going away.
+    ///
+    /// The clients owning the streams are told that they were killed.
+    pub(crate) fn kill_source_outputs(&self, source: u32) {
+        let killed = {
+            let mut source_outputs = self.source_outputs_mut();
+            let killed = source_outputs.iter()
+                .filter(|output| output.source() == source)
+                .map(|output| (output.index(), output.client(), output.channel()))
+                .collect::<Vec<_>>();
+            for &(index, _, _) in &killed {
+                let idx = source_outputs.idx_of(index).unwrap();
+                source_outputs.remove(idx);
+            }
+            killed
+        };
+
+        for (index, client, channel) in killed {
+            self.send_to_client(client, CommandKind::RecordStreamKilled(StreamChannel::new(channel)));
+            self.post_event(Facility::SourceOutput, EventType::Remove, index);
+        }
+    }
+
+    /// Sends a command that expects no reply to the client with index `client`, if it's still
+    /// connected.
+    ///
+    /// Must not be called while the client list is locked.
+    fn send_to_client(&self, client: u32, kind: CommandKind) {
+        let recipient = {
+            let clients = self.clients();
+            clients.idx_of(client).map(|idx| Recipient::of(clients.get(idx).unwrap()))
+        };
+        if let Some(recipient) = recipient {
+            recipient.send(Command::new(u32::MAX, kind).to_packet(&mut Vec::new(), recipient.protocol_version));
+        }
+    }
+
+    /// Creates the queue of messages waiting to be sent to a client.
+    fn client_queue(&self) -> (mpsc::Sender<Outgoing>, mpsc::Receiver<Outgoing>) {
+        // `DaemonConf::load` rejects smaller limits, but the configuration may be built by hand
+        let limit = cmp::max(self.conf().max_queued_messages, MIN_QUEUED_MESSAGES);
+        // The queue holds one message per sender on top of its buffer
+        mpsc::channel(limit - 1)
+    }
+
+    fn modules<'a>(&'a self) -> impl Deref<Target=IdxSet<LoadedModule>> + 'a {
+        self.modules.read().unwrap()
+    }
+
+    fn modules_mut<'a>(&'a self) -> impl DerefMut<Target=IdxSet<LoadedModule>> + 'a {
+        self.modules.write().unwrap()
+    }
+
+    fn default_sink(&self) -> Option<PaString> {
+        self.default_sink.read().unwrap().clone()
+    }
+
+    fn default_source(&self) -> Option<PaString> {
+        self.default_source.read().unwrap().clone()
+    }
+
+    fn set_default_sink(&self, name: Option<PaString>) {
+        *self.default_sink.write().unwrap() = name;
+        self.post_event(Facility::Server, EventType::Change, 0);
+    }
+
+    fn set_default_source(&self, name: Option<PaString>) {
+        *self.default_source.write().unwrap() = name;
+        self.post_event(Facility::Server, EventType::Change, 0);
+    }
+
+    /// Notifies the clients subscribed to `facility` that the object `index` was created,
+    /// changed or removed.
+    ///
+    /// Must not be called while the client list is locked.
+    pub(crate) fn post_event(&self, facility: Facility, event_type: EventType, index: u32) {
+        let event = Command::new(u32::MAX, CommandKind::SubscribeEvent(
+            SubscriptionEvent::new(facility, event_type, index)
+        ));
+        // Queueing may disconnect clients, which is done without holding the lock
+        let recipients = self.clients().iter()
+            .filter(|client| client.subscriptions.contains(facility.mask()))
+            .map(Recipient::of)
+            .collect::<Vec<_>>();
+        let mut buf = Vec::new();
+        for recipient in recipients {
+            recipient.send(event.to_packet(&mut buf, recipient.protocol_version));
+        }
+    }
+
+    fn default_sample_spec(&self) -> SampleSpec {
+        self.conf.default_sample_spec.clone()
+    }
+
+    fn default_channel_map(&self) -> ChannelMap {
+        self.conf.default_channel_map.clone()
+    }
+
+    fn conf(&self) -> &DaemonConf {
+        &self.conf
+    }
+
+    fn server_info(&self) -> ServerInfo {
+        let mut host_name = [0; 256];
+        let host_name = unistd::gethostname(&mut host_name)
+            .map(|name| PaString::from(name.to_owned()))
+            .unwrap_or_else(|_| PaString::new("localhost").unwrap());
+        let user_name = user::name(user::current_uid()).unwrap_or_else(|| "unknown".to_string());
+
+        ServerInfo {
+            package_name: PaString::new("pulsar").unwrap(),
+            package_version: PaString::new(env!("CARGO_PKG_VERSION")).unwrap(),
+            user_name: PaString::new(user_name).unwrap(),
+            host_name,
+            sample_spec: self.default_sample_spec(),
+            default_sink_name: self.default_sink(),
+            default_source_name: self.default_source(),
+            cookie: self.instance_cookie,
+            channel_map: Some(self.default_channel_map()),
+        }
+    }
+}
+
+/// Data associated with every client connected to the server.
+#[derive(Debug)]
+struct Client {
+    id: u32,
+    /// Version of the PulseAudio protocol implemented by the client.
+    ///
+    /// This starts off as `PROTOCOL_MIN_VERSION` and is set to the actual version when the client
+    /// informs us. If the client only supports an older version, is it rejected.
+    protocol_version: u16,
+    /// Whether this client is authenticated to the server.
+    authed: bool,
+    /// Client properties.
+    props: PropList,
+    /// Kinds of objects the client wants to receive events about.
+    subscriptions: SubscriptionMask,
+    /// Sends messages to the client.
+    outbox: Arc<Outbox>,
+    /// Identifies the client in log messages.
+    label: ClientLabel,
+}
+
+/// The sending end of a client's connection, which the rest of the server uses to send messages
+/// to the client or to disconnect it.
+///
+/// This is shared, so that it can be used without locking the client list.
+#[derive(Debug)]
+struct Outbox {
+    /// Messages waiting to be sent to the client.
+    queue: Mutex<mpsc::Sender<Outgoing>>,
+    /// Closes the client's connection. `None` once the client is being disconnected.
+    kill: Mutex<Option<oneshot::Sender<()>>>,
+}
+
+impl Outbox {
+    fn new(queue: mpsc::Sender<Outgoing>, kill: oneshot::Sender<()>) -> Self {
+        Self {
+            queue: Mutex::new(queue),
+            kill: Mutex::new(Some(kill)),
+        }
+    }
+
+    /// Queues a message for sending to the client identified by `label`.
+    ///
+    /// If the client's queue is full, the client isn't reading its messages, and it is
+    /// disconnected.
+    fn send(&self, packet: Packet, label: &ClientLabel) {
+        let result = self.queue.lock().unwrap().try_send(Outgoing::Event(packet));
+        if let Err(e) = result {
+            if e.is_full() && self.kill.lock().unwrap().is_some() {
+                warn!("{} isn't reading its messages, disconnecting it", label);
+            }
+            self.disconnect();
+        }
+    }
+
+    /// Closes the connection to the client, dropping any messages still queued.
+    fn disconnect(&self) {
+        if let Some(kill) = self.kill.lock().unwrap().take() {
+            kill.send(()).ok();
+        }
+    }
+}
+
+/// What's needed to send a message to a client once the client list is unlocked.
+struct Recipient {
+    outbox: Arc<Outbox>,
+    protocol_version: u16,
+    label: ClientLabel,
+}
+
+impl Recipient {
+    fn of(client: &Client) -> Self {
+        Self {
+            outbox: client.outbox.clone(),
+            protocol_version: client.protocol_version,
+            label: client.label.clone(),
+        }
+    }
+
+    fn send(&self, packet: Packet) {
+        self.outbox.send(packet, &self.label);
+    }
+}
+
+/// A message waiting in a client's queue.
+#[derive(Debug)]
+enum Outgoing {
+    /// A reply to a message from the client, which counts as a pending request until it's written.
+    Reply(Packet),
+    /// A message the server sends on its own, eg. a subscription event.
+    Event(Packet),
+}
+
+/// Identifies a client in log messages, eg. `client 3 (firefox, pid 1234)`.
+#[derive(Debug, Clone)]
+struct ClientLabel {
+    /// Index of the client. `None` if it was refused before being registered.
+    index: Option<u32>,
+    /// Application name, or the name of the client's binary.
+    name: Option<String>,
+    /// Process ID of local clients, or the address of network clients.
+    origin: Option<String>,
+}
+
+impl ClientLabel {
+    fn new(index: Option<u32>, peer: Option<SocketAddr>, creds: Option<PeerCreds>) -> Self {
+        let origin = match (peer, creds) {
+            (Some(addr), _) => Some(addr.to_string()),
+            (None, Some(creds)) => Some(format!("pid {}", creds.pid)),
+            (None, None) => None,
+        };
+        Self { index, name: None, origin }
+    }
+
+    /// Picks up the client's name from its properties.
+    fn update(&mut self, props: &PropList) {
+        if let Some(name) = props.get_string(Prop::ApplicationName)
+            .or_else(|| props.get_string(Prop::ApplicationProcessBinary)) {
+            self.name = Some(name.to_string());
+        }
+    }
+}
+
+impl fmt::Display for ClientLabel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.index {
+            Some(index) => write!(f, "client {}", index)?,
+            None => write!(f, "client")?,
+        }
+        match (&self.name, &self.origin) {
+            (&Some(ref name), &Some(ref origin)) => write!(f, " ({}, {})", name, origin),
+            (&Some(ref name), &None) => write!(f, " ({})", name),
+            (&None, &Some(ref origin)) => write!(f, " ({})", origin),
+            (&None, &None) => Ok(()),
+        }
+    }
+}
+
+/// Asynchronous communication processor for a connected client.
+#[derive(Debug)]
+struct ClientHandler {
+    /// Client handle. The managed `Client` structure is stored in the `ServerData` and must not be
+    /// removed as long as its `ClientHandler` exists.
+    client: Idx<Client>,
+    /// Network address of the client, or `None` for local clients.
+    peer: Option<SocketAddr>,
+    /// Credentials of the client process, if it's connected via a Unix socket.
+    creds: Option<PeerCreds>,
+    /// Authentication policy of the socket the client connected to.
+    auth: Arc<AuthOptions>,
+    /// Shared handle to the common server state.
+    data: Arc<ServerData>,
+    /// Buffer for command replies and errors sent to the client.
+    reply_buf: Vec<u8>,
+    /// Whether the client may send audio data in shared memory (negotiated during auth).
+    use_shm: bool,
+    /// Pool of shared memory exported to the client (only for clients using memfd).
+    pool: Option<ShmPool>,
+    /// Shared memory segments and blocks exported to us by the client.
+    imports: ShmImports,
+    /// Used to move the connection to an srbchannel, if the transport supports it.
+    srb_control: Option<Arc<SrbControl>>,
+    /// Tag of the `ENABLE_SRBCHANNEL` command the client has yet to acknowledge.
+    srb_tag: Option<u32>,
+    /// The client's playback streams, by channel.
+    ///
+    /// Streams can be removed by the server (see `ServerData::kill_sink_inputs`), so the sink
+    /// input might be gone.
+    playback: BTreeMap<u32, Idx<SinkInput>>,
+    /// The client's record streams, by channel. Like `playback`, this may contain removed streams.
+    record: BTreeMap<u32, Idx<SourceOutput>>,
+    /// The client's sample upload streams, by channel.
+    uploads: BTreeMap<u32, Upload>,
+    /// Channel assigned to the next stream the client creates.
+    next_channel: u32,
+    /// Number of replies to the client's messages that weren't written to the client yet.
+    ///
+    /// Once this reaches `DaemonConf::max_pending_requests`, further commands are refused.
+    pending: Arc<AtomicUsize>,
+}
+
+impl ClientHandler {
+    /// Create a new client handler.
+    ///
+    /// This will create and register a new `Client` with the server automatically. Messages sent
+    /// to the client are put into `queue`, and `kill` is fired to disconnect it. Returns
+    /// `ConnectionRefused` if `DaemonConf::max_connections` clients are already connected.
+    fn new(data: Arc<ServerData>, peer: Option<SocketAddr>, creds: Option<PeerCreds>, srb_control: Option<Arc<SrbControl>>, auth: Arc<AuthOptions>, queue: mpsc::Sender<Outgoing>, kill: oneshot::Sender<()>) -> Result<Self, PulseError> {
+        let mut label = ClientLabel::new(None, peer, creds);
+        let client = {
+            let mut clients = data.clients_mut();
+            let max = data.conf().max_connections;
+            if clients.len() >= max {
+                warn!("refusing {}, maximum of {} clients reached", label, max);
+                return Err(PulseError::ConnectionRefused);
+            }
+
+            clients.alloc(|idx| {
+                label.index = Some(idx.value());
+                match creds {
+                    Some(creds) => info!("new {} connected (uid {}, gid {})", label, creds.uid, creds.gid),
+                    None => info!("new {} connected", label),
+                }
+
+                let mut props = PropList::new();
+                if let Some(creds) = creds {
+                    creds.set_props(&mut props);
+                }
+
+                Client {
+                    id: idx.into(),
+                    protocol_version: PROTOCOL_MIN_VERSION,
+                    authed: false,
+                    props,
+                    subscriptions: SubscriptionMask::empty(),
+                    outbox: Arc::new(Outbox::new(queue, kill)),
+                    label,
+                }
+            }).idx()
+        };
+        data.post_event(Facility::Client, EventType::New, client.value());
+
+        Ok(Self {
+            client,
+            peer,
+            creds,
+            auth,
+            data,
+            reply_buf: Vec::with_capacity(512),
+            use_shm: false,
+            pool: None,
+            imports: ShmImports::new(),
+            srb_control,
+            srb_tag: None,
+            playback: BTreeMap::new(),
+            record: BTreeMap::new(),
+            uploads: BTreeMap::new(),
+            next_channel: 0,
+            pending: Arc::new(AtomicUsize::new(0)),
+        })
+    }
+
+    /// Process a packet sent to the server and return the packets to send back to the client.
+    ///
+    /// The returned packets count as pending requests until they're written to the client.
+    fn handle_packet(&mut self, packet: Packet) -> Result<Vec<Packet>, Error> {
+        let replies = self.handle_message(packet)?;
+        self.pending.fetch_add(replies.len(), Ordering::SeqCst);
+        Ok(replies)
+    }
+
+    fn handle_message(&mut self, mut packet: Packet) -> Result<Vec<Packet>, Error> {
+        let fds = packet.take_fds();
+        let msg = Message::from_packet(&packet)?;
+        debug!("{}: received msg: {:?}", self.label(), msg);
+
+        match msg {
+            Message::Control { tagstruct } => {
+                let protocol_version = self.with_client(|c| c.protocol_version);
+                let cmd = Command::from_tagstruct(tagstruct, protocol_version)?;
+
+                // These commands have no reply, and failing to execute them is fatal
+                match *cmd.kind() {
+                    CommandKind::RegisterMemfdShmid(ref params) => {
+                        self.register_memfd(params.shmid(), fds)?;
+                        return Ok(Vec::new());
+                    }
+                    CommandKind::EnableSrbchannel => {
+                        if self.srb_tag.take() != Some(cmd.tag()) {
+                            return Err(Error::string("client acknowledged unknown srbchannel"));
+                        }
+
+                        info!("{} enabled srbchannel", self.label());
+                        self.srb_control.as_ref().unwrap().enable();
+                        return Ok(Vec::new());
+                    }
+                    CommandKind::DisableSrbchannel => {
+                        return Err(Error::string("client tried to disable srbchannel"));
+                    }
+                    _ => {}
+                }
+
+                let (pending, max) = (self.pending.load(Ordering::SeqCst), self.data.conf().max_pending_requests);
+                if pending >= max {
+                    warn!("{} has {} unanswered requests, refusing more", self.label(), pending);
+                    return Ok(vec![cmd.error_reply(PulseError::TooLarge)
+                        .to_packet(&mut self.reply_buf, protocol_version)]);
+                }
+
+                match self.handle_control(&cmd) {
+                    Ok(packet) => {
+                        let mut packets = vec![packet];
+                        if let CommandKind::Auth(_) = *cmd.kind() {
+                            self.setup_shm(&mut packets)?;
+                        }
+                        Ok(packets)
+                    }
+                    Err(e) => {
+                        Ok(vec![cmd.error_reply(e)
+                            .to_packet(&mut self.reply_buf, protocol_version)])
+                    }
+                }
+            }
+            Message::Memblock { channel, data, .. } => {
+                if !self.with_client(|c| c.authed) {
+                    return Err(Error::string("unauthenticated client sent a memblock"));
+                }
+
+                let mut replies = Vec::new();
+                let (len, overflow) = match data {
+                    MemblockData::Inline(data) => {
+                        (data.len(), self.uploads.get_mut(&channel).map(|upload| !upload.write(data)))
+                    }
+                    MemblockData::Shm { block, memfd, .. } => {
+                        if !self.use_shm {
+                            return Err(Error::string("client sent shared memory block without negotiating shm"));
+                        }
+
+                        let result = {
+                            let data = self.imports.get(&block, memfd)?;
+                            (data.len(), self.uploads.get_mut(&channel).map(|upload| !upload.write(data)))
+                        };
+
+                        // We're done with the block, let the client reuse it
+                        if self.imports.release(block.block_id) {
+                            replies.push(Packet::new_shm_release(block.block_id));
+                        }
+                        result
+                    }
+                };
+
+                match overflow {
+                    Some(false) => {}
+                    Some(true) => warn!("{} sent more data than announced to upload stream {}", self.label(), channel),
+                    None => {
+                        // TODO: Pass the data to the sink once audio is routed
+                        let stream = if self.playback_stream(channel).is_ok() { "stream" } else { "invalid stream" };
+                        debug!("{} sent {} Bytes for {} {}", self.label(), len, stream, channel);
+                    }
+                }
+                Ok(replies)
+            }
+            Message::ShmRelease { block_id } => {
+                match self.pool {
+                    // Blocks of the srbchannel pool are released when the client disconnects
+                    Some(ref mut pool) => if let Err(e) = pool.release(block_id) {
+                        debug!("{}: {}", self.label(), e);
+                    },
+                    None => warn!("{} released block {}, but has no shared memory pool", self.label(), block_id),
+                }
+                Ok(Vec::new())
+            }
+            Message::ShmRevoke { block_id } => {
+                self.imports.revoke(block_id);
+                Ok(Vec::new())
+            }
+        }
+    }
+
+    /// Sends the packets that set up shared memory after successful authentication.
+    ///
+    /// This registers our memfd pool with the client and offers it an srbchannel.
+    fn setup_shm(&mut self, packets: &mut Vec<Packet>) -> Result<(), Error> {
+        let protocol_version = self.with_client(|c| c.protocol_version);
+
+        // Tell the client about our pool right after it learns we use memfd
+        if let Some(ref pool) = self.pool {
+            packets.push(Self::register_memfd_packet(pool, &mut self.reply_buf, protocol_version)?);
+        }
+
+        if !self.use_shm || protocol_version < SRBCHANNEL_MIN_VERSION || self.srb_tag.is_some() {
+            return Ok(());
+        }
+        let control = match self.srb_control {
+            Some(ref control) => control.clone(),
+            None => return Ok(()),
+        };
+
+        let kind = if self.pool.is_some() { ShmType::Memfd } else { ShmType::Posix };
+        let channel = match SrbChannel::new(kind) {
+            Ok(channel) => channel,
+            Err(e) => {
+                warn!("couldn't create srbchannel for {}: {}", self.label(), e);
+                return Ok(());
+            }
+        };
+
+        // The ringbuffers live in their own pool, which has to be registered too
+        if kind == ShmType::Memfd {
+            packets.push(Self::register_memfd_packet(channel.pool(), &mut self.reply_buf, protocol_version)?);
+        }
+
+        let tag = channel.block().shm_id;
+        let mut enable = Command::new(tag, CommandKind::EnableSrbchannel)
+            .to_packet(&mut self.reply_buf, protocol_version);
+        for sem in &channel.sems() {
+            enable.attach_fd(sem.try_clone()?);
+        }
+        packets.push(enable);
+        packets.push(Packet::new_shm_memblock(0, 0, SeekMode::Relative, channel.block(), kind == ShmType::Memfd, true));
+
+        debug!("offering srbchannel to {}", self.label());
+        self.srb_tag = Some(tag);
+        control.offer(channel);
+        Ok(())
+    }
+
+    /// Creates a `REGISTER_MEMFD_SHMID` packet carrying the memfd of `pool`.
+    fn register_memfd_packet(pool: &ShmPool, buf: &mut Vec<u8>, protocol_version: u16) -> Result<Packet, Error> {
+        let register = Command::new(u32::max_value(), CommandKind::RegisterMemfdShmid(
+            command::RegisterMemfdShmid::new(pool.segment().id())
+        ));
+        let mut packet = register.to_packet(buf, protocol_version);
+        packet.attach_fd(pool.segment().memfd().unwrap().try_clone()?);
+        Ok(packet)
+    }
+
+    /// Attaches the memfd-backed segment `shmid` sent by the client.
+    fn register_memfd(&mut self, shmid: u32, fds: Vec<Fd>) -> Result<(), Error> {
+        let protocol_version = self.with_client(|c| c.protocol_version);
+        if protocol_version < MEMFD_MIN_VERSION {
+            // PulseAudio ignores the command for these versions, so do we
+            debug!("ignoring memfd registration from {} with protocol version {}", self.label(), protocol_version);
+            return Ok(());
+        }
+        if !self.use_shm {
+            return Err(Error::string("client registered memfd without negotiating shm"));
+        }
+
+        let mut fds = fds.into_iter();
+        match (fds.next(), fds.next()) {
+            (Some(fd), None) => self.imports.register_memfd(shmid, fd),
+            _ => Err(Error::string("REGISTER_MEMFD_SHMID must carry exactly one file descriptor")),
+        }
+    }
+
+    /// Handle a `Command` type message and return the response `Packet` to send back to the client.
+    fn handle_control(&mut self, cmd: &Command) -> Result<Packet, PulseError> {
+        debug!("{}: handling control command: {:?}", self.label(), cmd);
+
+        let (authed, protocol_version) = self.with_client(|c| (c.authed, c.protocol_version));
+
+        if cmd.needs_auth() && !authed {
+            error!("unauthenticated {} tried to execute privileged cmd: {:?}", self.label(), cmd);
+            return Err(PulseError::Access);
+        }
+
+        Ok(match cmd.kind() {
+            CommandKind::Exit => {
+                if self.data.conf().disallow_exit {
+                    warn!("{} tried to shut down the server, but exiting is disallowed", self.label());
+                    return Err(PulseError::Access);
+                }
+                info!("{} requested the server to exit", self.label());
+                // The reply is still sent before the connection is closed
+                self.data.shutdown.trigger();
+                cmd.empty_reply_packet(&mut self.reply_buf)
+            }
+            CommandKind::Auth(auth) => {
+                let protocol_version = auth.protocol_version();
+                if protocol_version < PROTOCOL_MIN_VERSION {
+                    error!("{} uses protocol version {}, minimum supported is {}, rejecting it", self.label(), protocol_version, PROTOCOL_MIN_VERSION);
+                    return Err(PulseError::Version);
+                }
+
+                self.with_client_mut(|c| c.protocol_version = protocol_version);
+
+                if let Some(method) = self.auth.check(self.peer.as_ref(), self.creds.as_ref(), auth.auth_cookie()) {
+                    info!("{} authenticated via {}", self.label(), method);
+
+                    debug!("{} supports memfd={} shm={}", self.label(), auth.supports_memfd(), auth.supports_shm());
+
+                    // Only share memory with local clients running as the same user, since
+                    // other users might be able to read data private to the user otherwise
+                    let same_user = self.creds.map_or(false, |creds| creds.uid == user::current_uid());
+                    let conf = self.data.conf();
+                    let use_shm = conf.enable_shm && auth.supports_shm() && same_user;
+                    let use_memfd = use_shm && conf.enable_memfd && auth.supports_memfd()
+                        && protocol_version >= MEMFD_MIN_VERSION;
+                    if use_memfd {
+                        match ShmPool::new(ShmType::Memfd, DEFAULT_POOL_SIZE) {
+                            Ok(pool) => self.pool = Some(pool),
+                            Err(e) => warn!("couldn't create memfd pool for {}, not using shared memory: {}", self.label(), e),
+                        }
+                    }
+                    let use_memfd = use_memfd && self.pool.is_some();
+                    self.use_shm = use_shm;
+                    info!("{} uses {}", self.label(), match (use_memfd, use_shm) {
+                        (true, _) => "memfd shared memory",
+                        (false, true) => "POSIX shared memory",
+                        (false, false) => "no shared memory",
+                    });
+
+                    self.with_client_mut(|c| c.authed = true);
+
+                    let mut reply = command::AuthReply::new(command::PROTOCOL_VERSION);
+                    reply.set_use_memfd(use_memfd);
+                    reply.set_use_shm(use_shm);
+                    cmd.reply_packet(&mut self.reply_buf, protocol_version, reply)
+                } else {
+                    match self.peer {
+                        Some(_) => error!("{} failed to authenticate", self.label()),
+                        None => error!("{} failed to authenticate (auth cookie mismatch)", self.label()),
+                    }
+                    return Err(PulseError::Access);
+                }
+            },
+            CommandKind::SetClientName(params) => {
+                let creds = self.creds;
+                let previous = self.label();
+                self.with_client_mut(|c| {
+                    c.props.extend(params.props());
+                    // Don't let clients lie about who they are
+                    if let Some(creds) = creds {
+                        creds.set_props(&mut c.props);
+                    }
+                    c.label.update(&c.props);
+                });
+                if let Some(name) = params.props().get_string(Prop::ApplicationName) {
+                    info!("{} is {}", previous, name);
+                }
+
+                cmd.reply_packet(&mut self.reply_buf, protocol_version, command::SetClientNameReply::new(self.client.value()))
+            }
+            CommandKind::CreatePlaybackStream(params) => {
+                let reply = self.create_playback_stream(params)?;
+                cmd.reply_packet(&mut self.reply_buf, protocol_version, reply)
+            }
+            CommandKind::CreateRecordStream(params) => {
+                let reply = self.create_record_stream(params)?;
+                cmd.reply_packet(&mut self.reply_buf, protocol_version, reply)
+            }
+            CommandKind::CreateUploadStream(params) => {
+                let reply = self.create_upload_stream(params)?;
+                cmd.reply_packet(&mut self.reply_buf, protocol_version, reply)
+            }
+            CommandKind::DeleteUploadStream(params) => {
+                self.uploads.remove(&params.channel()).ok_or(PulseError::NoEntity)?;
+                cmd.empty_reply_packet(&mut self.reply_buf)
+            }
+            CommandKind::FinishUploadStream(params) => {
+                let upload = self.uploads.remove(&params.channel()).ok_or(PulseError::NoEntity)?;
+                let index = self.add_sample(upload);
+                cmd.reply_packet(&mut self.reply_buf, protocol_version, command::FinishUploadStreamReply {
+                    sample_index: index,
+                })
+            }
+            CommandKind::RemoveSample(params) => {
+                let index = {
+                    let mut samples = self.data.samples_mut();
+                    let index = samples.iter()
+                        .find(|sample| &**sample.name() == params.name())
+                        .map(|sample| sample.index())
+                        .ok_or(PulseError::NoEntity)?;
+                    let idx = samples.idx_of(index).unwrap();
+                    samples.remove(idx);
+                    index
+                };
+                self.data.post_event(Facility::SampleCache, EventType::Remove, index);
+                cmd.empty_reply_packet(&mut self.reply_buf)
+            }
+            CommandKind::DeletePlaybackStream(params) => {
+                let idx = self.playback.remove(&params.channel()).ok_or(PulseError::NoEntity)?;
+                let input = self.data.sink_inputs_mut().remove(idx).ok_or(PulseError::NoEntity)?;
+                self.data.post_event(Facility::SinkInput, EventType::Remove, input.index());
+                cmd.empty_reply_packet(&mut self.reply_buf)
+            }
+            CommandKind::DeleteRecordStream(params) => {
+                let idx = self.record.remove(&params.channel()).ok_or(PulseError::NoEntity)?;
+                let output = self.data.source_outputs_mut().remove(idx).ok_or(PulseError::NoEntity)?;
+                self.data.post_event(Facility::SourceOutput, EventType::Remove, output.index());
+                cmd.empty_reply_packet(&mut self.reply_buf)
+            }
+            // Nothing is buffered, so draining and flushing are done immediately
+            CommandKind::DrainPlaybackStream(params) |
+            CommandKind::FlushPlaybackStream(params) => {
+                self.playback_stream(params.channel())?;
+                cmd.empty_reply_packet(&mut self.reply_buf)
+            }
+            CommandKind::FlushRecordStream(params) => {
+                self.record_stream(params.channel())?;
+                cmd.empty_reply_packet(&mut self.reply_buf)
+            }
+            CommandKind::CorkPlaybackStream(params) => {
+                let idx = self.playback_stream(params.channel())?;
+                let index = {
+                    let mut sink_inputs = self.data.sink_inputs_mut();
+                    let input = sink_inputs.get_mut(idx).ok_or(PulseError::NoEntity)?;
+                    input.set_corked(params.cork());
+                    input.index()
+                };
+                self.data.post_event(Facility::SinkInput, EventType::Change, index);
+                cmd.empty_reply_packet(&mut self.reply_buf)
+            }
+            CommandKind::CorkRecordStream(params) => {
+                let idx = self.record_stream(params.channel())?;
+                let index = {
+                    let mut source_outputs = self.data.source_outputs_mut();
+                    let output = source_outputs.get_mut(idx).ok_or(PulseError::NoEntity)?;
+                    output.set_corked(params.cork());
+                    output.index()
+                };
+                self.data.post_event(Facility::SourceOutput, EventType::Change, index);
+                cmd.empty_reply_packet(&mut self.reply_buf)
+            }
+            CommandKind::GetPlaybackLatency(params) => {
+                let idx = self.playback_stream(params.channel())?;
+                let playing = !self.data.sink_inputs().get(idx).ok_or(PulseError::NoEntity)?.corked();
+                cmd.reply_packet(&mut self.reply_buf, protocol_version, LatencyInfo {
+                    sink_usec: Microseconds(0),
+                    source_usec: Microseconds(0),
+                    playing,
+                    local_time: params.local_time(),
+                    remote_time: Timeval::now(),
+                    write_index: 0,
+                    read_index: 0,
+                    underrun_for: Some(0),
+                    playing_for: Some(0),
+                })
+            }
+            CommandKind::GetRecordLatency(params) => {
+                let idx = self.record_stream(params.channel())?;
+                let playing = !self.data.source_outputs().get(idx).ok_or(PulseError::NoEntity)?.corked();
+                cmd.reply_packet(&mut self.reply_buf, protocol_version, LatencyInfo {
+                    sink_usec: Microseconds(0),
+                    source_usec: Microseconds(0),
+                    playing,
+                    local_time: params.local_time(),
+                    remote_time: Timeval::now(),
+                    write_index: 0,
+                    read_index: 0,
+                    underrun_for: None,
+                    playing_for: None,
+                })
+            }
+            CommandKind::SetSinkInputVolume(params) => return Err(self.sink_input_unsupported(params.index())),
+            CommandKind::SetSinkInputMute(params) => return Err(self.sink_input_unsupported(params.index())),
+            CommandKind::KillSinkInput(params) => return Err(self.sink_input_unsupported(params.index())),
+            CommandKind::MoveSinkInput(params) => return Err(self.sink_input_unsupported(params.index())),
+            CommandKind::SetSourceOutputVolume(params) => return Err(self.source_output_unsupported(params.index())),
+            CommandKind::SetSourceOutputMute(params) => return Err(self.source_output_unsupported(params.index())),
+            CommandKind::KillSourceOutput(params) => return Err(self.source_output_unsupported(params.index())),
+            CommandKind::MoveSourceOutput(params) => return Err(self.source_output_unsupported(params.index())),
+            CommandKind::GetServerInfo => {
+                cmd.reply_packet(&mut self.reply_buf, protocol_version, self.data.server_info())
+            }
+            CommandKind::GetSinkInfoList => cmd.reply_packet(
+                &mut self.reply_buf,
+                protocol_version,
+                command::GetSinkInfoListReply::new(
+                    self.data.sinks().iter()
+                )
+            ),
+            CommandKind::GetSourceInfoList => cmd.reply_packet(
+                &mut self.reply_buf,
+                protocol_version,
+                command::GetSourceInfoListReply::new(
+                    self.data.sources().iter()
+                )
+            ),
+            CommandKind::GetClientInfoList => cmd.reply_packet(
+                &mut self.reply_buf,
+                protocol_version,
+                command::GetClientInfoListReply::new(
+                    self.data.clients().iter()
+                        .map(|client| ClientInfo::new(client.id, Default::default(), &client.props))
+                ),
+            ),
+            CommandKind::GetModuleInfoList => cmd.reply_packet(
+                &mut self.reply_buf,
+                protocol_version,
+                command::GetModuleInfoListReply::new(
+                    self.data.modules().iter().map(|module| module.info())
+                )
+            ),
+            CommandKind::GetSinkInfo(params) => {
+                let sinks = self.data.sinks();
+                let sink = sinks.iter()
+                    .find(|sink| Some(sink.index()) == params.index() || Some(sink.name()) == params.name())
+                    .ok_or(PulseError::NoEntity)?;
+                cmd.reply_packet(&mut self.reply_buf, protocol_version, command::GetSinkInfoListReply::new(Some(sink)))
+            }
+            CommandKind::GetSourceInfo(params) => {
+                let sources = self.data.sources();
+                let source = sources.iter()
+                    .find(|source| Some(source.index()) == params.index() || Some(source.name()) == params.name())
+                    .ok_or(PulseError::NoEntity)?;
+                cmd.reply_packet(&mut self.reply_buf, protocol_version, command::GetSourceInfoListReply::new(Some(source)))
+            }
+            CommandKind::GetClientInfo(params) => {
+                let clients = self.data.clients();
+                let client = clients.iter()
+                    .find(|client| client.id == params.index())
+                    .ok_or(PulseError::NoEntity)?;
+                let info = ClientInfo::new(client.id, Default::default(), &client.props);
+                cmd.reply_packet(&mut self.reply_buf, protocol_version, info)
+            }
+            CommandKind::GetSinkInputInfo(params) => {
+                let sink_inputs = self.data.sink_inputs();
+                let idx = sink_inputs.idx_of(params.index()).ok_or(PulseError::NoEntity)?;
+                cmd.reply_packet(&mut self.reply_buf, protocol_version, sink_inputs.get(idx).unwrap().info())
+            }
+            CommandKind::GetSourceOutputInfo(params) => {
+                let source_outputs = self.data.source_outputs();
+                let idx = source_outputs.idx_of(params.index()).ok_or(PulseError::NoEntity)?;
+                cmd.reply_packet(&mut self.reply_buf, protocol_version, source_outputs.get(idx).unwrap().info())
+            }
+            CommandKind::GetCardInfo(_) => {
+                return Err(PulseError::NoEntity);
+            }
+            CommandKind::GetModuleInfo(params) => {
+                let modules = self.data.modules();
+                let idx = modules.idx_of(params.module_index()).ok_or(PulseError::NoEntity)?;
+                cmd.reply_packet(&mut self.reply_buf, protocol_version, modules.get(idx).unwrap().info())
+            }
+            CommandKind::LoadModule(params) => {
+                let name = params.name().to_str().map_err(|_| PulseError::Invalid)?;
+                let argument = match params.argument() {
+                    Some(arg) => Some(arg.to_str().map_err(|_| PulseError::Invalid)?),
+                    None => None,
+                };
+
+                let index = module::load(&self.data, name, argument)?;
+                cmd.reply_packet(&mut self.reply_buf, protocol_version, command::LoadModuleReply::new(index))
+            }
+            CommandKind::Subscribe(params) => {
+                self.with_client_mut(|c| c.subscriptions = params.mask());
+                cmd.empty_reply_packet(&mut self.reply_buf)
+            }
+            CommandKind::UnloadModule(params) => {
+                module::unload(&self.data, params.module_index())?;
+                cmd.empty_reply_packet(&mut self.reply_buf)
+            }
+            CommandKind::SetDefaultSink(params) => {
+                let name = params.name();
+                if !self.data.sinks().iter().any(|sink| sink.name() == name) {
+                    return Err(PulseError::NoEntity);
+                }
+                self.data.set_default_sink(Some(name.to_owned()));
+                cmd.empty_reply_packet(&mut self.reply_buf)
+            }
+            CommandKind::SetDefaultSource(params) => {
+                let name = params.name();
+                if !self.data.sources().iter().any(|source| source.name() == name) {
+                    return Err(PulseError::NoEntity);
+                }
+                self.data.set_default_source(Some(name.to_owned()));
+                cmd.empty_reply_packet(&mut self.reply_buf)
+            }
+            CommandKind::SetSinkVolume(_) |
+            CommandKind::SetSourceVolume(_) |
+            CommandKind::SetSinkMute(_) |
+            CommandKind::SetSourceMute(_) |
+            CommandKind::SuspendSink(_) |
+            CommandKind::SuspendSource(_) => {
+                return Err(PulseError::NotImplemented);  // TODO
+            }
+            CommandKind::KillClient(params) => {
+                let label = self.label();
+                let victim = {
+                    let clients = self.data.clients();
+                    let idx = clients.idx_of(params.index()).ok_or(PulseError::NoEntity)?;
+                    Recipient::of(clients.get(idx).unwrap())
+                };
+                info!("{} kills {}", label, victim.label);
+                victim.outbox.disconnect();
+                cmd.empty_reply_packet(&mut self.reply_buf)
+            }
+            CommandKind::GetSinkInputInfoList => cmd.reply_packet(
+                &mut self.reply_buf,
+                protocol_version,
+                command::GetSinkInputInfoListReply::new(
+                    self.data.sink_inputs().iter().map(|input| input.info())
+                )
+            ),
+            CommandKind::GetSourceOutputInfoList => cmd.reply_packet(
+                &mut self.reply_buf,
+                protocol_version,
+                command::GetSourceOutputInfoListReply::new(
+                    self.data.source_outputs().iter().map(|output| output.info())
+                )
+            ),
+            CommandKind::GetSampleInfo(params) => {
+                let info = self.data.samples().iter()
+                    .find(|sample| Some(sample.index()) == params.index() || Some(&**sample.name()) == params.name())
+                    .map(|sample| sample.info())
+                    .ok_or(PulseError::NoEntity)?;
+                cmd.reply_packet(&mut self.reply_buf, protocol_version, command::GetSampleInfoListReply::new(Some(info)))
+            }
+            CommandKind::GetSampleInfoList => cmd.reply_packet(
+                &mut self.reply_buf,
+                protocol_version,
+                command::GetSampleInfoListReply::new(
+                    self.data.samples().iter().map(|sample| sample.info())
+                )
+            ),
+            // There are no cards (yet), so reply with an empty list
+            CommandKind::GetCardInfoList => cmd.empty_reply_packet(&mut self.reply_buf),
+            // Handled in `handle_packet`
+            CommandKind::RegisterMemfdShmid(_) |
+            CommandKind::EnableSrbchannel |
+            CommandKind::DisableSrbchannel => unreachable!(),
+            CommandKind::Reply { .. } => {
+                return Err(PulseError::Protocol);
+            }
+            // Server to client notifications
+            CommandKind::Request(_) |
+            CommandKind::Overflow(_) |
+            CommandKind::Underflow(_) |
+            CommandKind::PlaybackStreamKilled(_) |
+            CommandKind::RecordStreamKilled(_) |
+            CommandKind::Started(_) |
+            CommandKind::SubscribeEvent(_) => {
+                return Err(PulseError::Protocol);
+            }
+            CommandKind::Error { .. } => {
+                // clients shouldn't send this (right?)
+                return Err(PulseError::Protocol);
+            }
+        })
+    }
+
+    /// Checks that the client may open another stream.
+    fn check_stream_quota(&self) -> Result<(), PulseError> {
+        let max = self.data.conf().max_streams_per_client;
+        if self.playback.len() + self.record.len() + self.uploads.len() >= max {
+            warn!("{} tried to open more than {} streams", self.label(), max);
+            return Err(PulseError::TooLarge);
+        }
+        Ok(())
+    }
+
+    fn create_playback_stream(&mut self, params: &CreatePlaybackStream) -> Result<command::CreatePlaybackStreamReply, PulseError> {
+        self.prune_streams();
+        self.check_stream_quota()?;
+        check_stream_channels(params.sample_spec(), params.channel_map(), params.volume())?;
+
+        let channel = self.next_channel;
+        let (idx, reply) = {
+            let sinks = self.data.sinks();
+            let default = self.data.default_sink();
+            let sink = sinks.iter()
+                .find(|sink| match params.sink_spec() {
+                    Some(&SinkSpec::Index(index)) => sink.index() == index,
+                    Some(&SinkSpec::Name(name)) => sink.name().as_cstr() == name,
+                    None => Some(sink.name()) == default.as_ref().map(|name| &**name),
+                })
+                .or_else(|| if params.sink_spec().is_none() { sinks.iter().next() } else { None })
+                .ok_or(PulseError::NoEntity)?;
+
+            let mut sink_inputs = self.data.sink_inputs_mut();
+            let client = self.client.value();
+            let input = sink_inputs.alloc(|idx| SinkInput::new(idx.value(), client, channel, sink, params));
+            (input.idx(), input.value().reply(sink))
+        };
+        self.next_channel += 1;
+        self.playback.insert(channel, idx);
+        info!("{} created playback stream {} on sink {}", self.label(), reply.sink_input_index, reply.sink_name);
+        self.data.post_event(Facility::SinkInput, EventType::New, reply.sink_input_index);
+        Ok(reply)
+    }
+
+    fn create_record_stream(&mut self, params: &CreateRecordStream) -> Result<command::CreateRecordStreamReply, PulseError> {
+        self.prune_streams();
+        self.check_stream_quota()?;
+        check_stream_channels(params.sample_spec(), params.channel_map(), params.volume())?;
+
+        let channel = self.next_channel;
+        let (idx, reply) = {
+            let sources = self.data.sources();
+            let default = self.data.default_source();
+            let source = sources.iter()
+                .find(|source| match params.source_spec() {
+                    Some(&SourceSpec::Index(index)) => source.index() == index,
+                    Some(&SourceSpec::Name(name)) => source.name().as_cstr() == name,
+                    None => Some(source.name()) == default.as_ref().map(|name| &**name),
+                })
+                .or_else(|| if params.source_spec().is_none() { sources.iter().next() } else { None })
+                .ok_or(PulseError::NoEntity)?;
+
+            let mut source_outputs = self.data.source_outputs_mut();
+            let client = self.client.value();
+            let output = source_outputs.alloc(|idx| SourceOutput::new(idx.value(), client, channel, source, params));
+            (output.idx(), output.value().reply(source))
+        };
+        self.next_channel += 1;
+        self.record.insert(channel, idx);
+        info!("{} created record stream {} on source {}", self.label(), reply.source_output_index, reply.source_name);
+        self.data.post_event(Facility::SourceOutput, EventType::New, reply.source_output_index);
+        Ok(reply)
+    }
+
+    fn create_upload_stream(&mut self, params: &CreateUploadStream) -> Result<command::CreateUploadStreamReply, PulseError> {
+        self.check_stream_quota()?;
+        let upload = Upload::new(params)?;
+
+        let max = self.data.conf().max_sample_cache_per_client;
+        let used = self.sample_cache_used();
+        if used + upload.length() as usize > max {
+            warn!("{} tried to upload {} Bytes of samples, but may only use {} Bytes",
+                self.label(), used + upload.length() as usize, max);
+            return Err(PulseError::TooLarge);
+        }
+
+        let channel = self.next_channel;
+        self.next_channel += 1;
+        let reply = command::CreateUploadStreamReply { channel, length: upload.length() };
+        self.uploads.insert(channel, upload);
+        Ok(reply)
+    }
+
+    /// Returns the number of bytes the client uses in the sample cache, including samples that are
+    /// still being uploaded.
+    fn sample_cache_used(&self) -> usize {
+        let uploading = self.uploads.values().map(|upload| upload.length() as usize).sum::<usize>();
+        let cached = self.data.samples().iter()
+            .filter(|sample| sample.owner() == self.client.value())
+            .map(|sample| sample.len())
+            .sum::<usize>();
+        uploading + cached
+    }
+
+    /// Adds a finished upload to the sample cache, replacing any sample of the same name.
+    ///
+    /// Returns the index of the sample.
+    fn add_sample(&mut self, upload: Upload) -> u32 {
+        let client = self.client.value();
+        let (index, event_type) = {
+            let mut samples = self.data.samples_mut();
+            let existing = samples.iter()
+                .find(|sample| sample.name() == upload.name())
+                .map(|sample| sample.index());
+            match existing {
+                Some(index) => {
+                    let idx = samples.idx_of(index).unwrap();
+                    *samples.get_mut(idx).unwrap() = upload.finish(index, client);
+                    (index, EventType::Change)
+                }
+                None => {
+                    let index = samples.alloc(|idx| upload.finish(idx.value(), client)).idx().value();
+                    (index, EventType::New)
+                }
+            }
+        };
+        self.data.post_event(Facility::SampleCache, event_type, index);
+        index
+    }
+
+    /// Forgets about streams that the server removed because their sink or source went away.
+    fn prune_streams(&mut self) {
+        {
+            let sink_inputs = self.data.sink_inputs();
+            self.playback.retain(|_, idx| sink_inputs.get(*idx).is_some());
+        }
+        let source_outputs = self.data.source_outputs();
+        self.record.retain(|_, idx| source_outputs.get(*idx).is_some());
+    }
+
+    /// Looks up the client's playback stream on `channel`.
+    fn playback_stream(&self, channel: u32) -> Result<Idx<SinkInput>, PulseError> {
+        self.playback.get(&channel).cloned()
+            .filter(|&idx| self.data.sink_inputs().get(idx).is_some())
+            .ok_or(PulseError::NoEntity)
+    }
+
+    /// Looks up the client's record stream on `channel`.
+    fn record_stream(&self, channel: u32) -> Result<Idx<SourceOutput>, PulseError> {
+        self.record.get(&channel).cloned()
+            .filter(|&idx| self.data.source_outputs().get(idx).is_some())
+            .ok_or(PulseError::NoEntity)
+    }
+
+    /// Returns the error for an operation on the sink input `index` that isn't supported yet.
+    fn sink_input_unsupported(&self, index: u32) -> PulseError {
+        match self.data.sink_inputs().idx_of(index) {
+            Some(_) => PulseError::NotImplemented,  // TODO
+            None => PulseError::NoEntity,
+        }
+    }
+
+    /// Returns the error for an operation on the source output `index` that isn't supported yet.
+    fn source_output_unsupported(&self, index: u32) -> PulseError {
+        match self.data.source_outputs().idx_of(index) {
+            Some(_) => PulseError::NotImplemented,  // TODO
+            None => PulseError::NoEntity,
+        }
+    }
+
+    /// Returns the label identifying the client in log messages.
+    ///
+    /// Must not be called while the client list is locked.
+    fn label(&self) -> ClientLabel {
+        self.data.client_label(self.client)
+    }
+
+    // helper functions to make the code more readable
+    // could also be implemented with a special guard that derefs to the target type, but this makes
+    // the locked area more explicit
+
+    fn with_client<F, R>(&self, f: F) -> R
+    where F: FnOnce(&Client) -> R {
+        let clients = self.data.clients();
+        let client = clients.get(self.client).unwrap();
+        f(client)
+    }
+
+    /// Obtains write access to the client container and applies a closure to the client managed by
+    /// this handler.
+    ///
+    /// In case the client has been removed from the container, this will panic. This should never
+    /// happen as the server is supposed to cancel handler tasks before removing the client.
+    fn with_client_mut<F, R>(&mut self, f: F) -> R
+    where F: FnOnce(&mut Client) -> R {
+        let mut clients = self.data.clients_mut();
+        let client = clients.get_mut(self.client).unwrap();
+        f(client)
+    }
+}
+
+/// Checks that a new stream's channel map and volume fit its sample spec.
+fn check_stream_channels(spec: &SampleSpec, map: &ChannelMap, volume: Option<&CVolume>) -> Result<(), PulseError> {
+    if map.len() != spec.channels() || volume.map_or(false, |volume| volume.len() != spec.channels()) {
+        return Err(PulseError::Invalid);
+    }
+    Ok(())
+}
+
+impl Drop for ClientHandler {
+    fn drop(&mut self) {
+        let removed_inputs = {
+            let mut sink_inputs = self.data.sink_inputs_mut();
+            self.playback.values().filter_map(|&idx| sink_inputs.remove(idx)).collect::<Vec<_>>()
+        };
+        for input in removed_inputs {
+            self.data.post_event(Facility::SinkInput, EventType::Remove, input.index());
+        }
+        let removed_outputs = {
+            let mut source_outputs = self.data.source_outputs_mut();
+            self.record.values().filter_map(|&idx| source_outputs.remove(idx)).collect::<Vec<_>>()
+        };
+        for output in removed_outputs {
+            self.data.post_event(Facility::SourceOutput, EventType::Remove, output.index());
+        }
+
+        self.data.clients_mut().remove(self.client);
+        self.data.post_event(Facility::Client, EventType::Remove, self.client.value());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pa_proto::command::{Auth, AuthReply, GetModuleInfo, LoadModule, LoadModuleReply, ModuleInfo};
+    use pa_proto::command::{UnloadModule, PROTOCOL_VERSION};
+    use pa_proto::command::{CreatePlaybackStreamReply, CreateRecordStreamReply, GetInfo, SinkInputInfo};
+    use pa_proto::command::{CreateUploadStreamReply, FinishUploadStreamReply, GetSampleInfo, RemoveSample, SampleInfo};
+    use pa_proto::command::{Kill, SourceOutputInfo, Subscribe};
+    use pa_proto::sample_spec::SampleFormat;
+    use pa_proto::stream::{BufferAttr, StreamFlags};
+    use pa_proto::tagstruct::FromTagStruct;
+    use tokio::runtime::current_thread::Runtime;
+    use std::io::Read;
+    use std::os::unix::net;
+
+    fn server_data(conf: DaemonConf) -> Arc<ServerData> {
+        Arc::new(ServerData::new(AuthCookie::from_bytes(&[0; 256]).unwrap(), conf))
+    }
+
+    /// A local client running as the same user as the server, which calls its `ClientHandler`
+    /// directly instead of going through a connection.
+    struct TestClient {
+        handler: ClientHandler,
+        /// Messages the server queued for the client.
+        queue: mpsc::Receiver<Outgoing>,
+        /// Fired when the server disconnects the client.
+        kill: oneshot::Receiver<()>,
+        next_tag: u32,
+    }
+
+    impl TestClient {
+        fn connect(data: &Arc<ServerData>) -> Result<Self, PulseError> {
+            let (queue_tx, queue) = data.client_queue();
+            let (kill_tx, kill) = oneshot::channel();
+            let creds = PeerCreds { pid: 1, uid: user::current_uid(), gid: 0 };
+            let auth = Arc::new(AuthOptions {
+                same_user: true,
+                ..AuthOptions::cookie_only(data.cookie.clone())
+            });
+            let handler = ClientHandler::new(data.clone(), None, Some(creds), None, auth, queue_tx, kill_tx)?;
+            Ok(Self { handler, queue, kill, next_tag: 0 })
+        }
+
+        /// Connects and authenticates a client.
+        fn new(data: &Arc<ServerData>) -> Self {
+            let mut client = Self::connect(data).unwrap();
+            let auth = Auth::new(PROTOCOL_VERSION, false, false, &[0; 256]);
+            client.request::<AuthReply>(CommandKind::Auth(auth)).unwrap();
+            client
+        }
+
+        /// Sends a command and decodes the reply.
+        fn request<R>(&mut self, kind: CommandKind) -> Result<R, Error>
+        where R: for<'a> FromTagStruct<'a> {
+            let tag = self.next_tag;
+            self.next_tag += 1;
+            let protocol_version = self.handler.with_client(|c| c.protocol_version);
+            let packet = Command::new(tag, kind).to_packet(&mut Vec::new(), protocol_version);
+            let replies = self.handler.handle_packet(packet)?;
+            // The replies are "written" right away
+            self.handler.pending.fetch_sub(replies.len(), Ordering::SeqCst);
+
+            let tagstruct = match Message::from_packet(&replies[0])? {
+                Message::Control { tagstruct } => tagstruct,
+                msg => panic!("expected reply, got {:?}", msg),
+            };
+            let reply = Command::from_tagstruct(tagstruct, protocol_version)?;
+            assert_eq!(reply.tag(), tag);
+            match reply.kind() {
+                CommandKind::Reply { params } => {
+                    let mut params = params.clone();
+                    let reply = R::from_tag_struct(&mut params, protocol_version)?;
+                    assert!(params.read()?.is_none(), "extra reply parameters");
+                    Ok(reply)
+                }
+                CommandKind::Error { code } => Err(Error::from(*code)),
+                kind => panic!("expected reply, got {:?}", kind),
+            }
+        }
+
+        /// Takes the messages the server queued for the client.
+        fn queued(&mut self) -> Vec<Packet> {
+            let queue = &mut self.queue;
+            future::lazy(|| {
+                let mut packets = Vec::new();
+                while let Ok(Async::Ready(Some(msg))) = queue.poll() {
+                    match msg {
+                        Outgoing::Event(packet) => packets.push(packet),
+                        Outgoing::Reply(packet) => panic!("reply {:?} was queued", packet),
+                    }
+                }
+                Ok::<_, ()>(packets)
+            }).wait().unwrap()
+        }
+
+        fn create_playback_stream(&mut self) -> Result<CreatePlaybackStreamReply, Error> {
+            let spec = SampleSpec::new_checked(SampleFormat::S16Le, 2, 44100).unwrap();
+            let params = CreatePlaybackStream::new(
+                PropList::new(), spec, ChannelMap::default_for(2).unwrap(), BufferAttr::server_default(),
+                StreamFlags::empty(), None,
+            );
+            self.request(CommandKind::CreatePlaybackStream(params))
+        }
+
+        fn create_record_stream(&mut self) -> Result<CreateRecordStreamReply, Error> {
+            let spec = SampleSpec::new_checked(SampleFormat::S16Le, 2, 44100).unwrap();
+            let params = CreateRecordStream::new(
+                PropList::new(), spec, ChannelMap::default_for(2).unwrap(), BufferAttr::server_default(),
+                StreamFlags::empty(), None,
+            );
+            self.request(CommandKind::CreateRecordStream(params))
+        }
+    }
+
+    /// Decodes a command the server sent to a client.
+    fn command(packet: &Packet) -> Command {
+        match Message::from_packet(packet).unwrap() {
+            Message::Control { tagstruct } => Command::from_tagstruct(tagstruct, PROTOCOL_VERSION).unwrap(),
+            msg => panic!("expected command, got {:?}", msg),
+        }
+    }
+
+    fn load_module(data: &Arc<ServerData>, name: &str, argument: &str) -> u32 {
+        module::load(data, name, Some(argument)).unwrap()
+    }
+
+    fn code<T>(result: Result<T, Error>) -> Option<PulseError> {
+        result.err().and_then(|e| e.code())
+    }
+
+    #[test]
+    fn module_commands() {
+        let data = server_data(DaemonConf::default());
+        let mut client = TestClient::new(&data);
+
+        let name = PaString::new("module-null-sink").unwrap();
+        let argument = PaString::new("sink_name=test").unwrap();
+        let load = LoadModule::new(&name, Some(&argument));
+        let index = client.request::<LoadModuleReply>(CommandKind::LoadModule(load)).unwrap().module_index();
+
+        let info = client.request::<ModuleInfo>(CommandKind::GetModuleInfo(GetModuleInfo::new(index))).unwrap();
+        assert_eq!(info.index, index);
+        assert_eq!(info.name, name);
+        assert_eq!(info.argument, Some(argument));
+        assert!(info.props.get_string(Prop::ModuleDescription).is_some());
+
+        let list = client.request::<Vec<ModuleInfo>>(CommandKind::GetModuleInfoList).unwrap();
+        assert_eq!(list.iter().map(|info| info.index).collect::<Vec<_>>(), [index]);
+
+        client.request::<()>(CommandKind::UnloadModule(UnloadModule::new(index))).unwrap();
+        let result = client.request::<ModuleInfo>(CommandKind::GetModuleInfo(GetModuleInfo::new(index)));
+        assert_eq!(code(result), Some(PulseError::NoEntity));
+        let result = client.request::<()>(CommandKind::UnloadModule(UnloadModule::new(index)));
+        assert_eq!(code(result), Some(PulseError::NoEntity));
+
+        let name = PaString::new("module-does-not-exist").unwrap();
+        let result = client.request::<LoadModuleReply>(CommandKind::LoadModule(LoadModule::new(&name, None)));
+        assert_eq!(code(result), Some(PulseError::ModInitFailed));
+    }
+
+    #[test]
+    fn stream_quota() {
+        let data = server_data(DaemonConf {
+            max_streams_per_client: 2,
+            ..DaemonConf::default()
+        });
+        load_module(&data, "module-null-sink", "sink_name=out");
+        load_module(&data, "module-null-source", "source_name=in");
+        let mut client = TestClient::new(&data);
+
+        let playback = client.create_playback_stream().unwrap();
+        let record = client.create_record_stream().unwrap();
+        assert_ne!(playback.stream_index, record.channel);
+        assert_eq!(code(client.create_playback_stream()), Some(PulseError::TooLarge));
+        assert_eq!(code(client.create_record_stream()), Some(PulseError::TooLarge));
+
+        // The quota applies to each client separately
+        let mut other = TestClient::new(&data);
+        other.create_playback_stream().unwrap();
+
+        let delete = CommandKind::DeletePlaybackStream(StreamChannel::new(playback.stream_index));
+        client.request::<()>(delete).unwrap();
+        let delete = CommandKind::DeletePlaybackStream(StreamChannel::new(playback.stream_index));
+        assert_eq!(code(client.request::<()>(delete)), Some(PulseError::NoEntity));
+        client.create_playback_stream().unwrap();
+        assert_eq!(code(client.create_playback_stream()), Some(PulseError::TooLarge));
+    }
+
+    #[test]
+    fn streams_are_listed() {
+        let data = server_data(DaemonConf::default());
+        load_module(&data, "module-null-sink", "sink_name=out");
+        load_module(&data, "module-null-source", "source_name=in");
+        let mut client = TestClient::new(&data);
+        let mut other = TestClient::new(&data);
+
+        let playback = client.create_playback_stream().unwrap();
+        let record = client.create_record_stream().unwrap();
+        assert_eq!(playback.sink_name, PaString::new("out").unwrap());
+        assert_eq!(record.source_name, PaString::new("in").unwrap());
+
+        let inputs = other.request::<Vec<SinkInputInfo>>(CommandKind::GetSinkInputInfoList).unwrap();
+        assert_eq!(inputs.len(), 1);
+        assert_eq!(inputs[0].index, playback.sink_input_index);
+        assert_eq!(inputs[0].sink, playback.sink_index);
+        assert_eq!(inputs[0].client, Some(client.handler.client.value()));
+        let outputs = other.request::<Vec<SourceOutputInfo>>(CommandKind::GetSourceOutputInfoList).unwrap();
+        assert_eq!(outputs.len(), 1);
+        assert_eq!(outputs[0].index, record.source_output_index);
+
+        let get = CommandKind::GetSinkInputInfo(GetInfo::new(playback.sink_input_index));
+        assert_eq!(other.request::<SinkInputInfo>(get).unwrap().index, playback.sink_input_index);
+
+        // Disconnecting removes the client's streams
+        drop(client);
+        assert!(other.request::<Vec<SinkInputInfo>>(CommandKind::GetSinkInputInfoList).unwrap().is_empty());
+        assert!(other.request::<Vec<SourceOutputInfo>>(CommandKind::GetSourceOutputInfoList).unwrap().is_empty());
+        let get = CommandKind::GetSinkInputInfo(GetInfo::new(playback.sink_input_index));
+        assert_eq!(code(other.request::<SinkInputInfo>(get)), Some(PulseError::NoEntity));
+    }
+
+    #[test]
+    fn streams_killed_with_sink() {
+        let data = server_data(DaemonConf {
+            max_streams_per_client: 1,
+            ..DaemonConf::default()
+        });
+        assert_eq!(code(TestClient::new(&data).create_playback_stream()), Some(PulseError::NoEntity));
+
+        let module = load_module(&data, "module-null-sink", "sink_name=out");
+        let mut client = TestClient::new(&data);
+        let playback = client.create_playback_stream().unwrap();
+        client.queued();
+
+        module::unload(&data, module).unwrap();
+        let queued = client.queued();
+        assert_eq!(queued.len(), 1);
+        match command(&queued[0]).kind() {
+            CommandKind::PlaybackStreamKilled(killed) => assert_eq!(killed.channel(), playback.stream_index),
+            kind => panic!("expected PLAYBACK_STREAM_KILLED, got {:?}", kind),
+        }
+        let delete = CommandKind::DeletePlaybackStream(StreamChannel::new(playback.stream_index));
+        assert_eq!(code(client.request::<()>(delete)), Some(PulseError::NoEntity));
+
+        // The killed stream no longer counts towards the quota
+        load_module(&data, "module-null-sink", "sink_name=out");
+        client.create_playback_stream().unwrap();
+    }
+
+    #[test]
+    fn sample_cache_quota() {
+        let data = server_data(DaemonConf {
+            max_sample_cache_per_client: 4000,
+            ..DaemonConf::default()
+        });
+        let mut client = TestClient::new(&data);
+        let spec = SampleSpec::new_checked(SampleFormat::S16Le, 1, 44100).unwrap();
+        let map = ChannelMap::default_for(1).unwrap();
+        let upload = |client: &mut TestClient, name: &str, length: u32| {
+            let name = PaString::new(name).unwrap();
+            let params = CreateUploadStream::new(&name, spec.clone(), map.clone(), length, PropList::new());
+            client.request::<CreateUploadStreamReply>(CommandKind::CreateUploadStream(params))
+        };
+
+        let reply = upload(&mut client, "bell", 3000).unwrap();
+        assert_eq!(reply.length, 3000);
+        let packet = Packet::new_memblock(reply.channel, 0, SeekMode::Relative, &[0; 3000]);
+        assert!(client.handler.handle_packet(packet).unwrap().is_empty());
+        let finish = CommandKind::FinishUploadStream(StreamChannel::new(reply.channel));
+        let index = client.request::<FinishUploadStreamReply>(finish).unwrap().sample_index;
+
+        let samples = client.request::<Vec<SampleInfo>>(CommandKind::GetSampleInfoList).unwrap();
+        assert_eq!(samples.len(), 1);
+        assert_eq!((samples[0].index, samples[0].length), (index, 3000));
+        let name = PaString::new("bell").unwrap();
+        let get = CommandKind::GetSampleInfo(GetSampleInfo::by_name(&name));
+        assert_eq!(client.request::<SampleInfo>(get).unwrap().index, index);
+        let get = CommandKind::GetSampleInfo(GetSampleInfo::by_index(index));
+        assert_eq!(&*client.request::<SampleInfo>(get).unwrap().name, &*name);
+        let get = CommandKind::GetSampleInfo(GetSampleInfo::by_index(index + 1));
+        assert_eq!(code(client.request::<SampleInfo>(get)), Some(PulseError::NoEntity));
+
+        // Cached samples and pending uploads count towards the limit
+        assert_eq!(code(upload(&mut client, "click", 2000)), Some(PulseError::TooLarge));
+        let reply = upload(&mut client, "click", 1000).unwrap();
+        assert_eq!(code(upload(&mut client, "beep", 2)), Some(PulseError::TooLarge));
+        let delete = CommandKind::DeleteUploadStream(StreamChannel::new(reply.channel));
+        client.request::<()>(delete).unwrap();
+
+        // Other clients have their own limit
+        let mut other = TestClient::new(&data);
+        upload(&mut other, "click", 4000).unwrap();
+
+        client.request::<()>(CommandKind::RemoveSample(RemoveSample::new(&name))).unwrap();
+        assert!(client.request::<Vec<SampleInfo>>(CommandKind::GetSampleInfoList).unwrap().is_empty());
+        let get = CommandKind::GetSampleInfo(GetSampleInfo::by_name(&name));
+        assert_eq!(code(client.request::<SampleInfo>(get)), Some(PulseError::NoEntity));
+        upload(&mut client, "click", 4000).unwrap();
+        let result = client.request::<()>(CommandKind::RemoveSample(RemoveSample::new(&name)));
+        assert_eq!(code(result), Some(PulseError::NoEntity));
+
+        // Odd lengths can't contain whole frames
+        assert_eq!(code(upload(&mut other, "odd", 3)), Some(PulseError::Invalid));
+    }
+
+    #[test]
+    fn pending_requests() {
+        let data = server_data(DaemonConf {
+            max_pending_requests: 3,
+            ..DaemonConf::default()
+        });
+        let mut client = TestClient::new(&data);
+        let request = |client: &mut TestClient, tag| {
+            let packet = Command::new(tag, CommandKind::GetServerInfo).to_packet(&mut Vec::new(), PROTOCOL_VERSION);
+            let replies = client.handler.handle_packet(packet).unwrap();
+            assert_eq!(replies.len(), 1);
+            match command(&replies[0]).kind() {
+                CommandKind::Reply { .. } => None,
+                CommandKind::Error { code } => Some(*code),
+                kind => panic!("expected reply, got {:?}", kind),
+            }
+        };
+
+        // The replies aren't written, so they stay pending
+        for tag in 0..3 {
+            assert_eq!(request(&mut client, tag), None);
+        }
+        assert_eq!(request(&mut client, 3), Some(PulseError::TooLarge));
+        assert_eq!(request(&mut client, 4), Some(PulseError::TooLarge));
+
+        // Once the client has read its replies, requests are answered again
+        client.handler.pending.store(2, Ordering::SeqCst);
+        assert_eq!(request(&mut client, 5), None);
+        assert_eq!(request(&mut client, 6), Some(PulseError::TooLarge));
+    }
+
+    /// Decodes the subscription events the server queued for `client`.
+    fn events(client: &mut TestClient) -> Vec<(Facility, EventType, u32)> {
+        client.queued().iter().map(|packet| match command(packet).kind() {
+            CommandKind::SubscribeEvent(event) => (event.facility(), event.event_type(), event.index()),
+            kind => panic!("expected SUBSCRIBE_EVENT, got {:?}", kind),
+        }).collect()
+    }
+
+    #[test]
+    fn subscription_events() {
+        let data = server_data(DaemonConf::default());
+        let mut client = TestClient::new(&data);
+        let mut other = TestClient::new(&data);
+        client.request::<()>(CommandKind::Subscribe(Subscribe::new(SubscriptionMask::SINK))).unwrap();
+        other.request::<()>(CommandKind::Subscribe(Subscribe::new(SubscriptionMask::CLIENT))).unwrap();
+
+        load_module(&data, "module-null-sink", "sink_name=out");
+        let sink = data.sinks().iter().next().unwrap().index();
+        assert_eq!(events(&mut client), [(Facility::Sink, EventType::New, sink)]);
+        assert_eq!(events(&mut other), []);
+
+        let third = TestClient::new(&data);
+        let index = third.handler.client.value();
+        drop(third);
+        assert_eq!(events(&mut client), []);
+        assert_eq!(events(&mut other), [(Facility::Client, EventType::New, index), (Facility::Client, EventType::Remove, index)]);
+
+        // Unsubscribing stops the events
+        other.request::<()>(CommandKind::Subscribe(Subscribe::new(SubscriptionMask::empty()))).unwrap();
+        TestClient::new(&data);
+        assert_eq!(events(&mut other), []);
+    }
+
+    #[test]
+    fn full_queue_disconnects() {
+        // Too small limits are raised to the minimum
+        let data = server_data(DaemonConf {
+            max_queued_messages: 0,
+            ..DaemonConf::default()
+        });
+        let mut slow = TestClient::new(&data);
+        let mut other = TestClient::new(&data);
+        for client in &mut [&mut slow, &mut other] {
+            client.request::<()>(CommandKind::Subscribe(Subscribe::new(SubscriptionMask::SERVER))).unwrap();
+        }
+
+        for i in 0..2 * MIN_QUEUED_MESSAGES {
+            data.post_event(Facility::Server, EventType::Change, 0);
+            other.queued();
+            if i < MIN_QUEUED_MESSAGES {
+                assert_eq!(slow.kill.try_recv(), Ok(None));
+            }
+        }
+        assert_eq!(slow.kill.try_recv(), Ok(Some(())));
+        assert_eq!(other.kill.try_recv(), Ok(None));
+    }
+
+    #[test]
+    fn kill_client() {
+        let data = server_data(DaemonConf::default());
+        let mut client = TestClient::new(&data);
+        let mut victim = TestClient::new(&data);
+
+        let index = victim.handler.client.value();
+        client.request::<()>(CommandKind::KillClient(Kill::new(index))).unwrap();
+        assert_eq!(victim.kill.try_recv(), Ok(Some(())));
+        assert_eq!(client.kill.try_recv(), Ok(None));
+
+        // The client is only removed once its connection is closed
+        drop(victim);
+        let result = client.request::<()>(CommandKind::KillClient(Kill::new(index)));
+        assert_eq!(code(result), Some(PulseError::NoEntity));
+    }
+
+    #[test]
+    fn client_labels() {
+        let creds = PeerCreds { pid: 1234, uid: 1000, gid: 1000 };
+        assert_eq!(ClientLabel::new(None, None, Some(creds)).to_string(), "client (pid 1234)");
+        assert_eq!(ClientLabel::new(Some(3), None, None).to_string(), "client 3");
+        let peer = "127.0.0.1:4713".parse().unwrap();
+        assert_eq!(ClientLabel::new(Some(3), Some(peer), None).to_string(), "client 3 (127.0.0.1:4713)");
+
+        let mut label = ClientLabel::new(Some(3), None, Some(creds));
+        let mut props = PropList::new();
+        props.set(Prop::ApplicationProcessBinary, "firefox-bin\0");
+        label.update(&props);
+        assert_eq!(label.to_string(), "client 3 (firefox-bin, pid 1234)");
+        props.set(Prop::ApplicationName, "Firefox\0");
+        label.update(&props);
+        assert_eq!(label.to_string(), "client 3 (Firefox, pid 1234)");
+    }
+
+    #[test]
+    fn refused_clients() {
+        let data = server_data(DaemonConf {
+            max_connections: 1,
+            ..DaemonConf::default()
+        });
+        let client = TestClient::new(&data);
+        assert_eq!(TestClient::connect(&data).err(), Some(PulseError::ConnectionRefused));
+        drop(client);
+        TestClient::connect(&data).unwrap();
+    }
+
+    #[test]
+    fn accept_errors() {
+        let mut results = vec![
+            Err(io::Error::from_raw_os_error(libc::EMFILE)),
+            Err(io::Error::from(io::ErrorKind::ConnectionAborted)),
+            Ok(Async::Ready((1, ()))),
+            Err(io::Error::from_raw_os_error(libc::ENFILE)),
+            Ok(Async::Ready((2, ()))),
+            Err(io::Error::from(io::ErrorKind::InvalidInput)),
+        ].into_iter();
+        let accepted = incoming(move || results.next().unwrap()).then(Ok::<_, ()>).take(3).collect();
+
+        let start = Instant::now();
+        let accepted = Runtime::new().unwrap().block_on(accepted).unwrap();
+        assert_eq!(accepted[..2].iter().map(|r| *r.as_ref().unwrap()).collect::<Vec<_>>(), [1, 2]);
+        assert_eq!(accepted[2].as_ref().unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        assert!(start.elapsed() >= 2 * ACCEPT_BACKOFF);
+    }
+
+    #[test]
+    fn refusals_are_limited() {
+        let data = server_data(DaemonConf::default());
+        let mut runtime = Runtime::new().unwrap();
+        let mut refuse_connection = |data: &Arc<ServerData>| {
+            let (ours, theirs) = net::UnixStream::pair().unwrap();
+            theirs.set_read_timeout(Some(Duration::from_millis(10))).unwrap();
+            runtime.block_on(future::lazy(|| {
+                let stream = UnixStream::from_std(ours, &Handle::default()).unwrap();
+                let label = ClientLabel::new(None, None, None);
+                refuse(framed(stream), label, PulseError::ConnectionRefused, data);
+                Ok::<_, ()>(())
+            })).unwrap();
+            theirs
+        };
+
+        // The connection is kept open until the client sends its first command
+        let mut pending = Vec::new();
+        for _ in 0..MAX_PENDING_REFUSALS {
+            let mut theirs = refuse_connection(&data);
+            let err = theirs.read(&mut [0; 1]).unwrap_err();
+            assert!(err.kind() == io::ErrorKind::WouldBlock || err.kind() == io::ErrorKind::TimedOut);
+            pending.push(theirs);
+        }
+        assert_eq!(data.refusals.load(Ordering::SeqCst), MAX_PENDING_REFUSALS);
+
+        // Further connections are closed right away
+        let mut theirs = refuse_connection(&data);
+        assert_eq!(theirs.read(&mut [0; 1]).unwrap(), 0);
+        assert_eq!(data.refusals.load(Ordering::SeqCst), MAX_PENDING_REFUSALS);
+
+        // Closing a pending connection makes room for another one
+        drop(pending.pop());
+        runtime.block_on(Delay::new(Instant::now() + Duration::from_millis(50))).unwrap();
+        assert_eq!(data.refusals.load(Ordering::SeqCst), MAX_PENDING_REFUSALS - 1);
+    }
+}
@@ -0,0 +1,257 @@
+//! Server modules.
+//!
+//! A module is a unit of functionality that can be loaded into a running server (eg. via the
+//! `LOAD_MODULE` command) and unloaded again later. Modules usually create sinks or sources, but
+//! can do anything they like with the server state, as long as they clean up after themselves when
+//! unloaded.
+//!
+//! Unlike PulseAudio, we don't load shared objects. All modules are built into the server and
+//! looked up by name in `BUILTIN_MODULES`.
+
+mod native_protocol_tcp;
+mod null_sink;
+mod null_source;
+
+use super::ServerData;
+
+use pa_proto::error::{Error, PulseError};
+use pa_proto::command::{EventType, Facility, ModuleInfo};
+use pa_proto::proplist::{Prop, PropList};
+use pa_proto::string::{PaStr, PaString};
+
+use std::sync::Arc;
+use std::fmt;
+
+/// All modules that can be loaded by name.
+const BUILTIN_MODULES: &[ModuleDef] = &[
+    native_protocol_tcp::DEF,
+    null_sink::DEF,
+    null_source::DEF,
+];
+
+/// Describes a module that is built into the server.
+pub(crate) struct ModuleDef {
+    /// Name under which the module can be loaded (eg. `module-null-sink`).
+    pub name: &'static str,
+    /// Human readable one-line description of the module's purpose.
+    pub description: &'static str,
+    /// Human readable description of the module's arguments.
+    pub usage: &'static str,
+    /// Creates a new instance of the module.
+    ///
+    /// Receives the server state, the index the module will be registered under, and the argument
+    /// string passed by the user (empty if there were no arguments).
+    ///
+    /// The module isn't part of the module list until this returns.
+    pub load: fn(&Arc<ServerData>, u32, &str) -> Result<Box<Module>, Error>,
+}
+
+impl fmt::Debug for ModuleDef {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ModuleDef")
+            .field("name", &self.name)
+            .finish()
+    }
+}
+
+/// An instance of a loaded module.
+pub(crate) trait Module: fmt::Debug + Send + Sync {
+    /// Called when the module is unloaded.
+    ///
+    /// The module must remove everything it registered with the server (sinks, sources, ...).
+    fn unload(&mut self, data: &ServerData);
+
+    /// Returns the number of entities (eg. streams) currently using this module.
+    ///
+    /// Returns `None` if the module doesn't keep track of its users, which is the default.
+    fn n_used(&self) -> Option<u32> {
+        None
+    }
+}
+
+/// A module loaded into the server, along with the information needed to describe it to clients.
+#[derive(Debug)]
+pub(crate) struct LoadedModule {
+    index: u32,
+    name: PaString,
+    argument: Option<PaString>,
+    props: PropList,
+    instance: Box<Module>,
+}
+
+impl LoadedModule {
+    pub fn index(&self) -> u32 { self.index }
+
+    pub fn name(&self) -> &PaStr { &self.name }
+
+    /// The argument string the module was loaded with, if any.
+    pub fn argument(&self) -> Option<&PaStr> {
+        self.argument.as_ref().map(|arg| arg.as_pastr())
+    }
+
+    pub fn props(&self) -> &PropList { &self.props }
+
+    /// Gets the information sent to clients that query the module list.
+    pub fn info(&self) -> ModuleInfo {
+        ModuleInfo::new(
+            self.index,
+            &self.name,
+            self.argument.as_ref().map(|arg| arg.as_pastr()),
+            self.instance.n_used(),
+            &self.props,
+        )
+    }
+}
+
+/// Looks up a built-in module by name.
+pub(crate) fn find(name: &str) -> Option<&'static ModuleDef> {
+    BUILTIN_MODULES.iter().find(|def| def.name == name)
+}
+
+/// Loads a module by name and registers it with the server.
+///
+/// Returns the index of the loaded module.
+pub(crate) fn load(data: &Arc<ServerData>, name: &str, argument: Option<&str>) -> Result<u32, PulseError> {
+    let def = find(name).ok_or_else(|| {
+        error!("failed to load module {}: no such module", name);
+        PulseError::ModInitFailed
+    })?;
+
+    // Reject arguments that can't be sent back to clients
+    let argument = match argument {
+        Some(arg) => Some(PaString::new(arg).map_err(|_| PulseError::Invalid)?),
+        None => None,
+    };
+
+    let mut props = PropList::new();
+    props.set(Prop::ModuleDescription, format!("{}\0", def.description));
+    props.set(Prop::ModuleUsage, format!("{}\0", def.usage));
+    props.set(Prop::ModuleVersion, concat!(env!("CARGO_PKG_VERSION"), "\0"));
+
+    // Only reserve the index, so the module list isn't locked while the module initializes
+    let idx = data.modules_mut().reserve();
+    let index = idx.value();
+    let arg_str = argument.as_ref().map(|arg| arg.to_string()).unwrap_or_default();
+    let instance = (def.load)(data, index, &arg_str).map_err(|e| {
+        error!("failed to load module {} (argument: \"{}\"): {}", name, arg_str, e);
+        PulseError::ModInitFailed
+    })?;
+
+    data.modules_mut().insert(idx, LoadedModule {
+        index,
+        name: PaString::new(def.name).unwrap(),
+        argument,
+        props,
+        instance,
+    });
+
+    info!("loaded module {} as index {}", name, index);
+    data.post_event(Facility::Module, EventType::New, index);
+    Ok(index)
+}
+
+/// Unloads the module with the given index.
+///
+/// Returns `PulseError::NoEntity` if no such module is loaded.
+pub(crate) fn unload(data: &ServerData, index: u32) -> Result<(), PulseError> {
+    let mut module = {
+        let mut modules = data.modules_mut();
+        let idx = modules.idx_of(index).ok_or(PulseError::NoEntity)?;
+        modules.remove(idx).unwrap()
+    };
+
+    module.instance.unload(data);
+    info!("unloaded module {} (index {})", module.name, index);
+    data.post_event(Facility::Module, EventType::Remove, index);
+    Ok(())
+}
+
+/// Unloads all modules, most recently loaded first.
+pub(crate) fn unload_all(data: &ServerData) {
+    let mut indices = data.modules().iter().map(|module| module.index()).collect::<Vec<_>>();
+    indices.sort_unstable_by(|a, b| b.cmp(a));
+    for index in indices {
+        // Modules can't unload each other, so this can't fail
+        unload(data, index).ok();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pa_proto::conf::DaemonConf;
+    use pa_proto::cookie::AuthCookie;
+
+    fn server_data() -> Arc<ServerData> {
+        Arc::new(ServerData::new(AuthCookie::from_bytes(&[0; 256]).unwrap(), DaemonConf::default()))
+    }
+
+    fn sink_names(data: &ServerData) -> Vec<String> {
+        data.sinks().iter().map(|sink| sink.name().to_string()).collect()
+    }
+
+    #[test]
+    fn load_and_unload() {
+        let data = server_data();
+        let index = load(&data, "module-null-sink", Some("sink_name=test")).unwrap();
+
+        {
+            let modules = data.modules();
+            let module = modules.get(modules.idx_of(index).unwrap()).unwrap();
+            assert_eq!(module.name().to_string(), "module-null-sink");
+            assert_eq!(module.argument().unwrap().to_string(), "sink_name=test");
+            assert_eq!(module.info().index, index);
+        }
+        assert_eq!(sink_names(&data), ["test"]);
+        assert_eq!(data.sinks().iter().next().unwrap().owner_module(), Some(index));
+
+        unload(&data, index).unwrap();
+        assert_eq!(data.modules().len(), 0);
+        assert!(sink_names(&data).is_empty());
+        assert_eq!(unload(&data, index), Err(PulseError::NoEntity));
+    }
+
+    #[test]
+    fn load_failures() {
+        let data = server_data();
+        assert_eq!(load(&data, "module-does-not-exist", None), Err(PulseError::ModInitFailed));
+        assert_eq!(load(&data, "module-null-sink", Some("no_such_arg=1")), Err(PulseError::ModInitFailed));
+        assert_eq!(load(&data, "module-null-sink", Some("a\0b")), Err(PulseError::Invalid));
+        assert_eq!(data.modules().len(), 0);
+
+        // The index reserved for the module that failed to initialize isn't reused
+        let index = load(&data, "module-null-sink", None).unwrap();
+        assert_eq!(index, 1);
+    }
+
+    #[test]
+    fn duplicate_sink_name() {
+        let data = server_data();
+        let first = load(&data, "module-null-sink", Some("sink_name=dup")).unwrap();
+        assert_eq!(load(&data, "module-null-sink", Some("sink_name=dup")), Err(PulseError::ModInitFailed));
+        assert_eq!(sink_names(&data), ["dup"]);
+        assert_eq!(data.modules().iter().map(|module| module.index()).collect::<Vec<_>>(), [first]);
+
+        // Loading in parallel must not create two sinks with the same name either
+        let handles = (0..8).map(|_| {
+            let data = data.clone();
+            ::std::thread::spawn(move || load(&data, "module-null-sink", Some("sink_name=race")).is_ok())
+        }).collect::<Vec<_>>();
+        let loaded = handles.into_iter().map(|h| h.join().unwrap()).filter(|&ok| ok).count();
+        assert_eq!(loaded, 1);
+        assert_eq!(sink_names(&data).iter().filter(|name| *name == "race").count(), 1);
+    }
+
+    #[test]
+    fn unload_all_modules() {
+        let data = server_data();
+        let first = load(&data, "module-null-source", None).unwrap();
+        let second = load(&data, "module-null-sink", None).unwrap();
+        assert!(second > first);
+
+        unload_all(&data);
+        assert_eq!(data.modules().len(), 0);
+        assert!(sink_names(&data).is_empty());
+        assert_eq!(data.sources().len(), 0);
+    }
+}
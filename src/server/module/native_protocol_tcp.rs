@@ -0,0 +1,149 @@
+//! `module-native-protocol-tcp`: Accepts native protocol clients via TCP.
+//!
+//! Unloading the module stops accepting new connections, but leaves established connections alone.
+
+use super::{Module, ModuleDef};
+use server::auth::{AuthOptions, IpAcl};
+use server::{ServerData, framed, incoming, process};
+use transport::DEFAULT_PORT;
+
+use pa_proto::cookie::AuthCookie;
+use pa_proto::error::Error;
+use pa_proto::modargs::ModArgs;
+use pa_proto::paths;
+
+use futures::sync::oneshot;
+use tokio::executor::{DefaultExecutor, Executor};
+use tokio::net::TcpListener;
+use tokio::prelude::*;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::Arc;
+use std::io;
+
+pub(crate) const DEF: ModuleDef = ModuleDef {
+    name: "module-native-protocol-tcp",
+    description: "Native protocol (TCP sockets)",
+    usage: "port=<TCP port number> listen=<address to listen on> \
+            auth-anonymous=<don't check for cookies?> auth-cookie-enabled=<enable cookie authentication?> \
+            auth-ip-acl=<IP address ACL to allow with no auth> auth-cookie=<path to cookie file>",
+    load,
+};
+
+const VALID_ARGS: &[&str] = &[
+    "port",
+    "listen",
+    "auth-anonymous",
+    "auth-cookie-enabled",
+    "auth-ip-acl",
+    "auth-cookie",
+    // Deprecated alias of `auth-cookie`
+    "cookie",
+];
+
+#[derive(Debug)]
+struct NativeProtocolTcp {
+    /// Used to stop the listener tasks.
+    shutdown: Vec<oneshot::Sender<()>>,
+}
+
+fn load(data: &Arc<ServerData>, _index: u32, argument: &str) -> Result<Box<Module>, Error> {
+    let args = ModArgs::parse(argument, VALID_ARGS).map_err(|e| Error::string(e.to_string()))?;
+    let port = match args.get_u32("port").map_err(|e| Error::string(e.to_string()))? {
+        Some(port) if port == 0 || port > u16::max_value() as u32 => {
+            return Err(Error::string(format!("invalid port {}", port)));
+        }
+        Some(port) => port as u16,
+        None => DEFAULT_PORT,
+    };
+
+    let auth = Arc::new(AuthOptions {
+        anonymous: args.get_bool("auth-anonymous")
+            .map_err(|e| Error::string(e.to_string()))?
+            .unwrap_or(false),
+        cookie: if args.get_bool("auth-cookie-enabled").map_err(|e| Error::string(e.to_string()))?.unwrap_or(true) {
+            match args.get("auth-cookie").or_else(|| args.get("cookie")) {
+                Some(path) => Some(Arc::new(AuthCookie::load_or_create(paths::config_home_file(path))?)),
+                None => Some(data.cookie.clone()),
+            }
+        } else {
+            None
+        },
+        ip_acl: match args.get("auth-ip-acl") {
+            Some(acl) => Some(IpAcl::parse(acl)?),
+            None => None,
+        },
+        same_user: false,
+        group: None,
+    });
+
+    let listeners = match args.get("listen") {
+        Some(addr) => {
+            let ip: IpAddr = addr.parse()
+                .map_err(|_| Error::string(format!("invalid listen address '{}'", addr)))?;
+            vec![TcpListener::bind(&SocketAddr::new(ip, port))?]
+        }
+        None => bind_any(port)?,
+    };
+
+    let mut executor = DefaultExecutor::current();
+    let mut shutdown = Vec::new();
+    for mut listener in listeners {
+        let addr = listener.local_addr()?;
+        info!("listening for native protocol clients on {}", addr);
+
+        let (tx, rx) = oneshot::channel();
+        shutdown.push(tx);
+
+        let (data, auth) = (data.clone(), auth.clone());
+        let accept = incoming(move || listener.poll_accept()).for_each(move |stream| {
+            // The peer may have reset the connection already, which only affects this connection
+            let peer = match stream.peer_addr() {
+                Ok(peer) => peer,
+                Err(e) => {
+                    warn!("couldn't get address of TCP client: {}", e);
+                    return Ok(());
+                }
+            };
+            process(framed(stream), Some(peer), None, None, auth.clone(), data.clone());
+            Ok(())
+        }).map_err(move |e| error!("TCP listener on {} failed: {}", addr, e));
+
+        // Stop when the module is unloaded (or dropped)
+        let task = accept.select(rx.then(|_| Ok(()))).then(|_| Ok(()));
+        executor.spawn(Box::new(task))
+            .map_err(|e| Error::string(format!("couldn't spawn TCP listener: {}", e)))?;
+    }
+
+    Ok(Box::new(NativeProtocolTcp { shutdown }))
+}
+
+/// Binds listeners on all IPv6 and IPv4 interfaces.
+///
+/// Succeeds if at least one of the listeners could be bound. On dual-stack systems, the IPv6
+/// listener also accepts IPv4 connections, so failing to bind the IPv4 one is expected there.
+fn bind_any(port: u16) -> io::Result<Vec<TcpListener>> {
+    let v6 = TcpListener::bind(&SocketAddr::new(Ipv6Addr::UNSPECIFIED.into(), port));
+    let v4 = TcpListener::bind(&SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), port));
+
+    match (v6, v4) {
+        (Ok(v6), Ok(v4)) => Ok(vec![v6, v4]),
+        (Ok(v6), Err(e)) => {
+            debug!("couldn't bind IPv4 listener (assuming dual-stack IPv6 listener): {}", e);
+            Ok(vec![v6])
+        }
+        (Err(e), Ok(v4)) => {
+            warn!("couldn't bind IPv6 listener, only accepting IPv4 connections: {}", e);
+            Ok(vec![v4])
+        }
+        (Err(e), Err(_)) => Err(e),
+    }
+}
+
+impl Module for NativeProtocolTcp {
+    fn unload(&mut self, _data: &ServerData) {
+        for tx in self.shutdown.drain(..) {
+            // The listener might've died on its own already
+            tx.send(()).ok();
+        }
+    }
+}
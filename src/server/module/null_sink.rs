@@ -0,0 +1,82 @@
+//! `module-null-sink`: A sink that drops all samples sent to it.
+
+use super::{Module, ModuleDef};
+use server::ServerData;
+
+use pa_proto::command::{EventType, Facility};
+use pa_proto::error::Error;
+use pa_proto::idxset::Idx;
+use pa_proto::modargs::ModArgs;
+use pa_proto::proplist::{Prop, PropList};
+use pa_proto::sink::Sink;
+use pa_proto::string::PaString;
+
+use std::sync::Arc;
+
+pub(crate) const DEF: ModuleDef = ModuleDef {
+    name: "module-null-sink",
+    description: "Clocked NULL sink",
+    usage: "sink_name=<name of sink> sink_properties=<properties for the sink> format=<sample format> \
+            rate=<sample rate> channels=<number of channels> channel_map=<channel map>",
+    load,
+};
+
+const VALID_ARGS: &[&str] = &[
+    "sink_name",
+    "sink_properties",
+    "format",
+    "rate",
+    "channels",
+    "channel_map",
+];
+
+#[derive(Debug)]
+struct NullSink {
+    sink: Idx<Sink>,
+}
+
+fn load(data: &Arc<ServerData>, index: u32, argument: &str) -> Result<Box<Module>, Error> {
+    let args = ModArgs::parse(argument, VALID_ARGS).map_err(|e| Error::string(e.to_string()))?;
+    let sink_name = args.get_or("sink_name", "null");
+    let (sample_spec, channel_map) = args.get_sample_spec_and_channel_map(
+        &data.default_sample_spec(), &data.default_channel_map()
+    ).map_err(|e| Error::string(e.to_string()))?;
+    let user_props = args.get_proplist("sink_properties")
+        .map_err(|e| Error::string(e.to_string()))?
+        .unwrap_or_else(PropList::new);
+
+    let name = PaString::new(sink_name)
+        .map_err(|_| Error::string("sink name contains nul bytes"))?;
+
+    let mut props = PropList::new();
+    props.set(Prop::DeviceDescription, "Null Output\0");
+    props.extend(&user_props);
+
+    // Check the name under the same lock, so that no other sink can take it in the meantime
+    let sink = {
+        let mut sinks = data.sinks_mut();
+        if sinks.iter().any(|sink| *sink.name() == *name) {
+            return Err(Error::string(format!("a sink named '{}' already exists", sink_name)));
+        }
+
+        sinks.alloc(|idx| {
+            let mut sink = Sink::new_null(
+                idx.into(), name, props, sample_spec, channel_map
+            );
+            sink.set_owner_module(Some(index));
+            sink
+        }).idx()
+    };
+    data.post_event(Facility::Sink, EventType::New, sink.value());
+
+    Ok(Box::new(NullSink { sink }))
+}
+
+impl Module for NullSink {
+    fn unload(&mut self, data: &ServerData) {
+        data.sinks_mut().remove(self.sink);
+        // Streams are created while the sink list is locked, so none can be added to the sink now
+        data.kill_sink_inputs(self.sink.value());
+        data.post_event(Facility::Sink, EventType::Remove, self.sink.value());
+    }
+}
@@ -0,0 +1,82 @@
+//! `module-null-source`: A source that produces silence.
+
+use super::{Module, ModuleDef};
+use server::ServerData;
+
+use pa_proto::command::{EventType, Facility};
+use pa_proto::error::Error;
+use pa_proto::idxset::Idx;
+use pa_proto::modargs::ModArgs;
+use pa_proto::proplist::{Prop, PropList};
+use pa_proto::source::Source;
+use pa_proto::string::PaString;
+
+use std::sync::Arc;
+
+pub(crate) const DEF: ModuleDef = ModuleDef {
+    name: "module-null-source",
+    description: "Clocked NULL source",
+    usage: "source_name=<name of source> source_properties=<properties for the source> format=<sample format> \
+            rate=<sample rate> channels=<number of channels> channel_map=<channel map>",
+    load,
+};
+
+const VALID_ARGS: &[&str] = &[
+    "source_name",
+    "source_properties",
+    "format",
+    "rate",
+    "channels",
+    "channel_map",
+];
+
+#[derive(Debug)]
+struct NullSource {
+    source: Idx<Source>,
+}
+
+fn load(data: &Arc<ServerData>, index: u32, argument: &str) -> Result<Box<Module>, Error> {
+    let args = ModArgs::parse(argument, VALID_ARGS).map_err(|e| Error::string(e.to_string()))?;
+    let source_name = args.get_or("source_name", "source.null");
+    let (sample_spec, channel_map) = args.get_sample_spec_and_channel_map(
+        &data.default_sample_spec(), &data.default_channel_map()
+    ).map_err(|e| Error::string(e.to_string()))?;
+    let user_props = args.get_proplist("source_properties")
+        .map_err(|e| Error::string(e.to_string()))?
+        .unwrap_or_else(PropList::new);
+
+    let name = PaString::new(source_name)
+        .map_err(|_| Error::string("source name contains nul bytes"))?;
+
+    let mut props = PropList::new();
+    props.set(Prop::DeviceDescription, "Null Input\0");
+    props.extend(&user_props);
+
+    // Check the name under the same lock, so that no other source can take it in the meantime
+    let source = {
+        let mut sources = data.sources_mut();
+        if sources.iter().any(|source| *source.name() == *name) {
+            return Err(Error::string(format!("a source named '{}' already exists", source_name)));
+        }
+
+        sources.alloc(|idx| {
+            let mut source = Source::new_null(
+                idx.into(), name, props, sample_spec, channel_map
+            );
+            source.set_owner_module(Some(index));
+            source
+        }).idx()
+    };
+    data.post_event(Facility::Source, EventType::New, source.value());
+
+    Ok(Box::new(NullSource { source }))
+}
+
+impl Module for NullSource {
+    fn unload(&mut self, data: &ServerData) {
+        data.sources_mut().remove(self.source);
+        // Streams are created while the source list is locked, so none can be added to the source now
+        data.kill_source_outputs(self.source.value());
+        data.post_event(Facility::Source, EventType::Remove, self.source.value());
+    }
+}
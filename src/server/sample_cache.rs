@@ -0,0 +1,118 @@
+//! The sample cache, which holds short sounds uploaded by clients (eg. event sounds).
+//!
+//! Samples outlive the client that uploaded them, but they only count towards the client's
+//! `max_sample_cache_per_client` limit while it's connected.
+
+use super::streams::norm_volume;
+
+use pa_proto::command::{CreateUploadStream, SampleInfo};
+use pa_proto::error::PulseError;
+use pa_proto::proplist::PropList;
+use pa_proto::sample_spec::SampleSpec;
+use pa_proto::channel_map::ChannelMap;
+use pa_proto::string::PaString;
+
+/// Maximum size of a single sample, in bytes.
+const MAX_SAMPLE_SIZE: u32 = 16 * 1024 * 1024;
+
+/// A stream uploading a sample into the cache.
+#[derive(Debug)]
+pub(crate) struct Upload {
+    name: PaString,
+    sample_spec: SampleSpec,
+    channel_map: ChannelMap,
+    props: PropList,
+    /// Size of the sample announced by the client.
+    length: u32,
+    data: Vec<u8>,
+}
+
+impl Upload {
+    /// Creates the upload stream requested by `params`.
+    pub fn new(params: &CreateUploadStream) -> Result<Self, PulseError> {
+        let name = params.name().ok_or(PulseError::Invalid)?;
+        let (spec, length) = (params.sample_spec(), params.length());
+        if params.channel_map().len() != spec.channels() || length == 0 || length % spec.frame_size() as u32 != 0 {
+            return Err(PulseError::Invalid);
+        }
+        if length > MAX_SAMPLE_SIZE {
+            return Err(PulseError::TooLarge);
+        }
+
+        Ok(Self {
+            name: name.to_owned(),
+            sample_spec: spec.clone(),
+            channel_map: params.channel_map().clone(),
+            props: params.props().clone(),
+            length,
+            data: Vec::new(),
+        })
+    }
+
+    pub fn name(&self) -> &PaString { &self.name }
+
+    /// Size of the sample in bytes.
+    pub fn length(&self) -> u32 { self.length }
+
+    /// Appends sample data sent by the client.
+    ///
+    /// Returns `false` if the data didn't fit into the announced length. The excess is dropped.
+    pub fn write(&mut self, data: &[u8]) -> bool {
+        let free = self.length as usize - self.data.len();
+        self.data.extend_from_slice(&data[..data.len().min(free)]);
+        data.len() <= free
+    }
+
+    /// Turns the uploaded data into a sample uploaded by the client with index `owner`.
+    pub fn finish(self, index: u32, owner: u32) -> Sample {
+        Sample {
+            index,
+            owner,
+            name: self.name,
+            sample_spec: self.sample_spec,
+            channel_map: self.channel_map,
+            props: self.props,
+            data: self.data,
+        }
+    }
+}
+
+/// A sample in the sample cache.
+#[derive(Debug)]
+pub(crate) struct Sample {
+    index: u32,
+    /// Index of the client that uploaded the sample.
+    owner: u32,
+    name: PaString,
+    sample_spec: SampleSpec,
+    channel_map: ChannelMap,
+    props: PropList,
+    data: Vec<u8>,
+}
+
+impl Sample {
+    pub fn index(&self) -> u32 { self.index }
+
+    pub fn owner(&self) -> u32 { self.owner }
+
+    pub fn name(&self) -> &PaString { &self.name }
+
+    /// Size of the sample data in bytes.
+    pub fn len(&self) -> usize { self.data.len() }
+
+    /// Gets the information sent to clients that query the sample cache.
+    pub fn info(&self) -> SampleInfo {
+        SampleInfo {
+            index: self.index,
+            name: self.name.clone(),
+            cvolume: norm_volume(&self.sample_spec),
+            duration: self.sample_spec.bytes_to_usec(self.data.len() as u64),
+            sample_spec: self.sample_spec.clone(),
+            channel_map: self.channel_map.clone(),
+            length: self.data.len() as u32,
+            lazy: false,
+            filename: None,
+            props: self.props.clone(),
+        }
+    }
+}
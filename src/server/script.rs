@@ -0,0 +1,748 @@
+//! Interpreter for `default.pa`-style scripts.
+//!
+//! Scripts consist of one command per line. Empty lines and lines starting with `#` or `;` are
+//! ignored. Apart from regular commands (see `COMMANDS`), the following meta commands are
+//! understood:
+//!
+//! * `.include <path>` executes another script. If `<path>` is a directory, all `*.pa` files in it
+//!   are executed in lexicographic order. Relative paths are resolved against the directory of the
+//!   including script.
+//! * `.ifexists <path>` only executes the following lines up to the matching `.else` or `.endif`
+//!   if `<path>` exists.
+//! * `.fail` makes a failing command abort the script (this is the default), `.nofail` makes the
+//!   interpreter log the error and carry on.
+//!
+//! Arguments are separated by whitespace. Like module arguments, they can be enclosed in `'single'`
+//! or `"double"` quotes to include whitespace, and a backslash escapes the following character.
+
+use super::{ServerData, module};
+
+use pa_proto::command::{EventType, Facility};
+use pa_proto::error::Error;
+use pa_proto::idxset::Idx;
+use pa_proto::sink::Sink;
+use pa_proto::source::Source;
+use pa_proto::string::PaString;
+use pa_proto::{ChannelMap, CVolume, PropList, Volume};
+
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::{fs, str};
+
+/// Script executed when no startup script can be found.
+pub(crate) const BUILTIN_SCRIPT: &str = "\
+load-module module-null-sink
+load-module module-null-source
+set-default-sink null
+set-default-source source.null
+";
+
+/// Maximum nesting depth of `.include`s, to catch recursive includes.
+const MAX_INCLUDE_DEPTH: u32 = 16;
+
+/// A command that can be used in scripts.
+struct CommandDef {
+    name: &'static str,
+    /// Description of the arguments, for usage messages.
+    args: &'static str,
+    /// One-line description of the command, shown by `help`.
+    help: &'static str,
+    /// Executes the command. Receives the (trimmed) rest of the line after the command name.
+    run: fn(&mut Interpreter, &str, &mut String) -> Result<(), Error>,
+}
+
+const COMMANDS: &[CommandDef] = &[
+    CommandDef { name: "help", args: "", help: "Show this help", run: help },
+    CommandDef { name: "list-modules", args: "", help: "List loaded modules", run: list_modules },
+    CommandDef { name: "list-sinks", args: "", help: "List sinks", run: list_sinks },
+    CommandDef { name: "list-sources", args: "", help: "List sources", run: list_sources },
+    CommandDef { name: "list-clients", args: "", help: "List connected clients", run: list_clients },
+    CommandDef { name: "list-sink-inputs", args: "", help: "List sink inputs", run: list_sink_inputs },
+    CommandDef { name: "stat", args: "", help: "Show server statistics", run: stat },
+    CommandDef { name: "dump", args: "", help: "Dump the server state as a script", run: dump },
+    CommandDef { name: "load-module", args: "<name> [<arguments>]", help: "Load a module", run: load_module },
+    CommandDef { name: "unload-module", args: "<index>", help: "Unload a module", run: unload_module },
+    CommandDef { name: "set-default-sink", args: "<name|index>", help: "Set the default sink", run: set_default_sink },
+    CommandDef { name: "set-default-source", args: "<name|index>", help: "Set the default source", run: set_default_source },
+    CommandDef { name: "set-sink-volume", args: "<name|index> <volume>...", help: "Set the volume of a sink (for all channels, or each one)", run: set_sink_volume },
+];
+
+/// Executes script commands against the server state.
+#[derive(Debug)]
+pub(crate) struct Interpreter {
+    data: Arc<ServerData>,
+    /// Whether a failing command aborts the script (`.fail`) or is only logged (`.nofail`).
+    fail: bool,
+    /// Results of the enclosing `.ifexists` conditions. Lines are only executed if all of them are
+    /// `true`.
+    conditions: Vec<bool>,
+    /// Directory against which relative `.include` paths are resolved.
+    base_dir: Option<PathBuf>,
+    include_depth: u32,
+}
+
+impl Interpreter {
+    pub fn new(data: Arc<ServerData>) -> Self {
+        Self {
+            data,
+            fail: true,
+            conditions: Vec::new(),
+            base_dir: None,
+            include_depth: 0,
+        }
+    }
+
+    /// Executes the script stored at `path`.
+    ///
+    /// Command output is logged.
+    pub fn run_file(&mut self, path: &Path) -> Result<(), Error> {
+        let script = fs::read_to_string(path)
+            .map_err(|e| Error::string(format!("couldn't read script '{}': {}", path.display(), e)))?;
+
+        let old_base = self.base_dir.take();
+        self.base_dir = path.parent().map(Path::to_path_buf);
+        let result = self.run_str(&script, &path.display().to_string());
+        self.base_dir = old_base;
+        result
+    }
+
+    /// Executes all lines of `script`.
+    ///
+    /// `origin` describes where the script came from and is used in error messages.
+    pub fn run_str(&mut self, script: &str, origin: &str) -> Result<(), Error> {
+        let depth = self.conditions.len();
+        let mut out = String::new();
+
+        for (i, line) in script.lines().enumerate() {
+            if let Err(e) = self.execute_line(line, &mut out) {
+                let e = Error::string(format!("{}:{}: {}", origin, i + 1, e));
+                if self.fail {
+                    self.conditions.truncate(depth);
+                    return Err(e);
+                } else {
+                    warn!("{} (ignored)", e);
+                }
+            }
+
+            for line in out.lines() {
+                info!("{}", line);
+            }
+            out.clear();
+        }
+
+        if self.conditions.len() != depth {
+            self.conditions.truncate(depth);
+            return Err(Error::string(format!("{}: missing .endif", origin)));
+        }
+
+        Ok(())
+    }
+
+    /// Executes a single line, appending the command's output to `out`.
+    pub fn execute_line(&mut self, line: &str, out: &mut String) -> Result<(), Error> {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+            return Ok(());
+        }
+
+        let (cmd, rest) = split_first_word(line);
+        let active = self.conditions.iter().all(|&cond| cond);
+
+        match cmd {
+            ".ifexists" => {
+                let exists = active && self.resolve_path(rest).exists();
+                self.conditions.push(exists);
+                return Ok(());
+            }
+            ".else" => {
+                let depth = self.conditions.len();
+                if depth == 0 {
+                    return Err(Error::string(".else without .ifexists"));
+                }
+                let outer_active = self.conditions[..depth - 1].iter().all(|&cond| cond);
+                let cond = self.conditions.last_mut().unwrap();
+                *cond = outer_active && !*cond;
+                return Ok(());
+            }
+            ".endif" => {
+                return self.conditions.pop()
+                    .map(|_| ())
+                    .ok_or_else(|| Error::string(".endif without .ifexists"));
+            }
+            _ if !active => return Ok(()),
+            ".fail" => self.fail = true,
+            ".nofail" => self.fail = false,
+            ".include" => self.include(rest)?,
+            _ => {
+                let def = COMMANDS.iter().find(|def| def.name == cmd)
+                    .ok_or_else(|| Error::string(format!("unknown command '{}'", cmd)))?;
+                (def.run)(self, rest, out)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn include(&mut self, path: &str) -> Result<(), Error> {
+        if path.is_empty() {
+            return Err(Error::string("usage: .include <path>"));
+        }
+        if self.include_depth >= MAX_INCLUDE_DEPTH {
+            return Err(Error::string("too many nested .include directives"));
+        }
+
+        let path = self.resolve_path(path);
+        let files = if path.is_dir() {
+            let mut files = fs::read_dir(&path)?
+                .map(|entry| entry.map(|entry| entry.path()))
+                .collect::<Result<Vec<_>, _>>()?;
+            files.retain(|file| file.extension().map_or(false, |ext| ext == "pa") && file.is_file());
+            files.sort();
+            files
+        } else {
+            vec![path]
+        };
+
+        self.include_depth += 1;
+        let result = files.iter().map(|file| self.run_file(file)).collect();
+        self.include_depth -= 1;
+        result
+    }
+
+    fn resolve_path(&self, path: &str) -> PathBuf {
+        match self.base_dir {
+            Some(ref base) => base.join(path),
+            None => PathBuf::from(path),
+        }
+    }
+}
+
+/// Splits `s` at the first whitespace, returning the first word and the trimmed rest.
+fn split_first_word(s: &str) -> (&str, &str) {
+    match s.find(char::is_whitespace) {
+        Some(pos) => (&s[..pos], s[pos..].trim()),
+        None => (s, ""),
+    }
+}
+
+/// Splits an argument string into exactly `count` arguments.
+fn split_args(args: &str, count: usize, cmd: &str) -> Result<Vec<String>, Error> {
+    let split = split_quoted(args)?;
+    if split.len() != count {
+        return Err(usage(cmd));
+    }
+    Ok(split)
+}
+
+/// Splits an argument string at whitespace, resolving quotes and escapes.
+fn split_quoted(args: &str) -> Result<Vec<String>, Error> {
+    let mut split = Vec::new();
+    let mut chars = args.chars();
+    // The argument being parsed, if any
+    let mut arg: Option<String> = None;
+    // The quote character of the quoted part being parsed
+    let mut quote = None;
+
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (_, '\\') => {
+                let escaped = chars.next().ok_or_else(|| Error::string("trailing backslash"))?;
+                arg.get_or_insert_with(String::new).push(escaped);
+            }
+            (None, '"') | (None, '\'') => {
+                quote = Some(c);
+                arg.get_or_insert_with(String::new);
+            }
+            (Some(q), _) if q == c => quote = None,
+            (None, _) if c.is_whitespace() => split.extend(arg.take()),
+            _ => arg.get_or_insert_with(String::new).push(c),
+        }
+    }
+
+    if quote.is_some() {
+        return Err(Error::string("unterminated quoted argument"));
+    }
+    split.extend(arg);
+    Ok(split)
+}
+
+/// Quotes `arg` if necessary, so that `split_quoted` turns it back into a single argument.
+fn quote(arg: &str) -> String {
+    let plain = !arg.is_empty() && !arg.chars().any(|c| c.is_whitespace() || "\"'\\#;".contains(c));
+    if plain {
+        return arg.to_string();
+    }
+
+    let mut quoted = String::from("\"");
+    for c in arg.chars() {
+        if c == '"' || c == '\\' {
+            quoted.push('\\');
+        }
+        quoted.push(c);
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Returns the usage message of the command `cmd`.
+fn usage(cmd: &str) -> Error {
+    let def = COMMANDS.iter().find(|def| def.name == cmd).unwrap();
+    Error::string(format!("usage: {} {}", def.name, def.args))
+}
+
+/// Looks up a sink by name or index.
+fn find_sink(data: &ServerData, name_or_index: &str) -> Result<Idx<Sink>, Error> {
+    let sinks = data.sinks();
+    let found = match name_or_index.parse() {
+        Ok(index) => sinks.idx_of(index),
+        Err(_) => sinks.iter().find(|sink| sink.name().to_bytes() == name_or_index.as_bytes())
+            .and_then(|sink| sinks.idx_of(sink.index())),
+    };
+    found.ok_or_else(|| Error::string(format!("no sink found by this name or index: {}", name_or_index)))
+}
+
+/// Looks up a source by name or index.
+fn find_source(data: &ServerData, name_or_index: &str) -> Result<Idx<Source>, Error> {
+    let sources = data.sources();
+    let found = match name_or_index.parse() {
+        Ok(index) => sources.idx_of(index),
+        Err(_) => sources.iter().find(|source| source.name().to_bytes() == name_or_index.as_bytes())
+            .and_then(|source| sources.idx_of(source.index())),
+    };
+    found.ok_or_else(|| Error::string(format!("no source found by this name or index: {}", name_or_index)))
+}
+
+fn load_module(interp: &mut Interpreter, args: &str, _out: &mut String) -> Result<(), Error> {
+    let (name, argument) = split_first_word(args);
+    if name.is_empty() {
+        return Err(Error::string("usage: load-module <name> [<arguments>]"));
+    }
+
+    let argument = if argument.is_empty() { None } else { Some(argument) };
+    module::load(&interp.data, name, argument)
+        .map(|_| ())
+        .map_err(|e| Error::string(format!("failed to load module {}: {}", name, e)))
+}
+
+fn unload_module(interp: &mut Interpreter, args: &str, _out: &mut String) -> Result<(), Error> {
+    let args = split_args(args, 1, "unload-module")?;
+    let index = args[0].parse()
+        .map_err(|_| Error::string(format!("invalid module index: {}", args[0])))?;
+    module::unload(&interp.data, index)
+        .map_err(|e| Error::string(format!("failed to unload module {}: {}", index, e)))
+}
+
+fn set_default_sink(interp: &mut Interpreter, args: &str, _out: &mut String) -> Result<(), Error> {
+    let args = split_args(args, 1, "set-default-sink")?;
+    let idx = find_sink(&interp.data, &args[0])?;
+    let name = PaString::new(interp.data.sinks().get(idx).unwrap().name().to_bytes()).unwrap();
+    interp.data.set_default_sink(Some(name));
+    Ok(())
+}
+
+fn set_default_source(interp: &mut Interpreter, args: &str, _out: &mut String) -> Result<(), Error> {
+    let args = split_args(args, 1, "set-default-source")?;
+    let idx = find_source(&interp.data, &args[0])?;
+    let name = PaString::new(interp.data.sources().get(idx).unwrap().name().to_bytes()).unwrap();
+    interp.data.set_default_source(Some(name));
+    Ok(())
+}
+
+fn set_sink_volume(interp: &mut Interpreter, args: &str, _out: &mut String) -> Result<(), Error> {
+    let args = split_quoted(args)?;
+    if args.len() < 2 {
+        return Err(usage("set-sink-volume"));
+    }
+    let idx = find_sink(&interp.data, &args[0])?;
+    let volumes = args[1..].iter()
+        .map(|arg| Volume::parse(arg).ok_or_else(|| Error::string(format!("invalid volume: {}", arg))))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    {
+        let mut sinks = interp.data.sinks_mut();
+        let sink = sinks.get_mut(idx).unwrap();
+        if volumes.len() == 1 {
+            sink.set_volume(volumes[0]);
+        } else if volumes.len() == sink.cvolume().len() as usize {
+            let mut cvolume = CVolume::new();
+            for &volume in &volumes {
+                cvolume.push(volume).unwrap();
+            }
+            sink.set_cvolume(cvolume);
+        } else {
+            return Err(Error::string(format!("expected 1 or {} volumes, got {}", sink.cvolume().len(), volumes.len())));
+        }
+    }
+    interp.data.post_event(Facility::Sink, EventType::Change, idx.value());
+    Ok(())
+}
+
+fn help(_interp: &mut Interpreter, _args: &str, out: &mut String) -> Result<(), Error> {
+    writeln!(out, "Available commands:").unwrap();
+    for def in COMMANDS {
+        let usage = format!("{} {}", def.name, def.args);
+        writeln!(out, "    {:<45} {}", usage.trim(), def.help).unwrap();
+    }
+    writeln!(out, "Meta commands: .include <path>, .ifexists <path>, .else, .endif, .fail, .nofail").unwrap();
+    Ok(())
+}
+
+fn list_modules(interp: &mut Interpreter, _args: &str, out: &mut String) -> Result<(), Error> {
+    let modules = interp.data.modules();
+    writeln!(out, "{} module(s) loaded.", modules.len()).unwrap();
+    for module in modules.iter() {
+        writeln!(out, "    index: {}", module.index()).unwrap();
+        writeln!(out, "\tname: <{}>", module.name()).unwrap();
+        writeln!(out, "\targument: <{}>", module.argument().map(|arg| arg.to_string()).unwrap_or_default()).unwrap();
+        writeln!(out, "\tproperties:").unwrap();
+        write_proplist(out, module.props());
+    }
+    Ok(())
+}
+
+fn list_sinks(interp: &mut Interpreter, _args: &str, out: &mut String) -> Result<(), Error> {
+    let default = interp.data.default_sink();
+    let sinks = interp.data.sinks();
+    writeln!(out, "{} sink(s) available.", sinks.len()).unwrap();
+    for sink in sinks.iter() {
+        let is_default = default.as_ref().map_or(false, |name| **name == *sink.name());
+        writeln!(out, "  {} index: {}", if is_default { '*' } else { ' ' }, sink.index()).unwrap();
+        writeln!(out, "\tname: <{}>", sink.name()).unwrap();
+        writeln!(out, "\tstate: {:?}", sink.state()).unwrap();
+        writeln!(out, "\tvolume: {}", format_cvolume(sink.cvolume(), sink.channel_map())).unwrap();
+        writeln!(out, "\tmuted: {}", if sink.muted() { "yes" } else { "no" }).unwrap();
+        writeln!(out, "\tsample spec: {}", sink.sample_spec()).unwrap();
+        writeln!(out, "\tchannel map: {}", sink.channel_map()).unwrap();
+        if let Some(module) = sink.owner_module() {
+            writeln!(out, "\tmodule: {}", module).unwrap();
+        }
+        writeln!(out, "\tproperties:").unwrap();
+        write_proplist(out, sink.props());
+    }
+    Ok(())
+}
+
+fn list_sources(interp: &mut Interpreter, _args: &str, out: &mut String) -> Result<(), Error> {
+    let default = interp.data.default_source();
+    let sources = interp.data.sources();
+    writeln!(out, "{} source(s) available.", sources.len()).unwrap();
+    for source in sources.iter() {
+        let is_default = default.as_ref().map_or(false, |name| **name == *source.name());
+        writeln!(out, "  {} index: {}", if is_default { '*' } else { ' ' }, source.index()).unwrap();
+        writeln!(out, "\tname: <{}>", source.name()).unwrap();
+        writeln!(out, "\tstate: {:?}", source.state()).unwrap();
+        writeln!(out, "\tvolume: {}", format_cvolume(source.cvolume(), source.channel_map())).unwrap();
+        writeln!(out, "\tmuted: {}", if source.muted() { "yes" } else { "no" }).unwrap();
+        writeln!(out, "\tsample spec: {}", source.sample_spec()).unwrap();
+        writeln!(out, "\tchannel map: {}", source.channel_map()).unwrap();
+        if let Some(module) = source.owner_module() {
+            writeln!(out, "\tmodule: {}", module).unwrap();
+        }
+        writeln!(out, "\tproperties:").unwrap();
+        write_proplist(out, source.props());
+    }
+    Ok(())
+}
+
+fn list_clients(interp: &mut Interpreter, _args: &str, out: &mut String) -> Result<(), Error> {
+    let clients = interp.data.clients();
+    writeln!(out, "{} client(s) connected.", clients.len()).unwrap();
+    for client in clients.iter() {
+        writeln!(out, "    index: {}", client.id).unwrap();
+        writeln!(out, "\tprotocol version: {}", client.protocol_version).unwrap();
+        writeln!(out, "\tauthenticated: {}", if client.authed { "yes" } else { "no" }).unwrap();
+        writeln!(out, "\tproperties:").unwrap();
+        write_proplist(out, &client.props);
+    }
+    Ok(())
+}
+
+fn list_sink_inputs(interp: &mut Interpreter, _args: &str, out: &mut String) -> Result<(), Error> {
+    let sink_inputs = interp.data.sink_inputs();
+    writeln!(out, "{} sink input(s) available.", sink_inputs.len()).unwrap();
+    for input in sink_inputs.iter() {
+        let info = input.info();
+        writeln!(out, "    index: {}", info.index).unwrap();
+        writeln!(out, "\tstate: {}", if info.corked { "CORKED" } else { "RUNNING" }).unwrap();
+        writeln!(out, "\tsink: {}", info.sink).unwrap();
+        writeln!(out, "\tvolume: {}", format_cvolume(&info.cvolume, &info.channel_map)).unwrap();
+        writeln!(out, "\tmuted: {}", if info.muted { "yes" } else { "no" }).unwrap();
+        writeln!(out, "\tsample spec: {}", info.sample_spec).unwrap();
+        writeln!(out, "\tchannel map: {}", info.channel_map).unwrap();
+        if let Some(client) = info.client {
+            writeln!(out, "\tclient: {}", client).unwrap();
+        }
+        writeln!(out, "\tproperties:").unwrap();
+        write_proplist(out, &info.props);
+    }
+    Ok(())
+}
+
+fn stat(interp: &mut Interpreter, _args: &str, out: &mut String) -> Result<(), Error> {
+    let data = &interp.data;
+    writeln!(out, "Clients connected: {}", data.clients().len()).unwrap();
+    writeln!(out, "Modules loaded: {}", data.modules().len()).unwrap();
+    writeln!(out, "Sinks: {}", data.sinks().len()).unwrap();
+    writeln!(out, "Sources: {}", data.sources().len()).unwrap();
+    writeln!(out, "Sink inputs: {}", data.sink_inputs().len()).unwrap();
+    writeln!(out, "Source outputs: {}", data.source_outputs().len()).unwrap();
+    writeln!(out, "Default sample spec: {}", data.default_sample_spec()).unwrap();
+    writeln!(out, "Default channel map: {}", data.default_channel_map()).unwrap();
+    writeln!(out, "Default sink name: {}", data.default_sink().map(|n| n.to_string()).unwrap_or_default()).unwrap();
+    writeln!(out, "Default source name: {}", data.default_source().map(|n| n.to_string()).unwrap_or_default()).unwrap();
+    Ok(())
+}
+
+/// Writes a script that restores the current server state when executed on a fresh server.
+fn dump(interp: &mut Interpreter, _args: &str, out: &mut String) -> Result<(), Error> {
+    let data = &interp.data;
+    writeln!(out, "### Configuration dump generated by pulsar {}", env!("CARGO_PKG_VERSION")).unwrap();
+    writeln!(out).unwrap();
+
+    for module in data.modules().iter() {
+        match module.argument() {
+            Some(arg) => writeln!(out, "load-module {} {}", module.name(), arg).unwrap(),
+            None => writeln!(out, "load-module {}", module.name()).unwrap(),
+        }
+    }
+    writeln!(out).unwrap();
+
+    for sink in data.sinks().iter() {
+        let volumes = sink.cvolume().into_iter().map(Volume::as_u32).collect::<Vec<_>>();
+        write!(out, "set-sink-volume {}", quote(&sink.name().to_string())).unwrap();
+        if volumes.iter().all(|&volume| volume == volumes[0]) {
+            write!(out, " {}", volumes[0]).unwrap();
+        } else {
+            for volume in volumes {
+                write!(out, " {}", volume).unwrap();
+            }
+        }
+        writeln!(out).unwrap();
+    }
+    writeln!(out).unwrap();
+
+    if let Some(sink) = data.default_sink() {
+        writeln!(out, "set-default-sink {}", quote(&sink.to_string())).unwrap();
+    }
+    if let Some(source) = data.default_source() {
+        writeln!(out, "set-default-source {}", quote(&source.to_string())).unwrap();
+    }
+    writeln!(out).unwrap();
+    writeln!(out, "### EOF").unwrap();
+    Ok(())
+}
+
+/// Formats a channel volume like `front-left: 65536 / 100% / 0.0 dB, front-right: ...`.
+fn format_cvolume(cvolume: &CVolume, map: &ChannelMap) -> String {
+    let mut s = String::new();
+    for (i, (volume, pos)) in cvolume.into_iter().zip(map).enumerate() {
+        if i != 0 {
+            s.push_str(", ");
+        }
+        let percent = (volume.as_u32() as f64 * 100.0 / Volume::NORM.as_u32() as f64).round();
+        write!(s, "{}: {} / {}% / {}", pos.name(), volume.as_u32(), percent, volume).unwrap();
+    }
+    s
+}
+
+/// Writes the properties in `props` as indented `key = "value"` lines.
+fn write_proplist(out: &mut String, props: &PropList) {
+    for (key, value) in props {
+        match value.split_last() {
+            Some((&0, string)) if !string.contains(&0) && str::from_utf8(string).is_ok() => {
+                let string = str::from_utf8(string).unwrap();
+                writeln!(out, "\t\t{} = \"{}\"", key.as_str(), string).unwrap();
+            }
+            _ => writeln!(out, "\t\t{} = ({} bytes of binary data)", key.as_str(), value.len()).unwrap(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pa_proto::conf::DaemonConf;
+    use pa_proto::cookie::AuthCookie;
+
+    use std::{env, process};
+
+    fn server_data() -> Arc<ServerData> {
+        Arc::new(ServerData::new(AuthCookie::from_bytes(&[0; 256]).unwrap(), DaemonConf::default()))
+    }
+
+    fn sink_names(data: &ServerData) -> Vec<String> {
+        data.sinks().iter().map(|sink| sink.name().to_string()).collect()
+    }
+
+    fn sink_volumes(data: &ServerData) -> Vec<Vec<u32>> {
+        data.sinks().iter().map(|sink| sink.cvolume().into_iter().map(Volume::as_u32).collect()).collect()
+    }
+
+    /// Creates an empty directory for script files used by a test.
+    fn script_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("pulsar-script-{}-{}", name, process::id()));
+        fs::remove_dir_all(&dir).ok();
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn quoting() {
+        assert_eq!(split_quoted("  a 'b c'  \"d 'e'\" f\\ g h\"i\"j ").unwrap(), ["a", "b c", "d 'e'", "f g", "hij"]);
+        assert_eq!(split_quoted("'' \"\"").unwrap(), ["", ""]);
+        assert!(split_quoted("").unwrap().is_empty());
+        assert!(split_quoted("'a").is_err());
+        assert!(split_quoted("a\\").is_err());
+
+        for &arg in &["plain", "", "with space", "\"quoted\"", "back\\slash", "it's", "#comment", "tab\there"] {
+            assert_eq!(split_quoted(&quote(arg)).unwrap(), [arg]);
+        }
+        assert_eq!(quote("null"), "null");
+    }
+
+    #[test]
+    fn ifexists() {
+        let dir = script_dir("ifexists");
+        let script = "\
+.ifexists does-not-exist
+load-module module-null-sink sink_name=a
+.else
+load-module module-null-sink sink_name=b
+.ifexists .
+load-module module-null-sink sink_name=c
+.else
+load-module module-null-sink sink_name=d
+.endif
+.endif
+.ifexists .
+.ifexists does-not-exist
+.else
+load-module module-null-sink sink_name=e
+.endif
+.endif
+";
+        fs::write(dir.join("test.pa"), script).unwrap();
+        let data = server_data();
+        Interpreter::new(data.clone()).run_file(&dir.join("test.pa")).unwrap();
+        assert_eq!(sink_names(&data), ["b", "c", "e"]);
+
+        // An `.else` in an inactive block stays inactive
+        let data = server_data();
+        let script = ".ifexists /does-not-exist\n.ifexists /\n.else\nload-module module-null-sink\n.endif\n.endif\n";
+        Interpreter::new(data.clone()).run_str(script, "test").unwrap();
+        assert!(sink_names(&data).is_empty());
+
+        let mut interp = Interpreter::new(server_data());
+        assert!(interp.run_str(".else", "test").is_err());
+        assert!(interp.run_str(".endif", "test").is_err());
+        assert!(interp.run_str(".ifexists /", "test").is_err());
+        // The unterminated block doesn't affect later scripts
+        interp.run_str("load-module module-null-sink", "test").unwrap();
+        assert_eq!(sink_names(&interp.data), ["null"]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn include() {
+        let dir = script_dir("include");
+        fs::create_dir(dir.join("conf.d")).unwrap();
+        fs::write(dir.join("conf.d/20-second.pa"), "load-module module-null-sink sink_name=second\n").unwrap();
+        fs::write(dir.join("conf.d/10-first.pa"), "load-module module-null-sink sink_name=first\n").unwrap();
+        fs::write(dir.join("conf.d/30-ignored.txt"), "load-module module-null-sink sink_name=ignored\n").unwrap();
+        fs::write(dir.join("extra.pa"), "load-module module-null-sink sink_name=extra\n").unwrap();
+        // Relative paths are resolved against the including script's directory
+        fs::write(dir.join("main.pa"), ".include conf.d\n.include extra.pa\n").unwrap();
+        fs::write(dir.join("recursive.pa"), ".include recursive.pa\n").unwrap();
+
+        let data = server_data();
+        Interpreter::new(data.clone()).run_file(&dir.join("main.pa")).unwrap();
+        assert_eq!(sink_names(&data), ["first", "second", "extra"]);
+
+        let mut interp = Interpreter::new(server_data());
+        assert!(interp.run_file(&dir.join("recursive.pa")).is_err());
+        assert!(interp.run_str(".include", "test").is_err());
+        assert!(interp.run_str(&format!(".include {}", dir.join("missing.pa").display()), "test").is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn fail_and_nofail() {
+        let data = server_data();
+        let mut interp = Interpreter::new(data.clone());
+        let script = "\
+load-module module-null-sink sink_name=a
+load-module module-does-not-exist
+load-module module-null-sink sink_name=b
+";
+        let err = interp.run_str(script, "test.pa").unwrap_err();
+        assert!(err.to_string().starts_with("test.pa:2: "), "{}", err);
+        assert_eq!(sink_names(&data), ["a"]);
+
+        let script = "\
+.nofail
+load-module module-does-not-exist
+unknown-command
+load-module module-null-sink sink_name=b
+.fail
+set-sink-volume does-not-exist 0
+load-module module-null-sink sink_name=c
+";
+        assert!(interp.run_str(script, "test.pa").is_err());
+        assert_eq!(sink_names(&data), ["a", "b"]);
+    }
+
+    #[test]
+    fn set_sink_volume() {
+        let data = server_data();
+        let mut interp = Interpreter::new(data.clone());
+        let mut out = String::new();
+        interp.execute_line("load-module module-null-sink sink_name=\"Living room\" channels=2", &mut out).unwrap();
+
+        interp.execute_line("set-sink-volume 'Living room' 1000", &mut out).unwrap();
+        assert_eq!(sink_volumes(&data), [[1000, 1000]]);
+        interp.execute_line("set-sink-volume \"Living room\" 2000 3000", &mut out).unwrap();
+        assert_eq!(sink_volumes(&data), [[2000, 3000]]);
+
+        assert!(interp.execute_line("set-sink-volume \"Living room\" 1 2 3", &mut out).is_err());
+        assert!(interp.execute_line("set-sink-volume \"Living room\" loud", &mut out).is_err());
+        assert!(interp.execute_line("set-sink-volume \"Living room\"", &mut out).is_err());
+        assert!(interp.execute_line("set-sink-volume Living room 1000", &mut out).is_err());
+        assert_eq!(sink_volumes(&data), [[2000, 3000]]);
+    }
+
+    #[test]
+    fn dump_and_replay() {
+        let data = server_data();
+        let mut interp = Interpreter::new(data.clone());
+        let script = "\
+load-module module-null-sink sink_name=\"Living room\" channels=2
+load-module module-null-sink sink_name=mono channels=1
+load-module module-null-source source_name=mic
+set-sink-volume \"Living room\" 1000 2000
+set-sink-volume mono 3000
+set-default-sink \"Living room\"
+set-default-source mic
+";
+        interp.run_str(script, "test").unwrap();
+
+        let mut dumped = String::new();
+        interp.execute_line("dump", &mut dumped).unwrap();
+        assert!(dumped.contains("set-sink-volume \"Living room\" 1000 2000\n"), "{}", dumped);
+        assert!(dumped.contains("set-sink-volume mono 3000\n"), "{}", dumped);
+
+        let replayed = server_data();
+        Interpreter::new(replayed.clone()).run_str(&dumped, "dump").unwrap();
+        assert_eq!(sink_names(&replayed), sink_names(&data));
+        assert_eq!(sink_volumes(&replayed), [vec![1000, 2000], vec![3000]]);
+        assert_eq!(replayed.default_sink().map(|n| n.to_string()), Some("Living room".to_string()));
+        assert_eq!(replayed.default_source().map(|n| n.to_string()), Some("mic".to_string()));
+
+        // Dumping the replayed state gives the same script
+        let mut redumped = String::new();
+        Interpreter::new(replayed).execute_line("dump", &mut redumped).unwrap();
+        assert_eq!(redumped, dumped);
+    }
+}
@@ -0,0 +1,97 @@
+//! Stopping a running server.
+//!
+//! Every connection task runs until the server is shut down (see `Shutdown::guard`), so that
+//! `Server::listen` can disconnect all clients and wait for them to go away before returning.
+
+use futures::future::Shared;
+use futures::sync::{mpsc, oneshot};
+use tokio::prelude::*;
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// Shutdown state shared by the server and its connections.
+#[derive(Debug)]
+pub(crate) struct Shutdown {
+    /// Fires the shutdown. `None` once the server is shutting down.
+    trigger: Mutex<Option<oneshot::Sender<()>>>,
+    /// Resolves (with an error) once `trigger` is gone.
+    signal: Shared<oneshot::Receiver<()>>,
+    /// Cloned into every connection task. `None` once the server is shutting down.
+    tasks: Mutex<Option<mpsc::Sender<()>>>,
+    /// Ends once all clones of the `tasks` sender are dropped.
+    tasks_done: Mutex<Option<mpsc::Receiver<()>>>,
+    /// Number of open connections.
+    connections: Arc<AtomicUsize>,
+}
+
+impl Shutdown {
+    pub fn new() -> Self {
+        let (trigger, signal) = oneshot::channel();
+        let (tasks, tasks_done) = mpsc::channel(0);
+        Self {
+            trigger: Mutex::new(Some(trigger)),
+            signal: signal.shared(),
+            tasks: Mutex::new(Some(tasks)),
+            tasks_done: Mutex::new(Some(tasks_done)),
+            connections: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// Starts shutting down the server. Does nothing if it's already shutting down.
+    pub fn trigger(&self) {
+        if self.trigger.lock().unwrap().take().is_some() {
+            info!("shutting down");
+        }
+    }
+
+    /// Returns a future that resolves once the shutdown was triggered.
+    pub fn triggered(&self) -> impl Future<Item=(), Error=()> + Send {
+        self.signal.clone().then(|_| Ok(()))
+    }
+
+    /// Wraps the task handling a connection, so that it is stopped when the server shuts down.
+    ///
+    /// Returns `None` if the server is already shutting down, in which case the connection should
+    /// be dropped.
+    pub fn guard<F>(&self, task: F) -> Option<impl Future<Item=(), Error=()> + Send>
+    where F: Future<Item=(), Error=()> + Send {
+        let token = ConnectionToken {
+            _tasks: self.tasks.lock().unwrap().clone()?,
+            connections: self.connections.clone(),
+        };
+        token.connections.fetch_add(1, Ordering::SeqCst);
+
+        Some(task.select(self.triggered()).then(move |_| {
+            drop(token);
+            Ok(())
+        }))
+    }
+
+    /// Returns the number of open connections.
+    pub fn connections(&self) -> usize {
+        self.connections.load(Ordering::SeqCst)
+    }
+
+    /// Returns a future that resolves once all connection tasks have finished.
+    ///
+    /// Must only be called once the shutdown was triggered, and only once.
+    pub fn finished(&self) -> impl Future<Item=(), Error=()> + Send {
+        self.tasks.lock().unwrap().take();
+        let tasks_done = self.tasks_done.lock().unwrap().take()
+            .expect("`Shutdown::finished` called twice");
+        tasks_done.for_each(|()| Ok(()))
+    }
+}
+
+/// Held by a connection task while it's running.
+struct ConnectionToken {
+    _tasks: mpsc::Sender<()>,
+    connections: Arc<AtomicUsize>,
+}
+
+impl Drop for ConnectionToken {
+    fn drop(&mut self) {
+        self.connections.fetch_sub(1, Ordering::SeqCst);
+    }
+}
@@ -0,0 +1,273 @@
+//! Playback and record streams created by clients.
+//!
+//! A playback stream is registered with the server as a sink input, a record stream as a source
+//! output. Audio isn't routed yet: data sent to a playback stream is dropped (as if its sink was a
+//! null sink), and record streams never receive any.
+
+use pa_proto::command::{CreatePlaybackStream, CreatePlaybackStreamReply, CreateRecordStream};
+use pa_proto::command::{CreateRecordStreamReply, SinkInputInfo, SourceOutputInfo};
+use pa_proto::proplist::{Prop, PropList};
+use pa_proto::sample_spec::SampleSpec;
+use pa_proto::channel_map::ChannelMap;
+use pa_proto::sink::{Sink, SinkState};
+use pa_proto::source::{Source, SourceState};
+use pa_proto::stream::{BufferAttr, StreamFlags};
+use pa_proto::string::PaString;
+use pa_proto::time::Microseconds;
+use pa_proto::{CVolume, FormatEncoding, FormatInfo, Volume};
+
+use std::u32;
+
+/// Upper limit for the length of stream buffers, in bytes.
+const MAX_BUFFER_LENGTH: u32 = 4 * 1024 * 1024;
+
+/// Default target length of playback buffers and size of record fragments.
+const DEFAULT_BUFFER_USEC: u64 = 2_000_000;
+
+/// Default minimum amount of data requested from playback streams at once.
+const DEFAULT_MINREQ_USEC: u64 = 20_000;
+
+/// A playback stream connected to a sink.
+#[derive(Debug)]
+pub(crate) struct SinkInput {
+    index: u32,
+    /// Index of the client owning the stream.
+    client: u32,
+    /// Channel the client uses to refer to the stream.
+    channel: u32,
+    /// Index of the sink the stream is connected to.
+    sink: u32,
+    sample_spec: SampleSpec,
+    channel_map: ChannelMap,
+    cvolume: CVolume,
+    muted: bool,
+    corked: bool,
+    buffer_attr: BufferAttr,
+    props: PropList,
+}
+
+impl SinkInput {
+    /// Creates the sink input for a playback stream requested by `params`.
+    pub fn new(index: u32, client: u32, channel: u32, sink: &Sink, params: &CreatePlaybackStream) -> Self {
+        let sample_spec = params.sample_spec().clone();
+        Self {
+            index,
+            client,
+            channel,
+            sink: sink.index(),
+            cvolume: params.volume().cloned().unwrap_or_else(|| norm_volume(&sample_spec)),
+            muted: params.muted() == Some(true),
+            corked: params.stream_flags().contains(StreamFlags::START_CORKED),
+            buffer_attr: playback_buffer_attr(params.buffer_attr(), &sample_spec),
+            channel_map: params.channel_map().clone(),
+            sample_spec,
+            props: params.stream_props().clone(),
+        }
+    }
+
+    pub fn index(&self) -> u32 { self.index }
+
+    pub fn client(&self) -> u32 { self.client }
+
+    pub fn channel(&self) -> u32 { self.channel }
+
+    pub fn sink(&self) -> u32 { self.sink }
+
+    pub fn corked(&self) -> bool { self.corked }
+
+    pub fn set_corked(&mut self, corked: bool) {
+        self.corked = corked;
+    }
+
+    /// Creates the reply to the `CREATE_PLAYBACK_STREAM` command that created the stream.
+    pub fn reply(&self, sink: &Sink) -> CreatePlaybackStreamReply {
+        CreatePlaybackStreamReply {
+            stream_index: self.channel,
+            sink_input_index: self.index,
+            // Nothing is buffered yet
+            missing: self.buffer_attr.tlength,
+            buffer_metrics: self.buffer_attr.clone(),
+            sample_spec: self.sample_spec.clone(),
+            channel_map: self.channel_map.clone(),
+            sink_index: sink.index(),
+            sink_name: sink.name().to_owned(),
+            sink_suspended: sink.state() == SinkState::Suspended,
+            sink_latency: sink.actual_latency(),
+            format: Some(FormatInfo::new(FormatEncoding::Pcm)),
+        }
+    }
+
+    /// Gets the information sent to clients that query the sink input list.
+    pub fn info(&self) -> SinkInputInfo {
+        SinkInputInfo {
+            index: self.index,
+            name: stream_name(&self.props),
+            owner_module: None,
+            client: Some(self.client),
+            sink: self.sink,
+            sample_spec: self.sample_spec.clone(),
+            channel_map: self.channel_map.clone(),
+            cvolume: self.cvolume.clone(),
+            buffer_usec: Microseconds(0),
+            sink_usec: Microseconds(0),
+            resample_method: None,
+            driver: None,
+            muted: self.muted,
+            props: self.props.clone(),
+            corked: self.corked,
+            has_volume: true,
+            volume_writable: true,
+            format: Some(FormatInfo::new(FormatEncoding::Pcm)),
+        }
+    }
+}
+
+/// A record stream connected to a source.
+#[derive(Debug)]
+pub(crate) struct SourceOutput {
+    index: u32,
+    /// Index of the client owning the stream.
+    client: u32,
+    /// Channel the client uses to refer to the stream.
+    channel: u32,
+    /// Index of the source the stream is connected to.
+    source: u32,
+    sample_spec: SampleSpec,
+    channel_map: ChannelMap,
+    cvolume: CVolume,
+    muted: bool,
+    corked: bool,
+    buffer_attr: BufferAttr,
+    props: PropList,
+}
+
+impl SourceOutput {
+    /// Creates the source output for a record stream requested by `params`.
+    pub fn new(index: u32, client: u32, channel: u32, source: &Source, params: &CreateRecordStream) -> Self {
+        let sample_spec = params.sample_spec().clone();
+        Self {
+            index,
+            client,
+            channel,
+            source: source.index(),
+            cvolume: params.volume().cloned().unwrap_or_else(|| norm_volume(&sample_spec)),
+            muted: params.muted() == Some(true),
+            corked: params.stream_flags().contains(StreamFlags::START_CORKED),
+            buffer_attr: record_buffer_attr(params.buffer_attr(), &sample_spec),
+            channel_map: params.channel_map().clone(),
+            sample_spec,
+            props: params.stream_props().clone(),
+        }
+    }
+
+    pub fn index(&self) -> u32 { self.index }
+
+    pub fn client(&self) -> u32 { self.client }
+
+    pub fn channel(&self) -> u32 { self.channel }
+
+    pub fn source(&self) -> u32 { self.source }
+
+    pub fn corked(&self) -> bool { self.corked }
+
+    pub fn set_corked(&mut self, corked: bool) {
+        self.corked = corked;
+    }
+
+    /// Creates the reply to the `CREATE_RECORD_STREAM` command that created the stream.
+    pub fn reply(&self, source: &Source) -> CreateRecordStreamReply {
+        CreateRecordStreamReply {
+            channel: self.channel,
+            source_output_index: self.index,
+            buffer_metrics: self.buffer_attr.clone(),
+            sample_spec: self.sample_spec.clone(),
+            channel_map: self.channel_map.clone(),
+            source_index: source.index(),
+            source_name: source.name().to_owned(),
+            source_suspended: source.state() == SourceState::Suspended,
+            source_latency: Microseconds(0),
+            format: Some(FormatInfo::new(FormatEncoding::Pcm)),
+        }
+    }
+
+    /// Gets the information sent to clients that query the source output list.
+    pub fn info(&self) -> SourceOutputInfo {
+        SourceOutputInfo {
+            index: self.index,
+            name: stream_name(&self.props),
+            owner_module: None,
+            client: Some(self.client),
+            source: self.source,
+            sample_spec: self.sample_spec.clone(),
+            channel_map: self.channel_map.clone(),
+            buffer_usec: Microseconds(0),
+            source_usec: Microseconds(0),
+            resample_method: None,
+            driver: None,
+            props: self.props.clone(),
+            corked: self.corked,
+            cvolume: self.cvolume.clone(),
+            muted: self.muted,
+            has_volume: true,
+            volume_writable: true,
+            format: Some(FormatInfo::new(FormatEncoding::Pcm)),
+        }
+    }
+}
+
+/// Fills in the buffer metrics a playback stream left to the server, and clamps the others to
+/// sane values.
+fn playback_buffer_attr(requested: &BufferAttr, spec: &SampleSpec) -> BufferAttr {
+    let frame_size = spec.frame_size() as u32;
+    let mut attr = requested.clone();
+    attr.maxlength = attr.maxlength.min(MAX_BUFFER_LENGTH);
+    if attr.tlength == u32::MAX {
+        attr.tlength = usec_to_bytes(DEFAULT_BUFFER_USEC, spec);
+    }
+    attr.tlength = round_to_frames(attr.tlength.min(attr.maxlength), frame_size);
+    if attr.minreq == u32::MAX {
+        attr.minreq = usec_to_bytes(DEFAULT_MINREQ_USEC, spec);
+    }
+    attr.minreq = round_to_frames(attr.minreq.min(attr.tlength), frame_size);
+    if attr.prebuf == u32::MAX || attr.prebuf > attr.tlength {
+        attr.prebuf = attr.tlength;
+    }
+    attr
+}
+
+/// Fills in the buffer metrics a record stream left to the server, and clamps the others to sane
+/// values.
+fn record_buffer_attr(requested: &BufferAttr, spec: &SampleSpec) -> BufferAttr {
+    let frame_size = spec.frame_size() as u32;
+    let mut attr = requested.clone();
+    attr.maxlength = attr.maxlength.min(MAX_BUFFER_LENGTH);
+    if attr.fragsize == u32::MAX {
+        attr.fragsize = usec_to_bytes(DEFAULT_BUFFER_USEC, spec);
+    }
+    attr.fragsize = round_to_frames(attr.fragsize.min(attr.maxlength), frame_size);
+    attr
+}
+
+fn usec_to_bytes(usec: u64, spec: &SampleSpec) -> u32 {
+    let frames = usec * u64::from(spec.sample_rate()) / 1_000_000;
+    (frames * spec.frame_size() as u64).min(u64::from(u32::MAX)) as u32
+}
+
+/// Rounds `bytes` down to whole frames, but to at least one frame.
+fn round_to_frames(bytes: u32, frame_size: u32) -> u32 {
+    (bytes - bytes % frame_size).max(frame_size)
+}
+
+pub(crate) fn norm_volume(spec: &SampleSpec) -> CVolume {
+    let mut cvolume = CVolume::new();
+    for _ in 0..spec.channels() {
+        cvolume.push(Volume::NORM).unwrap();
+    }
+    cvolume
+}
+
+fn stream_name(props: &PropList) -> PaString {
+    props.get_string(Prop::MediaName)
+        .map(|name| name.to_owned())
+        .unwrap_or_else(|| PaString::new("").unwrap())
+}
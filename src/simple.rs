@@ -0,0 +1,227 @@
+//! A blocking API for playing back and recording audio.
+//!
+//! This is the equivalent of PulseAudio's `pa_simple` API: `Playback` and `Record` each open a
+//! connection to the user's server with a single stream. Audio data is written to a `Playback`
+//! stream via `std::io::Write` and read from a `Record` stream via `std::io::Read`, blocking until
+//! the server is ready.
+//!
+//! The connection is driven by a tokio runtime owned by the stream, so these types must not be
+//! used from within another runtime's tasks.
+
+use client::{Client, PlaybackStream, RecordStream};
+
+use pa_proto::channel_map::ChannelMap;
+use pa_proto::error::Error;
+use pa_proto::proplist::{Prop, PropList};
+use pa_proto::sample_spec::SampleSpec;
+use pa_proto::stream::{BufferAttr, StreamBuilder, StreamFlags};
+use pa_proto::string::PaString;
+use pa_proto::time::Microseconds;
+
+use futures::future;
+use tokio::prelude::*;
+use tokio::runtime::Runtime;
+
+use std::collections::VecDeque;
+use std::ffi::CString;
+use std::{cmp, io};
+
+/// A blocking playback stream.
+///
+/// Audio data is written using the `std::io::Write` implementation. Writes block until the server
+/// requests more data, and write at most as much as was requested.
+#[derive(Debug)]
+pub struct Playback {
+    stream: PlaybackStream,
+    /// Drives the connection. Dropped last.
+    _runtime: Runtime,
+}
+
+impl Playback {
+    /// Connects to the user's server and creates a playback stream.
+    ///
+    /// # Parameters
+    ///
+    /// * `app_name`: Name of the application, shown by mixer applications.
+    /// * `stream_name`: Description of the stream (eg. the title of the song being played).
+    /// * `sample_spec`: Format of the audio data.
+    /// * `channel_map`: Meaning of the channels. If `None`, the default mapping for the number of
+    ///   channels in `sample_spec` is used.
+    /// * `buffer_attr`: Buffer metrics to request. If `None`, the server picks sensible defaults.
+    /// * `device`: Name of the sink to play on. If `None`, the default sink is used.
+    pub fn new(
+        app_name: &str,
+        stream_name: &str,
+        sample_spec: SampleSpec,
+        channel_map: Option<ChannelMap>,
+        buffer_attr: Option<BufferAttr>,
+        device: Option<&str>,
+    ) -> Result<Self, Error> {
+        let builder = stream_builder(stream_name, sample_spec, channel_map, buffer_attr);
+        let device = device_name(device)?;
+        let (mut runtime, client) = connect(app_name)?;
+
+        let stream = runtime.block_on(client.connect_playback(&builder, device.as_ref().map(|name| &**name)))?;
+        info!("created playback stream {} on sink {}", stream.channel(), stream.sink_index());
+
+        Ok(Self {
+            stream,
+            _runtime: runtime,
+        })
+    }
+
+    /// Blocks until all data written so far has been played.
+    pub fn drain(&mut self) -> Result<(), Error> {
+        self.stream.drain().wait()
+    }
+
+    /// Discards all data written to the stream that has not been played yet.
+    ///
+    /// Note that this is unrelated to `std::io::Write::flush`, which does nothing since written
+    /// data is sent to the server right away.
+    pub fn flush(&mut self) -> Result<(), Error> {
+        self.stream.flush().wait()
+    }
+
+    /// Returns the time until data written now will be heard.
+    pub fn latency(&mut self) -> Result<Microseconds, Error> {
+        self.stream.update_timing().wait()?;
+        Ok(self.stream.latency().expect("no timing info after update"))
+    }
+}
+
+impl io::Write for Playback {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        let stream = &mut self.stream;
+        let requested = future::poll_fn(|| stream.poll_writable()).wait().map_err(to_io_error)?;
+        let len = cmp::min(buf.len(), requested);
+        stream.write(&buf[..len]).map_err(to_io_error)?;
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// A blocking record stream.
+///
+/// Audio data is read using the `std::io::Read` implementation. Reads block until the server sends
+/// data.
+#[derive(Debug)]
+pub struct Record {
+    stream: RecordStream,
+    /// Data received from the server that hasn't been read yet.
+    buffer: VecDeque<u8>,
+    /// Drives the connection. Dropped last.
+    _runtime: Runtime,
+}
+
+impl Record {
+    /// Connects to the user's server and creates a record stream.
+    ///
+    /// The parameters are the same as for `Playback::new`, except that `device` names a source.
+    pub fn new(
+        app_name: &str,
+        stream_name: &str,
+        sample_spec: SampleSpec,
+        channel_map: Option<ChannelMap>,
+        buffer_attr: Option<BufferAttr>,
+        device: Option<&str>,
+    ) -> Result<Self, Error> {
+        let builder = stream_builder(stream_name, sample_spec, channel_map, buffer_attr);
+        let device = device_name(device)?;
+        let (mut runtime, client) = connect(app_name)?;
+
+        let stream = runtime.block_on(client.connect_record(&builder, device.as_ref().map(|name| &**name)))?;
+        info!("created record stream {} on source {}", stream.channel(), stream.source_index());
+
+        Ok(Self {
+            stream,
+            buffer: VecDeque::new(),
+            _runtime: runtime,
+        })
+    }
+
+    /// Discards all recorded data that has not been read yet.
+    pub fn flush(&mut self) -> Result<(), Error> {
+        self.stream.flush().wait()?;
+        self.buffer.clear();
+        Ok(())
+    }
+
+    /// Returns the time since the data that would be read next was recorded.
+    pub fn latency(&mut self) -> Result<Microseconds, Error> {
+        self.stream.update_timing().wait()?;
+        let latency = self.stream.latency().expect("no timing info after update");
+        let buffered = self.stream.sample_spec().bytes_to_usec(self.buffer.len() as u64);
+        Ok(Microseconds(latency.0 + buffered.0))
+    }
+}
+
+impl io::Read for Record {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        while self.buffer.is_empty() {
+            let stream = &mut self.stream;
+            match future::poll_fn(|| stream.poll()).wait().map_err(to_io_error)? {
+                Some(data) => self.buffer.extend(data),
+                None => return Ok(0),
+            }
+        }
+
+        let len = cmp::min(buf.len(), self.buffer.len());
+        for (dest, byte) in buf.iter_mut().zip(self.buffer.drain(..len)) {
+            *dest = byte;
+        }
+        Ok(len)
+    }
+}
+
+/// Starts a runtime and connects to the user's server.
+fn connect(app_name: &str) -> Result<(Runtime, Client), Error> {
+    let mut props = PropList::new();
+    props.set(Prop::ApplicationName, format!("{}\0", app_name));
+
+    let mut runtime = Runtime::new()?;
+    let client = runtime.block_on(Client::connect_default(props))?;
+    Ok((runtime, client))
+}
+
+fn stream_builder(
+    stream_name: &str,
+    sample_spec: SampleSpec,
+    channel_map: Option<ChannelMap>,
+    buffer_attr: Option<BufferAttr>,
+) -> StreamBuilder {
+    let mut props = PropList::new();
+    props.set(Prop::MediaName, format!("{}\0", stream_name));
+
+    let mut builder = StreamBuilder::new(sample_spec);
+    builder.add_flags(StreamFlags::ADJUST_LATENCY).props(props);
+    if let Some(channel_map) = channel_map {
+        builder.channel_map(channel_map);
+    }
+    if let Some(buffer_attr) = buffer_attr {
+        builder.buffer_attr(buffer_attr);
+    }
+    builder
+}
+
+fn device_name(device: Option<&str>) -> Result<Option<PaString>, Error> {
+    match device {
+        Some(name) => Ok(Some(PaString::from(CString::new(name)?))),
+        None => Ok(None),
+    }
+}
+
+fn to_io_error(e: Error) -> io::Error {
+    io::Error::new(io::ErrorKind::BrokenPipe, e.to_string())
+}
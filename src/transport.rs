@@ -1,10 +1,15 @@
-use std::net::{SocketAddr, TcpStream};
+use pa_proto::error::Error;
+use pa_proto::server_string::{IpFamily, ServerAddress};
+
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
 use std::os::unix::net::UnixStream;
 use std::path::PathBuf;
 use std::fmt::Debug;
 use std::io::prelude::*;
 use std::io;
 
+pub use pa_proto::server_string::DEFAULT_PORT;
+
 /// A bidirectional data stream.
 pub trait Stream: Read + Write + Debug {}
 
@@ -17,6 +22,34 @@ pub enum Transport {
 }
 
 impl Transport {
+    /// Resolves a server address to the transports it can be reached with.
+    ///
+    /// Host names are resolved using the system resolver, which may block.
+    pub fn resolve(address: &ServerAddress) -> Result<Vec<Self>, Error> {
+        match *address {
+            ServerAddress::Unix(ref path) => Ok(vec![Transport::Unix(path.clone())]),
+            ServerAddress::Tcp { ref host, port, family } => {
+                let addrs = (&**host, port).to_socket_addrs()?
+                    .filter(|addr| match family {
+                        IpFamily::Any => true,
+                        IpFamily::V4 => addr.is_ipv4(),
+                        IpFamily::V6 => addr.is_ipv6(),
+                    })
+                    .map(Transport::Network)
+                    .collect::<Vec<_>>();
+                if addrs.is_empty() {
+                    let kind = match family {
+                        IpFamily::Any => "",
+                        IpFamily::V4 => "IPv4 ",
+                        IpFamily::V6 => "IPv6 ",
+                    };
+                    return Err(Error::string(format!("no {}address found for '{}'", kind, host)));
+                }
+                Ok(addrs)
+            }
+        }
+    }
+
     pub fn open(self) -> io::Result<Box<Stream>> {
         Ok(match self {
             Transport::Network(addr) => Box::new(TcpStream::connect(addr)?) as Box<Stream>,
@@ -0,0 +1,457 @@
+//! Packet transport over Unix sockets that can pass file descriptors.
+//!
+//! `tokio_codec::Framed` only sees the byte stream, so it can't send or receive the `SCM_RIGHTS`
+//! ancillary data used to share memfd segments. `UnixFramed` does the equivalent of
+//! `PacketCodec::new().framed(stream)` with `sendmsg`/`recvmsg`, attaching received descriptors to
+//! the packet they were sent with.
+//!
+//! It also takes care of moving packets to an srbchannel once the client handler has set one up
+//! (see `SrbControl`).
+
+use pa_proto::packet::{check_payload_length, Packet, PacketCodec};
+use pa_proto::shm::Fd;
+use pa_proto::srbchannel::SrbChannel;
+use pa_proto::error::Error;
+
+use byteorder::{BigEndian, ByteOrder};
+use bytes::{BufMut, BytesMut};
+use mio::Ready;
+use mio_uds;
+use nix::errno::Errno;
+use nix::libc;
+use nix::sys::socket::{recvmsg, sendmsg, CmsgSpace, ControlMessage, MsgFlags};
+use nix::sys::uio::IoVec;
+use nix;
+use futures::{task, StartSend};
+use mio::{Evented, PollOpt, Token};
+use mio::unix::EventedFd;
+use tokio::prelude::*;
+use tokio::reactor::PollEvented2;
+use tokio_codec::{Decoder, Encoder};
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::io;
+
+/// Maximum number of file descriptors received with a single packet (same as PulseAudio).
+const MAX_FDS: usize = 2;
+
+/// Size of a packet header.
+const DESCRIPTOR_SIZE: usize = 20;
+
+/// Maximum number of Bytes received at once, so that buffer space is only allocated as the
+/// payload actually arrives.
+const RECV_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Lets the client handler hand an srbchannel to the transport.
+#[derive(Debug, Default)]
+pub(crate) struct SrbControl {
+    /// Channel set up by the handler, not yet picked up by the transport.
+    offered: Mutex<Option<SrbChannel>>,
+    /// Whether the client acknowledged the channel, so we can send packets through it.
+    enabled: AtomicBool,
+}
+
+impl SrbControl {
+    /// Hands a channel announced to the client to the transport.
+    ///
+    /// The transport starts receiving packets from the channel right away, since the client may
+    /// switch over as soon as it has acknowledged it.
+    pub fn offer(&self, channel: SrbChannel) {
+        *self.offered.lock().unwrap() = Some(channel);
+    }
+
+    /// Sends all further packets (except those carrying file descriptors) through the channel.
+    ///
+    /// Packets queued before this is called are still sent over the socket.
+    pub fn enable(&self) {
+        self.enabled.store(true, Ordering::SeqCst);
+    }
+}
+
+/// The eventfd an srbchannel is signalled through, registered with the reactor.
+#[derive(Debug)]
+struct SemIo(Fd);
+
+impl Evented for SemIo {
+    fn register(&self, poll: &mio::Poll, token: Token, interest: Ready, opts: PollOpt) -> io::Result<()> {
+        EventedFd(&self.0.as_raw_fd()).register(poll, token, interest, opts)
+    }
+
+    fn reregister(&self, poll: &mio::Poll, token: Token, interest: Ready, opts: PollOpt) -> io::Result<()> {
+        EventedFd(&self.0.as_raw_fd()).reregister(poll, token, interest, opts)
+    }
+
+    fn deregister(&self, poll: &mio::Poll) -> io::Result<()> {
+        EventedFd(&self.0.as_raw_fd()).deregister(poll)
+    }
+}
+
+/// State of an active srbchannel.
+#[derive(Debug)]
+struct Srb {
+    // Declared before `channel` so that it's deregistered before the channel closes the eventfd
+    sem: PollEvented2<SemIo>,
+    channel: SrbChannel,
+    codec: PacketCodec,
+    /// Data received through the channel that doesn't form a complete packet yet.
+    rd: BytesMut,
+    /// Whether packets are sent through the channel.
+    writing: bool,
+}
+
+impl Srb {
+    fn new(channel: SrbChannel) -> io::Result<Self> {
+        let sem = channel.read_sem().try_clone()
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?;
+        Ok(Self {
+            sem: PollEvented2::new(SemIo(sem)),
+            channel,
+            codec: PacketCodec::new(),
+            rd: BytesMut::new(),
+            writing: false,
+        })
+    }
+
+    /// Reads available data from the channel, or waits for the client to signal us.
+    fn poll_read(&mut self) -> Poll<(), io::Error> {
+        let mut buf = [0; 4096];
+        loop {
+            let n = self.channel.read(&mut buf);
+            if n > 0 {
+                self.rd.extend_from_slice(&buf[..n]);
+                return Ok(Async::Ready(()));
+            }
+
+            try_ready!(self.poll_signal());
+        }
+    }
+
+    /// Waits until the client signals that it wrote data or made room for us to write.
+    ///
+    /// Both directions share one semaphore, so whoever consumes a signal has to make sure the
+    /// other direction is checked as well.
+    fn poll_signal(&mut self) -> Poll<(), io::Error> {
+        if !self.channel.before_poll() {
+            return Ok(Async::Ready(()));
+        }
+
+        try_ready!(self.sem.poll_read_ready(Ready::readable()));
+        self.channel.after_poll();
+        // `after_poll` drained the eventfd, the next signal will cause a new readiness event
+        self.sem.clear_read_ready(Ready::readable())?;
+        Ok(Async::Ready(()))
+    }
+}
+
+/// A `Stream` and `Sink` of packets exchanged over a Unix socket.
+#[derive(Debug)]
+pub(crate) struct UnixFramed {
+    io: PollEvented2<mio_uds::UnixStream>,
+    codec: PacketCodec,
+    /// Data of the packet currently being received.
+    rd: BytesMut,
+    /// File descriptors received with the packet currently being received.
+    rd_fds: Vec<Fd>,
+    /// Packets waiting to be sent, encoded, with the number of Bytes already written.
+    wr: VecDeque<(BytesMut, Vec<Fd>, usize)>,
+    srb_control: Arc<SrbControl>,
+    srb: Option<Srb>,
+}
+
+impl UnixFramed {
+    /// Wraps a connected Unix socket and registers it with the current reactor.
+    pub fn new(stream: UnixStream) -> io::Result<Self> {
+        stream.set_nonblocking(true)?;
+        Ok(Self {
+            io: PollEvented2::new(mio_uds::UnixStream::from_stream(stream)?),
+            codec: PacketCodec::new(),
+            rd: BytesMut::new(),
+            rd_fds: Vec::new(),
+            wr: VecDeque::new(),
+            srb_control: Arc::new(SrbControl::default()),
+            srb: None,
+        })
+    }
+
+    /// Returns the handle used to move the connection to an srbchannel.
+    pub fn srb_control(&self) -> Arc<SrbControl> {
+        self.srb_control.clone()
+    }
+
+    /// Picks up a channel offered by the client handler, and switches to sending packets through
+    /// it once that's possible.
+    fn update_srb(&mut self) -> io::Result<()> {
+        if self.srb.is_none() {
+            if let Some(channel) = self.srb_control.offered.lock().unwrap().take() {
+                self.srb = Some(Srb::new(channel)?);
+            }
+        }
+
+        if let Some(ref mut srb) = self.srb {
+            // Only switch when all previously queued packets are sent, to preserve their order
+            if !srb.writing && self.wr.is_empty() && self.srb_control.enabled.load(Ordering::SeqCst) {
+                debug!("switching to srbchannel");
+                srb.writing = true;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns the number of Bytes needed to complete the packet currently being received.
+    ///
+    /// We never read past the end of the current packet, so that all descriptors received belong
+    /// to it (the sender attaches them to the first Byte of the packet).
+    ///
+    /// Fails if the header announces a payload larger than `MAX_PAYLOAD_SIZE`.
+    fn missing(&self) -> Result<usize, Error> {
+        if self.rd.len() < DESCRIPTOR_SIZE {
+            Ok(DESCRIPTOR_SIZE - self.rd.len())
+        } else {
+            let length = check_payload_length(BigEndian::read_u32(&self.rd[..4]))?;
+            Ok(DESCRIPTOR_SIZE + length - self.rd.len())
+        }
+    }
+
+    /// Receives up to `len` Bytes (at most `RECV_CHUNK_SIZE`) and any attached file descriptors.
+    ///
+    /// Returns the number of Bytes received (0 on EOF).
+    fn recv(&mut self, len: usize) -> io::Result<usize> {
+        let len = len.min(RECV_CHUNK_SIZE);
+        self.rd.reserve(len);
+        let mut cmsg: CmsgSpace<[RawFd; MAX_FDS]> = CmsgSpace::new();
+        let (n, fds) = {
+            // Receive directly into the spare capacity of `rd`. The Bytes are only marked as
+            // initialized below, once the kernel has written them.
+            let buf = unsafe { &mut self.rd.bytes_mut()[..len] };
+            let msg = recvmsg(
+                self.io.get_ref().as_raw_fd(),
+                &[IoVec::from_mut_slice(buf)],
+                Some(&mut cmsg),
+                MsgFlags::MSG_CMSG_CLOEXEC,
+            ).map_err(nix_to_io)?;
+
+            let mut fds = Vec::new();
+            for cmsg in msg.cmsgs() {
+                if let ControlMessage::ScmRights(received) = cmsg {
+                    fds.extend(received.iter().map(|&fd| unsafe { Fd::from_raw_fd(fd) }));
+                }
+            }
+            if msg.flags.contains(MsgFlags::MSG_CTRUNC) {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "too many file descriptors received"));
+            }
+            (msg.bytes, fds)
+        };
+
+        unsafe { self.rd.advance_mut(n) };
+        self.rd_fds.extend(fds);
+        Ok(n)
+    }
+
+    /// Tries to write the first queued packet.
+    ///
+    /// Returns `Ok(Async::Ready(()))` once it has been written completely.
+    fn poll_write_front(&mut self) -> Poll<(), io::Error> {
+        if let Some(ref mut srb) = self.srb {
+            if srb.writing && self.wr[0].1.is_empty() {
+                loop {
+                    let done = {
+                        let front = &mut self.wr[0];
+                        front.2 += srb.channel.write(&front.0[front.2..]);
+                        front.2 == front.0.len()
+                    };
+                    if done {
+                        self.wr.pop_front();
+                        return Ok(Async::Ready(()));
+                    }
+
+                    // The channel is full. If we consume a signal that was meant to wake up the
+                    // reading side, make sure it gets to check for data.
+                    try_ready!(srb.poll_signal());
+                    task::current().notify();
+                }
+            }
+        }
+
+        loop {
+            try_ready!(self.io.poll_write_ready());
+
+            let result = {
+                let (ref data, ref fds, written) = self.wr[0];
+                let raw_fds = fds.iter().map(|fd| fd.as_raw_fd()).collect::<Vec<_>>();
+                // Descriptors are sent along with the first Byte of the packet
+                let cmsgs = if written == 0 && !raw_fds.is_empty() {
+                    vec![ControlMessage::ScmRights(&raw_fds)]
+                } else {
+                    Vec::new()
+                };
+
+                sendmsg(
+                    self.io.get_ref().as_raw_fd(),
+                    &[IoVec::from_slice(&data[written..])],
+                    &cmsgs,
+                    // Don't raise `SIGPIPE` when the client is gone
+                    MsgFlags::from_bits_truncate(libc::MSG_NOSIGNAL),
+                    None,
+                ).map_err(nix_to_io)
+            };
+
+            match result {
+                Ok(n) => {
+                    let done = {
+                        let front = &mut self.wr[0];
+                        front.2 += n;
+                        front.2 == front.0.len()
+                    };
+                    if done {
+                        self.wr.pop_front();
+                        return Ok(Async::Ready(()));
+                    }
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    self.io.clear_write_ready()?;
+                    return Ok(Async::NotReady);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+impl Stream for UnixFramed {
+    type Item = Packet;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<Packet>, Error> {
+        self.update_srb()?;
+
+        loop {
+            if let Some(mut packet) = self.codec.decode(&mut self.rd)? {
+                for fd in self.rd_fds.drain(..) {
+                    packet.attach_fd(fd);
+                }
+                return Ok(Async::Ready(Some(packet)));
+            }
+
+            if let Some(ref mut srb) = self.srb {
+                if let Some(packet) = srb.codec.decode(&mut srb.rd)? {
+                    return Ok(Async::Ready(Some(packet)));
+                }
+
+                if srb.poll_read()?.is_ready() {
+                    continue;
+                }
+            }
+
+            // Packets carrying file descriptors still arrive through the socket
+            try_ready!(self.io.poll_read_ready(Ready::readable()));
+
+            let missing = self.missing()?;
+            match self.recv(missing) {
+                Ok(0) => {
+                    if self.rd.is_empty() {
+                        return Ok(Async::Ready(None));
+                    } else {
+                        return Err(Error::string("connection closed in the middle of a packet"));
+                    }
+                }
+                Ok(_) => {}
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    self.io.clear_read_ready(Ready::readable())?;
+                    return Ok(Async::NotReady);
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+}
+
+impl Sink for UnixFramed {
+    type SinkItem = Packet;
+    type SinkError = Error;
+
+    fn start_send(&mut self, mut item: Packet) -> StartSend<Packet, Error> {
+        self.update_srb()?;
+
+        let fds = item.take_fds();
+        let mut data = BytesMut::new();
+        self.codec.encode(item, &mut data)?;
+        self.wr.push_back((data, fds, 0));
+        Ok(AsyncSink::Ready)
+    }
+
+    fn poll_complete(&mut self) -> Poll<(), Error> {
+        while !self.wr.is_empty() {
+            try_ready!(self.poll_write_front());
+        }
+        Ok(Async::Ready(()))
+    }
+
+    fn close(&mut self) -> Poll<(), Error> {
+        self.poll_complete()
+    }
+}
+
+fn nix_to_io(e: nix::Error) -> io::Error {
+    match e {
+        nix::Error::Sys(Errno::EAGAIN) => io::ErrorKind::WouldBlock.into(),
+        nix::Error::Sys(errno) => io::Error::from_raw_os_error(errno as i32),
+        e => io::Error::new(io::ErrorKind::Other, e),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pa_proto::packet::{MemblockData, Message, SeekMode};
+    use tokio::runtime::current_thread::Runtime;
+
+    /// Returns the data of a memblock packet.
+    fn memblock_data(packet: &Packet) -> &[u8] {
+        match Message::from_packet(packet).unwrap() {
+            Message::Memblock { data: MemblockData::Inline(data), .. } => data,
+            message => panic!("unexpected message {:?}", message),
+        }
+    }
+
+    /// Receives packets sent as `data` through a `UnixFramed`.
+    fn receive(data: &[u8]) -> Result<Vec<Packet>, Error> {
+        let (server, mut client) = UnixStream::pair().unwrap();
+        client.write_all(data).unwrap();
+        drop(client);
+
+        let mut runtime = Runtime::new().unwrap();
+        runtime.block_on(future::lazy(|| UnixFramed::new(server).unwrap().collect()))
+    }
+
+    #[test]
+    fn large_packets() {
+        // Bigger than a single chunk
+        let payload = (0..200_000).map(|i| i as u8).collect::<Vec<_>>();
+        let mut data = BytesMut::new();
+        PacketCodec::new().encode(Packet::new_memblock(0, 0, SeekMode::Relative, &payload), &mut data).unwrap();
+        PacketCodec::new().encode(Packet::new_memblock(0, 0, SeekMode::Relative, b"next"), &mut data).unwrap();
+
+        let packets = receive(&data).unwrap();
+        assert_eq!(packets.len(), 2);
+        assert_eq!(memblock_data(&packets[0]), &payload[..]);
+        assert_eq!(memblock_data(&packets[1]), b"next");
+    }
+
+    #[test]
+    fn oversized_header() {
+        let mut header = [0; DESCRIPTOR_SIZE];
+        BigEndian::write_u32(&mut header[..4], u32::max_value());
+        BigEndian::write_i32(&mut header[4..8], -1);
+        assert!(receive(&header).is_err());
+
+        // Truncated packets are an error as well
+        BigEndian::write_u32(&mut header[..4], 16);
+        assert!(receive(&header).is_err());
+        assert_eq!(receive(&[]).unwrap().len(), 0);
+    }
+}
@@ -0,0 +1,127 @@
+//! Starts servers in-process and shuts them down via the `EXIT` command.
+
+extern crate futures;
+extern crate pa_proto;
+extern crate pulsar;
+extern crate tokio;
+
+use pa_proto::conf::DaemonConf;
+use pa_proto::error::{Error, PulseError};
+use pa_proto::proplist::{Prop, PropList};
+use pa_proto::runtime_dir::PID_FILE_NAME;
+use pulsar::client::Client;
+use pulsar::server::Server;
+
+use futures::future::Either;
+use tokio::prelude::*;
+use tokio::runtime::current_thread::Runtime;
+use tokio::timer::Delay;
+
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+use std::{env, fs, process};
+
+/// Creates a server using a fresh runtime directory called `name`.
+fn server(name: &str, conf: DaemonConf) -> (Server, PathBuf) {
+    let dir = env::temp_dir().join(format!("pulsar-{}-{}", name, process::id()));
+    fs::remove_dir_all(&dir).ok();
+    let server = Server::new_unix_with_conf(&dir, conf).unwrap();
+    (server, dir)
+}
+
+fn connect(dir: &PathBuf) -> impl Future<Item=Client, Error=Error> {
+    let mut props = PropList::new();
+    props.set(Prop::ApplicationName, "shutdown test\0");
+    Client::connect_unix(dir.join("native"), props)
+}
+
+/// Checks that the server removed its files and doesn't accept connections anymore.
+fn assert_stopped(runtime: &mut Runtime, dir: &PathBuf) {
+    assert!(!dir.join("native").exists());
+    assert!(!dir.join("cli").exists());
+    assert!(!dir.join(PID_FILE_NAME).exists());
+    assert!(runtime.block_on(connect(dir)).is_err());
+}
+
+#[test]
+fn exit() {
+    let (server, dir) = server("exit", DaemonConf::default());
+    let mut runtime = Runtime::new().unwrap();
+
+    // The reply to `EXIT` is sent before the connection is closed
+    let exit = connect(&dir).and_then(|client| client.exit_daemon().map(move |()| client));
+    let (client, ()) = runtime.block_on(exit.join(server.listen().from_err())).unwrap();
+
+    // All connections were closed before `listen` completed
+    assert!(runtime.block_on(client.exit_daemon()).is_err());
+    assert_stopped(&mut runtime, &dir);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn disallow_exit() {
+    let conf = DaemonConf {
+        disallow_exit: true,
+        ..DaemonConf::default()
+    };
+    let (server, dir) = server("disallow-exit", conf);
+    let handle = server.shutdown_handle();
+    let mut runtime = Runtime::new().unwrap();
+
+    let listen = server.listen().map_err(|e| panic!("server failed: {}", e));
+    let exit = connect(&dir).and_then(|client| client.exit_daemon().then(move |result| Ok((client, result))));
+    let (client, result, listen) = match runtime.block_on(exit.select2(listen)) {
+        Ok(Either::A(((client, result), listen))) => (client, result, listen),
+        Ok(Either::B(_)) => panic!("server stopped although exiting is disallowed"),
+        Err(Either::A((e, _))) => panic!("couldn't connect: {}", e),
+        Err(Either::B(_)) => unreachable!(),
+    };
+    assert_eq!(result.unwrap_err().code(), Some(PulseError::Access));
+
+    // The connection is still usable
+    let exit = client.exit_daemon().then(Ok::<_, ()>);
+    let (result, listen) = match runtime.block_on(exit.select2(listen)) {
+        Ok(Either::A((result, listen))) => (result, listen),
+        _ => panic!("server stopped although exiting is disallowed"),
+    };
+    assert_eq!(result.unwrap_err().code(), Some(PulseError::Access));
+
+    handle.shutdown();
+    runtime.block_on(listen).unwrap();
+    assert!(runtime.block_on(client.exit_daemon()).is_err());
+    assert_stopped(&mut runtime, &dir);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn exit_idle_time() {
+    let conf = DaemonConf {
+        exit_idle_time: Some(Duration::from_secs(1)),
+        ..DaemonConf::default()
+    };
+    let (server, dir) = server("exit-idle-time", conf);
+    let mut runtime = Runtime::new().unwrap();
+
+    // A connected client keeps the server running past the idle time
+    let listen = server.listen().map_err(|e| panic!("server failed: {}", e));
+    let wait = connect(&dir).and_then(|client| {
+        Delay::new(Instant::now() + Duration::from_secs(3))
+            .map(move |()| client)
+            .map_err(|e| Error::string(e.to_string()))
+    });
+    let (client, listen) = match runtime.block_on(wait.select2(listen)) {
+        Ok(Either::A((client, listen))) => (client, listen),
+        Ok(Either::B(_)) => panic!("server exited while a client was connected"),
+        Err(Either::A((e, _))) => panic!("couldn't connect: {}", e),
+        Err(Either::B(_)) => unreachable!(),
+    };
+    assert!(dir.join("native").exists());
+
+    // Once the client is gone, the server exits on its own
+    drop(client);
+    let start = Instant::now();
+    runtime.block_on(listen).unwrap();
+    assert!(start.elapsed() < Duration::from_secs(3));
+    assert_stopped(&mut runtime, &dir);
+    fs::remove_dir_all(&dir).unwrap();
+}